@@ -43,11 +43,41 @@ fn criterion_extract_labels_with_rgx(c: &mut Criterion) {
     });
 }
 
+fn criterion_sample_storage(c: &mut Criterion) {
+    let samples: Vec<prom::Sample> = (0..10_000)
+        .map(|index| {
+            prom::Sample::GaugeSample(prom::SingleValueSample {
+                timestamp: 1657000000 + index * 10,
+                value: (index % 100) as f64 * 0.5,
+            })
+        })
+        .collect();
+    c.bench_function("sample storage push", |b| {
+        b.iter(|| {
+            let mut storage = prom::SampleStorage::new();
+            for sample in &samples {
+                storage.push(black_box(sample.clone()));
+            }
+            storage
+        })
+    });
+    let storage: prom::SampleStorage = samples.into_iter().collect();
+    c.bench_function("sample storage iterate", |b| {
+        b.iter(|| {
+            black_box(&storage)
+                .iter()
+                .map(|sample| sample.timestamp())
+                .sum::<u64>()
+        })
+    });
+}
+
 criterion_group!(
     benches,
     criterion_decode_labels,
     criterion_decode_labels_with_rgx,
     criterion_extract_labels,
-    criterion_extract_labels_with_rgx
+    criterion_extract_labels_with_rgx,
+    criterion_sample_storage
 );
 criterion_main!(benches);
@@ -39,7 +39,7 @@ pub fn build() -> Command<'static> {
                 .env("PROM_SCRAPE_INTERVAL")
                 .value_hint(ValueHint::Other)
                 .value_name("SCRAPE_INTERVAL")
-                .global(false)
+                .global(true)
                 .takes_value(true)
                 .use_value_delimiter(false)
                 .help("Scrape interval of the prometheus endpoint")
@@ -47,6 +47,236 @@ pub fn build() -> Command<'static> {
                 .default_value("10")
                 .validator(|v| v.to_string().parse::<u16>())
         )
+        .arg(
+            Arg::new("Federate")
+                .long("federate")
+                .global(false)
+                .takes_value(false)
+                .requires("Match")
+                .help("Scrape the federation endpoint of a Prometheus server")
+                .long_help("Scrape the '/federate' endpoint of the Prometheus server given as endpoint. The series to federate are selected with the --match argument.")
+        )
+        .arg(
+            Arg::new("Match")
+                .short('m')
+                .long("match")
+                .value_name("SELECTOR")
+                .global(false)
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(false)
+                .requires("Federate")
+                .help("Series selector for the federation endpoint")
+                .long_help("A series selector passed as 'match[]' parameter to the federation endpoint. Can be repeated to federate several selections. Example: --match '{job=\"node\"}'")
+        )
+        .arg(
+            Arg::new("Query")
+                .short('q')
+                .long("query")
+                .value_name("PROMQL")
+                .global(false)
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(false)
+                .conflicts_with("Federate")
+                .help("PromQL expression evaluated by a Prometheus server")
+                .long_help("Evaluate the PromQL expression through the HTTP API of the Prometheus server given as endpoint instead of scraping the endpoint. Can be repeated. Example: --endpoint http://localhost:9090 --query 'rate(http_requests_total[1m])'")
+        )
+        .arg(
+            Arg::new("Query-Lookback")
+                .long("query-lookback")
+                .value_name("MINUTES")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .requires("Query")
+                .help("Minutes of history loaded for every query")
+                .long_help("The number of minutes of history loaded through a range query when starting to evaluate the queries. Default value is 15")
+                .default_value("15")
+                .validator(|v| v.to_string().parse::<u16>())
+        )
+        .arg(
+            Arg::new("Backfill-From")
+                .long("backfill-from")
+                .env("PROM_BACKFILL_FROM")
+                .value_hint(ValueHint::Url)
+                .value_name("PROMETHEUS_URL")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .conflicts_with("Query")
+                .help("Prometheus server to load the recent history from")
+                .long_help("The url of a Prometheus server scraping the same target. After the first scrape, the recent history of every metric is loaded from it. Example: http://localhost:9090")
+        )
+        .arg(
+            Arg::new("Backfill-Lookback")
+                .long("backfill-lookback")
+                .value_name("MINUTES")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .requires("Backfill-From")
+                .help("Minutes of history loaded from Prometheus")
+                .long_help("The number of minutes of history loaded from Prometheus before the first scrape. Default value is 30")
+                .default_value("30")
+                .validator(|v| v.to_string().parse::<u16>())
+        )
+        .arg(
+            Arg::new("Backfill-Selector")
+                .long("backfill-selector")
+                .value_name("LABEL_MATCHERS")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .requires("Backfill-From")
+                .help("Label matchers selecting the target in Prometheus")
+                .long_help("Label matchers added to every backfill query, needed when Prometheus scrapes several targets exposing the same metrics. Example: --backfill-selector 'job=\"api\",instance=\"localhost:8080\"'")
+        )
+        .arg(
+            Arg::new("Save-Session")
+                .long("save-session")
+                .value_hint(ValueHint::FilePath)
+                .value_name("FILE")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .help("File the session is saved to")
+                .long_help("The file the collected history is saved to when pressing 's', or when quitting with --autosave. A saved session can be opened again with --load-session.")
+        )
+        .arg(
+            Arg::new("Autosave")
+                .long("autosave")
+                .global(false)
+                .takes_value(false)
+                .requires("Save-Session")
+                .help("Save the session when quitting")
+        )
+        .arg(
+            Arg::new("Load-Session")
+                .long("load-session")
+                .value_hint(ValueHint::FilePath)
+                .value_name("FILE")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .conflicts_with_all(&["Federate", "Query", "Backfill-From"])
+                .help("Open a saved session read-only")
+                .long_help("Open a session saved with --save-session instead of scraping an endpoint. The session is shown read-only.")
+        )
+        .arg(
+            Arg::new("Alert-Rules")
+                .long("alert-rules")
+                .value_hint(ValueHint::FilePath)
+                .value_name("FILE")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .help("YAML file with alert rules evaluated after every scrape")
+                .long_help("A YAML file with a list of 'rules', each with a name, a PromQL 'expr', a 'comparator', a 'threshold', an optional 'for' duration and an optional 'severity'. The rules are evaluated against the collected history after every scrape, press 'a' to show the alerts.")
+        )
+        .arg(
+            Arg::new("Compare")
+                .long("compare")
+                .value_hint(ValueHint::Url)
+                .value_name("URL")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .conflicts_with_all(&["Query", "Load-Session"])
+                .help("Second endpoint scraped and compared with the first one")
+                .long_help("Scrape a second endpoint, e.g. the canary of a new version, next to the first one and compare their metrics by name and labels: the metrics and series found on one side only, and the differences of the values found on both sides. Press 'c' to switch between the comparison and the metrics of the first endpoint. Example: --endpoint http://old:8080/metrics --compare http://new:8080/metrics")
+        )
+        .arg(
+            Arg::new("Rule-Files")
+                .long("rule-files")
+                .value_hint(ValueHint::FilePath)
+                .value_name("FILE")
+                .global(false)
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(false)
+                .help("Prometheus rule file evaluated against the collected history")
+                .long_help("A rule file in the format of Prometheus. Can be repeated. The recording rules are shown as metrics of their own and the alerting rules with the alerts of '--alert-rules', press 'a' to show them. Rules with PromQL that prom-tui does not support are skipped.")
+        )
+        .arg(
+            Arg::new("Anomaly-Window")
+                .long("anomaly-window")
+                .value_name("SAMPLES")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .help("Samples the anomaly detection compares a sample to")
+                .long_help("The number of preceding samples whose mean and standard deviation a sample is compared to when looking for unusual samples. Default value is 20")
+                .default_value("20")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(window) if window >= 2 => Ok(()),
+                    _ => Err(String::from("expected a number of samples of at least 2")),
+                })
+        )
+        .arg(
+            Arg::new("Anomaly-Sigmas")
+                .long("anomaly-sigmas")
+                .value_name("SIGMAS")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .help("Standard deviations beyond which a sample is unusual")
+                .long_help("The distance from the rolling mean, in standard deviations, beyond which a sample or a level shift is highlighted as unusual. Lower values are more sensitive. Default value is 3")
+                .default_value("3")
+                .validator(|v| match v.parse::<f64>() {
+                    Ok(sigmas) if sigmas > 0.0 => Ok(()),
+                    _ => Err(String::from("expected a positive number")),
+                })
+        )
+        .arg(
+            Arg::new("Cardinality-Threshold")
+                .long("cardinality-threshold")
+                .value_name("SERIES")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .help("Number of series above which a metric is highlighted")
+                .long_help("The metrics with more series than this are highlighted in the cardinality view, press 'k' to show it. Default value is 1000")
+                .default_value("1000")
+                .validator(|v| v.to_string().parse::<usize>())
+        )
+        .arg(
+            Arg::new("Forecast-Threshold")
+                .long("forecast-threshold")
+                .value_name("VALUE")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .help("Value the trend of gauges is forecast to reach")
+                .long_help("Draws the linear trend of the selected gauge and estimates when it reaches this value, e.g. the size of a disk. Press 'f' to change the window the trend is fitted to. Example: --forecast-threshold 100")
+                .validator(|v| match v.parse::<f64>() {
+                    Ok(threshold) if threshold.is_finite() => Ok(()),
+                    _ => Err(String::from("expected a number")),
+                })
+        )
+        .arg(
+            Arg::new("Export-Dir")
+                .long("export-dir")
+                .value_hint(ValueHint::DirPath)
+                .value_name("DIR")
+                .global(false)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .help("Directory the exports are written to")
+                .long_help("The directory the selected series ('e') or the whole history ('E') are exported to. Default value is the current directory")
+                .default_value(".")
+        )
+        .arg(
+            Arg::new("Export-Format")
+                .long("export-format")
+                .value_name("FORMAT")
+                .global(true)
+                .takes_value(true)
+                .use_value_delimiter(false)
+                .possible_values(["csv", "json", "prometheus", "openmetrics"])
+                .help("Format of the exports")
+                .long_help("The format of the exports: csv writes one row per timestamp and label set, json writes the labels of every series with its samples, prometheus writes the latest values in the exposition format, openmetrics writes every sample with its timestamp for promtool. Default value is csv, or the extension of the output file (.csv, .json, .prom, .om) for the export subcommand")
+        )
         .arg(
             Arg::new("Logging")
                 .short('l')
@@ -62,9 +292,167 @@ pub fn build() -> Command<'static> {
                     _ => Err("the value should match the following: DEBUG,ERROR,WARN,INFO")
                 })
         )
+        .subcommand(
+            Command::new("record")
+                .about("Record the raw scrapes of the endpoint to an archive")
+                .long_about("Scrape the endpoint without showing the dashboard and append every raw scrape, with its timestamp, to an archive. The archive can be replayed with the replay subcommand.")
+                .arg(
+                    Arg::new("Output")
+                        .short('o')
+                        .long("output")
+                        .value_hint(ValueHint::FilePath)
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Archive the scrapes are appended to")
+                )
+                .arg(
+                    Arg::new("Scrapes")
+                        .long("scrapes")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .help("Stop after recording this number of scrapes")
+                        .long_help("The number of scrapes to record. Without it, the recording continues until the process is interrupted.")
+                        .validator(|v| v.to_string().parse::<u64>())
+                )
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export a saved session or a recording to CSV or JSON")
+                .long_about("Export the whole history, a metric or a single time series of a session saved with --save-session, or of an archive recorded with the record subcommand.")
+                .arg(
+                    Arg::new("Input")
+                        .value_hint(ValueHint::FilePath)
+                        .value_name("SESSION_OR_ARCHIVE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Saved session or recording to export")
+                )
+                .arg(
+                    Arg::new("Output")
+                        .short('o')
+                        .long("output")
+                        .value_hint(ValueHint::FilePath)
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("File the export is written to")
+                )
+                .arg(
+                    Arg::new("Metric")
+                        .long("metric")
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .help("Export only this metric")
+                )
+                .arg(
+                    Arg::new("Labels")
+                        .long("labels")
+                        .value_name("LABELS")
+                        .takes_value(true)
+                        .requires("Metric")
+                        .help("Export only the time series of the metric with these labels")
+                        .long_help("The labels of the time series to export, as shown in the labels list. Example: --labels 'code=\"200\",method=\"get\"'")
+                )
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Replay an archive recorded with the record subcommand")
+                .long_about("Feed the recorded scrapes through the parser into the dashboard, with the timestamps of the recording.")
+                .arg(
+                    Arg::new("Archive")
+                        .value_hint(ValueHint::FilePath)
+                        .value_name("ARCHIVE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Archive to replay")
+                )
+                .arg(
+                    Arg::new("Speed")
+                        .long("speed")
+                        .value_name("FACTOR")
+                        .takes_value(true)
+                        .conflicts_with("Step")
+                        .help("Replay speed factor")
+                        .long_help("The factor the time between two recorded scrapes is divided by. Default value is 1, replaying in real time. Example: --speed 10")
+                        .default_value("1")
+                        .validator(|v| match v.parse::<f64>() {
+                            Ok(speed) if speed > 0.0 => Ok(()),
+                            _ => Err("the value should be a positive number")
+                        })
+                )
+                .arg(
+                    Arg::new("Step")
+                        .long("step")
+                        .takes_value(false)
+                        .help("Replay the next scrape only when pressing 'n'")
+                )
+        )
 }
 
 #[test]
 fn verify() {
     build().debug_assert();
 }
+
+#[test]
+fn verify_replay_arguments() {
+    let matches = build()
+        .try_get_matches_from(vec![
+            "prom-tui",
+            "replay",
+            "scrapes.recording",
+            "--speed",
+            "4",
+        ])
+        .expect("replay arguments to be valid");
+    let (_, replay_matches) = matches.subcommand().expect("replay subcommand");
+    assert_eq!(
+        replay_matches.value_of("Archive"),
+        Some("scrapes.recording")
+    );
+    assert_eq!(replay_matches.value_of("Speed"), Some("4"));
+    assert!(build()
+        .try_get_matches_from(vec![
+            "prom-tui",
+            "replay",
+            "scrapes.recording",
+            "--speed",
+            "0"
+        ])
+        .is_err());
+}
+
+#[test]
+fn verify_export_labels_require_metric() {
+    assert!(build()
+        .try_get_matches_from(vec![
+            "prom-tui",
+            "export",
+            "incident.session",
+            "-o",
+            "out.csv",
+            "--labels",
+            "code=\"200\"",
+        ])
+        .is_err());
+}
+
+#[test]
+fn verify_federate_requires_match() {
+    assert!(build()
+        .try_get_matches_from(vec!["prom-tui", "--federate"])
+        .is_err());
+    let matches = build()
+        .try_get_matches_from(vec![
+            "prom-tui",
+            "--federate",
+            "--match",
+            "{job=\"node\"}",
+            "-m",
+            "up",
+        ])
+        .expect("federate arguments to be valid");
+    let selectors: Vec<&str> = matches.values_of("Match").unwrap().collect();
+    assert_eq!(selectors, vec!["{job=\"node\"}", "up"]);
+}
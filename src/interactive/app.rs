@@ -1,7 +1,19 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::prom::MetricScraper;
-use tui::widgets::ListState;
+use crate::prom::{
+    aggregate, cardinality, diff_histories, export_to_file, format_duration, has_active_anomaly,
+    rank_movers, save_session, snapshot_changes, Alerting, AnomalyConfig, CardinalitySort,
+    ColorScale, CounterView, ExportFormat, ExportScope, GroupBy, HistogramWindow, Metric,
+    MetricCardinality, MetricDiff, MetricHistory, MetricSource, Mover, MoverRanking, Query,
+    Recorder, Selector, SeriesChange, FORECAST_WINDOWS, MOVER_WINDOWS,
+};
+use crossterm::event::KeyCode;
+use tui::widgets::{ListState, TableState};
 
 pub enum ElementInFocus {
     MetricHeaders,
@@ -16,7 +28,7 @@ enum Direction {
 pub struct App<'a> {
     pub endpoint: &'a str,
     pub scrape_interval: u64,
-    pub metric_scraper: MetricScraper,
+    pub metric_source: Box<dyn MetricSource>,
 
     pub focus: ElementInFocus,
     pub metric_list_state: ListState,
@@ -24,20 +36,275 @@ pub struct App<'a> {
     pub selected_metric: Option<String>,
     pub selected_label: Option<String>,
     pub should_quit: bool,
+    /// file the session is saved to, on demand or when quitting with autosave
+    pub session_file: Option<String>,
+    pub autosave: bool,
+    /// directory and format of the exported series
+    pub export_dir: String,
+    pub export_format: ExportFormat,
+    /// feedback about the last user action, e.g. a saved session
+    pub status_msg: Option<String>,
+    /// selector filtering the labels list, e.g. `{code=~"5.."}`
+    pub label_filter: Option<Selector>,
+    /// the selector or the query being typed, the keys go to the input while it is set
+    pub input: Option<TextInput>,
+    /// expression evaluated against the history, shown instead of the metrics
+    pub query: Option<Query>,
+    /// the result of an expression in a generation of the history
+    query_result: Option<(Arc<MetricHistory>, String, Arc<anyhow::Result<Metric>>)>,
+    /// how counters are graphed
+    pub counter_view: CounterView,
+    /// observations the quantiles of histograms are estimated from
+    pub histogram_window: HistogramWindow,
+    /// colour scale of the histogram heatmap, when shown
+    pub heatmap: Option<ColorScale>,
+    /// seconds of the latest samples the trend of gauges is fitted to, when shown
+    pub forecast_window: Option<u64>,
+    /// value the trend of gauges is forecast to reach
+    pub forecast_threshold: Option<f64>,
+    /// the time series of the selected metric are aggregated by these labels
+    pub group_by: Option<GroupBy>,
+    /// sensitivity of the highlighting of unusual samples
+    pub anomaly_config: AnomalyConfig,
+    /// the metrics with active anomalies in a generation of the history
+    anomaly_marks: Option<(Arc<MetricHistory>, HashSet<String>)>,
+    /// the alert rules and the state of their alerts
+    pub alerting: Option<Alerting>,
+    /// the alerts are shown instead of the metrics
+    pub show_alerts: bool,
+    /// the recording rules, their metrics are listed with the scraped ones
+    pub recorder: Option<Recorder>,
+    /// the second endpoint, compared with the first one
+    pub comparison: Option<Comparison>,
+    /// the metrics of both endpoints are compared instead of shown
+    pub show_comparison: bool,
+    /// timestamp of the first mark, until the second one is set
+    pub first_mark: Option<u64>,
+    /// the changes between the last two marks, shown instead of the metrics
+    pub marked_changes: Option<MarkedChanges>,
+    /// the metrics with more series are highlighted in the cardinality view
+    pub cardinality_threshold: usize,
+    /// the cardinality of the metrics, shown instead of the metrics
+    pub cardinality: Option<CardinalityView>,
+    /// the series which moved the most, shown instead of the metrics
+    pub movers: Option<MoversView>,
+}
+
+/// How the cardinality of the metrics is listed.
+pub struct CardinalityView {
+    pub sort: CardinalitySort,
+    pub ascending: bool,
+    pub state: TableState,
+    /// the generation of the history the cardinality was computed for
+    computed: Option<Arc<MetricHistory>>,
+    metrics: Arc<Vec<MetricCardinality>>,
+}
+
+impl CardinalityView {
+    fn new() -> Self {
+        let mut state = TableState::default();
+        state.select(Some(0));
+        Self {
+            sort: CardinalitySort::Series,
+            ascending: false,
+            state,
+            computed: None,
+            metrics: Arc::new(Vec::new()),
+        }
+    }
+
+    /// The sorted cardinality of the metrics, only computed again for a new generation of the
+    /// history.
+    pub fn metrics(&mut self, history: &Arc<MetricHistory>) -> Arc<Vec<MetricCardinality>> {
+        let unchanged = match &self.computed {
+            Some(computed) => Arc::ptr_eq(computed, history),
+            None => false,
+        };
+        if !unchanged {
+            let mut metrics = cardinality(history);
+            self.sort.sort(&mut metrics, self.ascending);
+            self.metrics = Arc::new(metrics);
+            self.computed = Some(Arc::clone(history));
+        }
+        Arc::clone(&self.metrics)
+    }
+
+    fn sort(&mut self, sort: CardinalitySort, ascending: bool) {
+        self.sort = sort;
+        self.ascending = ascending;
+        let metrics: &mut Vec<MetricCardinality> = Arc::make_mut(&mut self.metrics);
+        sort.sort(metrics, ascending);
+        self.state.select(Some(0));
+    }
+}
+
+/// How the series which moved the most are ranked.
+pub struct MoversView {
+    pub ranking: MoverRanking,
+    /// seconds before the latest sample the series are ranked over, one of `MOVER_WINDOWS`
+    pub window: u64,
+    pub state: TableState,
+    /// the generation of the history the movers were ranked in
+    ranked: Option<Arc<MetricHistory>>,
+    movers: Arc<Vec<Mover>>,
+}
+
+impl MoversView {
+    fn new() -> Self {
+        let mut state = TableState::default();
+        state.select(Some(0));
+        Self {
+            ranking: MoverRanking::AbsoluteChange,
+            window: MOVER_WINDOWS[1],
+            state,
+            ranked: None,
+            movers: Arc::new(Vec::new()),
+        }
+    }
+
+    /// The movers of the history, only ranked again for a new generation of the history or
+    /// when the ranking or the window changed.
+    pub fn movers(&mut self, history: &Arc<MetricHistory>) -> Arc<Vec<Mover>> {
+        let unchanged = match &self.ranked {
+            Some(ranked) => Arc::ptr_eq(ranked, history),
+            None => false,
+        };
+        if !unchanged {
+            self.movers = Arc::new(rank_movers(history, self.ranking, self.window));
+            self.ranked = Some(Arc::clone(history));
+        }
+        Arc::clone(&self.movers)
+    }
+
+    fn rank(&mut self, ranking: MoverRanking, window: u64) {
+        self.ranking = ranking;
+        self.window = window;
+        self.ranked = None;
+        self.state.select(Some(0));
+    }
+}
+
+/// The series which changed between two marks, e.g. before and after a load test.
+pub struct MarkedChanges {
+    pub from: u64,
+    pub to: u64,
+    pub changes: Vec<SeriesChange>,
+    pub state: TableState,
+}
+
+/// A second endpoint, e.g. the new version of a service during a canary rollout, scraped to be
+/// compared with the first one.
+pub struct Comparison {
+    pub endpoint: String,
+    pub source: Box<dyn MetricSource>,
+    /// the generations of both histories the diff was computed from
+    diffed: Option<(Arc<MetricHistory>, Arc<MetricHistory>)>,
+    diff: Arc<Vec<MetricDiff>>,
+}
+
+impl Comparison {
+    pub fn new(endpoint: String, source: Box<dyn MetricSource>) -> Self {
+        Self {
+            endpoint,
+            source,
+            diffed: None,
+            diff: Arc::new(Vec::new()),
+        }
+    }
+
+    /// The metrics of the baseline aligned with the ones of the compared endpoint. The diff is
+    /// only computed again for a new generation of one of the histories.
+    fn diff(&mut self, baseline: &Arc<MetricHistory>) -> Arc<Vec<MetricDiff>> {
+        let candidate = self.source.history_snapshot();
+        let unchanged = match &self.diffed {
+            Some((diffed_baseline, diffed_candidate)) => {
+                Arc::ptr_eq(diffed_baseline, baseline) && Arc::ptr_eq(diffed_candidate, &candidate)
+            }
+            None => false,
+        };
+        if !unchanged {
+            self.diff = Arc::new(diff_histories(baseline, &candidate));
+            self.diffed = Some((Arc::clone(baseline), candidate));
+        }
+        Arc::clone(&self.diff)
+    }
+}
+
+/// How the metrics are shown, set from the command line.
+pub struct ViewOptions {
+    pub export_dir: String,
+    pub export_format: ExportFormat,
+    pub anomaly_config: AnomalyConfig,
+    pub cardinality_threshold: usize,
+    pub forecast_threshold: Option<f64>,
+    pub alerting: Option<Alerting>,
+    pub recorder: Option<Recorder>,
+    /// the endpoint compared with the shown one, and its source
+    pub compare_with: Option<(String, Box<dyn MetricSource>)>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InputTarget {
+    LabelFilter,
+    Query,
+    GroupBy,
+}
+
+pub struct TextInput {
+    pub target: InputTarget,
+    pub text: String,
 }
 
 impl<'a> App<'a> {
-    pub fn new(endpoint: &'a str, scrape_interval: u64, metric_scraper: MetricScraper) -> App<'a> {
+    pub fn new(
+        endpoint: &'a str,
+        scrape_interval: u64,
+        metric_source: Box<dyn MetricSource>,
+        session_file: Option<String>,
+        autosave: bool,
+        options: ViewOptions,
+    ) -> App<'a> {
+        let comparison = options
+            .compare_with
+            .map(|(endpoint, source)| Comparison::new(endpoint, source));
         let mut app = App {
             endpoint,
             scrape_interval,
-            metric_scraper,
+            metric_source,
             focus: ElementInFocus::MetricHeaders,
             metric_list_state: ListState::default(),
             labels_list_state: ListState::default(),
             selected_metric: None,
             selected_label: None,
             should_quit: false,
+            session_file,
+            autosave,
+            export_dir: options.export_dir,
+            export_format: options.export_format,
+            status_msg: None,
+            label_filter: None,
+            input: None,
+            query: None,
+            counter_view: CounterView::Raw,
+            histogram_window: HistogramWindow::Cumulative,
+            heatmap: None,
+            // a threshold is only given to see when it is reached
+            forecast_window: options.forecast_threshold.map(|_| FORECAST_WINDOWS[0]),
+            forecast_threshold: options.forecast_threshold,
+            group_by: None,
+            anomaly_config: options.anomaly_config,
+            anomaly_marks: None,
+            query_result: None,
+            alerting: options.alerting,
+            show_alerts: false,
+            show_comparison: comparison.is_some(),
+            first_mark: None,
+            marked_changes: None,
+            cardinality_threshold: options.cardinality_threshold,
+            cardinality: None,
+            movers: None,
+            comparison,
+            recorder: options.recorder,
         };
         // initialize lists state to index 0
         app.metric_list_state.select(Some(0));
@@ -45,12 +312,36 @@ impl<'a> App<'a> {
         app
     }
 
+    /// The history of the source with the metrics recorded by the recording rules.
+    pub fn history(&mut self) -> Arc<MetricHistory> {
+        let history = self.metric_source.history_snapshot();
+        match &mut self.recorder {
+            Some(recorder) => recorder.record(&history, self.scrape_interval),
+            None => history,
+        }
+    }
+
+    /// The diff of the history and the one of the compared endpoint, while it is shown.
+    pub fn comparison_diff(
+        &mut self,
+        history: &Arc<MetricHistory>,
+    ) -> Option<Arc<Vec<MetricDiff>>> {
+        match &mut self.comparison {
+            Some(comparison) if self.show_comparison => Some(comparison.diff(history)),
+            _ => None,
+        }
+    }
+
     fn change_selected_metric(&mut self, direction: Direction) -> Result<bool, Box<dyn Error>> {
-        let metrics_headers = self
-            .metric_scraper
-            .get_history_lock()?
-            .get_metrics_headers();
+        let history = self.history();
+        let metrics_headers = match self.comparison_diff(&history) {
+            Some(diff) => diff.iter().map(|metric| metric.name.clone()).collect(),
+            None => self.visible_metrics(&history),
+        };
         let metrics_headers_len = metrics_headers.len();
+        if metrics_headers_len == 0 {
+            return Ok(false);
+        }
         update_list_state_with_direction(
             direction,
             &mut self.metric_list_state,
@@ -73,29 +364,519 @@ impl<'a> App<'a> {
     }
 
     fn change_selected_labels(&mut self, direction: Direction) -> Result<bool, Box<dyn Error>> {
-        let selected_metric = self.selected_metric.clone().expect("metric to be selected");
-        if let Some(metric) = self
-            .metric_scraper
-            .get_history_lock()?
-            .get_metric(&selected_metric)
-        {
-            let labels: Vec<String> = metric.time_series.iter().map(|(k, _)| k.clone()).collect();
-            let labels_len = labels.len();
-            update_list_state_with_direction(direction, &mut self.labels_list_state, labels_len);
-            let selected_index = self
-                .labels_list_state
-                .selected()
-                .expect("a selected labels item");
-            let next_selected_label = labels.get(selected_index).map(|o| o.clone());
-            let different = self.selected_label != next_selected_label;
-            self.selected_label = next_selected_label;
-            return Ok(different);
-        }
-        Ok(false)
+        let history = self.history();
+        let comparison_diff = self.comparison_diff(&history);
+        let query_result = self.query_result(&history);
+        let labels = match (query_result.as_deref(), comparison_diff) {
+            (Some(Ok(result)), _) => self.visible_labels(result),
+            (Some(Err(_)), _) => return Ok(false),
+            (None, Some(diff)) => match diff
+                .iter()
+                .find(|metric| Some(&metric.name) == self.selected_metric.as_ref())
+            {
+                Some(metric) => metric
+                    .series
+                    .iter()
+                    .map(|series| series.key.clone())
+                    .collect(),
+                None => return Ok(false),
+            },
+            (None, None) => match self
+                .selected_metric
+                .as_ref()
+                .and_then(|selected_metric| history.get_metric(selected_metric))
+            {
+                Some(metric) => self.visible_labels(&self.grouped_metric(metric)),
+                None => return Ok(false),
+            },
+        };
+        let labels_len = labels.len();
+        if labels_len == 0 {
+            return Ok(false);
+        }
+        update_list_state_with_direction(direction, &mut self.labels_list_state, labels_len);
+        let selected_index = self
+            .labels_list_state
+            .selected()
+            .expect("a selected labels item");
+        let next_selected_label = labels.get(selected_index).cloned();
+        let different = self.selected_label != next_selected_label;
+        self.selected_label = next_selected_label;
+        Ok(different)
+    }
+
+    /// The metrics with time series matching the filter, or all the metrics without filter.
+    pub fn visible_metrics(&self, history: &MetricHistory) -> Vec<String> {
+        match &self.label_filter {
+            Some(filter) => history
+                .select(filter)
+                .into_iter()
+                .map(|(metric, _)| metric.details.name.clone())
+                .collect(),
+            None => history.get_metrics_headers(),
+        }
+    }
+
+    /// The metrics with an anomaly in the latest samples of one of their time series. The marks
+    /// are only computed again for a new generation of the history.
+    pub fn metrics_with_anomalies(&mut self, history: &Arc<MetricHistory>) -> HashSet<String> {
+        match &self.anomaly_marks {
+            Some((marked, metrics)) if Arc::ptr_eq(marked, history) => metrics.clone(),
+            _ => {
+                let metrics: HashSet<String> = history
+                    .metrics
+                    .values()
+                    .filter(|metric| has_active_anomaly(metric, &self.anomaly_config))
+                    .map(|metric| metric.details.name.clone())
+                    .collect();
+                self.anomaly_marks = Some((Arc::clone(history), metrics.clone()));
+                metrics
+            }
+        }
+    }
+
+    /// The metric with its time series matching the filter aggregated by the group-by labels, or
+    /// the metric itself without group-by.
+    pub fn grouped_metric<'m>(&self, metric: &'m Metric) -> Cow<'m, Metric> {
+        match &self.group_by {
+            Some(group_by) => {
+                let keys = match &self.label_filter {
+                    Some(filter) => metric.select(&filter.label_matchers()),
+                    None => metric.time_series().keys().map(String::as_str).collect(),
+                };
+                Cow::Owned(aggregate(metric, &keys, group_by))
+            }
+            None => Cow::Borrowed(metric),
+        }
+    }
+
+    /// The labels of the time series of the metric, restricted to the ones matching the filter.
+    /// The series of a query result or of an aggregation are all shown, in order.
+    pub fn visible_labels(&self, metric: &Metric) -> Vec<String> {
+        if self.query.is_some() || self.group_by.is_some() {
+            let mut labels: Vec<String> = metric.time_series().keys().cloned().collect();
+            labels.sort();
+            return labels;
+        }
+        match &self.label_filter {
+            Some(filter) if !filter.matches_metric_name(&metric.details.name) => Vec::new(),
+            Some(filter) => metric
+                .select(&filter.label_matchers())
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            None => metric.time_series().keys().cloned().collect(),
+        }
+    }
+
+    /// Starts editing the labels filter or the query, from the current one if any.
+    pub fn on_start_input(&mut self, target: InputTarget) {
+        let text = match target {
+            InputTarget::LabelFilter => self.label_filter.as_ref().map(Selector::to_string),
+            InputTarget::Query => self.query.as_ref().map(|query| query.expression.clone()),
+            InputTarget::GroupBy => self.group_by.as_ref().map(GroupBy::to_string),
+        };
+        self.input = Some(TextInput {
+            target,
+            text: text.unwrap_or_default(),
+        });
+    }
+
+    pub fn on_input(&mut self, key_code: KeyCode) {
+        let input = match &mut self.input {
+            Some(input) => input,
+            None => return,
+        };
+        match key_code {
+            KeyCode::Char(c) => input.text.push(c),
+            KeyCode::Backspace => {
+                input.text.pop();
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Enter => {
+                let text = input.text.trim().to_string();
+                let result = match input.target {
+                    InputTarget::LabelFilter if text.is_empty() => {
+                        self.set_label_filter(None);
+                        Ok(())
+                    }
+                    InputTarget::LabelFilter => {
+                        Selector::parse(&text).map(|selector| self.set_label_filter(Some(selector)))
+                    }
+                    InputTarget::Query if text.is_empty() => {
+                        self.set_query(None);
+                        Ok(())
+                    }
+                    InputTarget::Query => Query::parse(&text)
+                        .map(|query| self.set_query(Some(query)))
+                        .map_err(anyhow::Error::from),
+                    InputTarget::GroupBy if text.is_empty() => {
+                        self.set_group_by(None);
+                        Ok(())
+                    }
+                    InputTarget::GroupBy => {
+                        GroupBy::parse(&text).map(|group_by| self.set_group_by(Some(group_by)))
+                    }
+                };
+                match result {
+                    Ok(()) => self.input = None,
+                    // keep editing, so the input can be fixed
+                    Err(err) => self.status_msg = Some(err.to_string()),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_editing(&self, target: InputTarget) -> bool {
+        matches!(&self.input, Some(input) if input.target == target)
+    }
+
+    fn set_query(&mut self, query: Option<Query>) {
+        self.status_msg = None;
+        self.focus = if query.is_some() {
+            ElementInFocus::LabelsView
+        } else {
+            ElementInFocus::MetricHeaders
+        };
+        self.query = query;
+        self.labels_list_state.select(Some(0));
+        self.selected_label = None;
+    }
+
+    /// Switches counters between the raw value, the delta per scrape and the rate per second.
+    pub fn on_toggle_counter_view(&mut self) {
+        self.counter_view = self.counter_view.next();
+        self.status_msg = Some(format!("Counters are shown as {}", self.counter_view));
+    }
+
+    /// Switches histograms between all the observations, those of the last scrapes and those of
+    /// the last seconds.
+    pub fn on_toggle_histogram_window(&mut self) {
+        self.histogram_window = match self.histogram_window {
+            HistogramWindow::Cumulative => HistogramWindow::Scrapes(4),
+            HistogramWindow::Scrapes(_) => HistogramWindow::Seconds(4 * self.scrape_interval),
+            HistogramWindow::Seconds(_) => HistogramWindow::Cumulative,
+        };
+        self.set_histogram_window_status();
+    }
+
+    /// Widens or narrows the window of histograms by one scrape.
+    pub fn on_resize_histogram_window(&mut self, grow: bool) {
+        self.histogram_window = match self.histogram_window {
+            HistogramWindow::Cumulative => HistogramWindow::Cumulative,
+            HistogramWindow::Scrapes(scrapes) if grow => HistogramWindow::Scrapes(scrapes + 1),
+            HistogramWindow::Scrapes(scrapes) => HistogramWindow::Scrapes((scrapes - 1).max(1)),
+            HistogramWindow::Seconds(seconds) if grow => {
+                HistogramWindow::Seconds(seconds + self.scrape_interval)
+            }
+            HistogramWindow::Seconds(seconds) => HistogramWindow::Seconds(
+                (seconds.saturating_sub(self.scrape_interval)).max(self.scrape_interval),
+            ),
+        };
+        self.set_histogram_window_status();
+    }
+
+    fn set_histogram_window_status(&mut self) {
+        self.status_msg = Some(format!(
+            "Histograms show the observations {}",
+            self.histogram_window
+        ));
+    }
+
+    /// Shows histograms as a heatmap with a linear, then a log colour scale, then as bar chart.
+    pub fn on_toggle_heatmap(&mut self) {
+        self.heatmap = match self.heatmap {
+            None => Some(ColorScale::Linear),
+            Some(ColorScale::Linear) => Some(ColorScale::Log),
+            Some(ColorScale::Log) => None,
+        };
+        self.status_msg = Some(match self.heatmap {
+            Some(scale) => format!("Histograms are shown as heatmap with {}", scale),
+            None => String::from("Histograms are shown as bar chart"),
+        });
+    }
+
+    /// Fits the trend of gauges to the next larger window, then hides it.
+    pub fn on_toggle_forecast(&mut self) {
+        self.forecast_window = match self.forecast_window {
+            None => Some(FORECAST_WINDOWS[0]),
+            Some(window) => FORECAST_WINDOWS
+                .iter()
+                .find(|larger| **larger > window)
+                .copied(),
+        };
+        self.status_msg = Some(match self.forecast_window {
+            Some(window) => format!(
+                "Trend of gauges fitted to the last {}",
+                format_duration(window)
+            ),
+            None => String::from("Trend of gauges hidden"),
+        });
+    }
+
+    /// Switches between the alerts and the metrics.
+    pub fn on_toggle_alerts(&mut self) {
+        if self.alerting.is_some() {
+            self.show_alerts = !self.show_alerts;
+        } else {
+            self.status_msg = Some(String::from(
+                "No alert rules configured, start with --alert-rules <FILE>",
+            ));
+        }
+    }
+
+    /// Evaluates the alert rules if a scrape happened since the last evaluation.
+    pub fn update_alerts(&mut self, history: &MetricHistory) {
+        if let Some(alerting) = &mut self.alerting {
+            alerting.evaluate(history);
+        }
+    }
+
+    /// Switches between the comparison of the two endpoints and the metrics of the first one.
+    pub fn on_toggle_comparison(&mut self) {
+        if self.comparison.is_some() {
+            self.show_comparison = !self.show_comparison;
+            // the metrics and the series of the lists differ between the views
+            self.selected_metric = None;
+            self.selected_label = None;
+            self.metric_list_state.select(Some(0));
+            self.labels_list_state.select(Some(0));
+            self.focus = ElementInFocus::MetricHeaders;
+        } else {
+            self.status_msg = Some(String::from(
+                "No endpoint to compare with, start with --compare <URL>",
+            ));
+        }
+    }
+
+    /// Marks the current values of the series. The second mark shows what changed since the
+    /// first one, the next one starts over.
+    pub fn on_mark(&mut self) {
+        let history = self.history();
+        let latest = match history.latest_timestamp() {
+            Some(latest) => latest,
+            None => {
+                self.status_msg = Some(String::from("Nothing scraped to mark yet"));
+                return;
+            }
+        };
+        match self.first_mark {
+            Some(from) if latest > from => {
+                let changes = snapshot_changes(&history, from, latest);
+                self.status_msg = Some(format!(
+                    "{} series changed between the marks, Esc to close",
+                    changes.len()
+                ));
+                let mut state = TableState::default();
+                state.select(Some(0));
+                self.first_mark = None;
+                self.marked_changes = Some(MarkedChanges {
+                    from,
+                    to: latest,
+                    changes,
+                    state,
+                });
+            }
+            Some(_) => {
+                self.status_msg = Some(String::from(
+                    "Nothing scraped since the first mark, press 'b' again after the next scrape",
+                ));
+            }
+            None => {
+                self.marked_changes = None;
+                self.first_mark = Some(latest);
+                self.status_msg = Some(String::from(
+                    "Marked the current values, press 'b' again to see what changed since",
+                ));
+            }
+        }
+    }
+
+    /// Switches between the cardinality of the metrics and the metrics, the metrics with the
+    /// most series first.
+    pub fn on_toggle_cardinality(&mut self) {
+        self.cardinality = match self.cardinality {
+            Some(_) => None,
+            None => Some(CardinalityView::new()),
+        };
+    }
+
+    /// Sorts the cardinality by the next column, or reverses the order.
+    pub fn on_sort_cardinality(&mut self, reverse: bool) {
+        if let Some(view) = &mut self.cardinality {
+            if reverse {
+                view.sort(view.sort, !view.ascending);
+            } else {
+                let sort = view.sort.next();
+                // names are listed alphabetically, numbers from the largest
+                view.sort(sort, sort == CardinalitySort::Name);
+            }
+            self.status_msg = Some(format!(
+                "Sorted by {}, {}",
+                view.sort,
+                if view.ascending {
+                    "ascending"
+                } else {
+                    "descending"
+                }
+            ));
+        }
+    }
+
+    /// Switches between the series which moved the most and the metrics.
+    pub fn on_toggle_movers(&mut self) {
+        self.movers = match self.movers {
+            Some(_) => None,
+            None => Some(MoversView::new()),
+        };
+    }
+
+    /// Ranks the movers by the next criterion.
+    pub fn on_rank_movers(&mut self) {
+        if let Some(view) = &mut self.movers {
+            view.rank(view.ranking.next(), view.window);
+            self.status_msg = Some(format!("Ranked by {}", view.ranking));
+        }
+    }
+
+    /// Ranks the movers over the next larger or smaller window.
+    pub fn on_resize_movers_window(&mut self, larger: bool) {
+        if let Some(view) = &mut self.movers {
+            let index = MOVER_WINDOWS
+                .iter()
+                .position(|window| *window == view.window)
+                .unwrap_or(0);
+            let index = if larger {
+                (index + 1).min(MOVER_WINDOWS.len() - 1)
+            } else {
+                index.saturating_sub(1)
+            };
+            view.rank(view.ranking, MOVER_WINDOWS[index]);
+            self.status_msg = Some(format!("Ranked over the last {}s", view.window));
+        }
+    }
+
+    /// Closes the movers and shows the graph of the selected one in the metrics.
+    pub fn on_select_mover(&mut self) {
+        let history = self.history();
+        let mover = match &mut self.movers {
+            Some(view) => {
+                let movers = view.movers(&history);
+                match view.state.selected().and_then(|index| movers.get(index)) {
+                    Some(mover) => mover.clone(),
+                    None => return,
+                }
+            }
+            None => return,
+        };
+        self.movers = None;
+        self.set_query(None);
+        self.show_comparison = false;
+        // the series may be hidden by the filter or merged by the grouping
+        if self.group_by.is_some() {
+            self.set_group_by(None);
+        }
+        let visible = match history.get_metric(&mover.metric) {
+            Some(metric) => {
+                self.visible_metrics(&history).contains(&mover.metric)
+                    && self.visible_labels(metric).contains(&mover.labels)
+            }
+            None => false,
+        };
+        if !visible {
+            self.set_label_filter(None);
+        }
+        self.selected_metric = Some(mover.metric);
+        self.selected_label = Some(mover.labels);
+        self.focus = ElementInFocus::LabelsView;
+    }
+
+    /// Closes the query and returns to the metrics.
+    pub fn on_escape(&mut self) {
+        if self.marked_changes.is_some() {
+            self.marked_changes = None;
+        } else if self.movers.is_some() {
+            self.movers = None;
+        } else if self.cardinality.is_some() {
+            self.cardinality = None;
+        } else if self.show_alerts {
+            self.show_alerts = false;
+        } else if self.query.is_some() {
+            self.set_query(None);
+        }
+    }
+
+    /// The result of the query over the history, evaluated at every scrape interval. The query
+    /// is only evaluated again for a new generation of the history or a new expression.
+    pub fn query_result(
+        &mut self,
+        history: &Arc<MetricHistory>,
+    ) -> Option<Arc<anyhow::Result<Metric>>> {
+        let query = self.query.as_ref()?;
+        let unchanged = match &self.query_result {
+            Some((evaluated, expression, _)) => {
+                Arc::ptr_eq(evaluated, history) && expression == &query.expression
+            }
+            None => false,
+        };
+        if !unchanged {
+            let result = Arc::new(query.evaluate(history, self.scrape_interval));
+            self.query_result = Some((Arc::clone(history), query.expression.clone(), result));
+        }
+        self.query_result
+            .as_ref()
+            .map(|(_, _, result)| Arc::clone(result))
+    }
+
+    fn set_group_by(&mut self, group_by: Option<GroupBy>) {
+        self.status_msg = None;
+        self.group_by = group_by;
+        self.labels_list_state.select(Some(0));
+        self.selected_label = None;
+    }
+
+    fn set_label_filter(&mut self, label_filter: Option<Selector>) {
+        self.status_msg = None;
+        self.label_filter = label_filter;
+        // the selected metric stays selected as long as it matches the filter
+        let history = self.history();
+        let metrics = self.visible_metrics(&history);
+        let metric_index = self
+            .selected_metric
+            .as_ref()
+            .and_then(|selected| metrics.iter().position(|metric| metric == selected));
+        if metric_index.is_none() {
+            self.selected_metric = None;
+        }
+        self.metric_list_state
+            .select(Some(metric_index.unwrap_or(0)));
+        self.labels_list_state.select(Some(0));
+        self.selected_label = None;
     }
 
     pub fn on_down(&mut self) -> Result<(), Box<dyn Error>> {
         let direction = Direction::Down;
+        if let Some(marked) = &mut self.marked_changes {
+            update_table_state_with_direction(direction, &mut marked.state, marked.changes.len());
+            return Ok(());
+        }
+        if self.movers.is_some() {
+            let history = self.history();
+            if let Some(view) = &mut self.movers {
+                let movers = view.movers(&history).len();
+                update_table_state_with_direction(direction, &mut view.state, movers);
+            }
+            return Ok(());
+        }
+        if self.cardinality.is_some() {
+            let history = self.history();
+            if let Some(view) = &mut self.cardinality {
+                let metrics = view.metrics(&history).len();
+                update_table_state_with_direction(direction, &mut view.state, metrics);
+            }
+            return Ok(());
+        }
         match self.focus {
             ElementInFocus::MetricHeaders => {
                 self.change_selected_metric(direction)?;
@@ -109,6 +890,26 @@ impl<'a> App<'a> {
 
     pub fn on_up(&mut self) -> Result<(), Box<dyn Error>> {
         let direction = Direction::Up;
+        if let Some(marked) = &mut self.marked_changes {
+            update_table_state_with_direction(direction, &mut marked.state, marked.changes.len());
+            return Ok(());
+        }
+        if self.movers.is_some() {
+            let history = self.history();
+            if let Some(view) = &mut self.movers {
+                let movers = view.movers(&history).len();
+                update_table_state_with_direction(direction, &mut view.state, movers);
+            }
+            return Ok(());
+        }
+        if self.cardinality.is_some() {
+            let history = self.history();
+            if let Some(view) = &mut self.cardinality {
+                let metrics = view.metrics(&history).len();
+                update_table_state_with_direction(direction, &mut view.state, metrics);
+            }
+            return Ok(());
+        }
         match self.focus {
             ElementInFocus::MetricHeaders => {
                 self.change_selected_metric(direction)?;
@@ -120,7 +921,73 @@ impl<'a> App<'a> {
         Ok(())
     }
 
+    pub fn on_save_session(&mut self) {
+        self.status_msg = Some(match &self.session_file {
+            Some(session_file) => match save_session(
+                session_file,
+                self.endpoint,
+                self.scrape_interval,
+                &self.metric_source.history_snapshot(),
+            ) {
+                Ok(()) => {
+                    log::info!("Session saved to {}", session_file);
+                    format!("Session saved to {}", session_file)
+                }
+                Err(err) => {
+                    log::error!("Error saving the session to {}: {}", session_file, err);
+                    format!("Saving the session to {} failed: {}", session_file, err)
+                }
+            },
+            None => String::from("No session file configured, start with --save-session <FILE>"),
+        });
+    }
+
+    /// Exports the selected time series, or the selected metric if no labels are in focus. With
+    /// `whole_history`, every metric is exported.
+    pub fn on_export(&mut self, whole_history: bool) {
+        let scope = match (&self.selected_metric, &self.selected_label, &self.focus) {
+            _ if whole_history => ExportScope::History,
+            (Some(metric), Some(label), ElementInFocus::LabelsView) => {
+                ExportScope::TimeSeries(metric.clone(), label.clone())
+            }
+            (Some(metric), _, _) => ExportScope::Metric(metric.clone()),
+            _ => ExportScope::History,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = Path::new(&self.export_dir).join(format!(
+            "{}-{}.{}",
+            scope.file_stem(),
+            timestamp,
+            self.export_format.extension()
+        ));
+        let result = export_to_file(&self.history(), &scope, self.export_format, &path);
+        self.status_msg = Some(match result {
+            Ok(()) => {
+                log::info!("Exported to {}", path.display());
+                format!("Exported to {}", path.display())
+            }
+            Err(err) => {
+                log::error!("Error exporting to {}: {}", path.display(), err);
+                format!("Export to {} failed: {}", path.display(), err)
+            }
+        });
+    }
+
+    pub fn on_quit(&mut self) {
+        if self.autosave {
+            self.on_save_session();
+        }
+        self.should_quit = true;
+    }
+
     pub fn on_tab(&mut self) -> Result<(), Box<dyn Error>> {
+        // the metrics are hidden behind the query result
+        if self.query.is_some() {
+            return Ok(());
+        }
         self.focus = match self.focus {
             ElementInFocus::MetricHeaders => ElementInFocus::LabelsView,
             ElementInFocus::LabelsView => ElementInFocus::MetricHeaders,
@@ -130,23 +997,32 @@ impl<'a> App<'a> {
 }
 
 fn update_list_state_with_direction(direction: Direction, state: &mut ListState, list_len: usize) {
+    if let Some(selected) = state.selected() {
+        state.select(Some(next_index(direction, selected, list_len)));
+    }
+}
+
+fn update_table_state_with_direction(direction: Direction, state: &mut TableState, rows: usize) {
+    if let (Some(selected), true) = (state.selected(), rows > 0) {
+        state.select(Some(next_index(direction, selected, rows)));
+    }
+}
+
+/// The next index of a list, wrapping around at both ends.
+fn next_index(direction: Direction, selected: usize, len: usize) -> usize {
     match direction {
         Direction::Down => {
-            if let Some(selected) = state.selected() {
-                if selected >= list_len - 1 {
-                    state.select(Some(0));
-                } else {
-                    state.select(Some(selected + 1));
-                }
+            if selected >= len - 1 {
+                0
+            } else {
+                selected + 1
             }
         }
         Direction::Up => {
-            if let Some(selected) = state.selected() {
-                if selected > 0 {
-                    state.select(Some(selected - 1));
-                } else {
-                    state.select(Some(list_len - 1));
-                }
+            if selected > 0 {
+                selected - 1
+            } else {
+                len - 1
             }
         }
     }
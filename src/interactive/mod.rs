@@ -16,18 +16,36 @@ use std::{
 
 use tui::{backend::CrosstermBackend, Terminal};
 
-use crate::{interactive::app::App, prom::MetricScraper};
+use crate::{
+    interactive::app::{App, InputTarget},
+    prom::MetricSource,
+};
 mod app;
 mod ui;
 
+pub use self::app::ViewOptions;
+
 enum Event<I> {
     Input(I),
     Tick,
 }
 
-pub async fn show(endpoint: String, scrape_interval: u64) -> Result<(), Box<dyn Error>> {
-    let metric_scraper = MetricScraper::new(endpoint.clone(), scrape_interval.clone());
-    let mut app = App::new(&endpoint, scrape_interval, metric_scraper);
+pub async fn show(
+    endpoint: String,
+    scrape_interval: u64,
+    metric_source: Box<dyn MetricSource>,
+    session_file: Option<String>,
+    autosave: bool,
+    options: ViewOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut app = App::new(
+        &endpoint,
+        scrape_interval,
+        metric_source,
+        session_file,
+        autosave,
+        options,
+    );
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -82,14 +100,41 @@ pub async fn show(endpoint: String, scrape_interval: u64) -> Result<(), Box<dyn
         terminal.draw(|f| ui::draw(f, &mut app).expect("failed to draw ui"))?;
 
         match rx.recv().await {
+            Some(Event::Input(event)) if app.input.is_some() => app.on_input(event.code),
             Some(Event::Input(event)) => match event.code {
                 KeyCode::Char('q') => {
                     log::info!("Shuting down...");
+                    app.on_quit();
                     if let Err(e) = notify_shutdown.send(()) {
                         log::error!("Error sending shutdown signal: {}", e);
                     }
                     break;
                 }
+                KeyCode::Char('s') => app.on_save_session(),
+                KeyCode::Char('n') => app.metric_source.step(),
+                KeyCode::Char('e') => app.on_export(false),
+                KeyCode::Char('E') => app.on_export(true),
+                KeyCode::Char('/') => app.on_start_input(InputTarget::LabelFilter),
+                KeyCode::Char('p') => app.on_start_input(InputTarget::Query),
+                KeyCode::Char('g') => app.on_start_input(InputTarget::GroupBy),
+                KeyCode::Char('r') => app.on_toggle_counter_view(),
+                KeyCode::Char('w') => app.on_toggle_histogram_window(),
+                KeyCode::Char('+') if app.movers.is_some() => app.on_resize_movers_window(true),
+                KeyCode::Char('-') if app.movers.is_some() => app.on_resize_movers_window(false),
+                KeyCode::Char('+') => app.on_resize_histogram_window(true),
+                KeyCode::Char('-') => app.on_resize_histogram_window(false),
+                KeyCode::Char('m') => app.on_toggle_heatmap(),
+                KeyCode::Char('f') => app.on_toggle_forecast(),
+                KeyCode::Char('a') => app.on_toggle_alerts(),
+                KeyCode::Char('c') => app.on_toggle_comparison(),
+                KeyCode::Char('b') => app.on_mark(),
+                KeyCode::Char('k') => app.on_toggle_cardinality(),
+                KeyCode::Char('t') => app.on_toggle_movers(),
+                KeyCode::Char('o') if app.movers.is_some() => app.on_rank_movers(),
+                KeyCode::Char('o') => app.on_sort_cardinality(false),
+                KeyCode::Char('O') => app.on_sort_cardinality(true),
+                KeyCode::Enter => app.on_select_mover(),
+                KeyCode::Esc => app.on_escape(),
                 KeyCode::Down => app.on_down()?,
                 KeyCode::Up => app.on_up()?,
                 KeyCode::Tab | KeyCode::BackTab | KeyCode::Right | KeyCode::Left => app.on_tab()?,
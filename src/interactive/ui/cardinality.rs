@@ -0,0 +1,214 @@
+use chrono::{Local, TimeZone};
+use tui::backend::Backend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::symbols;
+use tui::text::Span;
+use tui::widgets::{Axis, Block, Borders, Chart, Dataset, GraphType, Row, Table};
+use tui::Frame;
+
+use std::sync::Arc;
+
+use crate::interactive::app::CardinalityView;
+use crate::prom::{
+    series_growth, CardinalitySort, Metric, MetricCardinality, MetricHistory, GROWTH_WINDOW,
+};
+
+const COLUMNS: [(CardinalitySort, &str); 5] = [
+    (CardinalitySort::Name, "Metric"),
+    (CardinalitySort::Series, "Series"),
+    (CardinalitySort::Labels, "Labels"),
+    (CardinalitySort::TopLabel, "Top label"),
+    (CardinalitySort::NewSeries, "New"),
+];
+
+/// Draws the number of series of every metric, sorted by a column, and the labels and the
+/// growth of the selected metric.
+pub fn draw<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    view: &mut CardinalityView,
+    history: &Arc<MetricHistory>,
+    threshold: usize,
+) where
+    B: Backend,
+{
+    let chunks = Layout::default()
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+        .direction(Direction::Horizontal)
+        .split(area);
+    let chunks_right = Layout::default()
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .direction(Direction::Vertical)
+        .split(chunks[1]);
+
+    let metrics = view.metrics(history);
+    draw_table(f, chunks[0], view, &metrics, threshold);
+
+    let selected = match view.state.selected().and_then(|index| metrics.get(index)) {
+        Some(selected) => selected,
+        None => return,
+    };
+    draw_labels(f, chunks_right[0], selected);
+    if let Some(metric) = history.get_metric(&selected.name) {
+        draw_growth(f, chunks_right[1], metric);
+    }
+}
+
+fn draw_table<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    view: &mut CardinalityView,
+    metrics: &[MetricCardinality],
+    threshold: usize,
+) where
+    B: Backend,
+{
+    let above = metrics
+        .iter()
+        .filter(|metric| metric.series > threshold)
+        .count();
+    let rows = metrics.iter().map(|metric| {
+        let row = Row::new(vec![
+            metric.name.clone(),
+            metric.series.to_string(),
+            metric.labels.len().to_string(),
+            metric.top_label().map_or_else(String::new, |label| {
+                format!("{} ({} values)", label.name, label.values)
+            }),
+            metric.new_series.to_string(),
+        ]);
+        if metric.series > threshold {
+            row.style(Style::default().fg(Color::LightRed))
+        } else {
+            row
+        }
+    });
+    // the sorted column is marked with the direction
+    let header = COLUMNS.iter().map(|(sort, name)| {
+        if *sort == view.sort {
+            format!("{} {}", name, if view.ascending { "▲" } else { "▼" })
+        } else {
+            name.to_string()
+        }
+    });
+    let title = format!(
+        "Cardinality ({} series, {} metrics above {} series), 'o' to sort, 'O' to reverse",
+        metrics.iter().map(|metric| metric.series).sum::<usize>(),
+        above,
+        threshold
+    );
+    let table = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .header(Row::new(header).style(Style::default().add_modifier(Modifier::BOLD)))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .widths(&[
+            Constraint::Percentage(40),
+            Constraint::Length(9),
+            Constraint::Length(8),
+            Constraint::Percentage(30),
+            Constraint::Length(7),
+        ]);
+    f.render_stateful_widget(table, area, &mut view.state);
+}
+
+/// The labels of the metric, the ones with the most distinct values first.
+fn draw_labels<B>(f: &mut Frame<B>, area: Rect, metric: &MetricCardinality)
+where
+    B: Backend,
+{
+    let rows = metric.labels.iter().map(|label| {
+        let top_values = label
+            .top_values
+            .iter()
+            .map(|(value, series)| format!("{} ({})", value, series))
+            .collect::<Vec<String>>()
+            .join(", ");
+        Row::new(vec![
+            label.name.clone(),
+            label.values.to_string(),
+            top_values,
+        ])
+    });
+    let title = format!(
+        "Labels of {} ({} new series in the last {}m)",
+        metric.name,
+        metric.new_series,
+        GROWTH_WINDOW / 60
+    );
+    let table = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .header(
+            Row::new(vec!["Label", "Values", "Most series"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .widths(&[
+            Constraint::Percentage(25),
+            Constraint::Length(7),
+            Constraint::Percentage(65),
+        ]);
+    f.render_widget(table, area);
+}
+
+/// The number of series of the metric over time.
+fn draw_growth<B>(f: &mut Frame<B>, area: Rect, metric: &Metric)
+where
+    B: Backend,
+{
+    let growth = series_growth(metric);
+    let latest = metric
+        .time_series()
+        .values()
+        .filter_map(|time_series| time_series.samples.last())
+        .map(|sample| sample.timestamp())
+        .max();
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    for (timestamp, series) in &growth {
+        // a step at every new series
+        if let Some((_, previous)) = points.last().cloned() {
+            points.push((*timestamp as f64, previous));
+        }
+        points.push((*timestamp as f64, *series as f64));
+    }
+    let block = Block::default()
+        .title("Series over time")
+        .borders(Borders::ALL);
+    let (first, last, series) = match (growth.first(), latest, growth.last()) {
+        (Some((first, _)), Some(latest), Some((_, series))) if latest > *first => {
+            (*first, latest, *series)
+        }
+        _ => {
+            f.render_widget(block, area);
+            return;
+        }
+    };
+    points.push((last as f64, series as f64));
+
+    let format_time = |timestamp: u64| {
+        Local
+            .timestamp(timestamp as i64, 0)
+            .format("%H:%M:%S")
+            .to_string()
+    };
+    let datasets = vec![Dataset::default()
+        .marker(symbols::Marker::Braille)
+        .style(Style::default().fg(Color::LightGreen))
+        .graph_type(GraphType::Line)
+        .data(&points)];
+    let chart = Chart::new(datasets)
+        .block(block)
+        .x_axis(
+            Axis::default()
+                .labels(vec![
+                    Span::raw(format_time(first)),
+                    Span::raw(format_time(last)),
+                ])
+                .bounds([first as f64, last as f64]),
+        )
+        .y_axis(
+            Axis::default()
+                .labels(vec![Span::raw("0"), Span::raw(series.to_string())])
+                .bounds([0.0, series as f64]),
+        );
+    f.render_widget(chart, area);
+}
@@ -0,0 +1,315 @@
+use chrono::{Local, TimeZone};
+use tui::backend::Backend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::symbols;
+use tui::text::{Span, Spans};
+use tui::widgets::{
+    Axis, Block, BorderType, Borders, Chart, Dataset, GraphType, List, ListItem, ListState, Row,
+    Table,
+};
+use tui::Frame;
+
+use crate::interactive::app::{App, ElementInFocus};
+use crate::prom::{comparable_points, MetricDiff, MetricHistory, MetricType, Presence};
+
+use super::focus_color;
+
+const BASELINE_COLOR: Color = Color::LightGreen;
+const CANDIDATE_COLOR: Color = Color::LightCyan;
+
+/// Draws the metrics of both endpoints aligned by name, the series of the selected metric with
+/// their values side by side, and the selected series of both endpoints in one graph.
+pub fn draw<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    app: &mut App,
+    diff: &[MetricDiff],
+    baseline: &MetricHistory,
+    candidate: &MetricHistory,
+) where
+    B: Backend,
+{
+    let chunks = Layout::default()
+        .constraints([Constraint::Percentage(35), Constraint::Percentage(65)].as_ref())
+        .direction(Direction::Horizontal)
+        .split(area);
+    let chunks_left = Layout::default()
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .direction(Direction::Vertical)
+        .split(chunks[0]);
+
+    let metrics: Vec<(String, Presence, Option<f64>)> = diff
+        .iter()
+        .map(|metric| {
+            (
+                metric.name.clone(),
+                metric.presence,
+                metric.largest_change(),
+            )
+        })
+        .collect();
+    draw_list(
+        f,
+        chunks_left[0],
+        "Compared metrics",
+        &metrics,
+        matches!(app.focus, ElementInFocus::MetricHeaders),
+        &app.selected_metric,
+        &mut app.metric_list_state,
+    );
+
+    let metric = match diff
+        .iter()
+        .find(|metric| Some(&metric.name) == app.selected_metric.as_ref())
+    {
+        Some(metric) => metric,
+        None => return,
+    };
+    let series: Vec<(String, Presence, Option<f64>)> = metric
+        .series
+        .iter()
+        .map(|series| (series.key.clone(), series.presence, series.percent_change()))
+        .collect();
+    draw_list(
+        f,
+        chunks_left[1],
+        "Series",
+        &series,
+        matches!(app.focus, ElementInFocus::LabelsView),
+        &app.selected_label,
+        &mut app.labels_list_state,
+    );
+
+    let metric_type = baseline
+        .get_metric(&metric.name)
+        .or_else(|| candidate.get_metric(&metric.name))
+        .map(|metric| metric.details.metric_type.clone())
+        .unwrap_or(MetricType::Gauge);
+    let chunks_right = Layout::default()
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+        .direction(Direction::Vertical)
+        .split(chunks[1]);
+    draw_table(
+        f,
+        chunks_right[0],
+        metric,
+        &metric_type,
+        &app.selected_label,
+    );
+
+    // the first series is graphed until one is selected
+    let key = match app
+        .selected_label
+        .as_ref()
+        .or_else(|| metric.series.first().map(|series| &series.key))
+    {
+        Some(key) => key,
+        None => return,
+    };
+    let points = |history: &MetricHistory| {
+        history
+            .get_metric(&metric.name)
+            .and_then(|metric| metric.time_series().get(key))
+            .map(|time_series| comparable_points(&time_series.samples, &metric_type))
+            .unwrap_or_default()
+    };
+    draw_graph(
+        f,
+        chunks_right[1],
+        &format!("{} ({})", key, value_name(&metric_type)),
+        &points(baseline),
+        &points(candidate),
+    );
+}
+
+fn value_name(metric_type: &MetricType) -> &'static str {
+    match metric_type {
+        MetricType::Gauge => "value",
+        MetricType::Counter => "rate per second",
+        MetricType::Histogram => "average observation",
+    }
+}
+
+fn presence_style(presence: Presence) -> Style {
+    match presence {
+        Presence::Both => Style::default(),
+        Presence::OnlyBaseline => Style::default().fg(BASELINE_COLOR),
+        Presence::OnlyCandidate => Style::default().fg(CANDIDATE_COLOR),
+    }
+}
+
+fn format_change(change: Option<f64>) -> String {
+    change.map_or_else(|| String::from("-"), |change| format!("{:+.1}%", change))
+}
+
+/// The items with the side they are found on, or their largest change when found on both.
+fn draw_list<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    title: &str,
+    items: &[(String, Presence, Option<f64>)],
+    has_focus: bool,
+    selected: &Option<String>,
+    state: &mut ListState,
+) where
+    B: Backend,
+{
+    if let Some(index) = selected
+        .as_ref()
+        .and_then(|selected| items.iter().position(|(name, _, _)| name == selected))
+    {
+        state.select(Some(index));
+    }
+    let list_items: Vec<ListItem> = items
+        .iter()
+        .map(|(name, presence, change)| {
+            let text = match (presence, change) {
+                (Presence::Both, Some(change)) => {
+                    format!("{} {}", name, format_change(Some(*change)))
+                }
+                (Presence::Both, None) => name.clone(),
+                (presence, _) => format!("{} ({})", name, presence),
+            };
+            ListItem::new(Spans::from(Span::styled(text, presence_style(*presence))))
+        })
+        .collect();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::White))
+        .title(format!("{} ({})", title, items.len()))
+        .border_type(BorderType::Plain);
+    let list = List::new(list_items).block(block).highlight_style(
+        Style::default()
+            .bg(focus_color(has_focus))
+            .fg(Color::Black)
+            .add_modifier(Modifier::BOLD),
+    );
+    f.render_stateful_widget(list, area, state);
+}
+
+fn draw_table<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    metric: &MetricDiff,
+    metric_type: &MetricType,
+    selected: &Option<String>,
+) where
+    B: Backend,
+{
+    let format_value = |value: Option<f64>| {
+        value.map_or_else(|| String::from("-"), |value| format!("{:+.4e}", value))
+    };
+    let rows = metric.series.iter().map(|series| {
+        let style = presence_style(series.presence);
+        let style = if Some(&series.key) == selected.as_ref() {
+            style.add_modifier(Modifier::BOLD | Modifier::REVERSED)
+        } else {
+            style
+        };
+        Row::new(vec![
+            series.key.clone(),
+            format_value(series.baseline),
+            format_value(series.candidate),
+            format_value(series.difference()),
+            format_change(series.percent_change()),
+        ])
+        .style(style)
+    });
+    let title = format!(
+        "{} ({}, {})",
+        metric.name,
+        value_name(metric_type),
+        metric.presence
+    );
+    let table = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .header(
+            Row::new(vec![
+                "Series",
+                "Baseline",
+                "Candidate",
+                "Difference",
+                "Change",
+            ])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .widths(&[
+            Constraint::Percentage(40),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(10),
+        ]);
+    f.render_widget(table, area);
+}
+
+/// Overlays the points of the baseline and of the candidate.
+fn draw_graph<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    title: &str,
+    baseline: &[(u64, f64)],
+    candidate: &[(u64, f64)],
+) where
+    B: Backend,
+{
+    let lines: Vec<Vec<(f64, f64)>> = [baseline, candidate]
+        .iter()
+        .map(|points| {
+            points
+                .iter()
+                .filter(|(_, value)| value.is_finite())
+                .map(|(timestamp, value)| (*timestamp as f64, *value))
+                .collect()
+        })
+        .collect();
+    let points = || lines.iter().flatten();
+    let x_min = points().map(|(x, _)| *x).fold(f64::INFINITY, f64::min);
+    let x_max = points().map(|(x, _)| *x).fold(f64::NEG_INFINITY, f64::max);
+    let y_min = points().map(|(_, y)| *y).fold(f64::INFINITY, f64::min);
+    let y_max = points().map(|(_, y)| *y).fold(f64::NEG_INFINITY, f64::max);
+    if x_min >= x_max || y_min > y_max {
+        let block = Block::default().title(title).borders(Borders::ALL);
+        f.render_widget(block, area);
+        return;
+    }
+
+    let datasets = lines
+        .iter()
+        .zip([("baseline", BASELINE_COLOR), ("candidate", CANDIDATE_COLOR)])
+        .map(|(line, (name, color))| {
+            Dataset::default()
+                .name(name)
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().fg(color))
+                .graph_type(GraphType::Line)
+                .data(line)
+        })
+        .collect();
+    let format_time = |timestamp: f64| {
+        Local
+            .timestamp(timestamp as i64, 0)
+            .format("%H:%M:%S")
+            .to_string()
+    };
+    let chart = Chart::new(datasets)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .x_axis(
+            Axis::default()
+                .labels(vec![
+                    Span::raw(format_time(x_min)),
+                    Span::raw(format_time(x_max)),
+                ])
+                .bounds([x_min, x_max]),
+        )
+        .y_axis(
+            Axis::default()
+                .labels(vec![
+                    Span::raw(format!("{:+.4e}", y_min)),
+                    Span::raw(format!("{:+.4e}", y_max)),
+                ])
+                .bounds([y_min, y_max]),
+        );
+    f.render_widget(chart, area);
+}
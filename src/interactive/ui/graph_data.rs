@@ -1,6 +1,7 @@
 use chrono::{DateTime, Local, TimeZone};
+use std::collections::HashSet;
 
-use crate::prom::{Metric, Sample};
+use crate::prom::LinearTrend;
 
 pub struct GraphData {
     pub data: Vec<(f64, f64)>,
@@ -10,29 +11,21 @@ pub struct GraphData {
     pub x_min: f64,
     pub y_max: f64,
     pub y_min: f64,
+    /// start of the live samples when the samples before were backfilled
+    pub live_since: Option<f64>,
+    /// the points flagged as unusual
+    pub anomalies: Vec<(f64, f64)>,
+    /// the trend line, from the start of its window into the future
+    pub forecast: Vec<(f64, f64)>,
+    /// the value the trend is forecast to reach, when it does so on the graph
+    pub threshold: Vec<(f64, f64)>,
 }
 
 impl GraphData {
-    pub fn parse(metric: &Metric, selected_label: &str) -> Option<Self> {
-        let samples = &metric
-            .time_series
-            .get(selected_label)
-            .expect("values for selected label")
-            .samples;
-        let data: Vec<(f64, f64)> = samples
+    pub fn from_points(points: &[(u64, f64)], live_since: Option<u64>) -> Option<Self> {
+        let data: Vec<(f64, f64)> = points
             .iter()
-            .map(|entry| {
-                let (timestamp, value) = match entry {
-                    Sample::GaugeSample(single_value) => {
-                        (single_value.timestamp, single_value.value)
-                    }
-                    Sample::CounterSample(single_value) => {
-                        (single_value.timestamp, single_value.value)
-                    }
-                    _ => unimplemented!(),
-                };
-                (timestamp as f64, value)
-            })
+            .map(|(timestamp, value)| (*timestamp as f64, *value))
             .collect();
         if data.len() < 2 {
             return None;
@@ -48,6 +41,9 @@ impl GraphData {
             .iter()
             .map(|(_, v)| *v)
             .fold(f64::NEG_INFINITY, f64::max);
+        let live_since = live_since
+            .map(|timestamp| timestamp as f64)
+            .filter(|timestamp| *timestamp > x_min && *timestamp <= x_max);
 
         Some(Self {
             data,
@@ -57,6 +53,51 @@ impl GraphData {
             x_min,
             y_max,
             y_min,
+            live_since,
+            anomalies: Vec::new(),
+            forecast: Vec::new(),
+            threshold: Vec::new(),
         })
     }
+
+    /// Highlights the points at the timestamps of the anomalies.
+    pub fn with_anomalies(mut self, timestamps: &HashSet<u64>) -> Self {
+        self.anomalies = self
+            .data
+            .iter()
+            .filter(|(timestamp, _)| timestamps.contains(&(*timestamp as u64)))
+            .cloned()
+            .collect();
+        self
+    }
+
+    /// Draws the trend fitted to the last `window` seconds as far into the future, and the
+    /// threshold when the trend reaches it before the end of the graph.
+    pub fn with_forecast(
+        mut self,
+        trend: &LinearTrend,
+        window: u64,
+        threshold: Option<f64>,
+    ) -> Self {
+        let start = trend.origin.saturating_sub(window).max(self.x_min as u64);
+        let end = trend.origin + window;
+        self.forecast = vec![
+            (start as f64, trend.value_at(start)),
+            (end as f64, trend.value_at(end)),
+        ];
+        self.x_max = end as f64;
+        self.last_time = Local.timestamp(end as i64, 0);
+        for (_, value) in &self.forecast {
+            self.y_min = self.y_min.min(*value);
+            self.y_max = self.y_max.max(*value);
+        }
+        if let Some(threshold) = threshold {
+            if matches!(trend.crossing(threshold), Some(at) if at <= end) {
+                self.threshold = vec![(self.x_min, threshold), (self.x_max, threshold)];
+                self.y_min = self.y_min.min(threshold);
+                self.y_max = self.y_max.max(threshold);
+            }
+        }
+        self
+    }
 }
@@ -0,0 +1,162 @@
+use std::ops::Range;
+
+use chrono::{Local, TimeZone};
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style},
+    widgets::{Block, Widget},
+};
+
+use crate::prom::ColorScale;
+
+/// Buckets over time, the colour of a cell is the increase of the bucket in an interval.
+pub struct Heatmap<'a> {
+    block: Option<Block<'a>>,
+    /// `(timestamp, [(upper bound, increase)])` in the order of time
+    columns: &'a [(u64, Vec<(f64, f64)>)],
+    scale: ColorScale,
+}
+
+impl<'a> Heatmap<'a> {
+    pub fn new(columns: &'a [(u64, Vec<(f64, f64)>)], scale: ColorScale) -> Self {
+        Self {
+            block: None,
+            columns,
+            scale,
+        }
+    }
+
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+}
+
+impl<'a> Widget for Heatmap<'a> {
+    fn render(mut self, area: Rect, buf: &mut Buffer) {
+        let area = match self.block.take() {
+            Some(block) => {
+                let inner = block.inner(area);
+                block.render(area, buf);
+                inner
+            }
+            None => area,
+        };
+        // the buckets of the latest sample are the rows
+        let bounds: Vec<f64> = match self.columns.last() {
+            Some((_, buckets)) => buckets.iter().map(|(bound, _)| *bound).collect(),
+            None => return,
+        };
+        let labels: Vec<String> = bounds.iter().map(|bound| format_bound(*bound)).collect();
+        let label_width = labels.iter().map(|label| label.len()).max().unwrap_or(0) as u16 + 1;
+        if bounds.is_empty() || area.width <= label_width || area.height < 2 {
+            return;
+        }
+        let width = area.width - label_width;
+        let height = area.height - 1;
+
+        // cells[x][y] with y = 0 at the bottom
+        let cells: Vec<Vec<f64>> = (0..width)
+            .map(|x| {
+                let columns = &self.columns[spread(x, width, self.columns.len())];
+                (0..height)
+                    .map(|y| {
+                        spread(y, height, bounds.len())
+                            .map(|row| increase(columns, bounds[row]))
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect();
+        let max = cells.iter().flatten().cloned().fold(0.0, f64::max);
+
+        let bottom = area.bottom() - 2;
+        for y in 0..height {
+            let rows = spread(y, height, bounds.len());
+            let new_row = y == 0 || spread(y - 1, height, bounds.len()) != rows;
+            if new_row {
+                buf.set_string(
+                    area.left(),
+                    bottom - y,
+                    &labels[rows.end - 1],
+                    Style::default(),
+                );
+            }
+            for x in 0..width {
+                let intensity = self.scale.intensity(cells[x as usize][y as usize], max);
+                if intensity > 0.0 {
+                    buf.get_mut(area.left() + label_width + x, bottom - y)
+                        .set_bg(ramp(intensity));
+                }
+            }
+        }
+
+        let format_time = |timestamp: u64| {
+            Local
+                .timestamp(timestamp as i64, 0)
+                .format("%H:%M:%S")
+                .to_string()
+        };
+        let first = format_time(self.columns[0].0);
+        let last = format_time(self.columns[self.columns.len() - 1].0);
+        buf.set_string(
+            area.left() + label_width,
+            area.bottom() - 1,
+            &first,
+            Style::default(),
+        );
+        if width as usize > first.len() + last.len() {
+            buf.set_string(
+                area.right() - last.len() as u16,
+                area.bottom() - 1,
+                &last,
+                Style::default(),
+            );
+        }
+    }
+}
+
+/// The items shown in one of `cells`: several items are summed up when there are more items
+/// than cells, and an item is stretched over several cells otherwise.
+fn spread(cell: u16, cells: u16, items: usize) -> Range<usize> {
+    let start = cell as usize * items / cells as usize;
+    let end = (cell as usize + 1) * items / cells as usize;
+    start..end.max(start + 1).min(items)
+}
+
+fn increase(columns: &[(u64, Vec<(f64, f64)>)], bound: f64) -> f64 {
+    columns
+        .iter()
+        .flat_map(|(_, buckets)| buckets.iter().filter(|(upper, _)| *upper == bound))
+        .map(|(_, increase)| increase)
+        .sum()
+}
+
+fn format_bound(bound: f64) -> String {
+    if bound.is_infinite() {
+        String::from("+Inf")
+    } else {
+        bound.to_string()
+    }
+}
+
+/// From dark blue over red to yellow.
+fn ramp(intensity: f64) -> Color {
+    let channel = |from: f64, to: f64, t: f64| (from + (to - from) * t).round() as u8;
+    if intensity < 0.5 {
+        let t = intensity * 2.0;
+        Color::Rgb(
+            channel(20.0, 220.0, t),
+            channel(30.0, 40.0, t),
+            channel(120.0, 40.0, t),
+        )
+    } else {
+        let t = (intensity - 0.5) * 2.0;
+        Color::Rgb(
+            channel(220.0, 255.0, t),
+            channel(40.0, 230.0, t),
+            channel(40.0, 60.0, t),
+        )
+    }
+}
@@ -1,6 +1,6 @@
 use chrono::{DateTime, Local, TimeZone};
 
-use crate::prom::{Metric, Sample};
+use crate::prom::{window_histogram, HistogramValueSample, HistogramWindow};
 
 pub struct BucketData {
     bucket: String,
@@ -53,62 +53,55 @@ pub struct HistogramData {
     pub time: DateTime<Local>,
     pub count: u64,
     pub sum: f64,
+    /// the observations the buckets count
+    pub window: HistogramWindow,
 }
 
 impl HistogramData {
-    pub fn parse(metric: &Metric, selected_label: &str) -> Option<Self> {
-        let last_sample = &metric
-            .time_series
-            .get(selected_label)
-            .expect("values for selected label")
-            .samples
-            .last();
+    /// The buckets of the latest sample, within the window. While the history is shorter than
+    /// the window, all the observations since the start are shown.
+    pub fn parse(histograms: &[HistogramValueSample], window: HistogramWindow) -> Option<Self> {
+        let last_index = histograms.len().checked_sub(1)?;
+        let (histogram, window) = match window_histogram(histograms, last_index, window) {
+            Some(histogram) => (histogram, window),
+            None => (histograms[last_index].clone(), HistogramWindow::Cumulative),
+        };
 
         let mut data = vec![];
-        let mut timestamp = 0;
-        let mut count = 0;
-        let mut sum = 0.0;
-
-        if let Some(sample) = *last_sample {
-            if let Sample::HistogramSample(histogram) = sample {
-                timestamp = histogram.timestamp;
-                count = histogram.count;
-                sum = histogram.sum;
-                for (index, bucket) in histogram.bucket_values.iter().enumerate() {
-                    let inc_per_bucket;
-                    if index == 0 {
-                        inc_per_bucket = bucket.value;
-                    } else {
-                        inc_per_bucket = bucket.value - histogram.bucket_values[index - 1].value;
-                    }
-                    let percentage = (bucket.value as f64 / histogram.count as f64) * 100.0;
-                    let inc_per_bucket_percentage =
-                        (inc_per_bucket as f64) / (histogram.count as f64) * 100.0;
-                    data.push(BucketData::new(
-                        bucket.name.clone(),
-                        bucket.value,
-                        percentage,
-                        inc_per_bucket,
-                        inc_per_bucket_percentage,
-                    ))
-                }
-            }
+        for (index, bucket) in histogram.bucket_values.iter().enumerate() {
+            let inc_per_bucket = match index {
+                0 => bucket.value,
+                _ => bucket
+                    .value
+                    .saturating_sub(histogram.bucket_values[index - 1].value),
+            };
+            let percentage = (bucket.value as f64 / histogram.count as f64) * 100.0;
+            let inc_per_bucket_percentage =
+                (inc_per_bucket as f64) / (histogram.count as f64) * 100.0;
+            data.push(BucketData::new(
+                bucket.name.clone(),
+                bucket.value,
+                percentage,
+                inc_per_bucket,
+                inc_per_bucket_percentage,
+            ))
         }
         if data.len() < 2 {
             return None;
         }
 
-        if timestamp == 0 {
+        if histogram.timestamp == 0 {
             return None;
         }
 
-        let time = Local.timestamp(timestamp as i64, 0);
+        let time = Local.timestamp(histogram.timestamp as i64, 0);
 
         Some(Self {
             data,
             time,
-            count,
-            sum,
+            count: histogram.count,
+            sum: histogram.sum,
+            window,
         })
     }
 }
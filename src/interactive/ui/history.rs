@@ -1,7 +1,6 @@
-use log::error;
 use tui::{
     backend::Backend,
-    layout::{Constraint, Layout, Rect},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     symbols,
     text::Span,
@@ -9,10 +8,45 @@ use tui::{
     Frame,
 };
 
-use crate::prom::{Metric, MetricType, Sample};
+use crate::prom::{
+    anomaly_points, average_rate, bucket_increases, counter_points, detect_anomalies,
+    format_duration, quantiles_over_time, sample_points, AnomalyConfig, ColorScale, CounterValue,
+    CounterView, HistogramValueSample, HistogramWindow, LinearTrend, Metric, MetricType, Sample,
+    SampleStorage, Stats, DEFAULT_QUANTILES,
+};
 use chrono::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+use super::{graph_data::GraphData, heatmap::Heatmap, histogram_data::HistogramData};
+
+/// How the history of the selected time series is drawn.
+pub struct HistoryOptions {
+    /// start of the live samples when the samples before were backfilled
+    pub live_since: Option<u64>,
+    /// applies to counters and to the count and sum of histograms
+    pub counter_view: CounterView,
+    /// seconds of samples the rate of a counter is computed over
+    pub rate_window: u64,
+    /// observations the quantiles of histograms are estimated from
+    pub histogram_window: HistogramWindow,
+    /// histograms are drawn as a heatmap over time instead of the latest sample
+    pub heatmap: Option<ColorScale>,
+    /// sensitivity of the highlighting of unusual samples
+    pub anomaly: AnomalyConfig,
+    /// seconds of the latest samples the trend of gauges is fitted to, when shown
+    pub forecast_window: Option<u64>,
+    /// value the trend of gauges is forecast to reach
+    pub forecast_threshold: Option<f64>,
+}
 
-use super::{graph_data::GraphData, histogram_data::HistogramData};
+impl HistoryOptions {
+    fn counter_title(&self) -> String {
+        match self.counter_view {
+            CounterView::Rate => format!("{} over {}s", self.counter_view, self.rate_window),
+            view => view.to_string(),
+        }
+    }
+}
 
 pub fn draw<B>(
     f: &mut Frame<B>,
@@ -20,54 +54,177 @@ pub fn draw<B>(
     chunk_left: Rect,
     metric: &Metric,
     selected_label: &str,
+    options: &HistoryOptions,
 ) where
     B: Backend,
 {
+    let samples = &metric
+        .time_series()
+        .get(selected_label)
+        .expect("values for selected label")
+        .samples;
     match metric.details.metric_type {
         MetricType::Histogram => {
-            if let Some(histogram_data) = HistogramData::parse(metric, selected_label) {
-                draw_histogram_table(f, chunk_left, &histogram_data);
-                draw_histogram(f, chunk_right, &histogram_data);
+            draw_histogram_history(f, chunk_right, chunk_left, samples, options);
+        }
+        MetricType::Counter => {
+            let raw_points = sample_points(samples, CounterValue::Value);
+            let points = counter_points(&raw_points, options.counter_view, options.rate_window);
+            let anomalies = anomaly_timestamps(samples, &metric.details.metric_type, options);
+            let chunks = stats_layout(chunk_right);
+            let title = format!("Graph ({})", options.counter_title());
+            if let Some(graph_data) = GraphData::from_points(&points, options.live_since) {
+                draw_graph(f, chunks[0], &graph_data.with_anomalies(&anomalies), &title);
             }
+            draw_stats(f, chunks[1], &points, average_rate(&raw_points));
+            let value_header = match options.counter_view {
+                CounterView::Raw => "Value",
+                CounterView::Delta => "Delta",
+                CounterView::Rate => "Rate/s",
+            };
+            draw_table(f, chunk_left, &points, value_header, &anomalies, options);
         }
-        _ => {
-            if let Some(graph_data) = GraphData::parse(metric, selected_label) {
-                draw_graph(f, chunk_right, &graph_data);
+        MetricType::Gauge => {
+            let points = sample_points(samples, CounterValue::Value);
+            let anomalies = anomaly_timestamps(samples, &metric.details.metric_type, options);
+            let chunks = stats_layout(chunk_right);
+            if let Some(graph_data) = GraphData::from_points(&points, options.live_since) {
+                let graph_data = graph_data.with_anomalies(&anomalies);
+                let trend = options
+                    .forecast_window
+                    .and_then(|window| Some((window, LinearTrend::fit(&points, window)?)));
+                match trend {
+                    Some((window, trend)) => draw_graph(
+                        f,
+                        chunks[0],
+                        &graph_data.with_forecast(&trend, window, options.forecast_threshold),
+                        &forecast_title(&trend, window, options.forecast_threshold),
+                    ),
+                    None => draw_graph(f, chunks[0], &graph_data, "Graph"),
+                }
             }
-            draw_table(f, chunk_left, metric, &selected_label);
+            draw_stats(f, chunks[1], &points, None);
+            draw_table(f, chunk_left, &points, "Value", &anomalies, options);
         }
     }
 }
 
-#[allow(clippy::cast_precision_loss)]
-fn draw_table<B>(f: &mut Frame<B>, area: Rect, metric: &Metric, selected_label: &str)
+/// The slope of the trend and when it reaches the threshold, e.g. a disk being full.
+fn forecast_title(trend: &LinearTrend, window: u64, threshold: Option<f64>) -> String {
+    let title = format!(
+        "Graph, trend {:+.4e}/s over {}",
+        trend.slope,
+        format_duration(window)
+    );
+    match threshold {
+        Some(threshold) => match trend.crossing(threshold) {
+            Some(at) => format!(
+                "{}, reaches {} in {}",
+                title,
+                threshold,
+                format_duration(at - trend.origin)
+            ),
+            None => format!("{}, not reaching {}", title, threshold),
+        },
+        None => title,
+    }
+}
+
+/// The timestamps of the unusual samples. Counters are judged by their increase per scrape,
+/// whatever the view.
+fn anomaly_timestamps(
+    samples: &SampleStorage,
+    metric_type: &MetricType,
+    options: &HistoryOptions,
+) -> HashSet<u64> {
+    detect_anomalies(&anomaly_points(samples, metric_type), &options.anomaly)
+        .into_iter()
+        .map(|anomaly| anomaly.timestamp)
+        .collect()
+}
+
+/// The graph on the left, the statistics of the graphed points on the right.
+fn stats_layout(area: Rect) -> Vec<Rect> {
+    Layout::default()
+        .constraints([Constraint::Min(20), Constraint::Length(30)].as_ref())
+        .direction(Direction::Horizontal)
+        .split(area)
+}
+
+fn draw_stats<B>(f: &mut Frame<B>, area: Rect, points: &[(u64, f64)], average_rate: Option<f64>)
 where
     B: Backend,
 {
-    let samples = &metric
-        .time_series
-        .get(selected_label)
-        .expect("values for selected label")
-        .samples;
-    let title = format!("History ({})", samples.len());
-
-    let rows = samples.iter().map(|entry| {
-        let (timestamp, value) = match entry {
-            Sample::GaugeSample(single_value) => (single_value.timestamp, single_value.value),
-            Sample::CounterSample(single_value) => (single_value.timestamp, single_value.value),
-            _ => {
-                error!("History table is not implemented for this kind of sample.");
-                unimplemented!();
+    let stats = match Stats::from_points(points) {
+        Some(stats) => stats,
+        None => return,
+    };
+    let mut rows = vec![
+        ("Samples", stats.count.to_string()),
+        ("Min", format!("{:+.4e}", stats.min)),
+        ("Max", format!("{:+.4e}", stats.max)),
+        ("Mean", format!("{:+.4e}", stats.mean)),
+        ("Median", format!("{:+.4e}", stats.median)),
+        ("Stddev", format!("{:+.4e}", stats.stddev)),
+        ("p95", format!("{:+.4e}", stats.p95)),
+        ("First", format!("{:+.4e}", stats.first)),
+        ("Last", format!("{:+.4e}", stats.last)),
+        ("Change", format!("{:+.4e}", stats.change)),
+        (
+            "Max at",
+            Local
+                .timestamp(stats.time_of_max as i64, 0)
+                .format("%H:%M:%S")
+                .to_string(),
+        ),
+    ];
+    if let Some(average_rate) = average_rate {
+        rows.push(("Avg rate/s", format!("{:+.4e}", average_rate)));
+    }
+    let t = Table::new(
+        rows.into_iter()
+            .map(|(name, value)| Row::new(vec![name.to_string(), value])),
+    )
+    .block(Block::default().borders(Borders::ALL).title("Stats"))
+    .widths(&[Constraint::Length(11), Constraint::Length(15)]);
+    f.render_widget(t, area);
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn draw_table<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    points: &[(u64, f64)],
+    value_header: &str,
+    anomalies: &HashSet<u64>,
+    options: &HistoryOptions,
+) where
+    B: Backend,
+{
+    let title = if anomalies.is_empty() {
+        format!("History ({})", points.len())
+    } else {
+        format!("History ({}, {} unusual)", points.len(), anomalies.len())
+    };
+
+    let rows = points.iter().map(|(timestamp, value)| {
+        let time = Local.timestamp(*timestamp as i64, 0).to_rfc2822();
+        let row = Row::new(vec![time, format!("{:+.4e}", value)]);
+        // unusual samples are highlighted, backfilled samples are dimmed
+        match options.live_since {
+            _ if anomalies.contains(timestamp) => row.style(Style::default().fg(Color::LightRed)),
+            Some(live_since) if *timestamp < live_since => {
+                row.style(Style::default().fg(Color::DarkGray))
             }
-        };
-        let time = Local.timestamp(timestamp as i64, 0).to_rfc2822();
-        Row::new(vec![time, format!("{:+.4e}", value)])
+            _ => row,
+        }
     });
 
     let t = Table::new(rows)
         .block(Block::default().borders(Borders::ALL).title(title))
         .header(
-            Row::new(vec!["Time", "Value"]).style(Style::default().add_modifier(Modifier::BOLD)),
+            Row::new(vec!["Time", value_header])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
         )
         .highlight_style(Style::default().add_modifier(Modifier::BOLD))
         .widths(&[
@@ -77,23 +234,69 @@ where
         ]);
 
     let mut state = TableState::default();
-    state.select(Some(samples.len() - 1));
+    state.select(points.len().checked_sub(1));
 
     f.render_stateful_widget(t, area, &mut state);
 }
 
-fn draw_graph<B>(f: &mut Frame<B>, area: Rect, points: &GraphData)
+fn draw_graph<B>(f: &mut Frame<B>, area: Rect, points: &GraphData, title: &str)
 where
     B: Backend,
 {
-    let datasets = vec![Dataset::default()
+    let seam;
+    let mut datasets = vec![Dataset::default()
         .marker(symbols::Marker::Braille)
         .style(Style::default().fg(Color::LightGreen))
         .graph_type(GraphType::Line)
         .data(&points.data)];
+    let mut title = String::from(title);
+    if !points.forecast.is_empty() {
+        datasets.push(
+            Dataset::default()
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().fg(Color::Yellow))
+                .graph_type(GraphType::Line)
+                .data(&points.forecast),
+        );
+    }
+    if !points.threshold.is_empty() {
+        datasets.push(
+            Dataset::default()
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().fg(Color::Red))
+                .graph_type(GraphType::Line)
+                .data(&points.threshold),
+        );
+    }
+    if !points.anomalies.is_empty() {
+        datasets.push(
+            Dataset::default()
+                .marker(symbols::Marker::Block)
+                .style(Style::default().fg(Color::LightRed))
+                .graph_type(GraphType::Scatter)
+                .data(&points.anomalies),
+        );
+        title = format!("{}, {} unusual", title, points.anomalies.len());
+    }
+    // mark where the backfilled samples end and the live samples start
+    if let Some(live_since) = points.live_since {
+        seam = [(live_since, points.y_min), (live_since, points.y_max)];
+        datasets.push(
+            Dataset::default()
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().fg(Color::DarkGray))
+                .graph_type(GraphType::Line)
+                .data(&seam),
+        );
+        title = format!(
+            "{}, backfilled until {}",
+            title,
+            Local.timestamp(live_since as i64, 0).format("%H:%M:%S")
+        );
+    }
 
     let chart = Chart::new(datasets)
-        .block(Block::default().title("Graph").borders(Borders::ALL))
+        .block(Block::default().title(title).borders(Borders::ALL))
         .x_axis(
             Axis::default()
                 .labels(vec![
@@ -113,16 +316,236 @@ where
     f.render_widget(chart, area);
 }
 
-fn draw_histogram_table<B>(f: &mut Frame<B>, area: Rect, histogram_data: &HistogramData)
-where
+fn draw_histogram_history<B>(
+    f: &mut Frame<B>,
+    chunk_right: Rect,
+    chunk_left: Rect,
+    samples: &SampleStorage,
+    options: &HistoryOptions,
+) where
     B: Backend,
 {
-    let chunks = Layout::default()
-        .constraints([Constraint::Percentage(25), Constraint::Min(8)].as_ref())
-        .split(area);
+    let histograms: Vec<HistogramValueSample> = samples
+        .iter()
+        .filter_map(|sample| match sample {
+            Sample::HistogramSample(histogram) => Some(histogram),
+            _ => None,
+        })
+        .collect();
+    let histogram_data = match HistogramData::parse(&histograms, options.histogram_window) {
+        Some(histogram_data) => histogram_data,
+        None => return,
+    };
+    let quantiles = quantiles_over_time(&histograms, &DEFAULT_QUANTILES, options.histogram_window);
 
+    let chunks_left = Layout::default()
+        .constraints(
+            [
+                Constraint::Length(4),
+                Constraint::Percentage(40),
+                Constraint::Min(5),
+            ]
+            .as_ref(),
+        )
+        .split(chunk_left);
+    draw_histogram_table(f, chunks_left[0], chunks_left[1], &histogram_data);
+    draw_quantile_table(f, chunks_left[2], &histograms, &quantiles, options);
+
+    let chunks_right = Layout::default()
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(chunk_right);
+    if let Some(scale) = options.heatmap {
+        let increases = bucket_increases(&histograms);
+        let title = format!("Heatmap (increase per scrape, {})", scale);
+        let heatmap = Heatmap::new(&increases, scale)
+            .block(Block::default().title(title).borders(Borders::ALL));
+        f.render_widget(heatmap, chunks_right[0]);
+    } else if options.counter_view == CounterView::Raw {
+        draw_histogram(f, chunks_right[0], &histogram_data);
+    } else {
+        let chunks = Layout::default()
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .direction(Direction::Horizontal)
+            .split(chunks_right[0]);
+        for (area, value, name) in [
+            (chunks[0], CounterValue::HistogramCount, "Count"),
+            (chunks[1], CounterValue::HistogramSum, "Sum"),
+        ] {
+            let points = counter_points(
+                &sample_points(samples, value),
+                options.counter_view,
+                options.rate_window,
+            );
+            let title = format!("{} ({})", name, options.counter_title());
+            if let Some(graph_data) = GraphData::from_points(&points, options.live_since) {
+                draw_graph(f, area, &graph_data, &title);
+            }
+        }
+    }
+    draw_quantile_graph(f, chunks_right[1], &quantiles, options);
+}
+
+pub fn quantile_name(quantile: f64) -> String {
+    format!("p{}", (quantile * 1000.0).round() / 10.0)
+}
+
+/// Every sample of the histogram with its count, sum and quantile estimates.
+fn draw_quantile_table<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    histograms: &[HistogramValueSample],
+    quantiles: &[(u64, Vec<f64>)],
+    options: &HistoryOptions,
+) where
+    B: Backend,
+{
+    let quantiles_by_timestamp: HashMap<u64, &Vec<f64>> = quantiles
+        .iter()
+        .map(|(timestamp, estimates)| (*timestamp, estimates))
+        .collect();
+    let rows = histograms.iter().map(|histogram| {
+        let mut cells = vec![
+            Local
+                .timestamp(histogram.timestamp as i64, 0)
+                .format("%H:%M:%S")
+                .to_string(),
+            histogram.count.to_string(),
+            format!("{:.2}", histogram.sum),
+        ];
+        cells.extend(DEFAULT_QUANTILES.iter().enumerate().map(|(index, _)| {
+            match quantiles_by_timestamp.get(&histogram.timestamp) {
+                Some(estimates) if !estimates[index].is_nan() => {
+                    format!("{:+.4e}", estimates[index])
+                }
+                _ => String::from("-"),
+            }
+        }));
+        let row = Row::new(cells);
+        // backfilled samples are dimmed
+        match options.live_since {
+            Some(live_since) if histogram.timestamp < live_since => {
+                row.style(Style::default().fg(Color::DarkGray))
+            }
+            _ => row,
+        }
+    });
+
+    let mut header = vec![
+        String::from("Time"),
+        String::from("Count"),
+        String::from("Sum"),
+    ];
+    header.extend(
+        DEFAULT_QUANTILES
+            .iter()
+            .map(|quantile| quantile_name(*quantile)),
+    );
+    let title = format!(
+        "History ({}, quantiles {})",
+        histograms.len(),
+        options.histogram_window
+    );
+    let t = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .header(Row::new(header).style(Style::default().add_modifier(Modifier::BOLD)))
+        .widths(&[
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ]);
+    let mut state = TableState::default();
+    state.select(histograms.len().checked_sub(1));
+    f.render_stateful_widget(t, area, &mut state);
+}
+
+/// One line per quantile, the samples without estimate are left out.
+fn draw_quantile_graph<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    quantiles: &[(u64, Vec<f64>)],
+    options: &HistoryOptions,
+) where
+    B: Backend,
+{
+    let lines: Vec<Vec<(f64, f64)>> = (0..DEFAULT_QUANTILES.len())
+        .map(|index| {
+            quantiles
+                .iter()
+                .filter(|(_, estimates)| estimates[index].is_finite())
+                .map(|(timestamp, estimates)| (*timestamp as f64, estimates[index]))
+                .collect()
+        })
+        .collect();
+    let values = || lines.iter().flatten().map(|(_, value)| *value);
+    let (x_min, x_max) = match (quantiles.first(), quantiles.last()) {
+        (Some((first, _)), Some((last, _))) if first < last => (*first, *last),
+        _ => return,
+    };
+    let y_min = values().fold(f64::INFINITY, f64::min);
+    let y_max = values().fold(f64::NEG_INFINITY, f64::max);
+    if y_min > y_max {
+        return;
+    }
+
+    let colors = [Color::LightGreen, Color::Yellow, Color::LightRed];
+    let datasets = lines
+        .iter()
+        .zip(DEFAULT_QUANTILES)
+        .zip(colors)
+        .map(|((line, quantile), color)| {
+            Dataset::default()
+                .name(quantile_name(quantile))
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().fg(color))
+                .graph_type(GraphType::Line)
+                .data(line)
+        })
+        .collect();
+    let title = format!("Quantiles ({})", options.histogram_window);
+    let chart = Chart::new(datasets)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .x_axis(
+            Axis::default()
+                .labels(vec![
+                    Span::raw(
+                        Local
+                            .timestamp(x_min as i64, 0)
+                            .format("%H:%M:%S")
+                            .to_string(),
+                    ),
+                    Span::raw(
+                        Local
+                            .timestamp(x_max as i64, 0)
+                            .format("%H:%M:%S")
+                            .to_string(),
+                    ),
+                ])
+                .bounds([x_min as f64, x_max as f64]),
+        )
+        .y_axis(
+            Axis::default()
+                .labels(vec![
+                    Span::raw(format!("{:+.4e}", y_min)),
+                    Span::raw(format!("{:+.4e}", y_max)),
+                ])
+                .bounds([y_min, y_max]),
+        );
+    f.render_widget(chart, area);
+}
+
+fn draw_histogram_table<B>(
+    f: &mut Frame<B>,
+    details_area: Rect,
+    buckets_area: Rect,
+    histogram_data: &HistogramData,
+) where
+    B: Backend,
+{
     // Draw histogram details
-    let title_details = format!("Histogram Details");
+    let title_details = format!("Histogram Details ({})", histogram_data.window);
 
     let row_details = [Row::new(vec![
         histogram_data.time.to_rfc2822(),
@@ -143,7 +566,7 @@ where
             Constraint::Length(15),
             Constraint::Percentage(100),
         ]);
-    f.render_widget(t, chunks[0]);
+    f.render_widget(t, details_area);
 
     // Draw histogram buckets details
     let title = format!("Histogram Buckets Details");
@@ -172,7 +595,7 @@ where
             Constraint::Length(15),
             Constraint::Percentage(100),
         ]);
-    f.render_widget(t, chunks[1]);
+    f.render_widget(t, buckets_area);
 }
 
 fn draw_histogram<B>(f: &mut Frame<B>, area: Rect, histogram_data: &HistogramData)
@@ -191,7 +614,11 @@ where
         .collect();
     let bar_width = area.width / (data.len() + 1) as u16;
     let t = BarChart::default()
-        .block(Block::default().title("Histogram").borders(Borders::ALL))
+        .block(
+            Block::default()
+                .title(format!("Histogram ({})", histogram_data.window))
+                .borders(Borders::ALL),
+        )
         .data(&data)
         .bar_width(bar_width)
         .bar_style(Style::default().fg(Color::LightGreen))
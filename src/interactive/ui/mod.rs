@@ -1,17 +1,31 @@
+use chrono::{Local, TimeZone};
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
+use std::sync::Arc;
 use tui::backend::Backend;
 use tui::layout::{Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
 use tui::text::{Span, Spans};
-use tui::widgets::{Block, BorderType, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use tui::widgets::{
+    Block, BorderType, Borders, List, ListItem, ListState, Paragraph, Row, Table, Wrap,
+};
 use tui::Frame;
 
-use crate::interactive::app::{App, ElementInFocus};
-use crate::prom::Metric;
+use crate::interactive::app::{App, ElementInFocus, InputTarget, MarkedChanges};
+use crate::prom::{
+    AlertState, Alerting, Change, GroupBy, Metric, MetricHistory, Recorder, Selector,
+    SHIFT_QUANTILES,
+};
 
+use self::history::{quantile_name, HistoryOptions};
+
+mod cardinality;
+mod comparison;
 mod graph_data;
+mod heatmap;
 mod histogram_data;
 mod history;
+mod movers;
 mod search;
 mod style;
 
@@ -24,24 +38,77 @@ const fn focus_color(has_focus: bool) -> Color {
 }
 
 pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) -> Result<(), Box<dyn Error>> {
+    // the whole frame is drawn from the same generation of the history
+    let history = app.history();
+    app.update_alerts(&history);
+    let header_text = info_header_text(app, &history)?;
     let chunks = Layout::default()
-        .constraints([Constraint::Length(2 + 3), Constraint::Min(8)].as_ref())
+        .constraints(
+            [
+                Constraint::Length(2 + header_text.len() as u16),
+                Constraint::Min(8),
+            ]
+            .as_ref(),
+        )
         .split(f.size());
-    draw_info_header(f, chunks[0], app);
-    draw_main(f, chunks[1], app)?;
+    draw_info_header(f, chunks[0], header_text);
+    draw_main(f, chunks[1], app, &history)?;
     Ok(())
 }
 
-fn draw_info_header<B>(f: &mut Frame<B>, area: Rect, app: &App)
-where
-    B: Backend,
-{
-    let endpoint = format!("Metrics endpoint: {}", app.endpoint);
+fn info_header_text(
+    app: &App,
+    history: &MetricHistory,
+) -> Result<Vec<Spans<'static>>, Box<dyn Error>> {
+    let endpoint = if app.metric_source.is_live() {
+        format!("Metrics endpoint: {}", app.endpoint)
+    } else {
+        format!(
+            "Metrics endpoint: {} (saved session, read-only)",
+            app.endpoint
+        )
+    };
     let scrape_interval = format!("Scraping interval: {}s", app.scrape_interval);
     let mut text = vec![Spans::from(endpoint), Spans::from(scrape_interval)];
 
+    if let Some(comparison) = &app.comparison {
+        text.push(Spans::from(format!(
+            "Compared with endpoint: {}{}",
+            comparison.endpoint,
+            if app.show_comparison {
+                ""
+            } else {
+                " (press 'c' to compare)"
+            }
+        )));
+        let error_msg_guard = comparison
+            .source
+            .get_error_msg_read_guard()
+            .expect("to get error msg guard");
+        if let Some(error_msg) = &*error_msg_guard {
+            text.push(Spans::from(Span::styled(
+                format!(
+                    "Scraping the compared endpoint fails with error: {}",
+                    error_msg
+                ),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )));
+        }
+    }
+
+    if let Some(source_status) = app.metric_source.status() {
+        text.push(Spans::from(source_status));
+    }
+
+    if let Some(status_msg) = &app.status_msg {
+        text.push(Spans::from(Span::styled(
+            status_msg.clone(),
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+
     let error_msg_guard = app
-        .metric_scraper
+        .metric_source
         .get_error_msg_read_guard()
         .expect("to get error msg guard");
     if let Some(error_msg) = &*error_msg_guard {
@@ -53,29 +120,138 @@ where
         )));
     }
 
+    // a saved session has no live error, show the errors recorded while it was scraped
+    if !app.metric_source.is_live() {
+        if let Some(last_error) = history.scrape_errors.last() {
+            text.push(Spans::from(Span::styled(
+                format!(
+                    "Recorded scrape errors: {}, last at {}: {}",
+                    history.scrape_errors.len(),
+                    Local.timestamp(last_error.timestamp as i64, 0).to_rfc2822(),
+                    last_error.message
+                ),
+                Style::default().fg(Color::Red),
+            )));
+        }
+    }
+
+    if let Some(alerting) = &app.alerting {
+        let firing = alerting.count(AlertState::Firing);
+        let pending = alerting.count(AlertState::Pending);
+        let color = if firing > 0 {
+            Color::Red
+        } else if pending > 0 {
+            Color::Yellow
+        } else {
+            Color::Green
+        };
+        let mut alerts = format!(
+            "Alerts: {} firing, {} pending, {} rules",
+            firing,
+            pending,
+            alerting.rules.len()
+        );
+        if !alerting.errors.is_empty() {
+            alerts = format!("{}, {} failing", alerts, alerting.errors.len());
+        }
+        text.push(Spans::from(Span::styled(
+            alerts,
+            Style::default().fg(color).add_modifier(Modifier::BOLD),
+        )));
+    }
+    if let Some(recorder) = &app.recorder {
+        let (recording, color) = match recorder.errors.len() {
+            0 => (
+                format!("Recording rules: {}", recorder.rules.len()),
+                Color::Green,
+            ),
+            failing => (
+                format!(
+                    "Recording rules: {}, {} failing or skipped",
+                    recorder.rules.len(),
+                    failing
+                ),
+                Color::Yellow,
+            ),
+        };
+        text.push(Spans::from(Span::styled(
+            recording,
+            Style::default().fg(color),
+        )));
+    }
+
+    if let Some(first_mark) = app.first_mark {
+        text.push(Spans::from(Span::styled(
+            format!(
+                "Marked at {}, press 'b' again to see what changed since",
+                Local.timestamp(first_mark as i64, 0).format("%H:%M:%S")
+            ),
+            Style::default().fg(Color::LightCyan),
+        )));
+    }
+
     if let Some(selected_metric) = &app.selected_metric {
+        let selected_metric = match &app.group_by {
+            Some(group_by) => format!("{} ({})", group_by, selected_metric),
+            None => selected_metric.clone(),
+        };
         text.push(Spans::from(format!("Selected metric: {}", selected_metric)));
     }
+    Ok(text)
+}
 
+fn draw_info_header<B>(f: &mut Frame<B>, area: Rect, text: Vec<Spans>)
+where
+    B: Backend,
+{
     let title = format!("PROM TUI {}", env!("CARGO_PKG_VERSION"));
     let block = Block::default().borders(Borders::ALL).title(title);
     let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
     f.render_widget(paragraph, area);
 }
 
-fn draw_main<B>(f: &mut Frame<B>, area: Rect, app: &mut App) -> Result<(), Box<dyn Error>>
+fn draw_main<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    app: &mut App,
+    history: &Arc<MetricHistory>,
+) -> Result<(), Box<dyn Error>>
 where
     B: Backend,
 {
-    let metric_headers = app.metric_scraper.get_history_lock()?.get_metrics_headers();
+    if let Some(marked) = &mut app.marked_changes {
+        draw_changes(f, area, marked);
+        return Ok(());
+    }
+    if let Some(view) = &mut app.movers {
+        movers::draw(f, area, view, history);
+        return Ok(());
+    }
+    if let Some(view) = &mut app.cardinality {
+        cardinality::draw(f, area, view, history, app.cardinality_threshold);
+        return Ok(());
+    }
+    if let (true, Some(alerting)) = (app.show_alerts, &app.alerting) {
+        draw_alerts(f, area, alerting, app.recorder.as_ref());
+        return Ok(());
+    }
+    if app.query.is_some() || app.is_editing(InputTarget::Query) {
+        draw_query(f, area, app, history);
+        return Ok(());
+    }
+    if let Some(diff) = app.comparison_diff(history) {
+        let candidate = match &app.comparison {
+            Some(comparison) => comparison.source.history_snapshot(),
+            None => return Ok(()),
+        };
+        comparison::draw(f, area, app, &diff, history, &candidate);
+        return Ok(());
+    }
+    let metric_headers = app.visible_metrics(history);
 
     #[allow(clippy::option_if_let_else)]
     let metric_headers_area = if let Some(selected_metric) = &app.selected_metric {
-        if let Some(metric) = app
-            .metric_scraper
-            .get_history_lock()?
-            .get_metric(selected_metric)
-        {
+        if let Some(metric) = history.get_metric(selected_metric) {
             let chunks = Layout::default()
                 .constraints([Constraint::Percentage(35), Constraint::Percentage(65)].as_ref())
                 .direction(Direction::Horizontal)
@@ -86,14 +262,41 @@ where
                 .direction(Direction::Vertical)
                 .split(chunks[0]);
 
+            let metric = app.grouped_metric(metric);
+            let labels = app.visible_labels(&metric);
+            let mut inputs = Vec::new();
+            for (target, title, current) in [
+                (
+                    InputTarget::LabelFilter,
+                    "Filter",
+                    app.label_filter.as_ref().map(Selector::to_string),
+                ),
+                (
+                    InputTarget::GroupBy,
+                    "Group by",
+                    app.group_by.as_ref().map(GroupBy::to_string),
+                ),
+            ] {
+                match (&app.input, current) {
+                    (Some(input), _) if input.target == target => {
+                        inputs.push((title, input.text.clone(), true))
+                    }
+                    (_, Some(current)) => inputs.push((title, current, false)),
+                    _ => {}
+                }
+            }
+            let options = history_options(app, history);
             draw_details(
                 f,
                 chunks[1],
                 chunks_left[1],
-                metric,
+                &metric,
+                &labels,
+                &inputs,
                 matches!(app.focus, ElementInFocus::LabelsView),
                 &mut app.labels_list_state,
                 &app.selected_label,
+                &options,
             );
             chunks_left[0]
         } else {
@@ -103,10 +306,12 @@ where
         area
     };
 
+    let marked = app.metrics_with_anomalies(history);
     draw_list(
         f,
         metric_headers_area,
         &metric_headers,
+        &marked,
         matches!(app.focus, ElementInFocus::MetricHeaders),
         &app.selected_metric,
         &mut app.metric_list_state,
@@ -116,10 +321,288 @@ where
     Ok(())
 }
 
+/// Draws the query and its result, like a metric with the series of the result as labels.
+fn draw_query<B>(f: &mut Frame<B>, area: Rect, app: &mut App, history: &Arc<MetricHistory>)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+        .constraints([Constraint::Percentage(35), Constraint::Percentage(65)].as_ref())
+        .direction(Direction::Horizontal)
+        .split(area);
+    let chunks_left = Layout::default()
+        .constraints(
+            [
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Min(8),
+            ]
+            .as_ref(),
+        )
+        .direction(Direction::Vertical)
+        .split(chunks[0]);
+
+    let editing = app.is_editing(InputTarget::Query);
+    let expression = match (&app.input, &app.query) {
+        (Some(input), _) if editing => input.text.clone(),
+        (_, Some(query)) => query.expression.clone(),
+        _ => String::new(),
+    };
+    search::draw(f, chunks_left[0], "Query", &expression, editing);
+
+    let result = app.query_result(history);
+    let summary = match result.as_deref() {
+        Some(Ok(metric)) => Span::raw(format!(
+            "{} series, evaluated every {}s, Esc to close",
+            metric.time_series().len(),
+            app.scrape_interval
+        )),
+        Some(Err(err)) => Span::styled(err.to_string(), Style::default().fg(Color::Red)),
+        None => Span::raw("Enter to evaluate, Esc to cancel"),
+    };
+    let paragraph = Paragraph::new(Spans::from(summary))
+        .block(Block::default().borders(Borders::ALL).title("Result"));
+    f.render_widget(paragraph, chunks_left[1]);
+
+    if let Some(Ok(metric)) = result.as_deref() {
+        let labels = app.visible_labels(metric);
+        // the series of a result may change when the history grows
+        if let Some(selected_label) = &app.selected_label {
+            if !labels.contains(selected_label) {
+                app.selected_label = None;
+                app.labels_list_state.select(Some(0));
+            }
+        }
+        let options = history_options(app, history);
+        draw_details(
+            f,
+            chunks[1],
+            chunks_left[2],
+            metric,
+            &labels,
+            &[],
+            matches!(app.focus, ElementInFocus::LabelsView),
+            &mut app.labels_list_state,
+            &app.selected_label,
+            &options,
+        );
+    }
+}
+
+/// The series which changed between the marks, the largest relative change first.
+fn draw_changes<B>(f: &mut Frame<B>, area: Rect, marked: &mut MarkedChanges)
+where
+    B: Backend,
+{
+    let format_value = |value: f64| format!("{:+.4e}", value);
+    let format_quantiles = |quantiles: &[f64]| {
+        SHIFT_QUANTILES
+            .iter()
+            .zip(quantiles)
+            .map(|(quantile, value)| {
+                format!("{} {}", quantile_name(*quantile), format_value(*value))
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let rows = marked.changes.iter().map(|series| {
+        let (kind, before, after, change) = match &series.change {
+            Change::Gauge { before, after } => (
+                "gauge",
+                format_value(*before),
+                format_value(*after),
+                format_value(after - before),
+            ),
+            Change::Counter {
+                before,
+                after,
+                increase,
+            } => (
+                "counter",
+                before.map_or_else(
+                    || String::from("-"),
+                    |before| format!("{}/s", format_value(before)),
+                ),
+                format!("{}/s", format_value(*after)),
+                format!("{} total", format_value(*increase)),
+            ),
+            Change::Histogram {
+                before,
+                after,
+                observations,
+            } => (
+                "histogram",
+                format_quantiles(before),
+                format_quantiles(after),
+                format!("{} observations", observations),
+            ),
+        };
+        let percent = series.change.percent();
+        let percent = if percent.is_finite() {
+            format!("{:+.1}%", percent)
+        } else {
+            String::from("new")
+        };
+        Row::new(vec![
+            series.metric.clone(),
+            series.labels.clone(),
+            kind.to_string(),
+            before,
+            after,
+            change,
+            percent,
+        ])
+    });
+    let format_time = |timestamp: u64| {
+        Local
+            .timestamp(timestamp as i64, 0)
+            .format("%H:%M:%S")
+            .to_string()
+    };
+    let title = format!(
+        "Changes between the marks at {} and {} ({} series), Esc to close",
+        format_time(marked.from),
+        format_time(marked.to),
+        marked.changes.len()
+    );
+    let table = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .header(
+            Row::new(vec![
+                "Metric", "Series", "Type", "Before", "Between", "Change", "Relative",
+            ])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .widths(&[
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Length(9),
+            Constraint::Percentage(18),
+            Constraint::Percentage(18),
+            Constraint::Length(20),
+            Constraint::Length(9),
+        ]);
+    f.render_stateful_widget(table, area, &mut marked.state);
+}
+
+/// The alerts, firing first, and the alerting and recording rules failing to evaluate or skipped.
+fn draw_alerts<B>(f: &mut Frame<B>, area: Rect, alerting: &Alerting, recorder: Option<&Recorder>)
+where
+    B: Backend,
+{
+    let errors: BTreeMap<&String, &String> = alerting
+        .errors
+        .iter()
+        .chain(
+            recorder
+                .into_iter()
+                .flat_map(|recorder| recorder.errors.iter()),
+        )
+        .collect();
+    let errors: Vec<Spans> = errors
+        .into_iter()
+        .map(|(name, error)| {
+            Spans::from(Span::styled(
+                format!("{}: {}", name, error),
+                Style::default().fg(Color::Red),
+            ))
+        })
+        .collect();
+    let chunks = Layout::default()
+        .constraints(
+            [
+                Constraint::Min(5),
+                Constraint::Length(if errors.is_empty() {
+                    0
+                } else {
+                    2 + errors.len() as u16
+                }),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    let format_time = |timestamp: u64| {
+        Local
+            .timestamp(timestamp as i64, 0)
+            .format("%H:%M:%S")
+            .to_string()
+    };
+    let alerts = alerting.alerts();
+    let rows = alerts.iter().map(|alert| {
+        let (since, color) = match alert.state {
+            AlertState::Firing => (alert.fired_at.unwrap_or(alert.active_since), Color::Red),
+            AlertState::Pending => (alert.active_since, Color::Yellow),
+            AlertState::Resolved => (
+                alert.resolved_at.unwrap_or(alert.active_since),
+                Color::Green,
+            ),
+        };
+        Row::new(vec![
+            alert.state.to_string(),
+            alert.rule.clone(),
+            alert.labels.clone(),
+            format!("{:+.4e}", alert.value),
+            format_time(since),
+            alert.severity.clone(),
+        ])
+        .style(Style::default().fg(color))
+    });
+    let title = format!(
+        "Alerts ({}), {} rules evaluated after every scrape, Esc to close",
+        alerts.len(),
+        alerting.rules.len()
+    );
+    let table = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .header(
+            Row::new(vec![
+                "State", "Alert", "Labels", "Value", "Since", "Severity",
+            ])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .widths(&[
+            Constraint::Length(10),
+            Constraint::Length(25),
+            Constraint::Percentage(40),
+            Constraint::Length(12),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ]);
+    f.render_widget(table, chunks[0]);
+
+    if !errors.is_empty() {
+        let paragraph = Paragraph::new(errors)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Failing rules"),
+            )
+            .wrap(Wrap { trim: true });
+        f.render_widget(paragraph, chunks[1]);
+    }
+}
+
+fn history_options(app: &App, history: &MetricHistory) -> HistoryOptions {
+    HistoryOptions {
+        live_since: history.live_since,
+        counter_view: app.counter_view,
+        // like the rate interval of Grafana, so every window holds several scrapes
+        rate_window: 4 * app.scrape_interval,
+        histogram_window: app.histogram_window,
+        heatmap: app.heatmap,
+        anomaly: app.anomaly_config,
+        forecast_window: app.forecast_window,
+        forecast_threshold: app.forecast_threshold,
+    }
+}
+
 fn draw_list<B>(
     f: &mut Frame<B>,
     area: Rect,
     items: &[String],
+    marked: &HashSet<String>,
     has_focus: bool,
     selected_label_option: &Option<String>,
     state: &mut ListState,
@@ -145,13 +628,19 @@ fn draw_list<B>(
         .style(Style::default().fg(Color::White))
         .title(title)
         .border_type(BorderType::Plain);
+    // the marked items, e.g. metrics with unusual samples, are highlighted
     let list_item: Vec<ListItem> = items
         .iter()
         .map(|header| {
-            ListItem::new(Spans::from(vec![Span::styled(
-                header.clone(),
-                Style::default(),
-            )]))
+            let span = if marked.contains(header) {
+                Span::styled(
+                    format!("{} !", header),
+                    Style::default().fg(Color::LightRed),
+                )
+            } else {
+                Span::styled(header.clone(), Style::default())
+            };
+            ListItem::new(Spans::from(vec![span]))
         })
         .collect();
     let focus_color = focus_color(has_focus);
@@ -169,26 +658,37 @@ fn draw_details<B>(
     chunk_right: Rect,
     chunk_left: Rect,
     metric: &Metric,
+    labels: &[String],
+    inputs: &[(&str, String, bool)],
     is_in_focus: bool,
     labels_state: &mut ListState,
     selected_label_option: &Option<String>,
+    options: &HistoryOptions,
 ) where
     B: Backend,
 {
-    let time_series_keys: Vec<String> = metric.time_series.iter().map(|(k, _)| k.clone()).collect();
     let chunks = Layout::default()
         .constraints([Constraint::Percentage(25), Constraint::Min(16)].as_ref())
         .split(chunk_right);
+    // one line for every filter or group-by being edited or applied, above the labels
+    let mut constraints = vec![Constraint::Length(3); inputs.len()];
+    constraints.push(Constraint::Min(3));
+    let input_chunks = Layout::default().constraints(constraints).split(chunks[0]);
+    for ((title, input, editing), area) in inputs.iter().zip(&input_chunks) {
+        search::draw(f, *area, title, input, *editing);
+    }
+    let labels_area = input_chunks[inputs.len()];
     draw_list(
         f,
-        chunks[0],
-        &time_series_keys,
+        labels_area,
+        labels,
+        &HashSet::new(),
         is_in_focus,
         selected_label_option,
         labels_state,
         "Labels",
     );
     if let Some(selected_label) = selected_label_option {
-        history::draw(f, chunks[1], chunk_left, metric, selected_label);
+        history::draw(f, chunks[1], chunk_left, metric, selected_label, options);
     }
 }
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use tui::backend::Backend;
+use tui::layout::{Constraint, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::Span;
+use tui::widgets::{Block, Borders, Cell, Row, Table};
+use tui::Frame;
+
+use crate::interactive::app::MoversView;
+use crate::prom::{MetricHistory, MoverRanking};
+
+/// Number of characters of the sparkline of a series.
+const SPARKLINE_WIDTH: usize = 30;
+const SPARKLINE_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Draws the series which moved the most within the window, with a sparkline of each.
+pub fn draw<B>(f: &mut Frame<B>, area: Rect, view: &mut MoversView, history: &Arc<MetricHistory>)
+where
+    B: Backend,
+{
+    let movers = view.movers(history);
+    let rows = movers.iter().map(|mover| {
+        Row::new(vec![
+            Cell::from(mover.metric.clone()),
+            Cell::from(mover.labels.clone()),
+            Cell::from(format_score(mover.score, view.ranking)),
+            Cell::from(Span::styled(
+                sparkline(&mover.points, SPARKLINE_WIDTH),
+                Style::default().fg(Color::LightGreen),
+            )),
+        ])
+    });
+    let title = format!(
+        "Top movers by {} over the last {} ({} series), 'o' to rank, '+'/'-' window, Enter to show",
+        view.ranking,
+        format_window(view.window),
+        movers.len()
+    );
+    let table = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .header(
+            Row::new(vec!["Metric", "Series", "Score", "Trend"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .widths(&[
+            Constraint::Percentage(25),
+            Constraint::Percentage(35),
+            Constraint::Length(12),
+            Constraint::Length(SPARKLINE_WIDTH as u16),
+        ]);
+    f.render_stateful_widget(table, area, &mut view.state);
+}
+
+fn format_score(score: f64, ranking: MoverRanking) -> String {
+    match ranking {
+        _ if !score.is_finite() => String::from("new"),
+        MoverRanking::AbsoluteChange => format!("{:.4e}", score),
+        MoverRanking::RelativeChange | MoverRanking::Volatility => format!("{:.1}%", score),
+        MoverRanking::Rate => format!("{:.4e}/s", score),
+    }
+}
+
+fn format_window(window: u64) -> String {
+    if window >= 60 * 60 {
+        format!("{}h", window / (60 * 60))
+    } else {
+        format!("{}m", window / 60)
+    }
+}
+
+/// The points averaged into at most `width` bars scaled between their minimum and maximum.
+fn sparkline(points: &[(u64, f64)], width: usize) -> String {
+    let values: Vec<f64> = points
+        .iter()
+        .map(|(_, value)| *value)
+        .filter(|value| value.is_finite())
+        .collect();
+    if values.is_empty() {
+        return String::new();
+    }
+    let bars = values.len().min(width);
+    let averages: Vec<f64> = (0..bars)
+        .map(|bar| {
+            let chunk = &values[bar * values.len() / bars..(bar + 1) * values.len() / bars];
+            chunk.iter().sum::<f64>() / chunk.len() as f64
+        })
+        .collect();
+    let min = averages.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = averages.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    averages
+        .iter()
+        .map(|value| {
+            let level = if max > min {
+                ((value - min) / (max - min) * (SPARKLINE_BARS.len() - 1) as f64).round() as usize
+            } else {
+                0
+            };
+            SPARKLINE_BARS[level]
+        })
+        .collect()
+}
@@ -1,18 +1,18 @@
-// use tui::{
-//     style::{Color, Modifier, Style},
-//     text::Span,
-// };
+use tui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
 
-// pub fn create_styled_title(title: &str, active: bool) -> Span {
-//     let title = String::from(title);
-//     if active {
-//         return Span::styled(
-//             title,
-//             Style::default()
-//                 .fg(Color::Blue)
-//                 .add_modifier(Modifier::ITALIC),
-//         );
-//     } else {
-//         return Span::styled(title, Style::default());
-//     }
-// }
+pub fn create_styled_title(title: &str, active: bool) -> Span<'static> {
+    let title = String::from(title);
+    if active {
+        Span::styled(
+            title,
+            Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::ITALIC),
+        )
+    } else {
+        Span::styled(title, Style::default())
+    }
+}
@@ -1,4 +1,10 @@
+use crate::interactive::ViewOptions;
 use crate::logging::app_config;
+use crate::prom::{
+    export_to_file, load_session, read_recording, replay_into_history, Alerting, AnomalyConfig,
+    BackfillConfig, ExportFormat, ExportScope, MetricHistory, MetricQuerier, MetricScraper,
+    MetricSource, Recorder, ReplaySource, ReplaySpeed, RuleFiles, SessionSource,
+};
 use regex::Regex;
 
 mod cli;
@@ -25,16 +31,239 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .to_string(),
         None => endpoint_option.unwrap().to_string(),
     };
+    let endpoint = if matches.is_present("Federate") {
+        let selectors: Vec<&str> = matches
+            .values_of("Match")
+            .expect("match selectors to be available")
+            .collect();
+        prom::build_federate_url(&endpoint, &selectors)?
+    } else {
+        endpoint
+    };
     let scrape_interval = matches
         .value_of("Scrape-Interval")
         .expect("scrape interval value to be available")
         .parse::<u64>()
         .expect("scrape interval value to be parsable to u64");
+    let export_format = matches.value_of("Export-Format").map(|v| {
+        v.parse::<ExportFormat>()
+            .expect("export format to be valid")
+    });
+    let anomaly_config = AnomalyConfig {
+        window: matches
+            .value_of("Anomaly-Window")
+            .expect("anomaly window value to be available")
+            .parse::<usize>()
+            .expect("anomaly window value to be parsable to usize"),
+        sigmas: matches
+            .value_of("Anomaly-Sigmas")
+            .expect("anomaly sigmas value to be available")
+            .parse::<f64>()
+            .expect("anomaly sigmas value to be parsable to f64"),
+    };
+    let cardinality_threshold = matches
+        .value_of("Cardinality-Threshold")
+        .expect("cardinality threshold value to be available")
+        .parse::<usize>()
+        .expect("cardinality threshold value to be parsable to usize");
+    let forecast_threshold = matches.value_of("Forecast-Threshold").map(|threshold| {
+        threshold
+            .parse::<f64>()
+            .expect("forecast threshold value to be parsable to f64")
+    });
+    let mut alerting = match matches.value_of("Alert-Rules") {
+        Some(path) => {
+            log::info!("Loading the alert rules from: {}", path);
+            Some(Alerting::load(path)?)
+        }
+        None => None,
+    };
+    let recorder = match matches.values_of("Rule-Files") {
+        Some(paths) => {
+            let paths: Vec<&str> = paths.collect();
+            log::info!("Loading the rule files: {:?}", paths);
+            let rule_files = RuleFiles::load(&paths)?;
+            for (name, err) in rule_files
+                .skipped_recording_rules
+                .iter()
+                .chain(rule_files.skipped_alert_rules.iter())
+            {
+                log::warn!("Skipping the rule {}: {}", name, err);
+            }
+            let alerting = alerting.get_or_insert_with(|| Alerting::new(Vec::new()));
+            alerting.add_rules(rule_files.alert_rules);
+            alerting.errors.extend(rule_files.skipped_alert_rules);
+            Some(Recorder::new(
+                rule_files.recording_rules,
+                rule_files.skipped_recording_rules,
+            ))
+        }
+        None => None,
+    };
+    let mut options = ViewOptions {
+        export_dir: matches
+            .value_of("Export-Dir")
+            .expect("export dir value to be available")
+            .to_string(),
+        export_format: export_format.unwrap_or(ExportFormat::Csv),
+        anomaly_config,
+        cardinality_threshold,
+        forecast_threshold,
+        alerting,
+        recorder,
+        compare_with: None,
+    };
     log::info!("Reading metrics from endpoint: {}", endpoint);
     log::info!("Scraping interval is: {}s", scrape_interval);
 
+    match matches.subcommand() {
+        Some(("record", record_matches)) => {
+            let output = record_matches
+                .value_of("Output")
+                .expect("output value to be available");
+            let max_scrapes = record_matches.value_of("Scrapes").map(|v| {
+                v.parse::<u64>()
+                    .expect("scrapes value to be parsable to u64")
+            });
+            println!("Recording the scrapes of {} to {}", endpoint, output);
+            prom::record(&endpoint, scrape_interval, output, max_scrapes).await?;
+            return Ok(());
+        }
+        Some(("export", export_matches)) => {
+            let input = export_matches
+                .value_of("Input")
+                .expect("input value to be available");
+            let output = export_matches
+                .value_of("Output")
+                .expect("output value to be available");
+            let scope = match (
+                export_matches.value_of("Metric"),
+                export_matches.value_of("Labels"),
+            ) {
+                (Some(metric), Some(labels)) => {
+                    ExportScope::TimeSeries(metric.to_string(), labels.to_string())
+                }
+                (Some(metric), None) => ExportScope::Metric(metric.to_string()),
+                _ => ExportScope::History,
+            };
+            let format = export_format.unwrap_or_else(|| ExportFormat::from_path(output));
+            export_to_file(&load_history(input)?, &scope, format, output)?;
+            println!("Exported {} to {}", input, output);
+            return Ok(());
+        }
+        Some(("replay", replay_matches)) => {
+            let archive = replay_matches
+                .value_of("Archive")
+                .expect("archive value to be available");
+            let speed = if replay_matches.is_present("Step") {
+                ReplaySpeed::Step
+            } else {
+                ReplaySpeed::Factor(
+                    replay_matches
+                        .value_of("Speed")
+                        .expect("speed value to be available")
+                        .parse::<f64>()
+                        .expect("speed value to be parsable to f64"),
+                )
+            };
+            log::info!("Replaying the recording: {}", archive);
+            let scrapes = read_recording(archive)?;
+            // show the interval of the recording rather than the configured one
+            let scrape_interval = match scrapes.as_slice() {
+                [first, second, ..] => second.timestamp().saturating_sub(first.timestamp()),
+                _ => scrape_interval,
+            };
+            interactive::show(
+                archive.to_string(),
+                scrape_interval,
+                Box::new(ReplaySource::new(scrapes, speed)),
+                None,
+                false,
+                options,
+            )
+            .await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    options.compare_with = matches.value_of("Compare").map(|url| {
+        log::info!("Comparing with endpoint: {}", url);
+        let source: Box<dyn MetricSource> =
+            Box::new(MetricScraper::new(url.to_string(), scrape_interval, None));
+        (url.to_string(), source)
+    });
+    let (endpoint, scrape_interval, metric_source) = match matches.value_of("Load-Session") {
+        Some(session_file) => {
+            log::info!("Loading the session from: {}", session_file);
+            let session = load_session(session_file)?;
+            let metric_source: Box<dyn MetricSource> =
+                Box::new(SessionSource::new(session.history));
+            (session.endpoint, session.scrape_interval, metric_source)
+        }
+        None => {
+            let metric_source: Box<dyn MetricSource> = match matches.values_of("Query") {
+                Some(queries) => {
+                    let queries: Vec<String> = queries.map(String::from).collect();
+                    let lookback = matches
+                        .value_of("Query-Lookback")
+                        .expect("query lookback value to be available")
+                        .parse::<u64>()
+                        .expect("query lookback value to be parsable to u64");
+                    log::info!("Evaluating the queries: {:?}", queries);
+                    Box::new(MetricQuerier::new(
+                        endpoint.clone(),
+                        queries,
+                        scrape_interval,
+                        lookback * 60,
+                    ))
+                }
+                None => {
+                    let backfill = matches.value_of("Backfill-From").map(|url| BackfillConfig {
+                        url: url.to_string(),
+                        lookback: matches
+                            .value_of("Backfill-Lookback")
+                            .expect("backfill lookback value to be available")
+                            .parse::<u64>()
+                            .expect("backfill lookback value to be parsable to u64")
+                            * 60,
+                        selector: matches.value_of("Backfill-Selector").map(String::from),
+                    });
+                    Box::new(MetricScraper::new(
+                        endpoint.clone(),
+                        scrape_interval,
+                        backfill,
+                    ))
+                }
+            };
+            (endpoint, scrape_interval, metric_source)
+        }
+    };
+
     // start dashboard
     log::info!("Showing the dashboard");
-    interactive::show(endpoint.clone(), scrape_interval).await?;
+    interactive::show(
+        endpoint,
+        scrape_interval,
+        metric_source,
+        matches.value_of("Save-Session").map(String::from),
+        matches.is_present("Autosave"),
+        options,
+    )
+    .await?;
     Ok(())
 }
+
+/// Loads the history of a saved session, or replays a recording into a history.
+fn load_history(path: &str) -> anyhow::Result<MetricHistory> {
+    match load_session(path) {
+        Ok(session) => Ok(session.history),
+        Err(session_err) => match read_recording(path) {
+            Ok(scrapes) => Ok(replay_into_history(scrapes)),
+            Err(_) => Err(session_err.context(format!(
+                "{} is neither a saved session nor a recording",
+                path
+            ))),
+        },
+    }
+}
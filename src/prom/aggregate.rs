@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::encoder::escape_label_value;
+use super::model::{
+    Bucket, HistogramValueSample, Metric, MetricDetails, Sample, SingleValueSample, TimeSeries,
+};
+use super::parser::extract_labels_key_and_map;
+use super::storage::SampleStorage;
+
+/// How the samples of the time series of a group are combined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregation {
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    fn apply(self, values: &[f64]) -> f64 {
+        match self {
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Aggregation::Sum => "sum",
+            Aggregation::Avg => "avg",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+        })
+    }
+}
+
+/// The labels kept when the time series of a metric are aggregated, written like in PromQL:
+/// `sum by (code, method)`. Without labels, all the time series are aggregated into one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupBy {
+    pub aggregation: Aggregation,
+    pub labels: Vec<String>,
+}
+
+impl GroupBy {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let input = input.trim();
+        let (operator, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let aggregation = match operator {
+            "sum" => Aggregation::Sum,
+            "avg" => Aggregation::Avg,
+            "min" => Aggregation::Min,
+            "max" => Aggregation::Max,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "unknown aggregation '{}', expected sum, avg, min or max",
+                    operator
+                ))
+            }
+        };
+        let rest = rest.trim();
+        if rest.is_empty() {
+            return Ok(Self {
+                aggregation,
+                labels: Vec::new(),
+            });
+        }
+        // 'by' is a word of its own, e.g. not the start of 'bytes'
+        let labels = match rest.strip_prefix("by") {
+            Some(labels) if labels.is_empty() || labels.starts_with(char::is_whitespace) => {
+                labels.trim()
+            }
+            Some(labels) if labels.starts_with('(') => labels,
+            _ => return Err(anyhow::anyhow!("expected 'by' after '{}'", operator)),
+        };
+        let labels = match labels.strip_prefix('(') {
+            Some(labels) => labels
+                .strip_suffix(')')
+                .ok_or_else(|| anyhow::anyhow!("expected ')' after the labels"))?,
+            None => labels,
+        };
+        let labels = labels
+            .split(',')
+            .map(str::trim)
+            .filter(|label| !label.is_empty())
+            .map(|label| {
+                let valid = label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !label.starts_with(|c: char| c.is_ascii_digit());
+                if valid {
+                    Ok(label.to_string())
+                } else {
+                    Err(anyhow::anyhow!("invalid label name '{}'", label))
+                }
+            })
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        Ok(Self {
+            aggregation,
+            labels,
+        })
+    }
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.labels.is_empty() {
+            write!(f, "{}", self.aggregation)
+        } else {
+            write!(f, "{} by ({})", self.aggregation, self.labels.join(", "))
+        }
+    }
+}
+
+/// Aggregates the time series with the given keys into one virtual time series per value of the
+/// kept labels. Samples scraped at the same time are combined. The buckets of histograms are
+/// always summed up by their upper bound, whatever the aggregation.
+pub fn aggregate(metric: &Metric, keys: &[&str], group_by: &GroupBy) -> Metric {
+    let mut groups: BTreeMap<BTreeMap<String, String>, Vec<&TimeSeries>> = BTreeMap::new();
+    for time_series in keys.iter().filter_map(|key| metric.time_series().get(*key)) {
+        let labels = group_by
+            .labels
+            .iter()
+            .filter_map(|name| {
+                let value = time_series.labels.get(name)?;
+                Some((name.clone(), value.clone()))
+            })
+            .collect();
+        groups.entry(labels).or_default().push(time_series);
+    }
+
+    let mut aggregated = Metric::new(MetricDetails {
+        name: format!("{} ({})", group_by, metric.details.name),
+        docstring: metric.details.docstring.clone(),
+        metric_type: metric.details.metric_type.clone(),
+    });
+    for (labels, group) in groups {
+        let mut samples_by_time: BTreeMap<u64, Vec<Sample>> = BTreeMap::new();
+        for sample in group
+            .iter()
+            .flat_map(|time_series| time_series.samples.iter())
+        {
+            samples_by_time
+                .entry(sample.timestamp())
+                .or_default()
+                .push(sample);
+        }
+        let samples: SampleStorage = samples_by_time
+            .into_iter()
+            .filter_map(|(timestamp, samples)| combine(group_by.aggregation, timestamp, samples))
+            .collect();
+        let (labels, key) = if labels.is_empty() {
+            extract_labels_key_and_map(None)
+        } else {
+            let key = labels
+                .iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+                .collect::<Vec<String>>()
+                .join(",");
+            (labels.into_iter().collect(), key)
+        };
+        let labels = Arc::new(labels);
+        aggregated.insert_time_series(key, TimeSeries { labels, samples });
+    }
+    aggregated
+}
+
+fn combine(aggregation: Aggregation, timestamp: u64, samples: Vec<Sample>) -> Option<Sample> {
+    let single_value = |sample: &Sample| match sample {
+        Sample::GaugeSample(sample) | Sample::CounterSample(sample) => Some(sample.value),
+        Sample::HistogramSample(_) => None,
+    };
+    match samples.first()? {
+        Sample::GaugeSample(_) => {
+            let values: Vec<f64> = samples.iter().filter_map(single_value).collect();
+            let value = aggregation.apply(&values);
+            Some(Sample::GaugeSample(SingleValueSample { timestamp, value }))
+        }
+        Sample::CounterSample(_) => {
+            let values: Vec<f64> = samples.iter().filter_map(single_value).collect();
+            let value = aggregation.apply(&values);
+            Some(Sample::CounterSample(SingleValueSample {
+                timestamp,
+                value,
+            }))
+        }
+        Sample::HistogramSample(_) => {
+            let mut histogram = HistogramValueSample {
+                timestamp,
+                bucket_values: Vec::new(),
+                sum: 0.0,
+                count: 0,
+            };
+            for sample in samples {
+                if let Sample::HistogramSample(sample) = sample {
+                    histogram.sum += sample.sum;
+                    histogram.count += sample.count;
+                    for bucket in sample.bucket_values {
+                        match histogram
+                            .bucket_values
+                            .iter_mut()
+                            .find(|summed| summed.name == bucket.name)
+                        {
+                            Some(summed) => summed.value += bucket.value,
+                            None => histogram
+                                .bucket_values
+                                .push(Bucket::new(bucket.name, bucket.value)),
+                        }
+                    }
+                }
+            }
+            Some(Sample::HistogramSample(histogram))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::model::{MetricHistory, NO_LABELS_KEY};
+    use crate::prom::test_data::history_of;
+
+    fn history() -> MetricHistory {
+        history_of([(1000, 1.0), (1010, 2.0)].map(|(timestamp, factor)| {
+            let body = format!(
+                "# HELP http_requests_total Requests.\n# TYPE http_requests_total counter\n\
+                 http_requests_total{{code=\"200\",method=\"GET\"}} {}\n\
+                 http_requests_total{{code=\"200\",method=\"POST\"}} {}\n\
+                 http_requests_total{{code=\"500\",method=\"GET\"}} {}\n\
+                 # HELP latency_seconds Latency.\n# TYPE latency_seconds histogram\n\
+                 latency_seconds_bucket{{path=\"/a\",le=\"0.1\"}} {}\n\
+                 latency_seconds_bucket{{path=\"/a\",le=\"+Inf\"}} {}\n\
+                 latency_seconds_sum{{path=\"/a\"}} 1.5\n\
+                 latency_seconds_count{{path=\"/a\"}} {}\n\
+                 latency_seconds_bucket{{path=\"/b\",le=\"0.1\"}} 1\n\
+                 latency_seconds_bucket{{path=\"/b\",le=\"+Inf\"}} 3\n\
+                 latency_seconds_sum{{path=\"/b\"}} 0.5\n\
+                 latency_seconds_count{{path=\"/b\"}} 3\n",
+                10.0 * factor,
+                5.0 * factor,
+                1.0 * factor,
+                2.0 * factor,
+                4.0 * factor,
+                4.0 * factor,
+            );
+            (timestamp, body)
+        }))
+    }
+
+    fn values(metric: &Metric, key: &str) -> Vec<f64> {
+        metric.time_series()[key]
+            .samples
+            .iter()
+            .map(|sample| match sample {
+                Sample::CounterSample(sample) => sample.value,
+                _ => panic!("expected a counter sample"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_group_by() {
+        let group_by = GroupBy::parse(" sum by (code, method) ").unwrap();
+        assert_eq!(group_by.aggregation, Aggregation::Sum);
+        assert_eq!(group_by.labels, vec!["code", "method"]);
+        assert_eq!(group_by.to_string(), "sum by (code, method)");
+        assert_eq!(GroupBy::parse("max by code").unwrap().labels, vec!["code"]);
+        assert_eq!(GroupBy::parse("avg").unwrap().to_string(), "avg");
+        assert!(GroupBy::parse("count by (code)").is_err());
+        assert!(GroupBy::parse("sum without (code)").is_err());
+        assert!(GroupBy::parse("sum by (code").is_err());
+        assert!(GroupBy::parse("sum by (co-de)").is_err());
+        assert_eq!(GroupBy::parse("sum by(code)").unwrap().labels, vec!["code"]);
+        assert!(GroupBy::parse("sum bytes").is_err());
+        assert!(GroupBy::parse("sum byx").is_err());
+    }
+
+    #[test]
+    fn test_aggregate_counters() {
+        let history = history();
+        let metric = history.get_metric("http_requests_total").unwrap();
+        let keys: Vec<&str> = metric.time_series().keys().map(String::as_str).collect();
+
+        let by_code = aggregate(metric, &keys, &GroupBy::parse("sum by (code)").unwrap());
+        assert_eq!(by_code.details.name, "sum by (code) (http_requests_total)");
+        assert_eq!(by_code.time_series().len(), 2);
+        assert_eq!(values(&by_code, "code=\"200\""), vec![15.0, 30.0]);
+        assert_eq!(values(&by_code, "code=\"500\""), vec![1.0, 2.0]);
+        assert_eq!(by_code.time_series()["code=\"500\""].labels["code"], "500");
+
+        let max = aggregate(metric, &keys, &GroupBy::parse("max").unwrap());
+        assert_eq!(values(&max, NO_LABELS_KEY), vec![10.0, 20.0]);
+        let avg = aggregate(
+            metric,
+            &keys[..1],
+            &GroupBy::parse("avg by (method)").unwrap(),
+        );
+        assert_eq!(avg.time_series().len(), 1);
+    }
+
+    #[test]
+    fn test_aggregate_histograms() {
+        let history = history();
+        let metric = history.get_metric("latency_seconds").unwrap();
+        let keys: Vec<&str> = metric.time_series().keys().map(String::as_str).collect();
+        let aggregated = aggregate(metric, &keys, &GroupBy::parse("avg").unwrap());
+        let histograms: Vec<HistogramValueSample> = aggregated.time_series()[NO_LABELS_KEY]
+            .samples
+            .iter()
+            .map(|sample| match sample {
+                Sample::HistogramSample(histogram) => histogram,
+                _ => panic!("expected a histogram sample"),
+            })
+            .collect();
+        assert_eq!(histograms.len(), 2);
+        let latest = &histograms[1];
+        assert_eq!(latest.count, 11);
+        assert_eq!(latest.sum, 2.0);
+        let buckets: Vec<(&str, u64)> = latest
+            .bucket_values
+            .iter()
+            .map(|bucket| (bucket.name.as_str(), bucket.value))
+            .collect();
+        assert_eq!(buckets, vec![("0.1", 5), ("+Inf", 11)]);
+    }
+}
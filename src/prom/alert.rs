@@ -0,0 +1,407 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+use super::model::MetricHistory;
+use super::promql::{parse_duration, Query};
+
+/// How long resolved alerts are still shown, as Prometheus keeps sending them.
+const RESOLVED_RETENTION: u64 = 15 * 60;
+
+/// How the value of the expression is compared to the threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum Comparator {
+    #[serde(rename = ">")]
+    Greater,
+    #[serde(rename = ">=")]
+    GreaterOrEqual,
+    #[serde(rename = "<")]
+    Less,
+    #[serde(rename = "<=")]
+    LessOrEqual,
+    #[serde(rename = "==")]
+    Equal,
+    #[serde(rename = "!=")]
+    NotEqual,
+}
+
+impl Comparator {
+    pub fn matches(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparator::Greater => value > threshold,
+            Comparator::GreaterOrEqual => value >= threshold,
+            Comparator::Less => value < threshold,
+            Comparator::LessOrEqual => value <= threshold,
+            Comparator::Equal => value == threshold,
+            Comparator::NotEqual => value != threshold,
+        }
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Comparator::Greater => ">",
+            Comparator::GreaterOrEqual => ">=",
+            Comparator::Less => "<",
+            Comparator::LessOrEqual => "<=",
+            Comparator::Equal => "==",
+            Comparator::NotEqual => "!=",
+        })
+    }
+}
+
+/// The rules file, e.g.
+///
+/// ```yaml
+/// rules:
+///   - name: HighErrorRate
+///     expr: sum(rate(http_requests_total{code=~"5.."}[5m]))
+///     comparator: ">"
+///     threshold: 0.5
+///     for: 2m
+///     severity: critical
+/// ```
+#[derive(Deserialize)]
+struct RulesFile {
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Deserialize)]
+struct RuleConfig {
+    name: String,
+    expr: String,
+    comparator: Comparator,
+    threshold: f64,
+    #[serde(default, rename = "for")]
+    for_duration: Option<String>,
+    #[serde(default = "default_severity")]
+    severity: String,
+}
+
+fn default_severity() -> String {
+    String::from("warning")
+}
+
+/// An alert fires for every series of the expression whose value matches the threshold for at
+/// least the `for` duration. Without threshold, like the alerting rules of Prometheus, every
+/// series of the expression is active.
+#[derive(Clone, Debug)]
+pub struct AlertRule {
+    pub name: String,
+    pub query: Query,
+    pub threshold: Option<(Comparator, f64)>,
+    /// seconds the condition must hold before the alert fires
+    pub for_duration: u64,
+    pub severity: String,
+}
+
+impl AlertRule {
+    fn from_config(config: RuleConfig) -> anyhow::Result<Self> {
+        let query = Query::parse(&config.expr)
+            .map_err(|err| anyhow::anyhow!("invalid expr of rule '{}': {}", config.name, err))?;
+        let for_duration = match &config.for_duration {
+            Some(duration) => parse_duration(duration)
+                .map_err(|err| anyhow::anyhow!("invalid for of rule '{}': {}", config.name, err))?,
+            None => 0,
+        };
+        Ok(Self {
+            name: config.name,
+            query,
+            threshold: Some((config.comparator, config.threshold)),
+            for_duration,
+            severity: config.severity,
+        })
+    }
+}
+
+/// Like in Prometheus, an alert is pending until its condition held for the `for` duration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertState {
+    Firing,
+    Pending,
+    Resolved,
+}
+
+impl fmt::Display for AlertState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AlertState::Firing => "firing",
+            AlertState::Pending => "pending",
+            AlertState::Resolved => "resolved",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub rule: String,
+    /// key of the labels of the series the alert is about
+    pub labels: String,
+    pub severity: String,
+    pub state: AlertState,
+    /// the latest value matching the threshold
+    pub value: f64,
+    /// the first evaluation the condition held
+    pub active_since: u64,
+    pub fired_at: Option<u64>,
+    pub resolved_at: Option<u64>,
+}
+
+/// Evaluates the alert rules against the history after every scrape and keeps the state of the
+/// alerts.
+pub struct Alerting {
+    pub rules: Vec<AlertRule>,
+    alerts: Vec<Alert>,
+    /// the error of the last evaluation of a rule, by rule name
+    pub errors: HashMap<String, String>,
+    last_evaluation: Option<u64>,
+}
+
+impl Alerting {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            alerts: Vec::new(),
+            errors: HashMap::new(),
+            last_evaluation: None,
+        }
+    }
+
+    /// Adds rules, e.g. the alerting rules of Prometheus rule files.
+    pub fn add_rules(&mut self, rules: Vec<AlertRule>) {
+        self.rules.extend(rules);
+    }
+
+    /// Reads the rules from a YAML file.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("failed to read the alert rules {}: {}", path, err))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let file: RulesFile = serde_yaml::from_str(content)
+            .map_err(|err| anyhow::anyhow!("invalid alert rules: {}", err))?;
+        let rules = file
+            .rules
+            .into_iter()
+            .map(AlertRule::from_config)
+            .collect::<anyhow::Result<Vec<AlertRule>>>()?;
+        Ok(Self::new(rules))
+    }
+
+    /// Evaluates the rules at the time of the newest sample of the history, once per timestamp.
+    pub fn evaluate(&mut self, history: &MetricHistory) {
+        let time = match history.latest_timestamp() {
+            Some(time) if !matches!(self.last_evaluation, Some(last) if last >= time) => time,
+            _ => return,
+        };
+        self.last_evaluation = Some(time);
+        for rule in &self.rules {
+            match rule.query.evaluate_at(history, time) {
+                Ok(samples) => {
+                    self.errors.remove(&rule.name);
+                    let active: HashMap<String, f64> = samples
+                        .into_iter()
+                        .filter(|(_, value)| match rule.threshold {
+                            Some((comparator, threshold)) => comparator.matches(*value, threshold),
+                            None => true,
+                        })
+                        .collect();
+                    update_alerts(&mut self.alerts, rule, &active, time);
+                }
+                // the alerts of the rule keep their state until it can be evaluated again
+                Err(err) => {
+                    self.errors.insert(rule.name.clone(), err.to_string());
+                }
+            }
+        }
+        self.alerts.retain(|alert| match alert.resolved_at {
+            Some(resolved_at) => time < resolved_at + RESOLVED_RETENTION,
+            None => true,
+        });
+    }
+
+    /// The alerts, firing first, then pending, then resolved.
+    pub fn alerts(&self) -> Vec<&Alert> {
+        let mut alerts: Vec<&Alert> = self.alerts.iter().collect();
+        alerts.sort_by(|a, b| (a.state, &a.rule, &a.labels).cmp(&(b.state, &b.rule, &b.labels)));
+        alerts
+    }
+
+    pub fn count(&self, state: AlertState) -> usize {
+        self.alerts
+            .iter()
+            .filter(|alert| alert.state == state)
+            .count()
+    }
+}
+
+/// Moves the alerts of the rule to their next state: the new series matching the threshold start
+/// pending, or fire at once without `for` duration, the pending ones fire once the duration is
+/// over, and the ones not matching anymore are dropped while pending and resolved once fired.
+fn update_alerts(
+    alerts: &mut Vec<Alert>,
+    rule: &AlertRule,
+    active: &HashMap<String, f64>,
+    time: u64,
+) {
+    for (labels, value) in active {
+        let alert = alerts.iter_mut().find(|alert| {
+            alert.rule == rule.name
+                && &alert.labels == labels
+                && alert.state != AlertState::Resolved
+        });
+        match alert {
+            Some(alert) => {
+                alert.value = *value;
+                if alert.state == AlertState::Pending
+                    && time >= alert.active_since + rule.for_duration
+                {
+                    alert.state = AlertState::Firing;
+                    alert.fired_at = Some(time);
+                }
+            }
+            None => {
+                let firing = rule.for_duration == 0;
+                alerts.push(Alert {
+                    rule: rule.name.clone(),
+                    labels: labels.clone(),
+                    severity: rule.severity.clone(),
+                    state: if firing {
+                        AlertState::Firing
+                    } else {
+                        AlertState::Pending
+                    },
+                    value: *value,
+                    active_since: time,
+                    fired_at: if firing { Some(time) } else { None },
+                    resolved_at: None,
+                });
+            }
+        }
+    }
+    alerts.retain_mut(|alert| {
+        if alert.rule != rule.name || active.contains_key(&alert.labels) {
+            return true;
+        }
+        match alert.state {
+            AlertState::Pending => false,
+            AlertState::Firing => {
+                alert.state = AlertState::Resolved;
+                alert.resolved_at = Some(time);
+                true
+            }
+            AlertState::Resolved => true,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::test_data::{jobs, scrape};
+
+    const RULES: &str = r#"
+rules:
+  - name: QueueTooLong
+    expr: jobs
+    comparator: ">"
+    threshold: 10
+    for: 20s
+    severity: critical
+  - name: QueueEmpty
+    expr: jobs == 0
+    comparator: "=="
+    threshold: 0
+"#;
+
+    fn states(alerting: &Alerting) -> Vec<(String, String, AlertState)> {
+        alerting
+            .alerts()
+            .into_iter()
+            .map(|alert| (alert.rule.clone(), alert.labels.clone(), alert.state))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let alerting = Alerting::parse(RULES).unwrap();
+        assert_eq!(alerting.rules.len(), 2);
+        assert_eq!(alerting.rules[0].for_duration, 20);
+        assert_eq!(
+            alerting.rules[0].threshold,
+            Some((Comparator::Greater, 10.0))
+        );
+        assert_eq!(alerting.rules[1].severity, "warning");
+        assert_eq!(alerting.rules[1].for_duration, 0);
+
+        let invalid_expr = RULES.replace("expr: jobs\n", "expr: rate(jobs)\n");
+        assert!(Alerting::parse(&invalid_expr).is_err());
+        let invalid_for = RULES.replace("for: 20s", "for: soon");
+        assert!(Alerting::parse(&invalid_for).is_err());
+        let invalid_comparator = RULES.replace("\">\"", "\"=>\"");
+        assert!(Alerting::parse(&invalid_comparator).is_err());
+    }
+
+    #[test]
+    fn test_pending_firing_and_resolved() {
+        let mut alerting = Alerting::parse(RULES).unwrap();
+        let mut history = MetricHistory::new();
+        let firing = |labels: &str| {
+            (
+                String::from("QueueTooLong"),
+                labels.to_string(),
+                AlertState::Firing,
+            )
+        };
+        let pending = |labels: &str| (firing(labels).0, labels.to_string(), AlertState::Pending);
+
+        scrape(&mut history, 1000, &jobs(&[20.0, 5.0]));
+        alerting.evaluate(&history);
+        assert_eq!(states(&alerting), vec![pending("queue=\"a\"")]);
+
+        // a pending alert is dropped when the condition stops holding
+        scrape(&mut history, 1010, &jobs(&[20.0, 30.0]));
+        alerting.evaluate(&history);
+        scrape(&mut history, 1020, &jobs(&[20.0, 5.0]));
+        alerting.evaluate(&history);
+        // evaluated once per scrape
+        alerting.evaluate(&history);
+        assert_eq!(states(&alerting), vec![firing("queue=\"a\"")]);
+        assert_eq!(alerting.alerts()[0].fired_at, Some(1020));
+        assert_eq!(alerting.count(AlertState::Firing), 1);
+
+        scrape(&mut history, 1030, &jobs(&[3.0, 0.0]));
+        alerting.evaluate(&history);
+        assert_eq!(
+            states(&alerting),
+            vec![
+                (
+                    String::from("QueueEmpty"),
+                    String::from("queue=\"b\""),
+                    AlertState::Firing
+                ),
+                (
+                    String::from("QueueTooLong"),
+                    String::from("queue=\"a\""),
+                    AlertState::Resolved
+                ),
+            ]
+        );
+
+        // resolved alerts are kept for a while
+        scrape(&mut history, 1030 + RESOLVED_RETENTION, &jobs(&[3.0, 1.0]));
+        alerting.evaluate(&history);
+        assert_eq!(
+            states(&alerting),
+            vec![(
+                String::from("QueueEmpty"),
+                String::from("queue=\"b\""),
+                AlertState::Resolved
+            )]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+
+use super::counter::{counter_points, sample_points, CounterValue, CounterView};
+use super::model::{Metric, MetricType, Sample};
+
+/// The smallest distance from the mean which is unusual, relative to the mean. A series which
+/// was constant until then is not flagged for a tiny change.
+const MIN_RELATIVE_DEVIATION: f64 = 0.1;
+/// The smallest unusual distance from a mean of zero, e.g. an idle counter ticking once is not.
+const MIN_DEVIATION: f64 = 1.0;
+
+/// Sensitivity of the anomaly detection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnomalyConfig {
+    /// number of preceding samples the mean and the standard deviation are computed over
+    pub window: usize,
+    /// distance from the mean, in standard deviations, beyond which a sample is unusual
+    pub sigmas: f64,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            window: 20,
+            sigmas: 3.0,
+        }
+    }
+}
+
+impl AnomalyConfig {
+    /// The number of latest samples in which an anomaly counts as active. A level shift is
+    /// only recognized once half a window of samples follows it.
+    fn recent_samples(&self) -> usize {
+        self.window / 2 + 1
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnomalyKind {
+    /// a single sample far from the rolling mean of the preceding samples
+    Outlier,
+    /// the first sample of a lasting change of the level
+    LevelShift,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    pub timestamp: u64,
+    pub value: f64,
+    pub kind: AnomalyKind,
+}
+
+/// The unusual points, in the order of time. A point is an outlier when it is more than
+/// `sigmas` standard deviations away from the mean of the `window` points before it, and starts
+/// a level shift when the mean of the next half window is that far from the mean of the
+/// previous half window, while the points after it stay close to their own mean.
+pub fn detect_anomalies(points: &[(u64, f64)], config: &AnomalyConfig) -> Vec<Anomaly> {
+    let mut anomalies: BTreeMap<usize, AnomalyKind> = BTreeMap::new();
+    let window = config.window.max(2);
+    for index in window..points.len() {
+        let (mean, stddev) = mean_and_stddev(&points[index - window..index]);
+        if is_far(points[index].1 - mean, mean, stddev, config.sigmas) {
+            anomalies.insert(index, AnomalyKind::Outlier);
+        }
+    }
+    let half = (window / 2).max(2);
+    let mut index = half;
+    while index + half <= points.len() {
+        let (mean_before, stddev_before) = mean_and_stddev(&points[index - half..index]);
+        let (mean_after, stddev_after) = mean_and_stddev(&points[index..index + half]);
+        let shift = mean_after - mean_before;
+        if is_far(shift, mean_before, stddev_before, config.sigmas)
+            && is_far(shift, mean_before, stddev_after, config.sigmas)
+        {
+            anomalies.insert(index, AnomalyKind::LevelShift);
+            // the following points belong to the same shift
+            index += half;
+        } else {
+            index += 1;
+        }
+    }
+    anomalies
+        .into_iter()
+        .map(|(index, kind)| Anomaly {
+            timestamp: points[index].0,
+            value: points[index].1,
+            kind,
+        })
+        .collect()
+}
+
+/// The points anomalies are looked for in: the value of gauges, the increase per scrape of
+/// counters and the observations per scrape of histograms.
+pub fn anomaly_points<I>(samples: I, metric_type: &MetricType) -> Vec<(u64, f64)>
+where
+    I: IntoIterator<Item = Sample>,
+{
+    match metric_type {
+        MetricType::Gauge => sample_points(samples, CounterValue::Value),
+        MetricType::Counter => counter_points(
+            &sample_points(samples, CounterValue::Value),
+            CounterView::Delta,
+            0,
+        ),
+        MetricType::Histogram => counter_points(
+            &sample_points(samples, CounterValue::HistogramCount),
+            CounterView::Delta,
+            0,
+        ),
+    }
+}
+
+/// True if one of the latest samples of a time series of the metric is unusual.
+pub fn has_active_anomaly(metric: &Metric, config: &AnomalyConfig) -> bool {
+    let recent = config.recent_samples();
+    // only the samples needed to judge the latest points are decoded, counters and histograms
+    // need one more for their first delta
+    let needed = recent
+        + config.window.max(2)
+        + match metric.details.metric_type {
+            MetricType::Gauge => 0,
+            MetricType::Counter | MetricType::Histogram => 1,
+        };
+    metric.time_series().values().any(|time_series| {
+        let samples = &time_series.samples;
+        let points = match samples.get(samples.len().saturating_sub(needed)) {
+            Some(first) => anomaly_points(
+                samples.iter_since(first.timestamp()),
+                &metric.details.metric_type,
+            ),
+            None => return false,
+        };
+        match (
+            detect_anomalies(&points, config).last(),
+            points.len().checked_sub(recent),
+        ) {
+            (Some(anomaly), Some(start)) => anomaly.timestamp >= points[start].0,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    })
+}
+
+pub(super) fn mean_and_stddev(points: &[(u64, f64)]) -> (f64, f64) {
+    let count = points.len() as f64;
+    let mean = points.iter().map(|(_, value)| value).sum::<f64>() / count;
+    let variance = points
+        .iter()
+        .map(|(_, value)| (value - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    (mean, variance.sqrt())
+}
+
+/// Beyond `sigmas` standard deviations and the minimum deviation from the mean.
+fn is_far(distance: f64, mean: f64, stddev: f64, sigmas: f64) -> bool {
+    let min_deviation = if mean == 0.0 {
+        MIN_DEVIATION
+    } else {
+        MIN_RELATIVE_DEVIATION * mean.abs()
+    };
+    distance.abs() > (sigmas * stddev).max(min_deviation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::model::MetricHistory;
+    use crate::prom::test_data::{exposition, scrape};
+
+    fn points(values: &[f64]) -> Vec<(u64, f64)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (1000 + index as u64 * 10, *value))
+            .collect()
+    }
+
+    fn noisy(count: usize, level: f64) -> Vec<f64> {
+        (0..count)
+            .map(|index| level + if index % 2 == 0 { 1.0 } else { -1.0 })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_outlier() {
+        let config = AnomalyConfig {
+            window: 6,
+            sigmas: 3.0,
+        };
+        let mut values = noisy(10, 50.0);
+        values[8] = 60.0;
+        let anomalies = detect_anomalies(&points(&values), &config);
+        assert_eq!(
+            anomalies,
+            vec![Anomaly {
+                timestamp: 1080,
+                value: 60.0,
+                kind: AnomalyKind::Outlier
+            }]
+        );
+        // within a wider band, the spike is normal
+        let relaxed = AnomalyConfig {
+            window: 6,
+            sigmas: 10.0,
+        };
+        assert!(detect_anomalies(&points(&values), &relaxed).is_empty());
+    }
+
+    #[test]
+    fn test_detect_level_shift() {
+        let config = AnomalyConfig {
+            window: 6,
+            sigmas: 3.0,
+        };
+        let mut values = noisy(10, 50.0);
+        values.extend(noisy(10, 80.0));
+        let anomalies = detect_anomalies(&points(&values), &config);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].timestamp, 1100);
+        assert_eq!(anomalies[0].kind, AnomalyKind::LevelShift);
+        assert!(detect_anomalies(&points(&noisy(30, 50.0)), &config).is_empty());
+    }
+
+    #[test]
+    fn test_minimum_deviation() {
+        let config = AnomalyConfig {
+            window: 6,
+            sigmas: 3.0,
+        };
+        // an idle counter ticking once
+        let mut increases = vec![0.0; 10];
+        increases[8] = 1.0;
+        assert!(detect_anomalies(&points(&increases), &config).is_empty());
+        increases[8] = 5.0;
+        assert_eq!(detect_anomalies(&points(&increases), &config).len(), 1);
+        // a constant gauge moving by less than a tenth
+        let mut values = vec![50.0; 10];
+        values[8] = 52.0;
+        assert!(detect_anomalies(&points(&values), &config).is_empty());
+        values[8] = 60.0;
+        assert_eq!(detect_anomalies(&points(&values), &config).len(), 1);
+    }
+
+    #[test]
+    fn test_active_anomaly_of_metric() {
+        let config = AnomalyConfig {
+            window: 4,
+            sigmas: 3.0,
+        };
+        let mut history = MetricHistory::new();
+        let increases = [10, 10, 10, 10, 10, 10, 10, 10, 100, 10, 10, 10, 10, 10];
+        let mut total = 0;
+        for (index, increase) in increases.iter().enumerate() {
+            total += increase;
+            let body = exposition("jobs_total", "counter", &[("", total as f64)]);
+            scrape(&mut history, index as u64 * 10, &body);
+            let metric = history.get_metric("jobs_total").unwrap();
+            // the spike stays active for the latest three scrapes
+            assert_eq!(
+                has_active_anomaly(metric, &config),
+                (8..11).contains(&index),
+                "at scrape {}",
+                index
+            );
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use super::{
+    metric_querier::{PrometheusClient, QueryResult, RangeSeries},
+    model::{Bucket, HistogramValueSample, MetricHistory, MetricType, Sample, SingleValueSample},
+};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::{sync::Semaphore, task};
+
+/// Number of metrics whose history is loaded from Prometheus at the same time.
+const CONCURRENT_METRICS: usize = 8;
+
+/// Where and how far back the history of the scraped metrics is loaded from at startup.
+#[derive(Clone, Debug)]
+pub struct BackfillConfig {
+    /// base url of the Prometheus server scraping the same target
+    pub url: String,
+    /// number of seconds loaded before the first live scrape
+    pub lookback: u64,
+    /// label matchers selecting the target in Prometheus, e.g. `job="api",instance="host:8080"`
+    pub selector: Option<String>,
+}
+
+/// A time series discovered on the target: its key in the history and its labels.
+struct LocalSeries {
+    key: String,
+    labels: HashMap<String, String>,
+}
+
+/// The samples loaded from Prometheus for the metrics scraped so far.
+pub struct Backfill {
+    live_since: u64,
+    samples: Vec<(String, HashMap<String, Vec<Sample>>)>,
+    /// the error of the last metric which could not be loaded
+    error: Option<anyhow::Error>,
+}
+
+impl Backfill {
+    /// Inserts the loaded samples in front of the scraped ones. The start of the live data is
+    /// recorded in `MetricHistory::live_since`. Fails with the error of the last metric which
+    /// could not be loaded, after inserting the others.
+    pub fn apply(self, history: &mut MetricHistory) -> anyhow::Result<()> {
+        for (name, samples_per_key) in self.samples {
+            if let Some(metric) = history.metrics.get_mut(&name) {
+                let metric = Arc::make_mut(metric);
+                for (key, samples) in samples_per_key {
+                    if let Some(time_series) = metric.time_series_mut(&key) {
+                        time_series.prepend_samples(samples);
+                    }
+                }
+            }
+        }
+        history.live_since = Some(self.live_since);
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Loads the samples preceding the first live scrape of the metrics in `history` from
+/// Prometheus, querying `CONCURRENT_METRICS` metrics at a time.
+pub async fn load_backfill(
+    history: &MetricHistory,
+    config: &BackfillConfig,
+    step: u64,
+) -> anyhow::Result<Backfill> {
+    let (live_since, metrics) = discover_metrics(history)?;
+    let end = live_since.saturating_sub(1);
+    let start = live_since.saturating_sub(config.lookback);
+    log::info!(
+        "Backfilling {} metrics from {} between {} and {}",
+        metrics.len(),
+        config.url,
+        start,
+        end
+    );
+
+    let range = Arc::new(QueryRange {
+        client: PrometheusClient::new(&config.url),
+        selector: config.selector.clone(),
+        start,
+        end,
+        step: step.max(1),
+    });
+    let permits = Arc::new(Semaphore::new(CONCURRENT_METRICS));
+    let tasks: Vec<_> = metrics
+        .into_iter()
+        .map(|(name, metric_type, local_series)| {
+            let range = Arc::clone(&range);
+            let permits = Arc::clone(&permits);
+            task::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let samples_per_key = match metric_type {
+                    MetricType::Histogram => backfill_histogram(&range, &name, &local_series).await,
+                    _ => backfill_single_value(&range, &name, &metric_type, &local_series).await,
+                };
+                (name, samples_per_key)
+            })
+        })
+        .collect();
+
+    let mut backfill = Backfill {
+        live_since,
+        samples: Vec::new(),
+        error: None,
+    };
+    for task in tasks {
+        match task.await? {
+            (name, Ok(samples_per_key)) => backfill.samples.push((name, samples_per_key)),
+            (name, Err(err)) => {
+                log::error!("Not able to backfill the metric '{}': {}", name, err);
+                backfill.error = Some(err);
+            }
+        }
+    }
+    Ok(backfill)
+}
+
+type DiscoveredMetrics = Vec<(String, MetricType, Vec<LocalSeries>)>;
+
+/// Returns the timestamp of the first live sample and the metrics scraped so far.
+fn discover_metrics(history: &MetricHistory) -> anyhow::Result<(u64, DiscoveredMetrics)> {
+    let live_since = history
+        .metrics
+        .values()
+        .flat_map(|metric| metric.time_series().values())
+        .filter_map(|time_series| time_series.samples.first_timestamp())
+        .min()
+        .ok_or_else(|| anyhow::anyhow!("no metrics scraped yet to backfill"))?;
+    let metrics = history
+        .metrics
+        .values()
+        .map(|metric| {
+            let local_series = metric
+                .time_series()
+                .iter()
+                .map(|(key, time_series)| LocalSeries {
+                    key: key.clone(),
+                    // series without labels are stored with a placeholder label
+                    labels: if key.contains('=') {
+                        time_series.labels.as_ref().clone()
+                    } else {
+                        HashMap::new()
+                    },
+                })
+                .collect();
+            (
+                metric.details.name.clone(),
+                metric.details.metric_type.clone(),
+                local_series,
+            )
+        })
+        .collect();
+    Ok((live_since, metrics))
+}
+
+struct QueryRange {
+    client: PrometheusClient,
+    selector: Option<String>,
+    start: u64,
+    end: u64,
+    step: u64,
+}
+
+impl QueryRange {
+    async fn series(&self, name: &str) -> anyhow::Result<Vec<RangeSeries>> {
+        let query = match &self.selector {
+            Some(selector) => format!("{}{{{}}}", name, selector),
+            None => name.to_string(),
+        };
+        match self
+            .client
+            .query_range(&query, self.start, self.end, self.step)
+            .await?
+        {
+            QueryResult::Matrix(series) => Ok(series),
+            other => Err(anyhow::anyhow!(
+                "unexpected result for the range query '{}': {:?}",
+                query,
+                other
+            )),
+        }
+    }
+}
+
+/// Finds the series returned by Prometheus for a series of the target. Prometheus adds the target
+/// labels (e.g. `instance` and `job`), so the local labels only need to be part of the remote ones.
+fn find_remote_series<'a, T>(
+    local: &LocalSeries,
+    remote: &'a [(BTreeMap<String, String>, T)],
+) -> Option<&'a T> {
+    let candidates: Vec<&'a T> = remote
+        .iter()
+        .filter(|(labels, _)| {
+            local
+                .labels
+                .iter()
+                .all(|(name, value)| labels.get(name) == Some(value))
+        })
+        .map(|(_, series)| series)
+        .collect();
+    match candidates.len() {
+        1 => candidates.into_iter().next(),
+        0 => None,
+        _ => {
+            log::warn!(
+                "several Prometheus series match '{}', use a backfill selector to pick the target",
+                local.key
+            );
+            None
+        }
+    }
+}
+
+fn without_labels(labels: &BTreeMap<String, String>, names: &[&str]) -> BTreeMap<String, String> {
+    labels
+        .iter()
+        .filter(|(name, _)| !names.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+fn parse_samples(series: &RangeSeries) -> Vec<SingleValueSample> {
+    series
+        .values
+        .iter()
+        .filter_map(|sample| {
+            sample.1.parse::<f64>().ok().map(|value| SingleValueSample {
+                timestamp: sample.0 as u64,
+                value,
+            })
+        })
+        .collect()
+}
+
+async fn backfill_single_value(
+    range: &QueryRange,
+    name: &str,
+    metric_type: &MetricType,
+    local_series: &[LocalSeries],
+) -> anyhow::Result<HashMap<String, Vec<Sample>>> {
+    let remote: Vec<(BTreeMap<String, String>, Vec<SingleValueSample>)> = range
+        .series(name)
+        .await?
+        .iter()
+        .map(|series| {
+            (
+                without_labels(&series.metric, &["__name__"]),
+                parse_samples(series),
+            )
+        })
+        .collect();
+    Ok(local_series
+        .iter()
+        .filter_map(|local| {
+            find_remote_series(local, &remote).map(|samples| {
+                let samples = samples
+                    .iter()
+                    .cloned()
+                    .map(|sample| match metric_type {
+                        MetricType::Counter => Sample::CounterSample(sample),
+                        _ => Sample::GaugeSample(sample),
+                    })
+                    .collect();
+                (local.key.clone(), samples)
+            })
+        })
+        .collect())
+}
+
+/// Rebuilds the histogram samples from the `_bucket`, `_sum` and `_count` series.
+async fn backfill_histogram(
+    range: &QueryRange,
+    name: &str,
+    local_series: &[LocalSeries],
+) -> anyhow::Result<HashMap<String, Vec<Sample>>> {
+    let buckets = range.series(&format!("{}_bucket", name)).await?;
+    let sums = range.series(&format!("{}_sum", name)).await?;
+    let counts = range.series(&format!("{}_count", name)).await?;
+
+    // bucket values per timestamp, grouped by the labels of the histogram
+    let mut histograms: BTreeMap<BTreeMap<String, String>, BTreeMap<u64, Vec<Bucket>>> =
+        BTreeMap::new();
+    for series in &buckets {
+        let le = match series.metric.get("le") {
+            Some(le) => le.clone(),
+            None => continue,
+        };
+        let per_timestamp = histograms
+            .entry(without_labels(&series.metric, &["__name__", "le"]))
+            .or_default();
+        for sample in parse_samples(series) {
+            per_timestamp
+                .entry(sample.timestamp)
+                .or_default()
+                .push(Bucket::new(le.clone(), sample.value as u64));
+        }
+    }
+    let single_values =
+        |series: &[RangeSeries]| -> HashMap<BTreeMap<String, String>, HashMap<u64, f64>> {
+            series
+                .iter()
+                .map(|series| {
+                    (
+                        without_labels(&series.metric, &["__name__"]),
+                        parse_samples(series)
+                            .into_iter()
+                            .map(|sample| (sample.timestamp, sample.value))
+                            .collect(),
+                    )
+                })
+                .collect()
+        };
+    let sums = single_values(&sums);
+    let counts = single_values(&counts);
+
+    let remote: Vec<(BTreeMap<String, String>, Vec<Sample>)> = histograms
+        .into_iter()
+        .map(|(labels, per_timestamp)| {
+            let samples = per_timestamp
+                .into_iter()
+                .filter_map(|(timestamp, mut bucket_values)| {
+                    let count = counts.get(&labels)?.get(&timestamp)?;
+                    let sum = sums.get(&labels)?.get(&timestamp)?;
+                    bucket_values.sort_by(|a, b| {
+                        let le = |bucket: &Bucket| bucket.name.parse::<f64>().unwrap_or(f64::MAX);
+                        le(a).total_cmp(&le(b))
+                    });
+                    Some(Sample::HistogramSample(HistogramValueSample {
+                        timestamp,
+                        bucket_values,
+                        sum: *sum,
+                        count: *count as u64,
+                    }))
+                })
+                .collect();
+            (labels, samples)
+        })
+        .collect();
+    Ok(local_series
+        .iter()
+        .filter_map(|local| {
+            find_remote_series(local, &remote).map(|samples| (local.key.clone(), samples.clone()))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::{
+        metric_querier::tests::start_mock_server,
+        test_data::{generate_metric_lines, history_of},
+    };
+
+    fn scraped_history(timestamp: u64) -> MetricHistory {
+        history_of([(timestamp, generate_metric_lines().join("\n"))])
+    }
+
+    fn matrix(series: &[&str]) -> String {
+        format!(
+            r#"{{"status":"success","data":{{"resultType":"matrix","result":[{}]}}}}"#,
+            series.join(",")
+        )
+    }
+
+    #[tokio::test]
+    async fn test_backfill_history() {
+        let empty = matrix(&[]);
+        let url = start_mock_server(vec![
+            (
+                "/api/v1/query_range?query=metric_1&",
+                matrix(&[
+                    r#"{"metric":{"__name__":"metric_1","instance":"host:8080","job":"api","shard":"0"},"values":[[1657000000,"7"],[1657000010,"8"],[1657000020,"9"]]}"#,
+                ]),
+            ),
+            (
+                "/api/v1/query_range?query=response_time_bucket",
+                matrix(&[
+                    r#"{"metric":{"__name__":"response_time_bucket","env":"testing","job":"api","le":"+Inf"},"values":[[1657000010,"5"]]}"#,
+                    r#"{"metric":{"__name__":"response_time_bucket","env":"testing","job":"api","le":"0.5"},"values":[[1657000010,"2"]]}"#,
+                ]),
+            ),
+            (
+                "/api/v1/query_range?query=response_time_sum",
+                matrix(&[
+                    r#"{"metric":{"__name__":"response_time_sum","env":"testing","job":"api"},"values":[[1657000010,"1.5"]]}"#,
+                ]),
+            ),
+            (
+                "/api/v1/query_range?query=response_time_count",
+                matrix(&[
+                    r#"{"metric":{"__name__":"response_time_count","env":"testing","job":"api"},"values":[[1657000010,"5"]]}"#,
+                ]),
+            ),
+            ("/api/v1/query_range", empty),
+        ])
+        .await;
+
+        let mut history = scraped_history(1657000020);
+        let config = BackfillConfig {
+            url,
+            lookback: 3600,
+            selector: None,
+        };
+        load_backfill(&history, &config, 10)
+            .await
+            .unwrap()
+            .apply(&mut history)
+            .unwrap();
+
+        assert_eq!(history.live_since, Some(1657000020));
+        let samples = &history.get_metric("metric_1").unwrap().time_series()["shard=\"0\""].samples;
+        // the sample at the time of the live scrape is not duplicated
+        let timestamps: Vec<u64> = samples.iter().map(|sample| sample.timestamp()).collect();
+        assert_eq!(timestamps, vec![1657000000, 1657000010, 1657000020]);
+
+        let samples =
+            &history.get_metric("response_time").unwrap().time_series()["env=\"testing\""].samples;
+        assert_eq!(samples.len(), 2);
+        match &samples.get(0).unwrap() {
+            Sample::HistogramSample(histogram) => {
+                assert_eq!(histogram.timestamp, 1657000010);
+                assert_eq!(histogram.count, 5);
+                assert_eq!(histogram.sum, 1.5);
+                assert_eq!(
+                    histogram.bucket_values,
+                    vec![
+                        Bucket::new("0.5".to_string(), 2),
+                        Bucket::new("+Inf".to_string(), 5)
+                    ]
+                );
+            }
+            _ => panic!("expected a histogram sample"),
+        }
+        // metrics unknown to Prometheus keep their live samples only
+        let samples = &history
+            .get_metric("connected_clients")
+            .unwrap()
+            .time_series()["single-value-with-no-labels"]
+            .samples;
+        assert_eq!(samples.len(), 1);
+    }
+
+    #[test]
+    fn test_find_remote_series_is_ambiguous_without_selector() {
+        let local = LocalSeries {
+            key: "shard=\"0\"".to_string(),
+            labels: HashMap::from([("shard".to_string(), "0".to_string())]),
+        };
+        let remote = vec![
+            (
+                BTreeMap::from([
+                    ("instance".to_string(), "a".to_string()),
+                    ("shard".to_string(), "0".to_string()),
+                ]),
+                1,
+            ),
+            (
+                BTreeMap::from([
+                    ("instance".to_string(), "b".to_string()),
+                    ("shard".to_string(), "0".to_string()),
+                ]),
+                2,
+            ),
+            (
+                BTreeMap::from([
+                    ("instance".to_string(), "a".to_string()),
+                    ("shard".to_string(), "1".to_string()),
+                ]),
+                3,
+            ),
+        ];
+        assert_eq!(find_remote_series(&local, &remote), None);
+        assert_eq!(find_remote_series(&local, &remote[1..]), Some(&2));
+    }
+}
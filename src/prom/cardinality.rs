@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::model::{Metric, MetricHistory};
+
+/// Seconds before the latest sample the new series of a metric are counted in.
+pub const GROWTH_WINDOW: u64 = 5 * 60;
+
+/// The number of distinct values of a label of a metric.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelCardinality {
+    pub name: String,
+    pub values: usize,
+    /// the values carried by the most series, with their number of series
+    pub top_values: Vec<(String, usize)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricCardinality {
+    pub name: String,
+    pub series: usize,
+    /// the labels with the most distinct values first
+    pub labels: Vec<LabelCardinality>,
+    /// series which appeared within `GROWTH_WINDOW` before the latest sample of the history
+    pub new_series: usize,
+}
+
+impl MetricCardinality {
+    /// The label contributing most to the number of series.
+    pub fn top_label(&self) -> Option<&LabelCardinality> {
+        self.labels.first()
+    }
+}
+
+/// The number of series of every metric and of the values of their labels.
+pub fn cardinality(history: &MetricHistory) -> Vec<MetricCardinality> {
+    let growth_start = history
+        .latest_timestamp()
+        .map(|latest| latest.saturating_sub(GROWTH_WINDOW));
+    history
+        .metrics
+        .values()
+        .map(|metric| metric_cardinality(metric, growth_start))
+        .collect()
+}
+
+fn metric_cardinality(metric: &Metric, growth_start: Option<u64>) -> MetricCardinality {
+    let index = metric.label_index();
+    let mut labels: Vec<LabelCardinality> = index
+        .label_names()
+        .into_iter()
+        .map(|name| {
+            let mut values = index.label_values(name);
+            values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            LabelCardinality {
+                name: name.to_string(),
+                values: values.len(),
+                top_values: values
+                    .into_iter()
+                    .take(5)
+                    .map(|(value, series)| (value.to_string(), series))
+                    .collect(),
+            }
+        })
+        .collect();
+    labels.sort_by(|a, b| b.values.cmp(&a.values).then_with(|| a.name.cmp(&b.name)));
+    let firsts = || {
+        metric
+            .time_series()
+            .values()
+            .filter_map(|time_series| time_series.samples.first_timestamp())
+    };
+    // the series present since the first scrape of the metric are not new
+    let growth_start = growth_start.max(firsts().min());
+    let new_series = match growth_start {
+        Some(growth_start) => firsts().filter(|first| *first > growth_start).count(),
+        None => 0,
+    };
+    MetricCardinality {
+        name: metric.details.name.clone(),
+        series: metric.time_series().len(),
+        labels,
+        new_series,
+    }
+}
+
+/// The number of series of the metric after each timestamp a series first appeared at.
+pub fn series_growth(metric: &Metric) -> Vec<(u64, usize)> {
+    let mut firsts: Vec<u64> = metric
+        .time_series()
+        .values()
+        .filter_map(|time_series| time_series.samples.first_timestamp())
+        .collect();
+    firsts.sort_unstable();
+    let mut growth: Vec<(u64, usize)> = Vec::new();
+    for (index, first) in firsts.into_iter().enumerate() {
+        match growth.last_mut() {
+            Some((timestamp, count)) if *timestamp == first => *count = index + 1,
+            _ => growth.push((first, index + 1)),
+        }
+    }
+    growth
+}
+
+/// The column the cardinality of the metrics is sorted by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CardinalitySort {
+    Name,
+    Series,
+    Labels,
+    TopLabel,
+    NewSeries,
+}
+
+impl CardinalitySort {
+    pub fn next(self) -> Self {
+        match self {
+            CardinalitySort::Name => CardinalitySort::Series,
+            CardinalitySort::Series => CardinalitySort::Labels,
+            CardinalitySort::Labels => CardinalitySort::TopLabel,
+            CardinalitySort::TopLabel => CardinalitySort::NewSeries,
+            CardinalitySort::NewSeries => CardinalitySort::Name,
+        }
+    }
+
+    /// Sorts the metrics by the column, the largest first unless `ascending`. Equal rows are
+    /// ordered by name.
+    pub fn sort(self, metrics: &mut [MetricCardinality], ascending: bool) {
+        metrics.sort_by(|a, b| {
+            let ordering = match self {
+                CardinalitySort::Name => a.name.cmp(&b.name),
+                CardinalitySort::Series => a.series.cmp(&b.series),
+                CardinalitySort::Labels => a.labels.len().cmp(&b.labels.len()),
+                CardinalitySort::TopLabel => {
+                    let values = |metric: &MetricCardinality| {
+                        metric.top_label().map_or(0, |label| label.values)
+                    };
+                    values(a).cmp(&values(b))
+                }
+                CardinalitySort::NewSeries => a.new_series.cmp(&b.new_series),
+            };
+            let ordering = if ascending {
+                ordering
+            } else {
+                ordering.reverse()
+            };
+            match ordering {
+                Ordering::Equal => a.name.cmp(&b.name),
+                ordering => ordering,
+            }
+        });
+    }
+}
+
+impl fmt::Display for CardinalitySort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CardinalitySort::Name => "metric",
+            CardinalitySort::Series => "series",
+            CardinalitySort::Labels => "labels",
+            CardinalitySort::TopLabel => "top label",
+            CardinalitySort::NewSeries => "new series",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::test_data::{exposition, history_of};
+
+    fn body(users: &[&str]) -> String {
+        let labels: Vec<String> = users
+            .iter()
+            .flat_map(|user| {
+                ["200", "500"].map(|code| format!("code=\"{}\",user=\"{}\"", code, user))
+            })
+            .collect();
+        let series: Vec<(&str, f64)> = labels.iter().map(|labels| (labels.as_str(), 1.0)).collect();
+        exposition("requests", "gauge", &series) + &exposition("up", "gauge", &[("", 1.0)])
+    }
+
+    #[test]
+    fn test_cardinality_of_metrics() {
+        let history = history_of([
+            (1000, body(&["a", "b"])),
+            (1200, body(&["a", "b", "c"])),
+            (1400, body(&["a", "b", "c", "d"])),
+        ]);
+
+        let mut metrics = cardinality(&history);
+        CardinalitySort::Series.sort(&mut metrics, false);
+        let requests = &metrics[0];
+        assert_eq!(
+            (requests.name.as_str(), requests.series, requests.new_series),
+            ("requests", 8, 4)
+        );
+        let labels: Vec<(&str, usize)> = requests
+            .labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.values))
+            .collect();
+        assert_eq!(labels, vec![("user", 4), ("code", 2)]);
+        assert_eq!(
+            requests.top_label().unwrap().top_values[0],
+            (String::from("a"), 2)
+        );
+        // the series without labels has no label values
+        assert_eq!((metrics[1].series, metrics[1].labels.len()), (1, 0));
+
+        CardinalitySort::Name.sort(&mut metrics, true);
+        assert_eq!(metrics[0].name, "requests");
+        CardinalitySort::TopLabel.sort(&mut metrics, true);
+        assert_eq!(metrics[0].name, "up");
+
+        assert_eq!(
+            series_growth(&history.metrics["requests"]),
+            vec![(1000, 4), (1200, 6), (1400, 8)]
+        );
+    }
+}
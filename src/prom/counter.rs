@@ -0,0 +1,181 @@
+use std::fmt;
+
+use super::model::Sample;
+use super::promql::functions::extrapolated_rate;
+
+/// How the samples of a counter are shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CounterView {
+    /// the monotonically increasing value as scraped
+    Raw,
+    /// the increase since the previous scrape
+    Delta,
+    /// the per-second rate over a window, like `rate()` in Prometheus
+    Rate,
+}
+
+impl CounterView {
+    pub fn next(self) -> Self {
+        match self {
+            CounterView::Raw => CounterView::Delta,
+            CounterView::Delta => CounterView::Rate,
+            CounterView::Rate => CounterView::Raw,
+        }
+    }
+}
+
+impl fmt::Display for CounterView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CounterView::Raw => "raw value",
+            CounterView::Delta => "delta per scrape",
+            CounterView::Rate => "rate per second",
+        })
+    }
+}
+
+/// The part of a sample a counter view applies to. Histograms count their observations and sum
+/// their values like counters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CounterValue {
+    Value,
+    HistogramCount,
+    HistogramSum,
+}
+
+/// The `(timestamp, value)` pairs of the samples.
+pub fn sample_points<I>(samples: I, value: CounterValue) -> Vec<(u64, f64)>
+where
+    I: IntoIterator<Item = Sample>,
+{
+    samples
+        .into_iter()
+        .map(|sample| match (sample, value) {
+            (Sample::GaugeSample(sample) | Sample::CounterSample(sample), _) => {
+                (sample.timestamp, sample.value)
+            }
+            (Sample::HistogramSample(histogram), CounterValue::HistogramSum) => {
+                (histogram.timestamp, histogram.sum)
+            }
+            (Sample::HistogramSample(histogram), _) => {
+                (histogram.timestamp, histogram.count as f64)
+            }
+        })
+        .collect()
+}
+
+/// Transforms the points of a counter for the view. A counter going down was reset, the delta
+/// is then the value since the reset. The rate at a sample is computed over the `rate_window`
+/// seconds before it, so the first samples of the history have no rate.
+pub fn counter_points(
+    points: &[(u64, f64)],
+    view: CounterView,
+    rate_window: u64,
+) -> Vec<(u64, f64)> {
+    match view {
+        CounterView::Raw => points.to_vec(),
+        CounterView::Delta => points
+            .windows(2)
+            .map(|pair| {
+                let (previous, (timestamp, value)) = (pair[0].1, pair[1]);
+                let delta = if value < previous {
+                    value
+                } else {
+                    value - previous
+                };
+                (timestamp, delta)
+            })
+            .collect(),
+        CounterView::Rate => points
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (timestamp, _))| {
+                let range_start = timestamp.saturating_sub(rate_window);
+                let start = points[..index].partition_point(|(t, _)| *t <= range_start);
+                extrapolated_rate(
+                    &points[start..=index],
+                    range_start as f64,
+                    *timestamp as f64,
+                    true,
+                    true,
+                )
+                .map(|rate| (*timestamp, rate))
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::model::{Bucket, HistogramValueSample, SingleValueSample};
+
+    /// Scraped every 10s, increasing by 5 per second, restarted after the fourth scrape.
+    const POINTS: [(u64, f64); 6] = [
+        (1000, 100.0),
+        (1010, 150.0),
+        (1020, 200.0),
+        (1030, 250.0),
+        (1040, 30.0),
+        (1050, 80.0),
+    ];
+
+    #[test]
+    fn test_counter_delta() {
+        assert_eq!(
+            counter_points(&POINTS, CounterView::Raw, 30),
+            POINTS.to_vec()
+        );
+        assert_eq!(
+            counter_points(&POINTS, CounterView::Delta, 30),
+            vec![
+                (1010, 50.0),
+                (1020, 50.0),
+                (1030, 50.0),
+                (1040, 30.0),
+                (1050, 50.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_counter_rate() {
+        let rates = counter_points(&POINTS, CounterView::Rate, 30);
+        let timestamps: Vec<u64> = rates.iter().map(|(timestamp, _)| *timestamp).collect();
+        assert_eq!(timestamps, vec![1010, 1020, 1030, 1040, 1050]);
+        // the window of the first rate is not covered by samples, it is extrapolated by half a
+        // scrape interval only
+        assert_eq!(rates[0].1, 2.5);
+        assert_eq!(rates[1].1, 5.0);
+        assert_eq!(rates[2].1, 5.0);
+        // the reset is compensated, only the increase between the last scrape and the restart
+        // is missing
+        assert_eq!(rates[3].1, 4.0);
+        assert_eq!(rates[4].1, 4.0);
+    }
+
+    #[test]
+    fn test_histogram_sample_points() {
+        let samples = [10, 20].map(|timestamp| {
+            Sample::HistogramSample(HistogramValueSample {
+                timestamp,
+                bucket_values: vec![Bucket::new(String::from("+Inf"), timestamp)],
+                sum: timestamp as f64 / 2.0,
+                count: timestamp,
+            })
+        });
+        assert_eq!(
+            sample_points(samples.clone(), CounterValue::HistogramCount),
+            vec![(10, 10.0), (20, 20.0)]
+        );
+        assert_eq!(
+            sample_points(samples, CounterValue::HistogramSum),
+            vec![(10, 5.0), (20, 10.0)]
+        );
+        let gauge = Sample::GaugeSample(SingleValueSample {
+            timestamp: 5,
+            value: 1.5,
+        });
+        assert_eq!(sample_points([gauge], CounterValue::Value), vec![(5, 1.5)]);
+    }
+}
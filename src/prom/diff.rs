@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::counter::{counter_points, sample_points, CounterValue, CounterView};
+use super::model::{Metric, MetricHistory, MetricType, Sample};
+use super::stats::average_rate;
+use super::storage::SampleStorage;
+
+/// Which of the compared histories a metric or a time series was found in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Presence {
+    Both,
+    OnlyBaseline,
+    OnlyCandidate,
+}
+
+impl Presence {
+    fn of<T>(baseline: Option<T>, candidate: Option<T>) -> Self {
+        match (baseline, candidate) {
+            (Some(_), None) => Presence::OnlyBaseline,
+            (None, Some(_)) => Presence::OnlyCandidate,
+            _ => Presence::Both,
+        }
+    }
+}
+
+impl fmt::Display for Presence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Presence::Both => "both",
+            Presence::OnlyBaseline => "baseline only",
+            Presence::OnlyCandidate => "candidate only",
+        })
+    }
+}
+
+/// A time series aligned by its labels, with the values compared, see `comparable_value`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeriesDiff {
+    pub key: String,
+    pub presence: Presence,
+    pub baseline: Option<f64>,
+    pub candidate: Option<f64>,
+}
+
+impl SeriesDiff {
+    pub fn difference(&self) -> Option<f64> {
+        Some(self.candidate? - self.baseline?)
+    }
+
+    /// The change relative to the baseline, in percent. None for a baseline of zero.
+    pub fn percent_change(&self) -> Option<f64> {
+        let baseline = self.baseline?;
+        if baseline == 0.0 {
+            return None;
+        }
+        Some(self.difference()? / baseline.abs() * 100.0)
+    }
+}
+
+/// A metric aligned by its name, with its time series ordered by key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricDiff {
+    pub name: String,
+    pub presence: Presence,
+    pub series: Vec<SeriesDiff>,
+}
+
+impl MetricDiff {
+    /// The largest change in percent among the series found in both histories.
+    pub fn largest_change(&self) -> Option<f64> {
+        self.series
+            .iter()
+            .filter_map(SeriesDiff::percent_change)
+            .filter(|change| change.is_finite())
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+    }
+}
+
+/// Aligns the metrics of two histories, e.g. of the old and the new version of a service, by
+/// metric name and by the labels of their time series. The metrics are ordered by name.
+pub fn diff_histories(baseline: &MetricHistory, candidate: &MetricHistory) -> Vec<MetricDiff> {
+    let names: BTreeSet<&String> = baseline
+        .metrics
+        .keys()
+        .chain(candidate.metrics.keys())
+        .collect();
+    names
+        .into_iter()
+        .map(|name| {
+            let (baseline, candidate) = (baseline.get_metric(name), candidate.get_metric(name));
+            MetricDiff {
+                name: name.clone(),
+                presence: Presence::of(baseline, candidate),
+                series: diff_series(baseline, candidate),
+            }
+        })
+        .collect()
+}
+
+fn diff_series(baseline: Option<&Metric>, candidate: Option<&Metric>) -> Vec<SeriesDiff> {
+    let keys: BTreeSet<&String> = baseline
+        .into_iter()
+        .chain(candidate)
+        .flat_map(|metric| metric.time_series().keys())
+        .collect();
+    let value = |metric: Option<&Metric>, key: &str| {
+        let metric = metric?;
+        metric
+            .time_series()
+            .get(key)
+            .map(|time_series| comparable_value(&time_series.samples, &metric.details.metric_type))
+    };
+    keys.into_iter()
+        .map(|key| {
+            let (baseline, candidate) = (value(baseline, key), value(candidate, key));
+            SeriesDiff {
+                key: key.clone(),
+                presence: Presence::of(baseline, candidate),
+                baseline: baseline.flatten(),
+                candidate: candidate.flatten(),
+            }
+        })
+        .collect()
+}
+
+/// The value a time series is compared by: the latest value of a gauge, the average rate per
+/// second of a counter and the average observation of a histogram over the history. Unlike
+/// their totals, these don't depend on how long the processes have been running.
+pub fn comparable_value(samples: &SampleStorage, metric_type: &MetricType) -> Option<f64> {
+    match metric_type {
+        MetricType::Gauge => sample_points(samples, CounterValue::Value)
+            .last()
+            .map(|(_, value)| *value),
+        MetricType::Counter => average_rate(&sample_points(samples, CounterValue::Value)),
+        MetricType::Histogram => {
+            let increase = |value| -> f64 {
+                counter_points(&sample_points(samples, value), CounterView::Delta, 0)
+                    .iter()
+                    .map(|(_, delta)| delta)
+                    .sum()
+            };
+            let count = increase(CounterValue::HistogramCount);
+            if count > 0.0 {
+                Some(increase(CounterValue::HistogramSum) / count)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// The points of a time series as they are compared in graphs: the value of a gauge, the rate
+/// per second of a counter and the average observation of a histogram between two scrapes.
+pub fn comparable_points<I>(samples: I, metric_type: &MetricType) -> Vec<(u64, f64)>
+where
+    I: IntoIterator<Item = Sample> + Clone,
+{
+    match metric_type {
+        MetricType::Gauge => sample_points(samples, CounterValue::Value),
+        MetricType::Counter => {
+            let points = sample_points(samples, CounterValue::Value);
+            points
+                .windows(2)
+                .zip(counter_points(&points, CounterView::Delta, 0))
+                .filter(|(pair, _)| pair[1].0 > pair[0].0)
+                .map(|(pair, (timestamp, delta))| {
+                    (timestamp, delta / (pair[1].0 - pair[0].0) as f64)
+                })
+                .collect()
+        }
+        MetricType::Histogram => {
+            let deltas = |value| {
+                counter_points(
+                    &sample_points(samples.clone(), value),
+                    CounterView::Delta,
+                    0,
+                )
+            };
+            deltas(CounterValue::HistogramCount)
+                .into_iter()
+                .zip(deltas(CounterValue::HistogramSum))
+                .filter(|((_, count), _)| *count > 0.0)
+                .map(|((timestamp, count), (_, sum))| (timestamp, sum / count))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::test_data::{exposition, history_of, jobs};
+
+    #[test]
+    fn test_diff_histories() {
+        let old = exposition("old", "gauge", &[("", 1.0)]);
+        let baseline = history_of([
+            (1000, jobs(&[10.0, 0.0]) + &old),
+            (1010, jobs(&[20.0, 0.0]) + &old),
+        ]);
+        let candidate = history_of([(
+            1010,
+            jobs(&[25.0, 3.0, 1.0]) + &exposition("new", "gauge", &[("", 1.0)]),
+        )]);
+
+        let diff = diff_histories(&baseline, &candidate);
+        let metrics: Vec<(&str, Presence)> = diff
+            .iter()
+            .map(|metric| (metric.name.as_str(), metric.presence))
+            .collect();
+        assert_eq!(
+            metrics,
+            vec![
+                ("jobs", Presence::Both),
+                ("new", Presence::OnlyCandidate),
+                ("old", Presence::OnlyBaseline)
+            ]
+        );
+
+        let jobs = &diff[0];
+        let series = |key: &str| jobs.series.iter().find(|series| series.key == key).unwrap();
+        let a = series("queue=\"a\"");
+        assert_eq!((a.baseline, a.candidate), (Some(20.0), Some(25.0)));
+        assert_eq!(a.difference(), Some(5.0));
+        assert_eq!(a.percent_change(), Some(25.0));
+        // no percentage of a zero baseline
+        let b = series("queue=\"b\"");
+        assert_eq!((b.difference(), b.percent_change()), (Some(3.0), None));
+        let c = series("queue=\"c\"");
+        assert_eq!(
+            (c.presence, c.difference()),
+            (Presence::OnlyCandidate, None)
+        );
+        assert_eq!(jobs.largest_change(), Some(25.0));
+    }
+
+    #[test]
+    fn test_comparable_values() {
+        let counter = |timestamp, value| {
+            (
+                timestamp,
+                format!(
+                    "# HELP jobs_total Jobs.\n# TYPE jobs_total counter\njobs_total {}\n# HELP latency Latency.\n# TYPE latency histogram\nlatency_bucket{{le=\"+Inf\"}} {}\nlatency_sum {}\nlatency_count {}\n",
+                    value,
+                    value,
+                    value * 2.0,
+                    value
+                ),
+            )
+        };
+        let history = history_of([
+            counter(1000, 10.0),
+            counter(1010, 30.0),
+            counter(1020, 40.0),
+        ]);
+
+        let jobs = &history.metrics["jobs_total"];
+        let samples = &jobs.time_series().values().next().unwrap().samples;
+        assert_eq!(comparable_value(samples, &MetricType::Counter), Some(1.5));
+        assert_eq!(
+            comparable_points(samples, &MetricType::Counter),
+            vec![(1010, 2.0), (1020, 1.0)]
+        );
+
+        let latency = &history.metrics["latency"];
+        let samples = &latency.time_series().values().next().unwrap().samples;
+        assert_eq!(comparable_value(samples, &MetricType::Histogram), Some(2.0));
+        assert_eq!(
+            comparable_points(samples, &MetricType::Histogram),
+            vec![(1010, 2.0), (1020, 2.0)]
+        );
+    }
+}
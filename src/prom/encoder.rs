@@ -0,0 +1,468 @@
+use super::model::{
+    HistogramValueSample, Metric, MetricHistory, MetricType, Sample, TimeSeries, NO_LABELS_KEY,
+};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Renders the latest sample of every time series in the Prometheus text format, like the
+/// endpoint exposed them at the last scrape. Metrics are ordered by name.
+pub fn encode_snapshot(history: &MetricHistory) -> String {
+    let mut output = String::new();
+    for metric in sorted_metrics(history) {
+        if !metric.details.docstring.is_empty() {
+            writeln!(
+                output,
+                "# HELP {} {}",
+                metric.details.name,
+                escape_help(&metric.details.docstring)
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "# TYPE {} {}",
+            metric.details.name,
+            metric.details.metric_type.name()
+        )
+        .unwrap();
+        for (key, time_series) in sorted_time_series(metric) {
+            if let Some(sample) = time_series.samples.last() {
+                write_sample(&mut output, &metric.details.name, key, sample, None);
+            }
+        }
+    }
+    output
+}
+
+/// Renders every sample of the history as OpenMetrics with explicit timestamps, which can be
+/// imported with `promtool tsdb create-blocks-from openmetrics`.
+///
+/// OpenMetrics names counter families without the `_total` suffix of their samples. Counters
+/// whose name does not end with `_total` are written as `unknown`, to keep their name.
+pub fn encode_openmetrics(history: &MetricHistory) -> String {
+    let mut output = String::new();
+    for metric in sorted_metrics(history) {
+        let name = &metric.details.name;
+        let (family, family_type) = match metric.details.metric_type {
+            MetricType::Counter => match name.strip_suffix("_total") {
+                Some(family) => (family, "counter"),
+                None => (name.as_str(), "unknown"),
+            },
+            MetricType::Gauge => (name.as_str(), "gauge"),
+            MetricType::Histogram => (name.as_str(), "histogram"),
+        };
+        writeln!(output, "# TYPE {} {}", family, family_type).unwrap();
+        if !metric.details.docstring.is_empty() {
+            writeln!(
+                output,
+                "# HELP {} {}",
+                family,
+                escape_help(&metric.details.docstring).replace('"', "\\\"")
+            )
+            .unwrap();
+        }
+        for (key, time_series) in sorted_time_series(metric) {
+            for sample in &time_series.samples {
+                write_sample(&mut output, name, key, &sample, Some(sample.timestamp()));
+            }
+        }
+    }
+    output.push_str("# EOF\n");
+    output
+}
+
+fn sorted_metrics(history: &MetricHistory) -> Vec<&Metric> {
+    let mut metrics: Vec<&Metric> = history.metrics.values().map(Arc::as_ref).collect();
+    metrics.sort_by(|a, b| a.details.name.cmp(&b.details.name));
+    metrics
+}
+
+fn sorted_time_series(metric: &Metric) -> Vec<(&str, &TimeSeries)> {
+    let mut time_series: Vec<(&str, &TimeSeries)> = metric
+        .time_series()
+        .iter()
+        .map(|(key, time_series)| (key.as_str(), time_series))
+        .collect();
+    time_series.sort_by_key(|(key, _)| *key);
+    time_series
+}
+
+/// Writes the lines of a sample. The labels key is the label string exposed by the endpoint, so
+/// it is written as is.
+fn write_sample(
+    output: &mut String,
+    name: &str,
+    key: &str,
+    sample: &Sample,
+    timestamp: Option<u64>,
+) {
+    let timestamp = timestamp
+        .map(|timestamp| format!(" {}", timestamp))
+        .unwrap_or_default();
+    let labels = if key == NO_LABELS_KEY {
+        String::new()
+    } else {
+        format!("{{{}}}", key)
+    };
+    match sample {
+        Sample::GaugeSample(sample) | Sample::CounterSample(sample) => {
+            writeln!(
+                output,
+                "{}{} {}{}",
+                name,
+                labels,
+                format_value(sample.value),
+                timestamp
+            )
+            .unwrap();
+        }
+        Sample::HistogramSample(HistogramValueSample {
+            bucket_values,
+            sum,
+            count,
+            ..
+        }) => {
+            for bucket in bucket_values {
+                let bucket_labels = if key == NO_LABELS_KEY {
+                    format!("{{le=\"{}\"}}", bucket.name)
+                } else {
+                    format!("{{{},le=\"{}\"}}", key, bucket.name)
+                };
+                writeln!(
+                    output,
+                    "{}_bucket{} {}{}",
+                    name, bucket_labels, bucket.value, timestamp
+                )
+                .unwrap();
+            }
+            writeln!(
+                output,
+                "{}_sum{} {}{}",
+                name,
+                labels,
+                format_value(*sum),
+                timestamp
+            )
+            .unwrap();
+            writeln!(output, "{}_count{} {}{}", name, labels, count, timestamp).unwrap();
+        }
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value)
+    } else {
+        // the debug format is the shortest representation parsing back to the same value
+        format!("{:?}", value)
+    }
+}
+
+pub(super) fn escape_help(docstring: &str) -> String {
+    docstring.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+pub(super) fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::{
+        model::{Bucket, MetricDetails, SingleValueSample},
+        parser::{decode_single_scrape_metric, split_metric_lines},
+        recording::parse_recording,
+        replay::replay_into_history,
+    };
+    use proptest::prelude::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    fn restart_history() -> MetricHistory {
+        let scrapes = parse_recording(include_str!("fixtures/restart.recording").as_bytes());
+        replay_into_history(scrapes.unwrap())
+    }
+
+    fn parse(text: &str, timestamp: u64) -> MetricHistory {
+        let lines = text.split('\n').map(String::from).collect();
+        let mut history = MetricHistory::new();
+        for part in split_metric_lines(lines) {
+            let metric = decode_single_scrape_metric(part, timestamp).into_metric();
+            history
+                .metrics
+                .insert(metric.details.name.clone(), Arc::new(metric));
+        }
+        history
+    }
+
+    #[test]
+    fn test_encode_snapshot() {
+        assert_eq!(
+            encode_snapshot(&restart_history()),
+            "# HELP http_requests_total Requests served\n\
+             # TYPE http_requests_total counter\n\
+             http_requests_total{code=\"200\"} 5\n\
+             http_requests_total{code=\"500\"} 0\n\
+             # HELP request_duration_seconds Request durations\n\
+             # TYPE request_duration_seconds histogram\n\
+             request_duration_seconds_bucket{le=\"0.1\"} 1\n\
+             request_duration_seconds_bucket{le=\"0.5\"} 2\n\
+             request_duration_seconds_bucket{le=\"+Inf\"} 2\n\
+             request_duration_seconds_sum 0.4\n\
+             request_duration_seconds_count 2\n\
+             # HELP temperature_celsius Temperature\n\
+             # TYPE temperature_celsius gauge\n\
+             temperature_celsius 22.1\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_openmetrics() {
+        let text = encode_openmetrics(&restart_history());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "# TYPE http_requests counter");
+        assert_eq!(lines[1], "# HELP http_requests Requests served");
+        assert_eq!(lines[2], "http_requests_total{code=\"200\"} 100 1657000000");
+        assert_eq!(lines[4], "http_requests_total{code=\"200\"} 5 1657000030");
+        assert_eq!(
+            lines[10],
+            "request_duration_seconds_bucket{le=\"0.1\"} 10 1657000000"
+        );
+        assert_eq!(lines[13], "request_duration_seconds_sum 4 1657000000");
+        assert_eq!(lines[14], "request_duration_seconds_count 20 1657000000");
+        assert_eq!(lines.last(), Some(&"# EOF"));
+        // one line per gauge and counter sample, five per histogram sample, plus the headers
+        assert_eq!(lines.len(), 2 * 3 + 3 + 3 * 5 + 3 * 2 + 1);
+    }
+
+    #[test]
+    fn test_encode_openmetrics_counter_without_total_suffix() {
+        let mut history = MetricHistory::new();
+        let mut metric = Metric::new(MetricDetails {
+            name: String::from("requests"),
+            docstring: String::from("Path \"a\\b\""),
+            metric_type: MetricType::Counter,
+        });
+        metric.update_time_series(HashMap::from([(
+            String::from(NO_LABELS_KEY),
+            Sample::CounterSample(SingleValueSample {
+                timestamp: 1657000000,
+                value: 1.5,
+            }),
+        )]));
+        history
+            .metrics
+            .insert(String::from("requests"), Arc::new(metric));
+        assert_eq!(
+            encode_openmetrics(&history),
+            "# TYPE requests unknown\n\
+             # HELP requests Path \\\"a\\\\b\\\"\n\
+             requests 1.5 1657000000\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value(1.0), "1");
+        assert_eq!(format_value(-0.25), "-0.25");
+        assert_eq!(format_value(1e300), "1e300");
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(format_value(f64::NAN), "NaN");
+    }
+
+    /// Generated series of one metric: the label values of every series and its latest sample.
+    #[derive(Debug, Clone)]
+    struct GeneratedMetric {
+        name: String,
+        docstring: String,
+        metric_type: MetricType,
+        series: Vec<(BTreeMap<String, String>, Sample)>,
+    }
+
+    fn label_value() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-z0-9]{0,8}",
+            // separators and characters which must be escaped
+            "[a-z,={}\" \\\\\n:/]{0,12}",
+        ]
+    }
+
+    fn labels(excluded: &'static str) -> impl Strategy<Value = BTreeMap<String, String>> {
+        prop::collection::btree_map("[a-z_][a-z0-9_]{0,6}", label_value(), 0..4).prop_map(
+            move |mut labels| {
+                labels.remove(excluded);
+                labels
+            },
+        )
+    }
+
+    fn value() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            (-1_000_000i64..1_000_000).prop_map(|v| v as f64),
+            -1e12f64..1e12,
+            prop::num::f64::NORMAL,
+            Just(f64::INFINITY),
+            Just(f64::NEG_INFINITY),
+        ]
+    }
+
+    fn histogram_sample() -> impl Strategy<Value = Sample> {
+        (
+            prop::collection::btree_set(0u32..10_000, 0..5),
+            prop::collection::vec(0u64..1_000_000_000, 6),
+            -1e9f64..1e9,
+        )
+            .prop_map(|(bounds, mut counts, sum)| {
+                counts.sort_unstable();
+                let mut bucket_values: Vec<Bucket> = bounds
+                    .iter()
+                    .zip(counts.iter())
+                    .map(|(bound, count)| Bucket::new(format_value(*bound as f64 / 100.0), *count))
+                    .collect();
+                bucket_values.push(Bucket::new(String::from("+Inf"), counts[5]));
+                Sample::HistogramSample(HistogramValueSample {
+                    timestamp: 0,
+                    bucket_values,
+                    sum,
+                    count: counts[5],
+                })
+            })
+    }
+
+    fn generated_metric() -> impl Strategy<Value = GeneratedMetric> {
+        let metric_type = prop_oneof![
+            Just(MetricType::Gauge),
+            Just(MetricType::Counter),
+            Just(MetricType::Histogram)
+        ];
+        (
+            "[a-z][a-z0-9_]{0,10}",
+            // the parser trims the docstring, so it starts and ends with a letter
+            "([a-z]([a-zA-Z0-9 ,.\"{}=\\\\\n]{0,20}[a-z])?)?",
+            metric_type,
+        )
+            .prop_flat_map(|(name, docstring, metric_type)| {
+                let series = match metric_type {
+                    MetricType::Gauge => {
+                        prop::collection::vec((labels(""), value().prop_map(gauge)), 1..4).boxed()
+                    }
+                    MetricType::Counter => {
+                        prop::collection::vec((labels(""), value().prop_map(counter)), 1..4).boxed()
+                    }
+                    MetricType::Histogram => {
+                        prop::collection::vec((labels("le"), histogram_sample()), 1..4).boxed()
+                    }
+                };
+                (Just(name), Just(docstring), Just(metric_type), series)
+            })
+            .prop_map(|(name, docstring, metric_type, series)| GeneratedMetric {
+                name,
+                docstring,
+                metric_type,
+                series,
+            })
+    }
+
+    fn gauge(value: f64) -> Sample {
+        Sample::GaugeSample(SingleValueSample {
+            timestamp: 0,
+            value,
+        })
+    }
+
+    fn counter(value: f64) -> Sample {
+        Sample::CounterSample(SingleValueSample {
+            timestamp: 0,
+            value,
+        })
+    }
+
+    fn into_history(generated: &[GeneratedMetric]) -> MetricHistory {
+        let mut history = MetricHistory::new();
+        for generated in generated {
+            let mut metric = Metric::new(MetricDetails {
+                name: generated.name.clone(),
+                docstring: generated.docstring.clone(),
+                metric_type: generated.metric_type.clone(),
+            });
+            let value_per_labels = generated
+                .series
+                .iter()
+                .map(|(labels, sample)| {
+                    let key = if labels.is_empty() {
+                        String::from(NO_LABELS_KEY)
+                    } else {
+                        labels
+                            .iter()
+                            .map(|(name, value)| {
+                                format!("{}=\"{}\"", name, escape_label_value(value))
+                            })
+                            .collect::<Vec<String>>()
+                            .join(",")
+                    };
+                    (key, sample.clone())
+                })
+                .collect();
+            metric.update_time_series(value_per_labels);
+            history
+                .metrics
+                .insert(generated.name.clone(), Arc::new(metric));
+        }
+        history
+    }
+
+    proptest! {
+        #[test]
+        fn test_snapshot_round_trip(
+            generated in prop::collection::btree_map("[a-z][a-z0-9]{0,10}", generated_metric(), 1..5)
+        ) {
+            // the histogram series are exposed with suffixes, so keep a single metric per name
+            let generated: Vec<GeneratedMetric> = generated
+                .into_iter()
+                .map(|(name, metric)| GeneratedMetric { name, ..metric })
+                .collect();
+            let history = into_history(&generated);
+            let parsed = parse(&encode_snapshot(&history), 0);
+
+            prop_assert_eq!(parsed.metrics.len(), history.metrics.len());
+            for (name, metric) in &history.metrics {
+                let parsed_metric = parsed.get_metric(name).expect("metric to be parsed");
+                prop_assert_eq!(&parsed_metric.details.docstring, &metric.details.docstring);
+                prop_assert_eq!(
+                    parsed_metric.details.metric_type.name(),
+                    metric.details.metric_type.name()
+                );
+                prop_assert_eq!(parsed_metric.time_series().len(), metric.time_series().len());
+                for (key, time_series) in metric.time_series() {
+                    let parsed_series = parsed_metric.time_series().get(key).expect("series to be parsed");
+                    prop_assert_eq!(
+                        format!("{:?}", parsed_series.samples),
+                        format!("{:?}", time_series.samples)
+                    );
+                }
+            }
+            // the escaped label values are decoded back to the generated ones
+            for generated in &generated {
+                let parsed_metric = parsed.get_metric(&generated.name).unwrap();
+                for (labels, _) in generated.series.iter().filter(|(labels, _)| !labels.is_empty()) {
+                    let parsed_labels: BTreeMap<String, String> = parsed_metric
+                        .time_series()
+                        .values()
+                        .map(|time_series| time_series.labels.as_ref().clone().into_iter().collect())
+                        .find(|parsed_labels| parsed_labels == labels)
+                        .expect("labels to be decoded");
+                    prop_assert_eq!(&parsed_labels, labels);
+                }
+            }
+        }
+    }
+}
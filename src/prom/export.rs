@@ -0,0 +1,452 @@
+use super::encoder::{encode_openmetrics, encode_snapshot};
+use super::model::{Metric, MetricHistory, Sample, TimeSeries, NO_LABELS_KEY};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    /// the latest values in the Prometheus text format
+    Prometheus,
+    /// every sample with its timestamp, for `promtool tsdb create-blocks-from openmetrics`
+    OpenMetrics,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Prometheus => "prom",
+            ExportFormat::OpenMetrics => "om",
+        }
+    }
+
+    /// The format matching the extension of the file, CSV for unknown extensions.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json") => ExportFormat::Json,
+            Some("prom") => ExportFormat::Prometheus,
+            Some("om") => ExportFormat::OpenMetrics,
+            _ => ExportFormat::Csv,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> anyhow::Result<Self> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "prometheus" => Ok(ExportFormat::Prometheus),
+            "openmetrics" => Ok(ExportFormat::OpenMetrics),
+            _ => Err(anyhow::anyhow!("unknown export format '{}'", format)),
+        }
+    }
+}
+
+/// The part of the history to export.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportScope {
+    History,
+    Metric(String),
+    /// a single time series, given by the metric name and the labels key
+    TimeSeries(String, String),
+}
+
+impl ExportScope {
+    /// Name of the exported data, usable as file name.
+    pub fn file_stem(&self) -> String {
+        let stem = match self {
+            ExportScope::History => String::from("history"),
+            ExportScope::Metric(metric) => metric.clone(),
+            ExportScope::TimeSeries(metric, labels) if labels == NO_LABELS_KEY => metric.clone(),
+            ExportScope::TimeSeries(metric, labels) => format!("{}_{}", metric, labels),
+        };
+        let stem: String = stem
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        stem.split('_')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join("_")
+    }
+}
+
+/// A time series as exported to JSON.
+#[derive(Serialize)]
+struct ExportedSeries<'a> {
+    metric: &'a str,
+    #[serde(rename = "type")]
+    metric_type: &'static str,
+    labels: BTreeMap<&'a str, &'a str>,
+    samples: Vec<ExportedSample>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ExportedSample {
+    Value {
+        timestamp: u64,
+        value: f64,
+    },
+    Histogram {
+        timestamp: u64,
+        sum: f64,
+        count: u64,
+        buckets: Vec<ExportedBucket>,
+    },
+}
+
+#[derive(Serialize)]
+struct ExportedBucket {
+    le: String,
+    count: u64,
+}
+
+/// Writes the scope of the history to the file, in the given format.
+pub fn export_to_file<P: AsRef<Path>>(
+    history: &MetricHistory,
+    scope: &ExportScope,
+    format: ExportFormat,
+    path: P,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    export(history, scope, format, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn export<W: Write>(
+    history: &MetricHistory,
+    scope: &ExportScope,
+    format: ExportFormat,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    let series = select_series(history, scope)?;
+    match format {
+        ExportFormat::Csv => write_csv(&series, writer),
+        ExportFormat::Json => {
+            let series: Vec<ExportedSeries> = series
+                .into_iter()
+                .map(|(metric, key, time_series)| to_exported_series(metric, key, time_series))
+                .collect();
+            serde_json::to_writer_pretty(writer, &series)?;
+            Ok(())
+        }
+        ExportFormat::Prometheus => {
+            writer.write_all(encode_snapshot(&scoped_history(&series)).as_bytes())?;
+            Ok(())
+        }
+        ExportFormat::OpenMetrics => {
+            writer.write_all(encode_openmetrics(&scoped_history(&series)).as_bytes())?;
+            Ok(())
+        }
+    }
+}
+
+/// A history holding only the selected time series.
+fn scoped_history(series: &[(&Metric, &str, &TimeSeries)]) -> MetricHistory {
+    let mut history = MetricHistory::new();
+    for (metric, key, time_series) in series {
+        let scoped = history
+            .metrics
+            .entry(metric.details.name.clone())
+            .or_insert_with(|| Arc::new(Metric::new(metric.details.clone())));
+        Arc::make_mut(scoped).insert_time_series(key.to_string(), (*time_series).clone());
+    }
+    history
+}
+
+/// The time series of the scope, ordered by metric name and labels.
+fn select_series<'a>(
+    history: &'a MetricHistory,
+    scope: &ExportScope,
+) -> anyhow::Result<Vec<(&'a Metric, &'a str, &'a TimeSeries)>> {
+    let get_metric = |name: &str| {
+        history
+            .get_metric(name)
+            .ok_or_else(|| anyhow::anyhow!("unknown metric '{}'", name))
+    };
+    let metrics: Vec<&Metric> = match scope {
+        ExportScope::History => {
+            let mut metrics: Vec<&Metric> = history.metrics.values().map(Arc::as_ref).collect();
+            metrics.sort_by(|a, b| a.details.name.cmp(&b.details.name));
+            metrics
+        }
+        ExportScope::Metric(name) => vec![get_metric(name)?],
+        ExportScope::TimeSeries(name, key) => {
+            let metric = get_metric(name)?;
+            let (key, time_series) = metric.time_series().get_key_value(key).ok_or_else(|| {
+                anyhow::anyhow!("unknown time series '{}' of metric '{}'", key, name)
+            })?;
+            return Ok(vec![(metric, key.as_str(), time_series)]);
+        }
+    };
+    Ok(metrics
+        .into_iter()
+        .flat_map(|metric| {
+            let mut series: Vec<(&Metric, &str, &TimeSeries)> = metric
+                .time_series()
+                .iter()
+                .map(|(key, time_series)| (metric, key.as_str(), time_series))
+                .collect();
+            series.sort_by_key(|(_, key, _)| *key);
+            series
+        })
+        .collect())
+}
+
+fn exported_labels<'a>(key: &str, time_series: &'a TimeSeries) -> BTreeMap<&'a str, &'a str> {
+    if key == NO_LABELS_KEY {
+        return BTreeMap::new();
+    }
+    time_series
+        .labels
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect()
+}
+
+fn to_exported_series<'a>(
+    metric: &'a Metric,
+    key: &str,
+    time_series: &'a TimeSeries,
+) -> ExportedSeries<'a> {
+    ExportedSeries {
+        metric: &metric.details.name,
+        metric_type: metric.details.metric_type.name(),
+        labels: exported_labels(key, time_series),
+        samples: time_series
+            .samples
+            .iter()
+            .map(|sample| match sample {
+                Sample::GaugeSample(sample) | Sample::CounterSample(sample) => {
+                    ExportedSample::Value {
+                        timestamp: sample.timestamp,
+                        value: sample.value,
+                    }
+                }
+                Sample::HistogramSample(histogram) => ExportedSample::Histogram {
+                    timestamp: histogram.timestamp,
+                    sum: histogram.sum,
+                    count: histogram.count,
+                    buckets: histogram
+                        .bucket_values
+                        .into_iter()
+                        .map(|bucket| ExportedBucket {
+                            le: bucket.name,
+                            count: bucket.value,
+                        })
+                        .collect(),
+                },
+            })
+            .collect(),
+    }
+}
+
+/// One row per sample. Histograms leave the value empty and fill the sum, the count and the
+/// buckets, written as `le=count` pairs separated by `;`.
+fn write_csv<W: Write>(series: &[(&Metric, &str, &TimeSeries)], writer: W) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "metric",
+        "type",
+        "labels",
+        "timestamp",
+        "value",
+        "sum",
+        "count",
+        "buckets",
+    ])?;
+    for (metric, key, time_series) in series {
+        let labels = exported_labels(key, time_series)
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, value))
+            .collect::<Vec<String>>()
+            .join(",");
+        let metric_type = metric.details.metric_type.name();
+        for sample in &time_series.samples {
+            let (value, sum, count, buckets) = match &sample {
+                Sample::GaugeSample(sample) | Sample::CounterSample(sample) => (
+                    sample.value.to_string(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                ),
+                Sample::HistogramSample(histogram) => (
+                    String::new(),
+                    histogram.sum.to_string(),
+                    histogram.count.to_string(),
+                    histogram
+                        .bucket_values
+                        .iter()
+                        .map(|bucket| format!("{}={}", bucket.name, bucket.value))
+                        .collect::<Vec<String>>()
+                        .join(";"),
+                ),
+            };
+            writer.write_record([
+                metric.details.name.as_str(),
+                metric_type,
+                &labels,
+                &sample.timestamp().to_string(),
+                &value,
+                &sum,
+                &count,
+                &buckets,
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::{recording::parse_recording, replay::replay_into_history};
+
+    fn restart_history() -> MetricHistory {
+        let scrapes = parse_recording(include_str!("fixtures/restart.recording").as_bytes());
+        replay_into_history(scrapes.unwrap())
+    }
+
+    fn export_to_string(
+        history: &MetricHistory,
+        scope: ExportScope,
+        format: ExportFormat,
+    ) -> String {
+        let mut output = Vec::new();
+        export(history, &scope, format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_export_time_series_to_csv() {
+        let csv = export_to_string(
+            &restart_history(),
+            ExportScope::TimeSeries(
+                String::from("http_requests_total"),
+                String::from("code=\"500\""),
+            ),
+            ExportFormat::Csv,
+        );
+        assert_eq!(
+            csv,
+            "metric,type,labels,timestamp,value,sum,count,buckets\n\
+             http_requests_total,counter,\"code=\"\"500\"\"\",1657000000,2,,,\n\
+             http_requests_total,counter,\"code=\"\"500\"\"\",1657000010,3,,,\n\
+             http_requests_total,counter,\"code=\"\"500\"\"\",1657000030,0,,,\n"
+        );
+    }
+
+    #[test]
+    fn test_export_histogram_to_csv() {
+        let csv = export_to_string(
+            &restart_history(),
+            ExportScope::Metric(String::from("request_duration_seconds")),
+            ExportFormat::Csv,
+        );
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[2],
+            "request_duration_seconds,histogram,,1657000010,,9,45,0.1=25;0.5=40;+Inf=45"
+        );
+    }
+
+    #[test]
+    fn test_export_history_to_json() {
+        let json = export_to_string(&restart_history(), ExportScope::History, ExportFormat::Json);
+        let series: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let series = series.as_array().unwrap();
+        // both http_requests_total series, the histogram and the gauge, ordered by name
+        assert_eq!(series.len(), 4);
+        assert_eq!(series[0]["metric"], "http_requests_total");
+        assert_eq!(series[0]["labels"]["code"], "200");
+        assert_eq!(series[0]["samples"][2]["value"], 5.0);
+        assert_eq!(series[2]["type"], "histogram");
+        assert_eq!(series[2]["labels"], serde_json::json!({}));
+        assert_eq!(
+            series[2]["samples"][0],
+            serde_json::json!({
+                "timestamp": 1657000000,
+                "sum": 4.0,
+                "count": 20,
+                "buckets": [
+                    {"le": "0.1", "count": 10},
+                    {"le": "0.5", "count": 18},
+                    {"le": "+Inf", "count": 20}
+                ]
+            })
+        );
+        assert_eq!(series[3]["metric"], "temperature_celsius");
+    }
+
+    #[test]
+    fn test_export_metric_to_openmetrics() {
+        let text = export_to_string(
+            &restart_history(),
+            ExportScope::Metric(String::from("temperature_celsius")),
+            ExportFormat::OpenMetrics,
+        );
+        assert_eq!(
+            text,
+            "# TYPE temperature_celsius gauge\n\
+             # HELP temperature_celsius Temperature\n\
+             temperature_celsius 21.5 1657000000\n\
+             temperature_celsius 21.7 1657000010\n\
+             temperature_celsius 22.1 1657000030\n\
+             # EOF\n"
+        );
+        assert_eq!(
+            ExportFormat::from_path("history.om"),
+            ExportFormat::OpenMetrics
+        );
+    }
+
+    #[test]
+    fn test_export_unknown_metric() {
+        let mut output = Vec::new();
+        let result = export(
+            &restart_history(),
+            &ExportScope::Metric(String::from("unknown")),
+            ExportFormat::Json,
+            &mut output,
+        );
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "unknown metric 'unknown'"
+        );
+    }
+
+    #[test]
+    fn test_export_file_stem() {
+        let scope = ExportScope::TimeSeries(
+            String::from("http_requests_total"),
+            String::from("code=\"200\",method=\"get\""),
+        );
+        assert_eq!(scope.file_stem(), "http_requests_total_code_200_method_get");
+        assert_eq!(ExportScope::History.file_stem(), "history");
+    }
+}
@@ -0,0 +1,75 @@
+use reqwest::Url;
+
+const FEDERATE_PATH: &str = "federate";
+
+/// Builds the url of the federation endpoint of a Prometheus server, selecting the series
+/// to federate with one `match[]` query parameter per selector.
+///
+/// The path of the endpoint is kept if it already points to the federation endpoint, a trailing
+/// `metrics` segment gets replaced, otherwise `federate` is appended (e.g. Prometheus behind a prefix).
+pub fn build_federate_url(endpoint: &str, selectors: &[&str]) -> anyhow::Result<String> {
+    if selectors.is_empty() {
+        return Err(anyhow::anyhow!(
+            "at least one match[] selector is required to scrape the federation endpoint"
+        ));
+    }
+    let mut url = Url::parse(endpoint)?;
+    let path = url.path().trim_end_matches('/').to_string();
+    if !path.ends_with(FEDERATE_PATH) {
+        let base = path.strip_suffix("metrics").unwrap_or(&path);
+        let base = base.trim_end_matches('/');
+        url.set_path(&format!("{}/{}", base, FEDERATE_PATH));
+    }
+    {
+        let mut query = url.query_pairs_mut();
+        query.clear();
+        for selector in selectors {
+            query.append_pair("match[]", selector);
+        }
+    }
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_federate_url_replaces_metrics_path() {
+        let url = build_federate_url("http://localhost:9090/metrics", &["up"]).unwrap();
+        assert_eq!(url, "http://localhost:9090/federate?match%5B%5D=up");
+    }
+
+    #[test]
+    fn test_build_federate_url_keeps_prefix() {
+        let url = build_federate_url("http://prom:9090/prometheus/", &["up"]).unwrap();
+        assert_eq!(url, "http://prom:9090/prometheus/federate?match%5B%5D=up");
+        let url = build_federate_url("http://prom:9090/prometheus/federate", &["up"]).unwrap();
+        assert_eq!(url, "http://prom:9090/prometheus/federate?match%5B%5D=up");
+    }
+
+    #[test]
+    fn test_build_federate_url_encodes_selectors() {
+        let url = build_federate_url(
+            "http://localhost:9090",
+            &["{job=\"node\"}", "http_requests_total{code=~\"5..\"}"],
+        )
+        .unwrap();
+        let parsed = Url::parse(&url).unwrap();
+        let selectors: Vec<String> = parsed
+            .query_pairs()
+            .filter(|(key, _)| key == "match[]")
+            .map(|(_, value)| value.to_string())
+            .collect();
+        assert_eq!(parsed.path(), "/federate");
+        assert_eq!(
+            selectors,
+            vec!["{job=\"node\"}", "http_requests_total{code=~\"5..\"}"]
+        );
+    }
+
+    #[test]
+    fn test_build_federate_url_requires_selector() {
+        assert!(build_federate_url("http://localhost:9090", &[]).is_err());
+    }
+}
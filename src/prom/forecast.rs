@@ -0,0 +1,143 @@
+/// The windows in seconds of the latest samples a trend can be fitted to. The trend is extended
+/// as far into the future.
+pub const FORECAST_WINDOWS: [u64; 4] = [5 * 60, 15 * 60, 60 * 60, 6 * 60 * 60];
+
+/// A least-squares line through the samples of a series, like `predict_linear` of Prometheus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinearTrend {
+    /// change per second
+    pub slope: f64,
+    /// value of the line at `origin`
+    pub intercept: f64,
+    /// timestamp of the last fitted point
+    pub origin: u64,
+}
+
+impl LinearTrend {
+    /// Fits a line to the points within `window` seconds before the last one. None without two
+    /// points at different timestamps.
+    pub fn fit(points: &[(u64, f64)], window: u64) -> Option<Self> {
+        let origin = points.last()?.0;
+        let start = origin.saturating_sub(window);
+        // relative to the origin, so the timestamps do not swallow the precision
+        let fitted: Vec<(f64, f64)> = points
+            .iter()
+            .filter(|(timestamp, value)| *timestamp >= start && value.is_finite())
+            .map(|(timestamp, value)| (*timestamp as f64 - origin as f64, *value))
+            .collect();
+        if fitted.len() < 2 {
+            return None;
+        }
+        let count = fitted.len() as f64;
+        let mean_x = fitted.iter().map(|(x, _)| x).sum::<f64>() / count;
+        let mean_y = fitted.iter().map(|(_, y)| y).sum::<f64>() / count;
+        let covariance: f64 = fitted
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f64 = fitted.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        if variance == 0.0 {
+            return None;
+        }
+        let slope = covariance / variance;
+        Some(Self {
+            slope,
+            intercept: mean_y - slope * mean_x,
+            origin,
+        })
+    }
+
+    /// The value of the line at the timestamp.
+    pub fn value_at(&self, timestamp: u64) -> f64 {
+        self.intercept + self.slope * (timestamp as f64 - self.origin as f64)
+    }
+
+    /// The timestamp at or after the origin the line reaches the threshold at. None when the
+    /// line is flat, moves away from the threshold or reaches it beyond any timestamp.
+    pub fn crossing(&self, threshold: f64) -> Option<u64> {
+        if self.intercept == threshold {
+            return Some(self.origin);
+        }
+        let seconds = (threshold - self.intercept) / self.slope;
+        if seconds.is_finite() && seconds >= 0.0 {
+            // the cast saturates, the sum overflows for a crossing out of reach
+            self.origin.checked_add(seconds.ceil() as u64)
+        } else {
+            None
+        }
+    }
+}
+
+/// The seconds in the two largest units, e.g. `3h12m`.
+pub fn format_duration(seconds: u64) -> String {
+    let units = [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m"), (1, "s")];
+    let mut parts = units
+        .iter()
+        .scan(seconds, |rest, (unit, name)| {
+            let count = *rest / unit;
+            *rest %= unit;
+            Some((count, *name))
+        })
+        .skip_while(|(count, _)| *count == 0)
+        .take(2)
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, name)| format!("{}{}", count, name))
+        .peekable();
+    match parts.peek() {
+        Some(_) => parts.collect(),
+        None => String::from("0s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear_trend() {
+        // a disk filling up by 2 per second, with some noise
+        let points = vec![
+            (1000, 100.0),
+            (1010, 121.0),
+            (1020, 140.0),
+            (1030, 159.0),
+            (1040, 180.0),
+        ];
+        let trend = LinearTrend::fit(&points, 3600).unwrap();
+        assert_eq!(trend.origin, 1040);
+        assert!((trend.slope - 1.98).abs() < 1e-9);
+        assert!((trend.intercept - 179.6).abs() < 1e-9);
+        assert!((trend.value_at(1050) - 199.4).abs() < 1e-9);
+        // 10.3 seconds after the last point
+        assert_eq!(trend.crossing(200.0), Some(1051));
+        // moving away from the threshold
+        assert_eq!(trend.crossing(50.0), None);
+
+        // only the points within the window are fitted
+        let points = vec![(1000, 500.0), (1010, 10.0), (1020, 20.0), (1030, 30.0)];
+        let trend = LinearTrend::fit(&points, 20).unwrap();
+        assert_eq!((trend.slope, trend.intercept), (1.0, 30.0));
+        assert_eq!(trend.crossing(100.0), Some(1100));
+
+        let flat = LinearTrend::fit(&[(1000, 5.0), (1010, 5.0)], 60).unwrap();
+        assert_eq!(flat.crossing(10.0), None);
+        assert_eq!(flat.crossing(5.0), Some(1010));
+        let almost_flat = LinearTrend {
+            slope: 1e-18,
+            intercept: 5.0,
+            origin: 1010,
+        };
+        // beyond the largest timestamp
+        assert_eq!(almost_flat.crossing(100.0), None);
+        assert_eq!(LinearTrend::fit(&[(1000, 5.0)], 60), None);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(3 * 3600 + 12 * 60 + 5), "3h12m");
+        assert_eq!(format_duration(3600 + 5), "1h");
+        assert_eq!(format_duration(2 * 86400 + 4 * 3600), "2d4h");
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt;
+
+use super::model::{Bucket, HistogramValueSample};
+use super::promql::functions::bucket_quantile;
+
+/// The quantiles shown for histograms.
+pub const DEFAULT_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Which observations of a histogram are looked at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistogramWindow {
+    /// all the observations since the start of the process
+    Cumulative,
+    /// the observations of the last scrapes, from the deltas of the bucket counts
+    Scrapes(usize),
+    /// the observations of the last seconds, from the deltas of the bucket counts
+    Seconds(u64),
+}
+
+impl fmt::Display for HistogramWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistogramWindow::Cumulative => write!(f, "since start"),
+            HistogramWindow::Scrapes(scrapes) => write!(f, "last {} scrapes", scrapes),
+            HistogramWindow::Seconds(seconds) => write!(f, "last {}s", seconds),
+        }
+    }
+}
+
+/// How the increase of a bucket is mapped to the colour of a heatmap cell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorScale {
+    Linear,
+    /// makes the buckets with few observations visible next to the busy ones
+    Log,
+}
+
+impl ColorScale {
+    /// The intensity between 0 and 1 of `value`, relative to the largest value of the heatmap.
+    pub fn intensity(self, value: f64, max: f64) -> f64 {
+        if value <= 0.0 || max <= 0.0 {
+            return 0.0;
+        }
+        let intensity = match self {
+            ColorScale::Linear => value / max,
+            ColorScale::Log => value.ln_1p() / max.ln_1p(),
+        };
+        intensity.min(1.0)
+    }
+}
+
+impl fmt::Display for ColorScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorScale::Linear => "linear scale",
+            ColorScale::Log => "log scale",
+        })
+    }
+}
+
+/// The observations of every bucket between consecutive samples, by upper bound. Unlike the
+/// bucket counts, every observation is only counted in its own bucket.
+pub fn bucket_increases(samples: &[HistogramValueSample]) -> Vec<(u64, Vec<(f64, f64)>)> {
+    samples
+        .windows(2)
+        .map(|pair| {
+            let counts = bucket_counts(&histogram_delta(&pair[1], &pair[0]));
+            let mut below = 0.0;
+            let increases = counts
+                .into_iter()
+                .map(|(upper_bound, count)| {
+                    let increase = (count - below).max(0.0);
+                    below = count;
+                    (upper_bound, increase)
+                })
+                .collect();
+            (pair[1].timestamp, increases)
+        })
+        .collect()
+}
+
+/// The estimated quantiles at every sample, in the order of `quantiles`. Within a window, a
+/// sample has no estimate until the history covers the window.
+pub fn quantiles_over_time(
+    samples: &[HistogramValueSample],
+    quantiles: &[f64],
+    window: HistogramWindow,
+) -> Vec<(u64, Vec<f64>)> {
+    (0..samples.len())
+        .filter_map(|index| {
+            let buckets = bucket_counts(&window_histogram(samples, index, window)?);
+            let estimates = quantiles
+                .iter()
+                .map(|quantile| bucket_quantile(*quantile, &buckets))
+                .collect();
+            Some((samples[index].timestamp, estimates))
+        })
+        .collect()
+}
+
+/// The observations of the window ending with the sample at `index`, or `None` while the
+/// history is shorter than the window.
+pub fn window_histogram(
+    samples: &[HistogramValueSample],
+    index: usize,
+    window: HistogramWindow,
+) -> Option<HistogramValueSample> {
+    let sample = samples.get(index)?;
+    let base = match window {
+        HistogramWindow::Cumulative => return Some(sample.clone()),
+        HistogramWindow::Scrapes(scrapes) => index.checked_sub(scrapes)?,
+        HistogramWindow::Seconds(seconds) => {
+            let window_start = sample.timestamp.checked_sub(seconds)?;
+            samples[..index]
+                .iter()
+                .rposition(|base| base.timestamp <= window_start)?
+        }
+    };
+    histogram_increase(&samples[base..=index])
+}
+
+/// The observations between the first and the last sample. The deltas are summed scrape by
+/// scrape, so the observations before a restart in between are counted too.
+pub fn histogram_increase(samples: &[HistogramValueSample]) -> Option<HistogramValueSample> {
+    let first = samples.first()?;
+    Some(
+        samples
+            .windows(2)
+            .fold(histogram_delta(first, first), |increase, pair| {
+                add_delta(increase, histogram_delta(&pair[1], &pair[0]))
+            }),
+    )
+}
+
+/// Adds the observations of the delta. After a change of the bucket layout, the observations
+/// before the change can't be added to the new buckets and only the delta is kept.
+fn add_delta(increase: HistogramValueSample, delta: HistogramValueSample) -> HistogramValueSample {
+    if !same_layout(&increase, &delta) {
+        return delta;
+    }
+    HistogramValueSample {
+        timestamp: delta.timestamp,
+        bucket_values: increase
+            .bucket_values
+            .iter()
+            .zip(&delta.bucket_values)
+            .map(|(total, added)| Bucket::new(total.name.clone(), total.value + added.value))
+            .collect(),
+        sum: increase.sum + delta.sum,
+        count: increase.count + delta.count,
+    }
+}
+
+fn same_layout(sample: &HistogramValueSample, other: &HistogramValueSample) -> bool {
+    sample.bucket_values.len() == other.bucket_values.len()
+        && sample
+            .bucket_values
+            .iter()
+            .zip(&other.bucket_values)
+            .all(|(bucket, other)| bucket.name == other.name)
+}
+
+/// The cumulative counts of the buckets, by upper bound.
+pub fn bucket_counts(sample: &HistogramValueSample) -> Vec<(f64, f64)> {
+    sample
+        .bucket_values
+        .iter()
+        .filter_map(|bucket| {
+            let upper_bound = bucket.name.parse::<f64>().ok()?;
+            Some((upper_bound, bucket.value as f64))
+        })
+        .collect()
+}
+
+/// The observations since the base sample. When a count went down, the process restarted in
+/// between, and all the observations since the restart are counted.
+pub fn histogram_delta(
+    sample: &HistogramValueSample,
+    base: &HistogramValueSample,
+) -> HistogramValueSample {
+    let reset = sample.count < base.count
+        || sample
+            .bucket_values
+            .iter()
+            .zip(&base.bucket_values)
+            .any(|(current, previous)| current.value < previous.value);
+    if !same_layout(sample, base) || reset {
+        return sample.clone();
+    }
+    HistogramValueSample {
+        timestamp: sample.timestamp,
+        bucket_values: sample
+            .bucket_values
+            .iter()
+            .zip(&base.bucket_values)
+            .map(|(current, previous)| {
+                Bucket::new(current.name.clone(), current.value - previous.value)
+            })
+            .collect(),
+        sum: sample.sum - base.sum,
+        count: sample.count - base.count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(timestamp: u64, counts: [u64; 3]) -> HistogramValueSample {
+        HistogramValueSample {
+            timestamp,
+            bucket_values: vec![
+                Bucket::new(String::from("0.1"), counts[0]),
+                Bucket::new(String::from("1"), counts[1]),
+                Bucket::new(String::from("+Inf"), counts[2]),
+            ],
+            sum: 0.0,
+            count: counts[2],
+        }
+    }
+
+    #[test]
+    fn test_cumulative_quantiles() {
+        let samples = vec![
+            histogram(1000, [50, 90, 100]),
+            histogram(1010, [50, 90, 200]),
+        ];
+        let quantiles = quantiles_over_time(&samples, &[0.5, 0.9], HistogramWindow::Cumulative);
+        assert_eq!(quantiles.len(), 2);
+        assert_eq!(quantiles[0], (1000, vec![0.1, 1.0]));
+        // half of the observations are in the +Inf bucket, the highest finite bound is returned
+        assert_eq!(quantiles[1], (1010, vec![1.0, 1.0]));
+    }
+
+    #[test]
+    fn test_windowed_quantiles() {
+        let samples = vec![
+            histogram(1000, [100, 100, 100]),
+            histogram(1010, [150, 190, 200]),
+            histogram(1020, [150, 280, 300]),
+            // restarted, only the observations since the restart are in the window
+            histogram(1030, [10, 10, 10]),
+        ];
+        let quantiles = quantiles_over_time(&samples, &[0.5], HistogramWindow::Seconds(10));
+        let timestamps: Vec<u64> = quantiles.iter().map(|(timestamp, _)| *timestamp).collect();
+        assert_eq!(timestamps, vec![1010, 1020, 1030]);
+        // 50 of 100 observations below 0.1
+        assert_eq!(quantiles[0].1, vec![0.1]);
+        // 90 of 100 observations between 0.1 and 1
+        assert!((quantiles[1].1[0] - (0.1 + 0.9 * 50.0 / 90.0)).abs() < 1e-9);
+        assert_eq!(quantiles[2].1, vec![0.05]);
+    }
+
+    #[test]
+    fn test_window_of_scrapes() {
+        let mut samples = vec![
+            histogram(1000, [100, 100, 100]),
+            histogram(1010, [150, 190, 200]),
+            histogram(1020, [150, 280, 300]),
+        ];
+        samples[1].sum = 10.0;
+        samples[2].sum = 25.0;
+        let window = HistogramWindow::Scrapes(1);
+        assert_eq!(window_histogram(&samples, 0, window), None);
+        let latest = window_histogram(&samples, 2, window).unwrap();
+        assert_eq!(latest.timestamp, 1020);
+        assert_eq!(latest.count, 100);
+        assert_eq!(latest.sum, 15.0);
+        let counts: Vec<u64> = latest.bucket_values.iter().map(|b| b.value).collect();
+        assert_eq!(counts, vec![0, 90, 100]);
+        assert_eq!(
+            window_histogram(&samples, 2, HistogramWindow::Scrapes(2))
+                .unwrap()
+                .count,
+            200
+        );
+        assert_eq!(
+            window_histogram(&samples, 2, HistogramWindow::Cumulative),
+            Some(samples[2].clone())
+        );
+    }
+
+    #[test]
+    fn test_window_with_restart() {
+        let samples = vec![
+            histogram(1000, [40, 80, 100]),
+            // restarted, 20 observations since the restart
+            histogram(1010, [5, 10, 20]),
+            histogram(1020, [50, 100, 150]),
+        ];
+        // 20 observations before the scrape after the restart, 130 after it
+        let increase = window_histogram(&samples, 2, HistogramWindow::Seconds(20)).unwrap();
+        assert_eq!(increase.timestamp, 1020);
+        assert_eq!(increase.count, 150);
+        let counts: Vec<u64> = increase.bucket_values.iter().map(|b| b.value).collect();
+        assert_eq!(counts, vec![50, 100, 150]);
+        assert_eq!(
+            window_histogram(&samples, 2, HistogramWindow::Scrapes(0))
+                .unwrap()
+                .count,
+            0
+        );
+    }
+
+    #[test]
+    fn test_bucket_increases() {
+        let samples = vec![
+            histogram(1000, [10, 20, 30]),
+            histogram(1010, [15, 30, 50]),
+            // restarted
+            histogram(1020, [1, 2, 2]),
+        ];
+        let increases = bucket_increases(&samples);
+        assert_eq!(
+            increases,
+            vec![
+                (1010, vec![(0.1, 5.0), (1.0, 5.0), (f64::INFINITY, 10.0)]),
+                (1020, vec![(0.1, 1.0), (1.0, 1.0), (f64::INFINITY, 0.0)]),
+            ]
+        );
+        assert!(bucket_increases(&samples[..1]).is_empty());
+    }
+
+    #[test]
+    fn test_color_scale() {
+        assert_eq!(ColorScale::Linear.intensity(0.0, 100.0), 0.0);
+        assert_eq!(ColorScale::Linear.intensity(25.0, 100.0), 0.25);
+        assert_eq!(ColorScale::Log.intensity(100.0, 100.0), 1.0);
+        assert!((ColorScale::Log.intensity(9.0, 99.0) - 0.5).abs() < 1e-9);
+        assert_eq!(ColorScale::Log.intensity(5.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_window_with_changed_bucket_layout() {
+        let mut base = histogram(1000, [10, 20, 30]);
+        base.bucket_values.remove(1);
+        let counts = bucket_counts(&histogram_delta(&histogram(1010, [50, 90, 100]), &base));
+        assert_eq!(
+            counts,
+            vec![(0.1, 50.0), (1.0, 90.0), (f64::INFINITY, 100.0)]
+        );
+    }
+}
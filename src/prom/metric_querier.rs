@@ -0,0 +1,483 @@
+use super::{
+    encoder::escape_label_value,
+    metric_scraper::{get_timestamp_unix_epoch, update_error_status},
+    metric_source::{new_shared_history, publish, MetricSource, SharedHistory},
+    model::{
+        Metric, MetricDetails, MetricHistory, MetricType, Sample, SingleValueSample, NO_LABELS_KEY,
+    },
+};
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tokio::{task, time::sleep};
+
+/// Evaluates PromQL expressions through the HTTP API of a Prometheus server and keeps the
+/// results in a `MetricHistory`, like the `MetricScraper` does for an exporter.
+pub struct MetricQuerier {
+    metrics_history: SharedHistory,
+    error_msg: Arc<RwLock<Option<String>>>,
+}
+
+impl MetricQuerier {
+    /// Starts querying the server at `url`. The last `lookback` seconds of every query are loaded
+    /// through `/api/v1/query_range` first, afterwards each query is evaluated every
+    /// `query_interval` seconds through `/api/v1/query`.
+    pub fn new(url: String, queries: Vec<String>, query_interval: u64, lookback: u64) -> Self {
+        let metrics_history = new_shared_history(MetricHistory::new());
+        let error_msg = Arc::new(RwLock::new(None));
+
+        {
+            let history = Arc::clone(&metrics_history);
+            let error_msg = Arc::clone(&error_msg);
+            task::spawn(async move {
+                let client = PrometheusClient::new(&url);
+                query_prometheus(
+                    &client,
+                    &queries,
+                    &history,
+                    &error_msg,
+                    query_interval,
+                    lookback,
+                )
+                .await;
+            });
+        }
+        Self {
+            metrics_history,
+            error_msg,
+        }
+    }
+}
+
+impl MetricSource for MetricQuerier {
+    fn history_snapshot(&self) -> Arc<MetricHistory> {
+        self.metrics_history.load_full()
+    }
+
+    fn get_error_msg_read_guard(&self) -> anyhow::Result<RwLockReadGuard<'_, Option<String>>> {
+        self.error_msg
+            .read()
+            .map_err(|err| anyhow::anyhow!("failed to aquire lock: {}", err))
+    }
+}
+
+async fn query_prometheus(
+    client: &PrometheusClient,
+    queries: &[String],
+    shared_history: &SharedHistory,
+    error_msg: &Arc<RwLock<Option<String>>>,
+    query_interval: u64,
+    lookback: u64,
+) {
+    // the results are added to this copy, the dashboard only sees the published generations
+    let mut history = MetricHistory::new();
+    let tick_rate = Duration::from_millis(query_interval * 1000);
+
+    // the metadata is only used to enrich the results, the queries work without it
+    let metadata = client.metadata().await.unwrap_or_else(|err| {
+        log::warn!("Not able to read the metrics metadata: {}", err);
+        HashMap::new()
+    });
+
+    // load the recent history of every query
+    let end = get_timestamp_unix_epoch();
+    let start = end.saturating_sub(lookback);
+    for query in queries {
+        match client
+            .query_range(query, start, end, query_interval.max(1))
+            .await
+        {
+            Ok(result) => update_history_with_query_result(&mut history, query, result, &metadata),
+            Err(err) => {
+                update_error_status(error_msg, Some(err.to_string()));
+                log::error!("Not able to query the range of '{}': {}", query, err);
+            }
+        }
+    }
+    publish(shared_history, &history);
+
+    loop {
+        let last_tick = Instant::now();
+        let mut error = None;
+        for query in queries {
+            match client.query(query, get_timestamp_unix_epoch()).await {
+                Ok(result) => {
+                    update_history_with_query_result(&mut history, query, result, &metadata)
+                }
+                Err(err) => {
+                    log::error!("Not able to evaluate the query '{}': {}", query, err);
+                    history.add_scrape_error(get_timestamp_unix_epoch(), err.to_string());
+                    error = Some(err.to_string());
+                }
+            }
+        }
+        publish(shared_history, &history);
+        update_error_status(error_msg, error);
+        // sleep for the remaining time of the tick
+        let sleep_time = tick_rate
+            .checked_sub(last_tick.elapsed())
+            .unwrap_or_default();
+        sleep(sleep_time).await;
+    }
+}
+
+/// Type and help text of a metric as returned by `/api/v1/metadata`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MetricMetadata {
+    #[serde(rename = "type")]
+    pub metric_type: String,
+    pub help: String,
+}
+
+/// Minimal client for the parts of the Prometheus HTTP API used by prom-tui.
+pub struct PrometheusClient {
+    base_url: String,
+}
+
+impl PrometheusClient {
+    pub fn new(url: &str) -> Self {
+        Self {
+            base_url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn api_url(&self, path: &str, params: &[(&str, String)]) -> anyhow::Result<Url> {
+        let url = Url::parse_with_params(
+            &format!("{}/api/v1/{}", self.base_url, path),
+            params.iter().map(|(key, value)| (*key, value.as_str())),
+        )?;
+        Ok(url)
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, url: Url) -> anyhow::Result<T> {
+        let response: ApiResponse<T> = reqwest::get(url).await?.json().await?;
+        match (response.status.as_str(), response.data) {
+            ("success", Some(data)) => Ok(data),
+            _ => Err(anyhow::anyhow!(
+                "Prometheus API error ({}): {}",
+                response.error_type.unwrap_or_default(),
+                response.error.unwrap_or_default()
+            )),
+        }
+    }
+
+    /// Evaluates an instant query at the given time.
+    pub async fn query(&self, query: &str, time: u64) -> anyhow::Result<QueryResult> {
+        let url = self.api_url(
+            "query",
+            &[("query", query.to_string()), ("time", time.to_string())],
+        )?;
+        self.get(url).await
+    }
+
+    /// Evaluates a range query between `start` and `end` with a resolution of `step` seconds.
+    pub async fn query_range(
+        &self,
+        query: &str,
+        start: u64,
+        end: u64,
+        step: u64,
+    ) -> anyhow::Result<QueryResult> {
+        let url = self.api_url(
+            "query_range",
+            &[
+                ("query", query.to_string()),
+                ("start", start.to_string()),
+                ("end", end.to_string()),
+                ("step", step.to_string()),
+            ],
+        )?;
+        self.get(url).await
+    }
+
+    /// Reads the metadata of all the metrics known by the server, keyed by metric name.
+    pub async fn metadata(&self) -> anyhow::Result<HashMap<String, MetricMetadata>> {
+        let url = self.api_url("metadata", &[])?;
+        let metadata: HashMap<String, Vec<MetricMetadata>> = self.get(url).await?;
+        Ok(metadata
+            .into_iter()
+            .filter_map(|(name, entries)| entries.into_iter().next().map(|entry| (name, entry)))
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    status: String,
+    data: Option<T>,
+    #[serde(rename = "errorType")]
+    error_type: Option<String>,
+    error: Option<String>,
+}
+
+/// The `data` part of a query response.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "resultType", content = "result", rename_all = "lowercase")]
+pub enum QueryResult {
+    Matrix(Vec<RangeSeries>),
+    Vector(Vec<InstantSeries>),
+    Scalar(ApiSample),
+    String(ApiSample),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RangeSeries {
+    pub metric: BTreeMap<String, String>,
+    pub values: Vec<ApiSample>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InstantSeries {
+    pub metric: BTreeMap<String, String>,
+    pub value: ApiSample,
+}
+
+/// A sample as encoded by the API: the timestamp in seconds and the value as string.
+#[derive(Debug, Deserialize)]
+pub struct ApiSample(pub f64, pub String);
+
+impl ApiSample {
+    fn to_single_value_sample(&self) -> Option<SingleValueSample> {
+        match self.1.parse::<f64>() {
+            Ok(value) => Some(SingleValueSample {
+                timestamp: self.0 as u64,
+                value,
+            }),
+            Err(err) => {
+                log::error!("failed to parse sample value '{}': {}", self.1, err);
+                None
+            }
+        }
+    }
+}
+
+/// Builds the key of a time series from the labels returned by the API, in the same form as the
+/// labels exposed by an exporter. The metric name is not part of the key.
+pub fn labels_key(labels: &BTreeMap<String, String>) -> String {
+    let key = labels
+        .iter()
+        .filter(|(name, _)| name.as_str() != "__name__")
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect::<Vec<String>>()
+        .join(",");
+    if key.is_empty() {
+        String::from(NO_LABELS_KEY)
+    } else {
+        key
+    }
+}
+
+fn metric_details(name: &str, metadata: &HashMap<String, MetricMetadata>) -> MetricDetails {
+    // the series of histograms and summaries are named after their family
+    let family = ["_bucket", "_sum", "_count", "_total"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .filter(|family| !metadata.contains_key(name) && metadata.contains_key(*family));
+    let (metric_type, docstring) = match (metadata.get(name), family) {
+        (Some(entry), _) => (
+            match entry.metric_type.as_str() {
+                "counter" => MetricType::Counter,
+                _ => MetricType::Gauge,
+            },
+            entry.help.clone(),
+        ),
+        (None, Some(family)) => (MetricType::Counter, metadata[family].help.clone()),
+        (None, None) => (MetricType::Gauge, String::new()),
+    };
+    MetricDetails {
+        name: name.to_string(),
+        docstring,
+        metric_type,
+    }
+}
+
+fn to_sample(metric_type: &MetricType, sample: SingleValueSample) -> Sample {
+    match metric_type {
+        MetricType::Counter => Sample::CounterSample(sample),
+        _ => Sample::GaugeSample(sample),
+    }
+}
+
+/// Adds the series of a query result to the history. Series are grouped by metric name, series
+/// without name (e.g. the result of an aggregation) are grouped under the query expression.
+pub fn update_history_with_query_result(
+    history: &mut MetricHistory,
+    query: &str,
+    result: QueryResult,
+    metadata: &HashMap<String, MetricMetadata>,
+) {
+    let series: Vec<(BTreeMap<String, String>, Vec<ApiSample>)> = match result {
+        QueryResult::Matrix(series) => series
+            .into_iter()
+            .map(|series| (series.metric, series.values))
+            .collect(),
+        QueryResult::Vector(series) => series
+            .into_iter()
+            .map(|series| (series.metric, vec![series.value]))
+            .collect(),
+        QueryResult::Scalar(sample) => vec![(BTreeMap::new(), vec![sample])],
+        QueryResult::String(sample) => {
+            log::error!(
+                "the string result '{}' of query '{}' can not be shown",
+                sample.1,
+                query
+            );
+            Vec::new()
+        }
+    };
+
+    for (labels, samples) in series {
+        let name = labels
+            .get("__name__")
+            .cloned()
+            .unwrap_or_else(|| query.to_string());
+        let key = labels_key(&labels);
+        let metric = Arc::make_mut(
+            history
+                .metrics
+                .entry(name.clone())
+                .or_insert_with(|| Arc::new(Metric::new(metric_details(&name, metadata)))),
+        );
+        let samples: Vec<Sample> = samples
+            .iter()
+            .filter_map(ApiSample::to_single_value_sample)
+            .map(|sample| to_sample(&metric.details.metric_type, sample))
+            .collect();
+        metric.append_newer_series_samples(key, samples);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Starts a local HTTP server standing in for Prometheus. Every request is answered with the
+    /// body registered for the first matching path prefix. Returns the base url of the server.
+    pub async fn start_mock_server(routes: Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        task::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buffer = vec![0; 8192];
+                let read = socket.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                let (status, body) = match routes.iter().find(|(route, _)| path.starts_with(route))
+                {
+                    Some((_, body)) => ("200 OK", body.clone()),
+                    None => ("404 Not Found", String::new()),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    const MATRIX_RESPONSE: &str = r#"{"status":"success","data":{"resultType":"matrix","result":[
+        {"metric":{"__name__":"http_requests_total","code":"200","job":"api"},"values":[[1657000000,"10"],[1657000010,"15"]]},
+        {"metric":{"__name__":"http_requests_total","code":"500","job":"api"},"values":[[1657000000,"1"],[1657000010,"1"]]}
+    ]}}"#;
+
+    const VECTOR_RESPONSE: &str = r#"{"status":"success","data":{"resultType":"vector","result":[
+        {"metric":{"code":"200"},"value":[1657000020.5,"0.5"]},
+        {"metric":{"code":"500"},"value":[1657000020.5,"NaN"]}
+    ]}}"#;
+
+    const METADATA_RESPONSE: &str = r#"{"status":"success","data":{
+        "http_requests_total":[{"type":"counter","help":"Requests served","unit":""}]
+    }}"#;
+
+    #[tokio::test]
+    async fn test_query_range_and_metadata() {
+        let url = start_mock_server(vec![
+            ("/api/v1/query_range", MATRIX_RESPONSE.to_string()),
+            ("/api/v1/metadata", METADATA_RESPONSE.to_string()),
+        ])
+        .await;
+        let client = PrometheusClient::new(&url);
+        let metadata = client.metadata().await.unwrap();
+        let result = client
+            .query_range("http_requests_total", 1657000000, 1657000010, 10)
+            .await
+            .unwrap();
+
+        let mut history = MetricHistory::new();
+        update_history_with_query_result(&mut history, "http_requests_total", result, &metadata);
+        let metric = history.get_metric("http_requests_total").unwrap();
+        assert!(matches!(metric.details.metric_type, MetricType::Counter));
+        assert_eq!(metric.details.docstring, "Requests served");
+        assert_eq!(metric.time_series().len(), 2);
+        let time_series = metric
+            .time_series()
+            .get("code=\"200\",job=\"api\"")
+            .unwrap();
+        assert_eq!(time_series.labels.get("job").unwrap(), "api");
+        assert_eq!(time_series.samples.len(), 2);
+        match &time_series.samples.get(1).unwrap() {
+            Sample::CounterSample(sample) => {
+                assert_eq!(sample.timestamp, 1657000010);
+                assert_eq!(sample.value, 15.0);
+            }
+            _ => panic!("expected a counter sample"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_instant_query_without_metric_name() {
+        let url = start_mock_server(vec![("/api/v1/query", VECTOR_RESPONSE.to_string())]).await;
+        let client = PrometheusClient::new(&url);
+        let query = "sum by (code) (rate(http_requests_total[1m]))";
+        let result = client.query(query, 1657000020).await.unwrap();
+
+        let mut history = MetricHistory::new();
+        update_history_with_query_result(&mut history, query, result, &HashMap::new());
+        let metric = history.get_metric(query).unwrap();
+        assert!(matches!(metric.details.metric_type, MetricType::Gauge));
+        let time_series = metric.time_series().get("code=\"500\"").unwrap();
+        match &time_series.samples.get(0).unwrap() {
+            Sample::GaugeSample(sample) => {
+                assert_eq!(sample.timestamp, 1657000020);
+                assert!(sample.value.is_nan());
+            }
+            _ => panic!("expected a gauge sample"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_error_is_reported() {
+        let url = start_mock_server(vec![(
+            "/api/v1/query",
+            r#"{"status":"error","errorType":"bad_data","error":"parse error"}"#.to_string(),
+        )])
+        .await;
+        let client = PrometheusClient::new(&url);
+        let err = client.query("rate(", 1657000020).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Prometheus API error (bad_data): parse error"
+        );
+    }
+
+    #[test]
+    fn test_labels_key() {
+        let labels = BTreeMap::from([
+            ("__name__".to_string(), "up".to_string()),
+            ("job".to_string(), "api".to_string()),
+            ("instance".to_string(), "host:9090".to_string()),
+        ]);
+        assert_eq!(labels_key(&labels), "instance=\"host:9090\",job=\"api\"");
+        assert_eq!(labels_key(&BTreeMap::new()), "single-value-with-no-labels");
+    }
+}
@@ -1,28 +1,32 @@
 use super::{
+    backfill::{load_backfill, Backfill, BackfillConfig},
+    metric_source::{new_shared_history, publish, MetricSource, SharedHistory},
     model::MetricHistory,
     parser::{decode_single_scrape_metric, split_metric_lines},
 };
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::time::{Duration, Instant};
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot::{self, error::TryRecvError};
 use tokio::{task, time::sleep};
 
-type MetricHistoryArc = Arc<RwLock<MetricHistory>>;
 pub struct MetricScraper {
-    metrics_history: MetricHistoryArc,
+    metrics_history: SharedHistory,
     error_msg: Arc<RwLock<Option<String>>>,
 }
 
 impl MetricScraper {
-    pub fn new(url: String, scrape_interval: u64) -> Self {
-        let metrics_history = MetricHistoryArc::new(RwLock::new(MetricHistory::new()));
+    /// Starts scraping the endpoint at `url`. With a `backfill` configuration, the history
+    /// preceding the first scrape is loaded from Prometheus.
+    pub fn new(url: String, scrape_interval: u64, backfill: Option<BackfillConfig>) -> Self {
+        let metrics_history = new_shared_history(MetricHistory::new());
         let error_msg = Arc::new(RwLock::new(None));
 
         {
             let history = Arc::clone(&metrics_history);
             let error_msg = Arc::clone(&error_msg);
             task::spawn(async move {
-                scrape_metric_endpoint(&url, &history, &error_msg, scrape_interval).await;
+                scrape_metric_endpoint(&url, &history, &error_msg, scrape_interval, backfill).await;
             });
         }
         Self {
@@ -30,14 +34,14 @@ impl MetricScraper {
             error_msg,
         }
     }
+}
 
-    pub fn get_history_lock(&self) -> anyhow::Result<RwLockReadGuard<MetricHistory>> {
-        self.metrics_history
-            .read()
-            .map_err(|err| anyhow::anyhow!("failed to aquire lock of metrics history: {}", err))
+impl MetricSource for MetricScraper {
+    fn history_snapshot(&self) -> Arc<MetricHistory> {
+        self.metrics_history.load_full()
     }
 
-    pub fn get_error_msg_read_guard(&self) -> anyhow::Result<RwLockReadGuard<Option<String>>> {
+    fn get_error_msg_read_guard(&self) -> anyhow::Result<RwLockReadGuard<'_, Option<String>>> {
         self.error_msg
             .read()
             .map_err(|err| anyhow::anyhow!("failed to aquire lock: {}", err))
@@ -46,13 +50,18 @@ impl MetricScraper {
 
 async fn scrape_metric_endpoint(
     url: &str,
-    history: &MetricHistoryArc,
+    shared_history: &SharedHistory,
     error_msg: &Arc<RwLock<Option<String>>>,
     scrape_interval: u64,
+    mut backfill: Option<BackfillConfig>,
 ) {
+    // the scrapes are decoded into this copy, the dashboard only sees the published generations
+    let mut history = MetricHistory::new();
     let mut last_tick = Instant::now();
     let tick_rate = Duration::from_millis(scrape_interval * 1000);
     let mut must_scrape = true;
+    // the history preceding the first scrape, loaded while scraping goes on
+    let mut loading_backfill: Option<oneshot::Receiver<anyhow::Result<Backfill>>> = None;
 
     loop {
         // scrape and update history
@@ -61,14 +70,48 @@ async fn scrape_metric_endpoint(
 
             match splitted_metrics_result {
                 Ok(splitted_metrics) => {
-                    update_history_with_new_scrape(history, splitted_metrics);
+                    update_history_with_new_scrape(
+                        &mut history,
+                        splitted_metrics,
+                        get_timestamp_unix_epoch(),
+                    );
+                    publish(shared_history, &history);
                     update_error_status(error_msg, None);
+                    // once the metrics of the target are known, load their history
+                    if let Some(backfill_config) = backfill.take() {
+                        let scraped = shared_history.load_full();
+                        let (sender, receiver) = oneshot::channel();
+                        task::spawn(async move {
+                            let backfill =
+                                load_backfill(&scraped, &backfill_config, scrape_interval).await;
+                            let _ = sender.send(backfill);
+                        });
+                        loading_backfill = Some(receiver);
+                    }
                 }
                 Err(err) => {
                     update_error_status(error_msg, Some(err.to_string()));
+                    history.add_scrape_error(get_timestamp_unix_epoch(), err.to_string());
+                    publish(shared_history, &history);
                     log::error!("Not able to scrape the metrics endpoint: {}", err);
                 }
             }
+            // the loaded history is inserted at the next scrape after it arrived
+            if let Some(receiver) = &mut loading_backfill {
+                match receiver.try_recv() {
+                    Ok(backfill) => {
+                        if let Err(err) = backfill.and_then(|backfill| backfill.apply(&mut history))
+                        {
+                            update_error_status(error_msg, Some(err.to_string()));
+                            log::error!("Not able to backfill the metrics history: {}", err);
+                        }
+                        publish(shared_history, &history);
+                        loading_backfill = None;
+                    }
+                    Err(TryRecvError::Empty) => {}
+                    Err(TryRecvError::Closed) => loading_backfill = None,
+                }
+            }
             // set must_scrape to false to avoid scraping again until the next tick
             must_scrape = false;
             // after scraping, sleep for the remaining time of the tick
@@ -88,18 +131,23 @@ async fn scrape_metric_endpoint(
     }
 }
 
-fn update_history_with_new_scrape(history: &MetricHistoryArc, splitted_metrics: Vec<Vec<String>>) {
-    let mut history_guard = history
-        .write()
-        .expect("to acquire write lock of metrics history");
-    let timestamp = get_timestamp_unix_epoch();
+pub(super) fn update_history_with_new_scrape(
+    history: &mut MetricHistory,
+    splitted_metrics: Vec<Vec<String>>,
+    timestamp: u64,
+) {
     for part in splitted_metrics {
         let single_scrape_metric = decode_single_scrape_metric(part, timestamp);
-        let metric_to_update_option = history_guard.metrics.get_mut(&single_scrape_metric.name);
+        let metric_to_update_option = history.metrics.get_mut(&single_scrape_metric.name);
         match metric_to_update_option {
             Some(metric_to_update) => {
+                let metric_to_update = Arc::make_mut(metric_to_update);
                 log::debug!("updating metric: {}", metric_to_update.details.name);
-                metric_to_update.update_time_series(single_scrape_metric.value_per_labels);
+                if single_scrape_metric.explicit_timestamps {
+                    metric_to_update.append_newer_samples(single_scrape_metric.value_per_labels);
+                } else {
+                    metric_to_update.update_time_series(single_scrape_metric.value_per_labels);
+                }
             }
             None => {
                 let metric = single_scrape_metric.into_metric();
@@ -107,22 +155,25 @@ fn update_history_with_new_scrape(history: &MetricHistoryArc, splitted_metrics:
                     "add metric '{}' for the first time to the history.",
                     metric.details.name
                 );
-                history_guard
+                history
                     .metrics
-                    .insert(metric.details.name.clone(), metric);
+                    .insert(metric.details.name.clone(), Arc::new(metric));
             }
         }
     }
 }
 
-fn update_error_status(error_msg: &Arc<RwLock<Option<String>>>, error_message: Option<String>) {
+pub(super) fn update_error_status(
+    error_msg: &Arc<RwLock<Option<String>>>,
+    error_message: Option<String>,
+) {
     let mut has_error_guard = error_msg
         .write()
         .expect("to acquire write lock of has_error");
     *has_error_guard = error_message;
 }
 
-fn get_timestamp_unix_epoch() -> u64 {
+pub(super) fn get_timestamp_unix_epoch() -> u64 {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -132,12 +183,20 @@ fn get_timestamp_unix_epoch() -> u64 {
 
 // TODO handle error when scraping endpoint is down and make app surviving connection issues.
 async fn get_splitted_metrics_from_endpoint(url: &str) -> anyhow::Result<Vec<Vec<String>>> {
-    let resp = reqwest::get(url).await?.text().await?;
-    let lines = resp
+    let resp = get_body_from_endpoint(url).await?;
+    Ok(split_body(&resp))
+}
+
+pub(super) async fn get_body_from_endpoint(url: &str) -> anyhow::Result<String> {
+    Ok(reqwest::get(url).await?.text().await?)
+}
+
+pub(super) fn split_body(body: &str) -> Vec<Vec<String>> {
+    let lines = body
         .split("\n")
         .map(|s| String::from(s))
         .collect::<Vec<String>>();
-    return Ok(split_metric_lines(lines));
+    split_metric_lines(lines)
 }
 
 #[cfg(test)]
@@ -149,38 +208,29 @@ mod tests {
     #[test]
     fn test_update_history_with_new_scrape() {
         // initialize data structure
-        let metric_history = MetricHistoryArc::new(RwLock::new(MetricHistory::new()));
+        let mut metric_history = MetricHistory::new();
         // simulate first scrape
         let lines = split_metric_lines(generate_metric_lines());
-        let history = Arc::clone(&metric_history);
-        update_and_assert(history, lines, 1);
+        update_and_assert(&mut metric_history, lines, 1);
 
         // simulate second scrape
         let lines = split_metric_lines(generate_metric_lines());
-        let history = Arc::clone(&metric_history);
-        update_and_assert(history, lines, 2);
+        update_and_assert(&mut metric_history, lines, 2);
     }
 
     fn update_and_assert(
-        history: MetricHistoryArc,
+        history: &mut MetricHistory,
         lines: Vec<Vec<String>>,
         expected_length: usize,
     ) {
         // update history
-        update_history_with_new_scrape(&history, lines);
+        update_history_with_new_scrape(history, lines, get_timestamp_unix_epoch());
 
         // assert results
-        let history_read_guard = history
-            .read()
-            .map_err(|err| anyhow::anyhow!("failed to aquire lock of metric history: {}", err));
-        history_read_guard
-            .expect("to access the history")
-            .metrics
-            .iter()
-            .for_each(|m| {
-                m.1.time_series.values().for_each(|time_series| {
-                    assert_eq!(time_series.samples.len(), expected_length);
-                });
+        history.metrics.iter().for_each(|m| {
+            m.1.time_series().values().for_each(|time_series| {
+                assert_eq!(time_series.samples.len(), expected_length);
             });
+        });
     }
 }
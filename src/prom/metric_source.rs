@@ -0,0 +1,98 @@
+use super::model::MetricHistory;
+use arc_swap::ArcSwap;
+use std::sync::{Arc, RwLockReadGuard};
+
+/// The history a source shares with the dashboard. The source updates its own copy of the
+/// history and publishes every new generation as an immutable snapshot, so the dashboard renders
+/// a consistent history without ever waiting for a scrape to be decoded.
+pub type SharedHistory = Arc<ArcSwap<MetricHistory>>;
+
+pub fn new_shared_history(history: MetricHistory) -> SharedHistory {
+    Arc::new(ArcSwap::from_pointee(history))
+}
+
+/// Publishes a new generation of the history. The metrics are shared with the source's copy,
+/// which copies a metric again when updating it; even then the label maps, the label index and
+/// the compressed chunks stay shared, only the chunks being appended to are copied.
+pub fn publish(shared: &SharedHistory, history: &MetricHistory) {
+    shared.store(Arc::new(history.clone()));
+}
+
+/// A source feeding the metrics history shown in the dashboard, e.g. an exporter scraped
+/// directly or the HTTP API of a Prometheus server.
+pub trait MetricSource {
+    /// The latest published generation of the history.
+    fn history_snapshot(&self) -> Arc<MetricHistory>;
+
+    fn get_error_msg_read_guard(&self) -> anyhow::Result<RwLockReadGuard<'_, Option<String>>>;
+
+    /// false if the history is not updated anymore, e.g. for a session loaded from disk
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    /// Advances a source which is driven by the user, e.g. a step by step replay.
+    fn step(&self) {}
+
+    /// State of the source shown in the header, if any.
+    fn status(&self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::test_data::{exposition, history_of, jobs, scrape};
+
+    #[test]
+    fn test_publish() {
+        let up = |value: f64| exposition("up", "gauge", &[("", value)]);
+        let mut history = history_of([(1000, up(1.0) + &jobs(&[1.0]))]);
+        let shared = new_shared_history(MetricHistory::new());
+        publish(&shared, &history);
+        let first = shared.load_full();
+        // only pointers are copied
+        assert!(Arc::ptr_eq(&first.metrics["up"], &history.metrics["up"]));
+
+        scrape(&mut history, 1010, &up(0.0));
+        publish(&shared, &history);
+        let second = shared.load_full();
+        // the updated metric was copied, the published one is unchanged
+        assert_eq!(
+            first.metrics["up"]
+                .time_series()
+                .values()
+                .next()
+                .unwrap()
+                .samples
+                .len(),
+            1
+        );
+        assert_eq!(
+            second.metrics["up"]
+                .time_series()
+                .values()
+                .next()
+                .unwrap()
+                .samples
+                .len(),
+            2
+        );
+        assert!(Arc::ptr_eq(&first.metrics["jobs"], &second.metrics["jobs"]));
+        assert!(Arc::ptr_eq(
+            &first.metrics["up"]
+                .time_series()
+                .values()
+                .next()
+                .unwrap()
+                .labels,
+            &second.metrics["up"]
+                .time_series()
+                .values()
+                .next()
+                .unwrap()
+                .labels
+        ));
+    }
+}
@@ -9,7 +9,116 @@ pub use self::model::SingleValueSample;
 pub use self::model::TimeSeries;
 pub(crate) mod parser;
 
+mod selector;
+pub use self::selector::Selector;
+
+mod promql;
+pub use self::promql::Query;
+
+mod counter;
+pub use self::counter::counter_points;
+pub use self::counter::sample_points;
+pub use self::counter::CounterValue;
+pub use self::counter::CounterView;
+
+mod histogram;
+pub use self::histogram::bucket_increases;
+pub use self::histogram::quantiles_over_time;
+pub use self::histogram::window_histogram;
+pub use self::histogram::ColorScale;
+pub use self::histogram::HistogramWindow;
+pub use self::histogram::DEFAULT_QUANTILES;
+
+mod alert;
+pub use self::alert::AlertState;
+pub use self::alert::Alerting;
+
+mod rule_files;
+pub use self::rule_files::Recorder;
+pub use self::rule_files::RuleFiles;
+
+mod anomaly;
+pub use self::anomaly::anomaly_points;
+pub use self::anomaly::detect_anomalies;
+pub use self::anomaly::has_active_anomaly;
+pub use self::anomaly::AnomalyConfig;
+
+mod stats;
+pub use self::stats::average_rate;
+pub use self::stats::Stats;
+
+mod forecast;
+pub use self::forecast::format_duration;
+pub use self::forecast::LinearTrend;
+pub use self::forecast::FORECAST_WINDOWS;
+
+mod diff;
+pub use self::diff::comparable_points;
+pub use self::diff::diff_histories;
+pub use self::diff::MetricDiff;
+pub use self::diff::Presence;
+
+mod cardinality;
+pub use self::cardinality::cardinality;
+pub use self::cardinality::series_growth;
+pub use self::cardinality::CardinalitySort;
+pub use self::cardinality::MetricCardinality;
+pub use self::cardinality::GROWTH_WINDOW;
+
+mod movers;
+pub use self::movers::rank_movers;
+pub use self::movers::Mover;
+pub use self::movers::MoverRanking;
+pub use self::movers::MOVER_WINDOWS;
+
+mod snapshot;
+pub use self::snapshot::snapshot_changes;
+pub use self::snapshot::Change;
+pub use self::snapshot::SeriesChange;
+pub use self::snapshot::SHIFT_QUANTILES;
+
+mod aggregate;
+pub use self::aggregate::aggregate;
+pub use self::aggregate::GroupBy;
+
+mod storage;
+pub use self::storage::SampleStorage;
+
 mod metric_scraper;
 pub use self::metric_scraper::MetricScraper;
 
+mod metric_querier;
+pub use self::metric_querier::MetricQuerier;
+
+mod backfill;
+pub use self::backfill::BackfillConfig;
+
+mod metric_source;
+pub use self::metric_source::MetricSource;
+
+mod session;
+pub use self::session::load_session;
+pub use self::session::save_session;
+pub use self::session::SessionSource;
+
+mod recording;
+pub use self::recording::read_recording;
+pub use self::recording::record;
+
+mod replay;
+pub use self::replay::replay_into_history;
+pub use self::replay::ReplaySource;
+pub use self::replay::ReplaySpeed;
+
+mod export;
+pub use self::export::export_to_file;
+pub use self::export::ExportFormat;
+pub use self::export::ExportScope;
+
+mod encoder;
+
+mod federation;
+pub use self::federation::build_federate_url;
+
+#[cfg(test)]
 mod test_data;
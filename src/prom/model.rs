@@ -1,18 +1,45 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 
 use super::parser::extract_labels_key_and_map;
+use super::selector::{LabelMatcher, Selector};
+use super::storage::SampleStorage;
 
+/// Key of the time series of a metric exposed without labels.
+pub const NO_LABELS_KEY: &str = "single-value-with-no-labels";
+
+/// The metrics, their label maps and index, and the chunks of their samples are shared between
+/// clones, so a clone only copies pointers. A metric is copied when it is updated while shared,
+/// see `Arc::make_mut`.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MetricHistory {
-    pub metrics: HashMap<String, Metric>,
+    pub metrics: HashMap<String, Arc<Metric>>,
+    /// timestamp of the first live sample when older samples were backfilled from Prometheus
+    pub live_since: Option<u64>,
+    pub scrape_errors: Vec<ScrapeError>,
+}
+
+/// A failed scrape of the source, kept to reconstruct what happened during a session.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScrapeError {
+    pub timestamp: u64,
+    pub message: String,
 }
 
 impl MetricHistory {
     pub fn new() -> Self {
         Self {
             metrics: HashMap::new(),
+            live_since: None,
+            scrape_errors: Vec::new(),
         }
     }
 
+    pub fn add_scrape_error(&mut self, timestamp: u64, message: String) {
+        self.scrape_errors.push(ScrapeError { timestamp, message });
+    }
+
     pub fn is_empty(&self) -> bool {
         self.metrics.len() == 0
     }
@@ -22,50 +49,106 @@ impl MetricHistory {
     }
 
     pub fn get_metric(&self, metric_name: &str) -> Option<&Metric> {
-        self.metrics.get(metric_name)
+        self.metrics.get(metric_name).map(Arc::as_ref)
+    }
+
+    /// The timestamp of the newest sample of all the time series.
+    pub fn latest_timestamp(&self) -> Option<u64> {
+        self.metrics
+            .values()
+            .flat_map(|metric| metric.time_series.values())
+            .filter_map(|time_series| time_series.samples.last().map(Sample::timestamp))
+            .max()
+    }
+
+    /// The metrics and the keys of their time series matching the selector, ordered by metric
+    /// name. Metrics without any matching time series are left out.
+    pub fn select(&self, selector: &Selector) -> Vec<(&Metric, Vec<&str>)> {
+        let mut selected: Vec<(&Metric, Vec<&str>)> = self
+            .metrics
+            .iter()
+            .filter(|(name, _)| selector.matches_metric_name(name))
+            .map(|(_, metric)| (metric.as_ref(), metric.select(&selector.label_matchers())))
+            .filter(|(_, keys)| !keys.is_empty())
+            .collect();
+        selected.sort_by(|(a, _), (b, _)| a.details.name.cmp(&b.details.name));
+        selected
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum MetricType {
     Gauge,
     Counter,
     Histogram,
 }
 
+impl MetricType {
+    /// The name of the type in the exposition formats, e.g. `counter`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetricType::Gauge => "gauge",
+            MetricType::Counter => "counter",
+            MetricType::Histogram => "histogram",
+        }
+    }
+}
+
 pub struct SingleScrapeMetric {
     pub name: String,
     pub docstring: String,
     pub metric_type: MetricType,
     pub value_per_labels: HashMap<String, Sample>,
+    /// true if the samples carried their own timestamps instead of the scrape timestamp
+    pub explicit_timestamps: bool,
 }
 
 impl SingleScrapeMetric {
     pub fn into_metric(self) -> Metric {
-        let mut metric = Metric {
-            details: MetricDetails {
-                name: self.name,
-                docstring: self.docstring,
-                metric_type: self.metric_type,
-            },
-            time_series: HashMap::new(),
-        };
+        let mut metric = Metric::new(MetricDetails {
+            name: self.name,
+            docstring: self.docstring,
+            metric_type: self.metric_type,
+        });
         self.value_per_labels
             .into_iter()
             .for_each(|(labels, sample)| {
-                add_time_series_into_metric(labels, &mut metric.time_series, sample);
+                add_time_series_into_metric(labels, &mut metric, sample);
             });
         metric
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "MetricData")]
 pub struct Metric {
     pub details: MetricDetails,
-    pub time_series: HashMap<String, TimeSeries>,
+    /// new time series are only added by `insert_time_series`, which keeps the label index up to
+    /// date
+    time_series: HashMap<String, TimeSeries>,
+    /// derived from the labels of the time series, rebuilt when a session is loaded
+    #[serde(skip_serializing)]
+    label_index: Arc<LabelIndex>,
+}
+
+/// The serialized fields of a metric.
+#[derive(Deserialize)]
+struct MetricData {
+    details: MetricDetails,
+    time_series: HashMap<String, TimeSeries>,
 }
 
-#[derive(Clone)]
+impl From<MetricData> for Metric {
+    fn from(data: MetricData) -> Self {
+        let mut metric = Metric::new(data.details);
+        for (key, time_series) in data.time_series {
+            metric.insert_time_series(key, time_series);
+        }
+        metric
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MetricDetails {
     pub name: String,
     pub docstring: String,
@@ -73,6 +156,57 @@ pub struct MetricDetails {
 }
 
 impl Metric {
+    pub fn new(details: MetricDetails) -> Self {
+        Self {
+            details,
+            time_series: HashMap::new(),
+            label_index: Arc::default(),
+        }
+    }
+
+    pub fn label_index(&self) -> &LabelIndex {
+        &self.label_index
+    }
+
+    pub fn time_series(&self) -> &HashMap<String, TimeSeries> {
+        &self.time_series
+    }
+
+    /// The time series with the key, e.g. to append samples. Its labels must not be changed.
+    pub fn time_series_mut(&mut self, key: &str) -> Option<&mut TimeSeries> {
+        self.time_series.get_mut(key)
+    }
+
+    pub fn insert_time_series(&mut self, key: String, time_series: TimeSeries) {
+        Arc::make_mut(&mut self.label_index).insert(&key, &time_series.labels);
+        self.time_series.insert(key, time_series);
+    }
+
+    /// The keys of the time series matching all the matchers, in order. Like in Prometheus, a
+    /// matcher accepting the empty string also accepts the time series without the label.
+    pub fn select(&self, matchers: &[&LabelMatcher]) -> Vec<&str> {
+        let mut selected: Option<BTreeSet<&str>> = None;
+        for matcher in matchers {
+            let matching = self
+                .label_index
+                .matching_keys(matcher, || self.time_series.keys().map(String::as_str));
+            selected = Some(match selected {
+                Some(selected) => selected.intersection(&matching).copied().collect(),
+                None => matching,
+            });
+        }
+        match selected {
+            Some(selected) => selected.into_iter().collect(),
+            None => self
+                .time_series
+                .keys()
+                .map(String::as_str)
+                .collect::<BTreeSet<&str>>()
+                .into_iter()
+                .collect(),
+        }
+    }
+
     pub fn update_time_series(&mut self, value_per_labels: HashMap<String, Sample>) {
         value_per_labels.into_iter().for_each(|(key, value)| {
             if self.time_series.contains_key(&key) {
@@ -82,32 +216,168 @@ impl Metric {
                     .samples
                     .push(value);
             } else {
-                add_time_series_into_metric(key, &mut self.time_series, value);
+                add_time_series_into_metric(key, self, value);
             }
         })
     }
+
+    /// Like `update_time_series`, but skips the samples which are not newer than the last sample
+    /// of their time series. Sources exposing explicit timestamps (e.g. the federation endpoint)
+    /// return the same sample until the series gets updated.
+    pub fn append_newer_samples(&mut self, value_per_labels: HashMap<String, Sample>) {
+        let newer_samples = value_per_labels
+            .into_iter()
+            .filter(|(key, sample)| {
+                match self.time_series.get(key).and_then(|t| t.samples.last()) {
+                    Some(last) => sample.timestamp() > last.timestamp(),
+                    None => true,
+                }
+            })
+            .collect();
+        self.update_time_series(newer_samples);
+    }
+
+    /// Like `append_newer_samples`, for the samples of a single time series ordered by time.
+    pub fn append_newer_series_samples<I>(&mut self, key: String, samples: I)
+    where
+        I: IntoIterator<Item = Sample>,
+    {
+        let mut samples = samples.into_iter();
+        if !self.time_series.contains_key(&key) {
+            match samples.next() {
+                Some(first) => add_time_series_into_metric(key.clone(), self, first),
+                None => return,
+            }
+        }
+        let time_series = self
+            .time_series
+            .get_mut(&key)
+            .expect("should contain the time series");
+        for sample in samples {
+            let is_newer = match time_series.samples.last() {
+                Some(last) => sample.timestamp() > last.timestamp(),
+                None => true,
+            };
+            if is_newer {
+                time_series.samples.push(sample);
+            }
+        }
+    }
+}
+
+/// Inverted index from the label names and values to the keys of the time series carrying them.
+#[derive(Clone, Default)]
+pub struct LabelIndex {
+    postings: HashMap<String, HashMap<String, BTreeSet<String>>>,
+}
+
+impl LabelIndex {
+    /// The names of the labels of the time series, in order.
+    pub fn label_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.postings.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The values of the label with the number of time series having each of them.
+    pub fn label_values(&self, name: &str) -> Vec<(&str, usize)> {
+        self.postings
+            .get(name)
+            .into_iter()
+            .flat_map(|values| values.iter())
+            .map(|(value, keys)| (value.as_str(), keys.len()))
+            .collect()
+    }
+
+    fn insert(&mut self, key: &str, labels: &HashMap<String, String>) {
+        // the placeholder label of the series without labels is not indexed
+        if !key.contains('=') {
+            return;
+        }
+        for (name, value) in labels {
+            self.postings
+                .entry(name.clone())
+                .or_default()
+                .entry(value.clone())
+                .or_default()
+                .insert(key.to_string());
+        }
+    }
+
+    fn matching_keys<'a, I>(
+        &'a self,
+        matcher: &LabelMatcher,
+        all_keys: impl FnOnce() -> I,
+    ) -> BTreeSet<&'a str>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let keys_with_value = |matching: bool| {
+            self.postings
+                .get(&matcher.name)
+                .into_iter()
+                .flat_map(|values| values.iter())
+                .filter(move |(value, _)| matcher.matches(value) == matching)
+                .flat_map(|(_, keys)| keys.iter().map(String::as_str))
+        };
+        if matcher.matches("") {
+            // the series without the label match as well, exclude the ones with another value
+            let excluded: HashSet<&str> = keys_with_value(false).collect();
+            all_keys().filter(|key| !excluded.contains(key)).collect()
+        } else {
+            keys_with_value(true).collect()
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TimeSeries {
-    pub labels: HashMap<String, String>,
-    pub samples: Vec<Sample>,
+    pub labels: Arc<HashMap<String, String>>,
+    pub samples: SampleStorage,
+}
+
+impl TimeSeries {
+    /// Inserts samples in front of the existing ones. Only the samples older than the first
+    /// existing sample are kept, so the history stays ordered by time.
+    pub fn prepend_samples(&mut self, samples: Vec<Sample>) {
+        let first_timestamp = self.samples.first_timestamp();
+        let mut older_samples: Vec<Sample> = samples
+            .into_iter()
+            .filter(|sample| match first_timestamp {
+                Some(first_timestamp) => sample.timestamp() < first_timestamp,
+                None => true,
+            })
+            .collect();
+        older_samples.sort_by_key(Sample::timestamp);
+        self.samples.prepend(older_samples);
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Sample {
     GaugeSample(SingleValueSample),
     CounterSample(SingleValueSample),
     HistogramSample(HistogramValueSample),
 }
 
-#[derive(Clone, Debug)]
+impl Sample {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Sample::GaugeSample(single_value) | Sample::CounterSample(single_value) => {
+                single_value.timestamp
+            }
+            Sample::HistogramSample(histogram) => histogram.timestamp,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SingleValueSample {
     pub timestamp: u64,
     pub value: f64,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Bucket {
     pub name: String,
     pub value: u64,
@@ -119,7 +389,7 @@ impl Bucket {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct HistogramValueSample {
     pub timestamp: u64,
     pub bucket_values: Vec<Bucket>,
@@ -127,11 +397,7 @@ pub struct HistogramValueSample {
     pub count: u64,
 }
 
-fn add_time_series_into_metric(
-    labels: String,
-    time_series: &mut HashMap<String, TimeSeries>,
-    sample: Sample,
-) {
+fn add_time_series_into_metric(labels: String, metric: &mut Metric, sample: Sample) {
     let mut labels_map = HashMap::new();
     let key;
     if labels.contains("=") {
@@ -141,11 +407,11 @@ fn add_time_series_into_metric(
         labels_map.insert("key".to_string(), "value".to_string());
     }
 
-    time_series.insert(
+    metric.insert_time_series(
         key,
         TimeSeries {
-            labels: labels_map,
-            samples: vec![sample],
+            labels: Arc::new(labels_map),
+            samples: SampleStorage::from_iter([sample]),
         },
     );
 }
@@ -215,4 +481,109 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_append_newer_samples_skips_already_known_samples() {
+        let sample = |timestamp, value| Sample::GaugeSample(SingleValueSample { timestamp, value });
+        let mut metric = SingleScrapeMetric {
+            name: "up".to_string(),
+            docstring: String::new(),
+            metric_type: MetricType::Gauge,
+            value_per_labels: HashMap::from([("job=\"api\"".to_string(), sample(10, 1.0))]),
+            explicit_timestamps: true,
+        }
+        .into_metric();
+
+        metric.append_newer_samples(HashMap::from([(
+            "job=\"api\"".to_string(),
+            sample(10, 1.0),
+        )]));
+        metric.append_newer_samples(HashMap::from([(
+            "job=\"api\"".to_string(),
+            sample(20, 0.0),
+        )]));
+        metric.append_newer_samples(HashMap::from([("job=\"db\"".to_string(), sample(20, 1.0))]));
+
+        let time_series = metric.time_series.get("job=\"api\"").unwrap();
+        assert_eq!(time_series.samples.len(), 2);
+        assert_eq!(time_series.samples.get(1).unwrap().timestamp(), 20);
+        assert_eq!(
+            metric.time_series.get("job=\"db\"").unwrap().samples.len(),
+            1
+        );
+    }
+
+    fn indexed_metric() -> Metric {
+        let sample = |value| {
+            Sample::CounterSample(SingleValueSample {
+                timestamp: 10,
+                value,
+            })
+        };
+        SingleScrapeMetric {
+            name: "http_requests_total".to_string(),
+            docstring: String::new(),
+            metric_type: MetricType::Counter,
+            value_per_labels: HashMap::from([
+                ("code=\"200\",method=\"GET\"".to_string(), sample(1.0)),
+                ("code=\"500\",method=\"GET\"".to_string(), sample(2.0)),
+                ("code=\"503\",method=\"POST\"".to_string(), sample(3.0)),
+                ("method=\"PUT\"".to_string(), sample(4.0)),
+            ]),
+            explicit_timestamps: false,
+        }
+        .into_metric()
+    }
+
+    #[test]
+    fn test_select_time_series() {
+        let metric = indexed_metric();
+        let select = |selector: &str| {
+            let selector = Selector::parse(selector).unwrap();
+            metric
+                .select(&selector.label_matchers())
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(select("{}").len(), 4);
+        assert_eq!(
+            select(r#"{code=~"5..", method="GET"}"#),
+            vec!["code=\"500\",method=\"GET\""]
+        );
+        // the series without a code label has the empty string as code
+        assert_eq!(
+            select(r#"{code!="200"}"#),
+            vec![
+                "code=\"500\",method=\"GET\"",
+                "code=\"503\",method=\"POST\"",
+                "method=\"PUT\""
+            ]
+        );
+        assert_eq!(select(r#"{code=""}"#), vec!["method=\"PUT\""]);
+        assert_eq!(select(r#"{method!~"GET|POST"}"#), vec!["method=\"PUT\""]);
+        assert!(select(r#"{path="/"}"#).is_empty());
+    }
+
+    #[test]
+    fn test_select_in_history() {
+        let mut history = MetricHistory::new();
+        let metric = indexed_metric();
+        history
+            .metrics
+            .insert(metric.details.name.clone(), Arc::new(metric));
+
+        let selected = history.select(&Selector::parse(r#"{method="PUT"}"#).unwrap());
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].1, vec!["method=\"PUT\""]);
+        assert!(history
+            .select(&Selector::parse(r#"up{method="PUT"}"#).unwrap())
+            .is_empty());
+        assert_eq!(
+            history.select(&Selector::parse(r#"{__name__=~"http_.*"}"#).unwrap())[0]
+                .1
+                .len(),
+            4
+        );
+    }
 }
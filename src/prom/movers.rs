@@ -0,0 +1,225 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::anomaly::mean_and_stddev;
+use super::counter::{sample_points, CounterValue};
+use super::diff::comparable_points;
+use super::model::{MetricHistory, MetricType, Sample};
+use super::stats::average_rate;
+
+/// The windows in seconds before the latest sample the movers can be ranked over.
+pub const MOVER_WINDOWS: [u64; 5] = [60, 5 * 60, 15 * 60, 60 * 60, 6 * 60 * 60];
+
+/// What the series are ranked by over the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoverRanking {
+    /// the difference between the first and the last point
+    AbsoluteChange,
+    /// the difference relative to the first point, in percent
+    RelativeChange,
+    /// the change per second: of the value of gauges, of counters and of the number of
+    /// observations of histograms
+    Rate,
+    /// the standard deviation relative to the mean, in percent
+    Volatility,
+}
+
+impl MoverRanking {
+    pub fn next(self) -> Self {
+        match self {
+            MoverRanking::AbsoluteChange => MoverRanking::RelativeChange,
+            MoverRanking::RelativeChange => MoverRanking::Rate,
+            MoverRanking::Rate => MoverRanking::Volatility,
+            MoverRanking::Volatility => MoverRanking::AbsoluteChange,
+        }
+    }
+}
+
+impl fmt::Display for MoverRanking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MoverRanking::AbsoluteChange => "absolute change",
+            MoverRanking::RelativeChange => "relative change",
+            MoverRanking::Rate => "rate",
+            MoverRanking::Volatility => "volatility",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mover {
+    pub metric: String,
+    /// key of the labels of the series
+    pub labels: String,
+    pub score: f64,
+    /// the points of the series within the window, see `comparable_points`
+    pub points: Vec<(u64, f64)>,
+}
+
+/// The series which moved within `window` seconds before the latest sample of the history, the
+/// highest score first. Gauges are compared by their values, counters by their rate and
+/// histograms by their average observation. Series without a change are left out.
+pub fn rank_movers(history: &MetricHistory, ranking: MoverRanking, window: u64) -> Vec<Mover> {
+    let start = match history.latest_timestamp() {
+        Some(latest) => latest.saturating_sub(window),
+        None => return Vec::new(),
+    };
+    let mut movers: Vec<Mover> = history
+        .metrics
+        .values()
+        .flat_map(|metric| {
+            metric
+                .time_series()
+                .iter()
+                .filter_map(move |(labels, time_series)| {
+                    let metric_type = &metric.details.metric_type;
+                    // only the samples of the window are decoded, counters and histograms need
+                    // the one before it for their first delta
+                    let samples: Vec<Sample> = match metric_type {
+                        MetricType::Gauge => time_series.samples.iter_since(start).collect(),
+                        MetricType::Counter | MetricType::Histogram => {
+                            time_series.samples.iter_since_previous(start).collect()
+                        }
+                    };
+                    let points = comparable_points(samples.iter().cloned(), metric_type);
+                    let score = score(&samples, metric_type, &points, ranking, start)?;
+                    Some(Mover {
+                        metric: metric.details.name.clone(),
+                        labels: labels.clone(),
+                        score,
+                        points,
+                    })
+                })
+        })
+        .filter(|mover| mover.score != 0.0 && !mover.score.is_nan())
+        .collect();
+    movers.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| (&a.metric, &a.labels).cmp(&(&b.metric, &b.labels)))
+    });
+    movers
+}
+
+fn score(
+    samples: &[Sample],
+    metric_type: &MetricType,
+    points: &[(u64, f64)],
+    ranking: MoverRanking,
+    start: u64,
+) -> Option<f64> {
+    let (first, last) = match points {
+        [first, .., last] => (first, last),
+        _ => return None,
+    };
+    match ranking {
+        MoverRanking::AbsoluteChange => Some((last.1 - first.1).abs()),
+        MoverRanking::RelativeChange => Some(relative(last.1 - first.1, first.1)),
+        MoverRanking::Rate => {
+            let value = match metric_type {
+                MetricType::Gauge => CounterValue::Value,
+                MetricType::Counter => {
+                    return average_rate(&within(samples, CounterValue::Value, start))
+                }
+                MetricType::Histogram => {
+                    return average_rate(&within(samples, CounterValue::HistogramCount, start))
+                }
+            };
+            let points = within(samples, value, start);
+            let (first, last) = (points.first()?, points.last()?);
+            if last.0 <= first.0 {
+                return None;
+            }
+            Some((last.1 - first.1).abs() / (last.0 - first.0) as f64)
+        }
+        MoverRanking::Volatility => {
+            let (mean, stddev) = mean_and_stddev(points);
+            Some(relative(stddev, mean))
+        }
+    }
+}
+
+fn within(samples: &[Sample], value: CounterValue, start: u64) -> Vec<(u64, f64)> {
+    sample_points(
+        samples
+            .iter()
+            .filter(|sample| sample.timestamp() >= start)
+            .cloned(),
+        value,
+    )
+}
+
+/// The amount relative to the base in percent, infinite when the base is zero.
+fn relative(amount: f64, base: f64) -> f64 {
+    if amount == 0.0 {
+        0.0
+    } else if base == 0.0 {
+        f64::INFINITY
+    } else {
+        (amount / base).abs() * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::model::NO_LABELS_KEY;
+    use crate::prom::test_data::{exposition, history_of, jobs};
+
+    fn body(a: f64, b: f64, total: f64) -> String {
+        jobs(&[a, b, 1.0]) + &exposition("requests_total", "counter", &[("", total)])
+    }
+
+    fn ranked(movers: &[Mover]) -> Vec<(&str, f64)> {
+        movers
+            .iter()
+            .map(|mover| (mover.labels.as_str(), mover.score))
+            .collect()
+    }
+
+    #[test]
+    fn test_rank_movers() {
+        let history = history_of([
+            (1000, body(500.0, 0.0, 0.0)),
+            // the window starts at 1010
+            (1010, body(100.0, 2.0, 0.0)),
+            (1020, body(110.0, 6.0, 100.0)),
+            (1030, body(100.0, 4.0, 300.0)),
+        ]);
+
+        let movers = rank_movers(&history, MoverRanking::AbsoluteChange, 20);
+        // the unchanged gauges are left out, the counter rate went from 0 to 20 per second
+        assert_eq!(
+            ranked(&movers),
+            vec![(NO_LABELS_KEY, 20.0), ("queue=\"b\"", 2.0)]
+        );
+        assert_eq!(movers[0].metric, "requests_total");
+        assert_eq!(
+            movers[0].points,
+            vec![(1010, 0.0), (1020, 10.0), (1030, 20.0)]
+        );
+        assert_eq!(movers[1].points.len(), 3);
+
+        let movers = rank_movers(&history, MoverRanking::RelativeChange, 20);
+        assert_eq!(
+            ranked(&movers),
+            vec![(NO_LABELS_KEY, f64::INFINITY), ("queue=\"b\"", 100.0)]
+        );
+
+        let movers = rank_movers(&history, MoverRanking::Rate, 20);
+        assert_eq!(
+            ranked(&movers),
+            vec![(NO_LABELS_KEY, 15.0), ("queue=\"b\"", 0.1)]
+        );
+
+        let movers = rank_movers(&history, MoverRanking::Volatility, 20);
+        let volatile: Vec<&str> = movers.iter().map(|mover| mover.labels.as_str()).collect();
+        assert_eq!(volatile, vec![NO_LABELS_KEY, "queue=\"b\"", "queue=\"a\""]);
+        assert!(movers[2].score < 5.0);
+
+        // the whole history
+        let movers = rank_movers(&history, MoverRanking::AbsoluteChange, 3600);
+        assert_eq!(ranked(&movers)[0], ("queue=\"a\"", 400.0));
+    }
+}
@@ -1,51 +1,71 @@
 use regex::Regex;
 
-use super::model::{Bucket, MetricType, SingleScrapeMetric};
+use super::model::{Bucket, MetricType, SingleScrapeMetric, NO_LABELS_KEY};
 use super::Sample;
 use super::{HistogramValueSample, SingleValueSample};
 use log::error;
 use std::collections::HashMap;
 
 pub fn decode_single_scrape_metric(lines: Vec<String>, timestamp: u64) -> SingleScrapeMetric {
-    let (name, docstring) = extract_name_docstring(&lines[0]).unwrap();
-    let metric_type = extract_type(&lines[1]).unwrap();
+    // federation and other exposers might not emit a HELP line, the TYPE line is then the header
+    let (name, docstring, metric_type, header_len) = if lines[0].starts_with("# TYPE") {
+        let name = extract_name_from_type(&lines[0]).unwrap();
+        let metric_type = extract_type(&lines[0]).unwrap();
+        (name, String::new(), metric_type, 1)
+    } else {
+        let (name, docstring) = extract_name_docstring(&lines[0]).unwrap();
+        let metric_type = extract_type(&lines[1]).unwrap();
+        (name, docstring, metric_type, 2)
+    };
     let mut single_scrape_metric = SingleScrapeMetric {
         name: name,
         docstring: docstring,
         metric_type: MetricType::Gauge,
         value_per_labels: HashMap::new(),
+        explicit_timestamps: false,
     };
     match metric_type.as_str() {
-        "gauge" => {
-            for line in lines.iter().skip(2) {
+        // untyped metrics (e.g. the ones returned by the federation endpoint) are shown as gauges
+        "gauge" | "untyped" => {
+            for line in lines.iter().skip(header_len) {
                 if line == "" {
                     continue;
                 }
                 let labels = extract_labels(&line);
                 let (_, key) = extract_labels_key_and_map(labels);
-                let value = extract_value(&line);
+                let Some((value, explicit_timestamp)) =
+                    sample_value_and_timestamp(&single_scrape_metric.name, line)
+                else {
+                    continue;
+                };
+                single_scrape_metric.explicit_timestamps |= explicit_timestamp.is_some();
                 single_scrape_metric.value_per_labels.insert(
                     key,
                     Sample::GaugeSample(SingleValueSample {
-                        timestamp: timestamp,
+                        timestamp: explicit_timestamp.unwrap_or(timestamp),
                         value: value,
                     }),
                 );
             }
         }
         "counter" => {
-            for line in lines.iter().skip(2) {
+            for line in lines.iter().skip(header_len) {
                 if line == "" {
                     continue;
                 }
                 let labels = extract_labels(&line);
                 let (_, key) = extract_labels_key_and_map(labels);
-                let value = extract_value(&line);
+                let Some((value, explicit_timestamp)) =
+                    sample_value_and_timestamp(&single_scrape_metric.name, line)
+                else {
+                    continue;
+                };
+                single_scrape_metric.explicit_timestamps |= explicit_timestamp.is_some();
                 single_scrape_metric.metric_type = MetricType::Counter;
                 single_scrape_metric.value_per_labels.insert(
                     key,
                     Sample::CounterSample(SingleValueSample {
-                        timestamp: timestamp,
+                        timestamp: explicit_timestamp.unwrap_or(timestamp),
                         value: value,
                     }),
                 );
@@ -70,15 +90,20 @@ pub fn decode_single_scrape_metric(lines: Vec<String>, timestamp: u64) -> Single
                 let count_line = group_lines[group_lines.len() - 1].clone();
                 let labels = extract_labels(&count_line);
                 let (_, key) = extract_labels_key_and_map(labels);
-                let count = extract_value(&count_line) as u64;
+                let Some((count, explicit_timestamp)) =
+                    sample_value_and_timestamp(&single_scrape_metric.name, &count_line)
+                else {
+                    continue;
+                };
+                single_scrape_metric.explicit_timestamps |= explicit_timestamp.is_some();
                 single_scrape_metric.metric_type = MetricType::Histogram;
                 single_scrape_metric.value_per_labels.insert(
                     key,
                     Sample::HistogramSample(HistogramValueSample {
-                        timestamp,
+                        timestamp: explicit_timestamp.unwrap_or(timestamp),
                         bucket_values,
                         sum,
-                        count,
+                        count: count as u64,
                     }),
                 );
             }
@@ -94,8 +119,8 @@ pub fn extract_labels_key_and_map(labels: Option<String>) -> (HashMap<String, St
     match labels {
         Some(labels) => (decode_labels(&labels), labels),
         None => (
-            HashMap::from([("key".to_string(), "single-value-with-no-labels".to_string())]),
-            String::from("single-value-with-no-labels"),
+            HashMap::from([("key".to_string(), NO_LABELS_KEY.to_string())]),
+            String::from(NO_LABELS_KEY),
         ),
     }
 }
@@ -106,7 +131,7 @@ pub fn split_metric_lines(lines: Vec<String>) -> Vec<Vec<String>> {
 
     for (index, line) in lines.iter().enumerate() {
         if metric_lines.len() != 0
-            && (index + 1 == lines.len() || lines[index + 1].starts_with("# HELP"))
+            && (index + 1 == lines.len() || starts_new_metric(line, &lines[index + 1]))
         {
             metric_lines.push(line.to_string());
             metrics.push(metric_lines);
@@ -119,11 +144,18 @@ pub fn split_metric_lines(lines: Vec<String>) -> Vec<Vec<String>> {
     return metrics;
 }
 
+/// A metric starts with its HELP line, or with its TYPE line when no HELP line is exposed
+/// (as it is the case for the federation endpoint).
+fn starts_new_metric(line: &str, next_line: &str) -> bool {
+    next_line.starts_with("# HELP")
+        || (next_line.starts_with("# TYPE") && !line.starts_with("# HELP"))
+}
+
 pub fn further_split_metric_lines_for_histogram(lines: &[String]) -> Vec<Vec<String>> {
     let mut metrics: Vec<Vec<String>> = Vec::new();
     let mut metric_lines: Vec<String> = Vec::new();
 
-    for line in lines.iter().skip(2) {
+    for line in lines.iter().filter(|line| !line.starts_with('#')) {
         if line.contains("_count{") || line.contains("_count ") {
             metric_lines.push(line.to_string());
             metrics.push(metric_lines);
@@ -141,10 +173,14 @@ fn extract_name_docstring(line: &str) -> Option<(String, String)> {
         .match_indices(" ")
         .nth(0)
         .map(|(index, _)| name_desc.split_at(index))
-        .map(|(name, desc)| (String::from(name), String::from(desc.trim())));
+        .map(|(name, desc)| (String::from(name), unescape(desc.trim())));
     return name_desc;
 }
 
+fn extract_name_from_type(line: &str) -> Option<String> {
+    line.split_whitespace().nth(2).map(String::from)
+}
+
 fn extract_type(line: &str) -> Option<String> {
     let metric_type = line
         .match_indices(" ")
@@ -156,7 +192,7 @@ fn extract_type(line: &str) -> Option<String> {
 
 pub fn extract_labels(line: &String) -> Option<String> {
     match line.find("{") {
-        Some(firs_index) => match line.find("}") {
+        Some(firs_index) => match find_closing_brace(line) {
             Some(second_index) => {
                 let labels = line
                     .split_at(firs_index + 1)
@@ -171,6 +207,24 @@ pub fn extract_labels(line: &String) -> Option<String> {
     }
 }
 
+/// Finds the brace closing the labels, skipping the braces inside quoted label values.
+fn find_closing_brace(line: &str) -> Option<usize> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '\\' if in_quotes => escaped = !escaped,
+            '"' if !escaped => in_quotes = !in_quotes,
+            '}' if !in_quotes => return Some(index),
+            _ => {}
+        }
+        if c != '\\' {
+            escaped = false;
+        }
+    }
+    None
+}
+
 #[allow(dead_code)]
 pub fn extract_labels_with_rgx(line: &str) -> Option<String> {
     let regex = Regex::new(r"\{(.*?)}").unwrap();
@@ -181,28 +235,66 @@ pub fn extract_labels_with_rgx(line: &str) -> Option<String> {
 }
 
 pub fn decode_labels(labels: &str) -> HashMap<String, String> {
-    let parts: Vec<String> = labels
-        .split(",")
-        .map(|s| s.to_string())
-        .filter(|s| s.len() > 0)
-        .collect();
-    let mut labels = HashMap::new();
-    for label in parts {
-        let split: Vec<&str> = label.split("=").collect();
-        if split.len() != 2 {
-            error!("failed to split this value: {:?}", split);
-            continue;
+    let mut labels_map = HashMap::new();
+    for label in split_labels(labels) {
+        // split at the first '=' only, as label values might contain the character as well
+        match label.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                labels_map.insert(key.trim().to_string(), unescape(value));
+            }
+            _ => {
+                error!("failed to split this value: {:?}", label);
+            }
+        }
+    }
+    labels_map
+}
+
+/// Splits the labels at the commas which are not part of a quoted label value.
+fn split_labels(labels: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in labels.char_indices() {
+        match c {
+            '\\' if in_quotes => escaped = !escaped,
+            '"' if !escaped => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                parts.push(&labels[start..index]);
+                start = index + 1;
+            }
+            _ => {}
         }
+        if c != '\\' {
+            escaped = false;
+        }
+    }
+    parts.push(&labels[start..]);
+    parts.into_iter().filter(|s| !s.is_empty()).collect()
+}
 
-        let key_value: Vec<String> = split
-            .iter()
-            .map(|s| s.to_string())
-            .filter(|s| s.len() > 0)
-            .collect();
-        let value = key_value[1].clone().replace("\"", "");
-        labels.insert(key_value[0].clone(), value);
+/// Resolves the escape sequences of label values and docstrings: `\\`, `\"` and `\n`.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
     }
-    labels
+    unescaped
 }
 
 #[allow(dead_code)]
@@ -216,11 +308,48 @@ pub fn decode_labels_with_rgx(labels_to_split: &str) -> HashMap<String, String>
 }
 
 fn extract_value(line: &String) -> f64 {
-    line.split_whitespace()
-        .last()
-        .unwrap()
-        .parse::<f64>()
-        .unwrap()
+    let (value, _) = split_value_and_timestamp(line);
+    value.parse::<f64>().unwrap()
+}
+
+/// Extracts the sample value and the optional explicit timestamp of a sample line.
+/// Timestamps are exposed in milliseconds and truncated to the seconds the history is kept in,
+/// like the scrape timestamps. Samples of a series less than a second apart therefore share a
+/// timestamp, and only the first one is kept (see `Metric::append_newer_samples`). Timestamps
+/// before the epoch are rejected.
+/// The value and the optional timestamp of a sample line of the metric, or `None` once the
+/// reason the sample is skipped is logged.
+fn sample_value_and_timestamp(metric_name: &str, line: &str) -> Option<(f64, Option<u64>)> {
+    match extract_value_and_timestamp(line) {
+        Ok(value_and_timestamp) => Some(value_and_timestamp),
+        Err(err) => {
+            error!("skipping the sample of {}: {}", metric_name, err);
+            None
+        }
+    }
+}
+
+fn extract_value_and_timestamp(line: &str) -> Result<(f64, Option<u64>), String> {
+    let (value, timestamp) = split_value_and_timestamp(line);
+    let value = value.parse::<f64>().unwrap();
+    let timestamp = match timestamp.map(str::parse::<i64>) {
+        Some(Ok(timestamp_ms)) if timestamp_ms < 0 => {
+            return Err(format!("negative timestamp {}", timestamp_ms))
+        }
+        Some(Ok(timestamp_ms)) => Some((timestamp_ms / 1000) as u64),
+        Some(Err(_)) | None => None,
+    };
+    Ok((value, timestamp))
+}
+
+/// The value and the optional timestamp of a sample line, as text.
+fn split_value_and_timestamp(line: &str) -> (&str, Option<&str>) {
+    let after_name = match line.rfind('}') {
+        Some(index) => &line[index + 1..],
+        None => line.split_once(' ').map(|(_, rest)| rest).unwrap_or(line),
+    };
+    let mut parts = after_name.split_whitespace();
+    (parts.next().unwrap(), parts.next())
 }
 
 #[cfg(test)]
@@ -479,4 +608,85 @@ mod tests {
             _ => panic!("Failed to decode histogram"),
         }
     }
+
+    #[test]
+    fn test_decode_federated_metrics() {
+        let lines: Vec<String> = vec![
+            "# TYPE http_requests_total counter",
+            "http_requests_total{code=\"200\",instance=\"10.0.0.1:8080\",job=\"api\"} 1027 1657000000123",
+            "http_requests_total{code=\"500\",instance=\"10.0.0.1:8080\",job=\"api\"} 3 1657000000123",
+            "http_requests_total{code=\"503\",instance=\"10.0.0.1:8080\",job=\"api\"} 1 -1000",
+            "# TYPE up untyped",
+            "up{instance=\"10.0.0.1:8080\",job=\"api\"} 1 1657000001000",
+            "",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let splitted_lines = split_metric_lines(lines);
+        assert_eq!(splitted_lines.len(), 2);
+
+        let metric = decode_single_scrape_metric(splitted_lines[0].clone(), 42);
+        assert_eq!(metric.name, "http_requests_total");
+        assert_eq!(metric.docstring, "");
+        assert!(metric.explicit_timestamps);
+        assert!(matches!(metric.metric_type, MetricType::Counter));
+        // the sample before the epoch is skipped
+        assert_eq!(metric.value_per_labels.len(), 2);
+        let key = "code=\"200\",instance=\"10.0.0.1:8080\",job=\"api\"";
+        match metric.value_per_labels.get(key).unwrap() {
+            Sample::CounterSample(sample) => {
+                assert_eq!(sample.value, 1027.0);
+                assert_eq!(sample.timestamp, 1657000000);
+            }
+            _ => panic!("Failed to decode federated counter"),
+        }
+        let (labels, _) = extract_labels_key_and_map(Some(key.to_string()));
+        assert_eq!(labels.get("instance").unwrap(), "10.0.0.1:8080");
+        assert_eq!(labels.get("job").unwrap(), "api");
+
+        let metric = decode_single_scrape_metric(splitted_lines[1].clone(), 42);
+        assert_eq!(metric.name, "up");
+        assert!(matches!(metric.metric_type, MetricType::Gauge));
+        assert_eq!(metric.value_per_labels.len(), 1);
+    }
+
+    #[test]
+    fn test_extract_value_and_timestamp() {
+        assert_eq!(extract_value_and_timestamp("metric_1 10"), Ok((10.0, None)));
+        assert_eq!(
+            extract_value_and_timestamp("metric_1{shard=\"0\"} 10.5 1657000000999"),
+            Ok((10.5, Some(1657000000)))
+        );
+        assert_eq!(
+            extract_value_and_timestamp("metric_1 -3 1657000000000"),
+            Ok((-3.0, Some(1657000000)))
+        );
+        assert!(extract_value_and_timestamp("metric_1 3 -1000").is_err());
+    }
+
+    #[test]
+    fn test_decode_escaped_labels_and_docstring() {
+        let line = String::from(r#"metric_1{path="/{id}",msg="say \"hi\"\n\\"} 1"#);
+        let labels = extract_labels(&line).unwrap();
+        assert_eq!(labels, r#"path="/{id}",msg="say \"hi\"\n\\""#);
+        let labels = decode_labels(&labels);
+        assert_eq!(labels.get("path").unwrap(), "/{id}");
+        assert_eq!(labels.get("msg").unwrap(), "say \"hi\"\n\\");
+        assert_eq!(extract_value(&line), 1.0);
+
+        let (name, docstring) =
+            extract_name_docstring(r"# HELP metric_1 First line\nsecond \\ line").unwrap();
+        assert_eq!(name, "metric_1");
+        assert_eq!(docstring, "First line\nsecond \\ line");
+    }
+
+    #[test]
+    fn test_decode_labels_with_separators_in_values() {
+        let labels = decode_labels("path=\"/a,b\",query=\"x=y\",instance=\"host:9090\"");
+        assert_eq!(labels.keys().count(), 3);
+        assert_eq!(labels.get("path").unwrap(), "/a,b");
+        assert_eq!(labels.get("query").unwrap(), "x=y");
+        assert_eq!(labels.get("instance").unwrap(), "host:9090");
+    }
 }
@@ -0,0 +1,275 @@
+use std::fmt;
+
+use super::super::selector::Selector;
+
+/// The type of the value an expression evaluates to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueType {
+    Scalar,
+    Vector,
+    /// a range vector, only valid as a function argument
+    Matrix,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ValueType::Scalar => "scalar",
+            ValueType::Vector => "instant vector",
+            ValueType::Matrix => "range vector",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(f64),
+    VectorSelector(Selector),
+    /// the range is in seconds
+    MatrixSelector(Selector, f64),
+    Negation(Box<Expr>),
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        /// comparisons return 0 or 1 instead of filtering
+        return_bool: bool,
+        matching: Option<VectorMatching>,
+    },
+    Call {
+        function: Function,
+        args: Vec<Expr>,
+    },
+    Aggregate {
+        op: AggregateOp,
+        grouping: Grouping,
+        /// the `k` of `topk` and `bottomk`, the quantile of `quantile`
+        param: Option<Box<Expr>>,
+        expr: Box<Expr>,
+    },
+}
+
+impl Expr {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Expr::Number(_) => ValueType::Scalar,
+            Expr::VectorSelector(_) => ValueType::Vector,
+            Expr::MatrixSelector(_, _) => ValueType::Matrix,
+            Expr::Negation(expr) => expr.value_type(),
+            Expr::Binary { lhs, rhs, .. } => {
+                if lhs.value_type() == ValueType::Scalar && rhs.value_type() == ValueType::Scalar {
+                    ValueType::Scalar
+                } else {
+                    ValueType::Vector
+                }
+            }
+            Expr::Call { .. } | Expr::Aggregate { .. } => ValueType::Vector,
+        }
+    }
+
+    /// The longest range of the range selectors of the expression in seconds, 0 without any.
+    pub fn max_range(&self) -> f64 {
+        match self {
+            Expr::Number(_) | Expr::VectorSelector(_) => 0.0,
+            Expr::MatrixSelector(_, range) => *range,
+            Expr::Negation(expr) => expr.max_range(),
+            Expr::Binary { lhs, rhs, .. } => lhs.max_range().max(rhs.max_range()),
+            Expr::Call { args, .. } => args.iter().map(Expr::max_range).fold(0.0, f64::max),
+            Expr::Aggregate { param, expr, .. } => {
+                let param = param.as_ref().map(|p| p.max_range()).unwrap_or_default();
+                param.max(expr.max_range())
+            }
+        }
+    }
+
+    /// The selectors of the expression, to load their series before the evaluation.
+    pub fn selectors(&self) -> Vec<&Selector> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::VectorSelector(selector) | Expr::MatrixSelector(selector, _) => vec![selector],
+            Expr::Negation(expr) => expr.selectors(),
+            Expr::Binary { lhs, rhs, .. } => {
+                let mut selectors = lhs.selectors();
+                selectors.extend(rhs.selectors());
+                selectors
+            }
+            Expr::Call { args, .. } => args.iter().flat_map(Expr::selectors).collect(),
+            Expr::Aggregate { param, expr, .. } => {
+                let mut selectors = param.as_ref().map(|p| p.selectors()).unwrap_or_default();
+                selectors.extend(expr.selectors());
+                selectors
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eql,
+    Neq,
+    Gtr,
+    Lss,
+    Gte,
+    Lte,
+    And,
+    Or,
+    Unless,
+}
+
+impl BinaryOp {
+    /// Higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And | BinaryOp::Unless => 2,
+            BinaryOp::Eql
+            | BinaryOp::Neq
+            | BinaryOp::Gtr
+            | BinaryOp::Lss
+            | BinaryOp::Gte
+            | BinaryOp::Lte => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 5,
+            BinaryOp::Pow => 6,
+        }
+    }
+
+    pub fn is_comparison(self) -> bool {
+        self.precedence() == 3
+    }
+
+    pub fn is_set_operator(self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or | BinaryOp::Unless)
+    }
+}
+
+/// `on(labels)` or `ignoring(labels)` of a binary operation between two vectors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorMatching {
+    pub on: bool,
+    pub labels: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AggregateOp {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    Stddev,
+    Stdvar,
+    Topk,
+    Bottomk,
+    Quantile,
+}
+
+impl AggregateOp {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sum" => AggregateOp::Sum,
+            "avg" => AggregateOp::Avg,
+            "min" => AggregateOp::Min,
+            "max" => AggregateOp::Max,
+            "count" => AggregateOp::Count,
+            "stddev" => AggregateOp::Stddev,
+            "stdvar" => AggregateOp::Stdvar,
+            "topk" => AggregateOp::Topk,
+            "bottomk" => AggregateOp::Bottomk,
+            "quantile" => AggregateOp::Quantile,
+            _ => return None,
+        })
+    }
+
+    pub fn has_param(self) -> bool {
+        matches!(
+            self,
+            AggregateOp::Topk | AggregateOp::Bottomk | AggregateOp::Quantile
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Grouping {
+    By(Vec<String>),
+    Without(Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    Rate,
+    Irate,
+    Increase,
+    Delta,
+    AvgOverTime,
+    SumOverTime,
+    MinOverTime,
+    MaxOverTime,
+    CountOverTime,
+    LastOverTime,
+    Abs,
+    Ceil,
+    Floor,
+    Sqrt,
+    Exp,
+    Ln,
+    ClampMin,
+    ClampMax,
+    HistogramQuantile,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "rate" => Function::Rate,
+            "irate" => Function::Irate,
+            "increase" => Function::Increase,
+            "delta" => Function::Delta,
+            "avg_over_time" => Function::AvgOverTime,
+            "sum_over_time" => Function::SumOverTime,
+            "min_over_time" => Function::MinOverTime,
+            "max_over_time" => Function::MaxOverTime,
+            "count_over_time" => Function::CountOverTime,
+            "last_over_time" => Function::LastOverTime,
+            "abs" => Function::Abs,
+            "ceil" => Function::Ceil,
+            "floor" => Function::Floor,
+            "sqrt" => Function::Sqrt,
+            "exp" => Function::Exp,
+            "ln" => Function::Ln,
+            "clamp_min" => Function::ClampMin,
+            "clamp_max" => Function::ClampMax,
+            "histogram_quantile" => Function::HistogramQuantile,
+            _ => return None,
+        })
+    }
+
+    pub fn arg_types(self) -> &'static [ValueType] {
+        match self {
+            Function::Rate
+            | Function::Irate
+            | Function::Increase
+            | Function::Delta
+            | Function::AvgOverTime
+            | Function::SumOverTime
+            | Function::MinOverTime
+            | Function::MaxOverTime
+            | Function::CountOverTime
+            | Function::LastOverTime => &[ValueType::Matrix],
+            Function::Abs
+            | Function::Ceil
+            | Function::Floor
+            | Function::Sqrt
+            | Function::Exp
+            | Function::Ln => &[ValueType::Vector],
+            Function::ClampMin | Function::ClampMax => &[ValueType::Vector, ValueType::Scalar],
+            Function::HistogramQuantile => &[ValueType::Scalar, ValueType::Vector],
+        }
+    }
+}
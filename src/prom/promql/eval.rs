@@ -0,0 +1,884 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::super::model::{Metric, MetricHistory, MetricType, Sample};
+use super::super::selector::Selector;
+use super::ast::{AggregateOp, BinaryOp, Expr, Function, Grouping, ValueType, VectorMatching};
+use super::functions::{bucket_quantile, extrapolated_rate, instant_rate, quantile};
+
+pub type Labels = BTreeMap<String, String>;
+
+/// How far back a vector selector looks for the latest sample, as in Prometheus.
+const LOOKBACK_DELTA: u64 = 300;
+
+/// The maximum number of evaluations of a range query, the step grows for longer histories.
+const MAX_STEPS: u64 = 11000;
+
+/// A time series of the history or of the result, with the label `__name__` for the metric name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    pub labels: Labels,
+    pub points: Vec<(u64, f64)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstantSample {
+    pub labels: Labels,
+    pub value: f64,
+}
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Scalar(f64),
+    Vector(Vec<InstantSample>),
+}
+
+/// Evaluates the expression every `step` seconds from the first to the last sample of the
+/// history. The samples of the result are grouped into series by their labels.
+pub fn evaluate_range(
+    expr: &Expr,
+    history: &MetricHistory,
+    step: u64,
+) -> anyhow::Result<Vec<Series>> {
+    let (start, end) = match time_range(history) {
+        Some(range) => range,
+        None => return Ok(Vec::new()),
+    };
+    let step = step.max(1).max((end - start) / MAX_STEPS + 1);
+    evaluate_steps(expr, history, start, end, step)
+}
+
+/// Evaluates the expression every `step` seconds from `start` to `end`. Only the samples the
+/// evaluations look at are decoded.
+pub fn evaluate_steps(
+    expr: &Expr,
+    history: &MetricHistory,
+    start: u64,
+    end: u64,
+    step: u64,
+) -> anyhow::Result<Vec<Series>> {
+    let evaluator = Evaluator::new(expr, history, start.saturating_sub(lookback(expr)));
+    let step = step.max(1);
+    let mut result: BTreeMap<Labels, Vec<(u64, f64)>> = BTreeMap::new();
+    let mut time = start;
+    while time <= end {
+        match evaluator.eval(expr, time)? {
+            Value::Scalar(value) => result.entry(Labels::new()).or_default().push((time, value)),
+            Value::Vector(samples) => {
+                for sample in samples {
+                    let points = result.entry(sample.labels).or_default();
+                    if matches!(points.last(), Some((last, _)) if *last == time) {
+                        return Err(anyhow::anyhow!(
+                            "vector cannot contain metrics with the same labelset"
+                        ));
+                    }
+                    points.push((time, sample.value));
+                }
+            }
+        }
+        time += step;
+    }
+    Ok(result
+        .into_iter()
+        .map(|(labels, points)| Series { labels, points })
+        .collect())
+}
+
+/// Evaluates the expression at a single point in time, a scalar becomes a sample without labels.
+pub fn evaluate_instant(
+    expr: &Expr,
+    history: &MetricHistory,
+    time: u64,
+) -> anyhow::Result<Vec<InstantSample>> {
+    let evaluator = Evaluator::new(expr, history, time.saturating_sub(lookback(expr)));
+    Ok(match evaluator.eval(expr, time)? {
+        Value::Scalar(value) => vec![InstantSample {
+            labels: Labels::new(),
+            value,
+        }],
+        Value::Vector(samples) => samples,
+    })
+}
+
+/// How far back of the evaluation time the expression looks at samples.
+fn lookback(expr: &Expr) -> u64 {
+    LOOKBACK_DELTA.max(expr.max_range().ceil() as u64)
+}
+
+/// The timestamps of the oldest and the newest sample of the history.
+pub fn time_range(history: &MetricHistory) -> Option<(u64, u64)> {
+    let time_series = history
+        .metrics
+        .values()
+        .flat_map(|metric| metric.time_series().values());
+    let mut range: Option<(u64, u64)> = None;
+    for time_series in time_series {
+        if let (Some(first), Some(last)) = (
+            time_series.samples.first_timestamp(),
+            time_series.samples.last().map(Sample::timestamp),
+        ) {
+            range = Some(match range {
+                Some((start, end)) => (start.min(first), end.max(last)),
+                None => (first, last),
+            });
+        }
+    }
+    range
+}
+
+struct Evaluator {
+    /// the series of every selector of the expression, decoded once for all the evaluations
+    series: HashMap<String, Vec<Series>>,
+}
+
+impl Evaluator {
+    /// Decodes the samples of the selectors from the timestamp `since` on.
+    fn new(expr: &Expr, history: &MetricHistory, since: u64) -> Self {
+        let series = expr
+            .selectors()
+            .into_iter()
+            .map(|selector| {
+                (
+                    selector.to_string(),
+                    select_series(history, selector, since),
+                )
+            })
+            .collect();
+        Self { series }
+    }
+
+    fn selected(&self, selector: &Selector) -> &[Series] {
+        self.series
+            .get(&selector.to_string())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn eval(&self, expr: &Expr, time: u64) -> anyhow::Result<Value> {
+        Ok(match expr {
+            Expr::Number(number) => Value::Scalar(*number),
+            Expr::VectorSelector(selector) => Value::Vector(
+                self.selected(selector)
+                    .iter()
+                    .filter_map(|series| {
+                        let end = series.points.partition_point(|(t, _)| *t <= time);
+                        let (timestamp, value) = *series.points[..end].last()?;
+                        (timestamp + LOOKBACK_DELTA > time).then(|| InstantSample {
+                            labels: series.labels.clone(),
+                            value,
+                        })
+                    })
+                    .collect(),
+            ),
+            Expr::MatrixSelector(_, _) => {
+                return Err(anyhow::anyhow!(
+                    "a range vector can only be used as function argument"
+                ))
+            }
+            Expr::Negation(expr) => match self.eval(expr, time)? {
+                Value::Scalar(value) => Value::Scalar(-value),
+                Value::Vector(samples) => Value::Vector(
+                    samples
+                        .into_iter()
+                        .map(|sample| InstantSample {
+                            labels: without_name(sample.labels),
+                            value: -sample.value,
+                        })
+                        .collect(),
+                ),
+            },
+            Expr::Binary {
+                op,
+                lhs,
+                rhs,
+                return_bool,
+                matching,
+            } => {
+                let lhs = self.eval(lhs, time)?;
+                let rhs = self.eval(rhs, time)?;
+                eval_binary(*op, lhs, rhs, *return_bool, matching.as_ref())?
+            }
+            Expr::Call { function, args } => self.eval_call(*function, args, time)?,
+            Expr::Aggregate {
+                op,
+                grouping,
+                param,
+                expr,
+            } => {
+                let param = match param {
+                    Some(param) => Some(self.eval_scalar(param, time)?),
+                    None => None,
+                };
+                let samples = self.eval_vector(expr, time)?;
+                Value::Vector(aggregate(*op, grouping, param, samples))
+            }
+        })
+    }
+
+    fn eval_scalar(&self, expr: &Expr, time: u64) -> anyhow::Result<f64> {
+        match self.eval(expr, time)? {
+            Value::Scalar(value) => Ok(value),
+            Value::Vector(_) => Err(anyhow::anyhow!("expected a scalar")),
+        }
+    }
+
+    fn eval_vector(&self, expr: &Expr, time: u64) -> anyhow::Result<Vec<InstantSample>> {
+        match self.eval(expr, time)? {
+            Value::Vector(samples) => Ok(samples),
+            Value::Scalar(_) => Err(anyhow::anyhow!("expected an instant vector")),
+        }
+    }
+
+    fn eval_call(&self, function: Function, args: &[Expr], time: u64) -> anyhow::Result<Value> {
+        if function.arg_types() == [ValueType::Matrix] {
+            let (selector, range) = match &args[0] {
+                Expr::MatrixSelector(selector, range) => (selector, *range),
+                _ => return Err(anyhow::anyhow!("expected a range vector selector")),
+            };
+            let range_start = time as f64 - range;
+            let samples = self
+                .selected(selector)
+                .iter()
+                .filter_map(|series| {
+                    let start = series
+                        .points
+                        .partition_point(|(t, _)| (*t as f64) <= range_start);
+                    let end = series.points.partition_point(|(t, _)| *t <= time);
+                    let window = &series.points[start..end.max(start)];
+                    let value = eval_range_function(function, window, range_start, time as f64)?;
+                    Some(InstantSample {
+                        labels: without_name(series.labels.clone()),
+                        value,
+                    })
+                })
+                .collect();
+            return Ok(Value::Vector(samples));
+        }
+
+        let samples = match function {
+            Function::HistogramQuantile => {
+                let quantile = self.eval_scalar(&args[0], time)?;
+                return Ok(Value::Vector(histogram_quantile(
+                    quantile,
+                    self.eval_vector(&args[1], time)?,
+                )));
+            }
+            _ => self.eval_vector(&args[0], time)?,
+        };
+        let apply: Box<dyn Fn(f64) -> f64> = match function {
+            Function::Abs => Box::new(f64::abs),
+            Function::Ceil => Box::new(f64::ceil),
+            Function::Floor => Box::new(f64::floor),
+            Function::Sqrt => Box::new(f64::sqrt),
+            Function::Exp => Box::new(f64::exp),
+            Function::Ln => Box::new(f64::ln),
+            Function::ClampMin => {
+                let min = self.eval_scalar(&args[1], time)?;
+                Box::new(move |value| value.max(min))
+            }
+            Function::ClampMax => {
+                let max = self.eval_scalar(&args[1], time)?;
+                Box::new(move |value| value.min(max))
+            }
+            _ => unreachable!("range vector functions are evaluated above"),
+        };
+        Ok(Value::Vector(
+            samples
+                .into_iter()
+                .map(|sample| InstantSample {
+                    labels: without_name(sample.labels),
+                    value: apply(sample.value),
+                })
+                .collect(),
+        ))
+    }
+}
+
+fn eval_range_function(
+    function: Function,
+    window: &[(u64, f64)],
+    range_start: f64,
+    range_end: f64,
+) -> Option<f64> {
+    let values = || window.iter().map(|(_, value)| *value);
+    if window.is_empty() {
+        return None;
+    }
+    match function {
+        Function::Rate => extrapolated_rate(window, range_start, range_end, true, true),
+        Function::Increase => extrapolated_rate(window, range_start, range_end, true, false),
+        Function::Delta => extrapolated_rate(window, range_start, range_end, false, false),
+        Function::Irate => instant_rate(window),
+        Function::AvgOverTime => Some(values().sum::<f64>() / window.len() as f64),
+        Function::SumOverTime => Some(values().sum()),
+        Function::MinOverTime => values().reduce(f64::min),
+        Function::MaxOverTime => values().reduce(f64::max),
+        Function::CountOverTime => Some(window.len() as f64),
+        Function::LastOverTime => window.last().map(|(_, value)| *value),
+        _ => None,
+    }
+}
+
+fn without_name(mut labels: Labels) -> Labels {
+    labels.remove("__name__");
+    labels
+}
+
+/// Groups the buckets by their labels without `le` and estimates the quantile of every group.
+fn histogram_quantile(quantile: f64, samples: Vec<InstantSample>) -> Vec<InstantSample> {
+    let mut groups: BTreeMap<Labels, Vec<(f64, f64)>> = BTreeMap::new();
+    for sample in samples {
+        let mut labels = without_name(sample.labels);
+        let upper_bound = match labels.remove("le").map(|le| le.parse::<f64>()) {
+            Some(Ok(upper_bound)) => upper_bound,
+            // series without a valid bucket bound are ignored, as in Prometheus
+            _ => continue,
+        };
+        groups
+            .entry(labels)
+            .or_default()
+            .push((upper_bound, sample.value));
+    }
+    groups
+        .into_iter()
+        .map(|(labels, buckets)| InstantSample {
+            labels,
+            value: bucket_quantile(quantile, &buckets),
+        })
+        .collect()
+}
+
+fn apply_op(op: BinaryOp, lhs: f64, rhs: f64) -> (f64, bool) {
+    let compare = |result: bool| (if result { 1.0 } else { 0.0 }, result);
+    match op {
+        BinaryOp::Add => (lhs + rhs, true),
+        BinaryOp::Sub => (lhs - rhs, true),
+        BinaryOp::Mul => (lhs * rhs, true),
+        BinaryOp::Div => (lhs / rhs, true),
+        BinaryOp::Mod => (lhs % rhs, true),
+        BinaryOp::Pow => (lhs.powf(rhs), true),
+        BinaryOp::Eql => compare(lhs == rhs),
+        BinaryOp::Neq => compare(lhs != rhs),
+        BinaryOp::Gtr => compare(lhs > rhs),
+        BinaryOp::Lss => compare(lhs < rhs),
+        BinaryOp::Gte => compare(lhs >= rhs),
+        BinaryOp::Lte => compare(lhs <= rhs),
+        BinaryOp::And | BinaryOp::Or | BinaryOp::Unless => {
+            unreachable!("set operators apply to vectors only")
+        }
+    }
+}
+
+/// Applies the operator to a sample of a vector. A comparison without `bool` keeps the sample
+/// with the value of the vector if it holds, and drops it otherwise.
+fn vector_sample_op(
+    op: BinaryOp,
+    return_bool: bool,
+    labels: Labels,
+    lhs: f64,
+    rhs: f64,
+    vector_value: f64,
+) -> Option<InstantSample> {
+    let (result, keep) = apply_op(op, lhs, rhs);
+    if op.is_comparison() && !return_bool {
+        return keep.then_some(InstantSample {
+            labels,
+            value: vector_value,
+        });
+    }
+    Some(InstantSample {
+        labels: without_name(labels),
+        value: result,
+    })
+}
+
+fn eval_binary(
+    op: BinaryOp,
+    lhs: Value,
+    rhs: Value,
+    return_bool: bool,
+    matching: Option<&VectorMatching>,
+) -> anyhow::Result<Value> {
+    Ok(match (lhs, rhs) {
+        (Value::Scalar(lhs), Value::Scalar(rhs)) => Value::Scalar(apply_op(op, lhs, rhs).0),
+        (Value::Vector(lhs), Value::Scalar(rhs)) => Value::Vector(
+            lhs.into_iter()
+                .filter_map(|s| vector_sample_op(op, return_bool, s.labels, s.value, rhs, s.value))
+                .collect(),
+        ),
+        (Value::Scalar(lhs), Value::Vector(rhs)) => Value::Vector(
+            rhs.into_iter()
+                .filter_map(|s| vector_sample_op(op, return_bool, s.labels, lhs, s.value, s.value))
+                .collect(),
+        ),
+        (Value::Vector(lhs), Value::Vector(rhs)) if op.is_set_operator() => {
+            Value::Vector(set_operation(op, lhs, rhs, matching))
+        }
+        (Value::Vector(lhs), Value::Vector(rhs)) => {
+            Value::Vector(one_to_one(op, lhs, rhs, return_bool, matching)?)
+        }
+    })
+}
+
+/// The labels identifying the series to match on the other side of the operation.
+fn signature(labels: &Labels, matching: Option<&VectorMatching>) -> Labels {
+    match matching {
+        Some(VectorMatching {
+            on: true,
+            labels: on,
+        }) => labels
+            .iter()
+            .filter(|(name, _)| on.contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        Some(VectorMatching {
+            on: false,
+            labels: ignoring,
+        }) => labels
+            .iter()
+            .filter(|(name, _)| *name != "__name__" && !ignoring.contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        None => without_name(labels.clone()),
+    }
+}
+
+fn format_labels(labels: &Labels) -> String {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}={:?}", name, value))
+        .collect();
+    format!("{{{}}}", labels.join(", "))
+}
+
+fn one_to_one(
+    op: BinaryOp,
+    lhs: Vec<InstantSample>,
+    rhs: Vec<InstantSample>,
+    return_bool: bool,
+    matching: Option<&VectorMatching>,
+) -> anyhow::Result<Vec<InstantSample>> {
+    let mut right_by_signature: HashMap<Labels, f64> = HashMap::new();
+    for sample in rhs {
+        let signature = signature(&sample.labels, matching);
+        if right_by_signature.contains_key(&signature) {
+            return Err(anyhow::anyhow!(
+                "many-to-many matching not allowed: found duplicate series for the match group \
+                 {} on the right hand-side of the operation",
+                format_labels(&signature)
+            ));
+        }
+        right_by_signature.insert(signature, sample.value);
+    }
+
+    let mut matched_signatures = HashSet::new();
+    let mut result = Vec::new();
+    for sample in lhs {
+        let signature = signature(&sample.labels, matching);
+        let rhs_value = match right_by_signature.get(&signature) {
+            Some(value) => *value,
+            None => continue,
+        };
+        if !matched_signatures.insert(signature.clone()) {
+            return Err(anyhow::anyhow!(
+                "multiple matches for labels {}: many-to-one matching must be explicit \
+                 (group_left/group_right), which is not supported",
+                format_labels(&signature)
+            ));
+        }
+        let labels = match matching {
+            Some(VectorMatching { on: true, .. }) => signature,
+            Some(VectorMatching {
+                on: false,
+                labels: ignoring,
+            }) => sample
+                .labels
+                .into_iter()
+                .filter(|(name, _)| !ignoring.contains(name))
+                .collect(),
+            None => sample.labels,
+        };
+        result.extend(vector_sample_op(
+            op,
+            return_bool,
+            labels,
+            sample.value,
+            rhs_value,
+            sample.value,
+        ));
+    }
+    Ok(result)
+}
+
+fn set_operation(
+    op: BinaryOp,
+    lhs: Vec<InstantSample>,
+    rhs: Vec<InstantSample>,
+    matching: Option<&VectorMatching>,
+) -> Vec<InstantSample> {
+    let rhs_signatures: HashSet<Labels> = rhs
+        .iter()
+        .map(|sample| signature(&sample.labels, matching))
+        .collect();
+    match op {
+        BinaryOp::And => lhs
+            .into_iter()
+            .filter(|sample| rhs_signatures.contains(&signature(&sample.labels, matching)))
+            .collect(),
+        BinaryOp::Unless => lhs
+            .into_iter()
+            .filter(|sample| !rhs_signatures.contains(&signature(&sample.labels, matching)))
+            .collect(),
+        _ => {
+            let lhs_signatures: HashSet<Labels> = lhs
+                .iter()
+                .map(|sample| signature(&sample.labels, matching))
+                .collect();
+            let mut result = lhs;
+            result.extend(
+                rhs.into_iter().filter(|sample| {
+                    !lhs_signatures.contains(&signature(&sample.labels, matching))
+                }),
+            );
+            result
+        }
+    }
+}
+
+fn aggregate(
+    op: AggregateOp,
+    grouping: &Grouping,
+    param: Option<f64>,
+    samples: Vec<InstantSample>,
+) -> Vec<InstantSample> {
+    let mut groups: BTreeMap<Labels, Vec<InstantSample>> = BTreeMap::new();
+    for sample in samples {
+        let group: Labels = match grouping {
+            Grouping::By(by) => sample
+                .labels
+                .iter()
+                .filter(|(name, _)| by.contains(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            Grouping::Without(without) => sample
+                .labels
+                .iter()
+                .filter(|(name, _)| *name != "__name__" && !without.contains(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        };
+        groups.entry(group).or_default().push(sample);
+    }
+
+    let mut result = Vec::new();
+    for (labels, mut samples) in groups {
+        let values: Vec<f64> = samples.iter().map(|sample| sample.value).collect();
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count;
+        let value = match op {
+            AggregateOp::Sum => values.iter().sum(),
+            AggregateOp::Avg => mean,
+            AggregateOp::Min => values.iter().copied().fold(f64::NAN, f64::min),
+            AggregateOp::Max => values.iter().copied().fold(f64::NAN, f64::max),
+            AggregateOp::Count => count,
+            AggregateOp::Stddev => variance.sqrt(),
+            AggregateOp::Stdvar => variance,
+            AggregateOp::Quantile => quantile(param.unwrap_or(f64::NAN), &values),
+            AggregateOp::Topk | AggregateOp::Bottomk => {
+                // the selected samples keep all their labels
+                let k = param.unwrap_or(0.0).max(0.0) as usize;
+                samples.sort_by(|a, b| {
+                    let order = a.value.total_cmp(&b.value);
+                    if op == AggregateOp::Topk {
+                        order.reverse()
+                    } else {
+                        order
+                    }
+                });
+                result.extend(samples.into_iter().take(k));
+                continue;
+            }
+        };
+        result.push(InstantSample { labels, value });
+    }
+    result
+}
+
+/// The series of the history matching the selector. The histograms are exposed like in
+/// Prometheus, as the `_bucket` series with their `le` label, `_sum` and `_count`.
+fn select_series(history: &MetricHistory, selector: &Selector, since: u64) -> Vec<Series> {
+    let mut selected = Vec::new();
+    for metric in history.metrics.values() {
+        let name = &metric.details.name;
+        let names: Vec<(String, SeriesKind)> = match metric.details.metric_type {
+            MetricType::Histogram => vec![
+                (format!("{}_bucket", name), SeriesKind::Bucket),
+                (format!("{}_sum", name), SeriesKind::Sum),
+                (format!("{}_count", name), SeriesKind::Count),
+            ],
+            _ => vec![(name.clone(), SeriesKind::Value)],
+        };
+        for (series_name, kind) in names {
+            if selector.matches_metric_name(&series_name) {
+                selected.extend(metric_series(metric, selector, &series_name, kind, since));
+            }
+        }
+    }
+    selected
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SeriesKind {
+    Value,
+    Bucket,
+    Sum,
+    Count,
+}
+
+fn metric_series(
+    metric: &Metric,
+    selector: &Selector,
+    series_name: &str,
+    kind: SeriesKind,
+    since: u64,
+) -> Vec<Series> {
+    let label_matchers = selector.label_matchers();
+    // the bucket bounds are not labels of the histogram in the history
+    let (le_matchers, matchers): (Vec<_>, Vec<_>) = label_matchers
+        .into_iter()
+        .partition(|matcher| kind == SeriesKind::Bucket && matcher.name == "le");
+
+    let mut selected = Vec::new();
+    for key in metric.select(&matchers) {
+        let time_series = &metric.time_series()[key];
+        let mut labels = Labels::new();
+        // the series without labels have a placeholder label in the history
+        if key.contains('=') {
+            labels.extend(
+                time_series
+                    .labels
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            );
+        }
+        labels.insert(String::from("__name__"), series_name.to_string());
+
+        if kind != SeriesKind::Bucket {
+            let points = time_series
+                .samples
+                .iter_since(since)
+                .map(|sample| match (&sample, kind) {
+                    (Sample::GaugeSample(s) | Sample::CounterSample(s), _) => {
+                        (s.timestamp, s.value)
+                    }
+                    (Sample::HistogramSample(h), SeriesKind::Sum) => (h.timestamp, h.sum),
+                    (Sample::HistogramSample(h), _) => (h.timestamp, h.count as f64),
+                })
+                .collect();
+            selected.push(Series { labels, points });
+            continue;
+        }
+
+        let mut buckets: BTreeMap<String, Vec<(u64, f64)>> = BTreeMap::new();
+        for sample in time_series.samples.iter_since(since) {
+            if let Sample::HistogramSample(histogram) = sample {
+                for bucket in &histogram.bucket_values {
+                    buckets
+                        .entry(bucket.name.clone())
+                        .or_default()
+                        .push((histogram.timestamp, bucket.value as f64));
+                }
+            }
+        }
+        for (le, points) in buckets {
+            if le_matchers.iter().all(|matcher| matcher.matches(&le)) {
+                let mut labels = labels.clone();
+                labels.insert(String::from("le"), le);
+                selected.push(Series { labels, points });
+            }
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::parse;
+    use super::*;
+    use crate::prom::test_data::history_of;
+
+    /// Two scrapes, 60s apart, of requests counters, a gauge and a latency histogram.
+    fn history() -> MetricHistory {
+        let scrape = |requests: [f64; 3], le_01: u64, le_1: u64, count: u64| {
+            format!(
+                "# HELP http_requests_total Requests.\n\
+                 # TYPE http_requests_total counter\n\
+                 http_requests_total{{code=\"200\",job=\"api\"}} {}\n\
+                 http_requests_total{{code=\"500\",job=\"api\"}} {}\n\
+                 http_requests_total{{code=\"200\",job=\"web\"}} {}\n\
+                 # HELP memory_bytes Memory.\n\
+                 # TYPE memory_bytes gauge\n\
+                 memory_bytes{{job=\"api\"}} 100\n\
+                 memory_bytes{{job=\"web\"}} 300\n\
+                 # HELP latency_seconds Latency.\n\
+                 # TYPE latency_seconds histogram\n\
+                 latency_seconds_bucket{{le=\"0.1\"}} {}\n\
+                 latency_seconds_bucket{{le=\"1\"}} {}\n\
+                 latency_seconds_bucket{{le=\"+Inf\"}} {}\n\
+                 latency_seconds_sum 10\n\
+                 latency_seconds_count {}\n",
+                requests[0], requests[1], requests[2], le_01, le_1, count, count
+            )
+        };
+        history_of([
+            (1000, scrape([0.0, 0.0, 0.0], 0, 0, 0)),
+            (1060, scrape([120.0, 60.0, 30.0], 50, 90, 100)),
+        ])
+    }
+
+    fn eval_at(expression: &str, time: u64) -> Value {
+        let expr = parse(expression).unwrap();
+        let history = history();
+        Evaluator::new(&expr, &history, 0)
+            .eval(&expr, time)
+            .unwrap()
+    }
+
+    /// The samples of the vector as `(labels, value)`, sorted by labels.
+    fn vector_at(expression: &str, time: u64) -> Vec<(String, f64)> {
+        match eval_at(expression, time) {
+            Value::Vector(samples) => {
+                let mut samples: Vec<(String, f64)> = samples
+                    .into_iter()
+                    .map(|sample| (format_labels(&sample.labels), sample.value))
+                    .collect();
+                samples.sort_by(|a, b| a.0.cmp(&b.0));
+                samples
+            }
+            Value::Scalar(value) => panic!("expected a vector, got {}", value),
+        }
+    }
+
+    #[test]
+    fn test_eval_selectors() {
+        assert_eq!(
+            vector_at("memory_bytes", 1060),
+            vec![
+                (
+                    String::from("{__name__=\"memory_bytes\", job=\"api\"}"),
+                    100.0
+                ),
+                (
+                    String::from("{__name__=\"memory_bytes\", job=\"web\"}"),
+                    300.0
+                ),
+            ]
+        );
+        // no sample within the lookback delta
+        assert!(vector_at("memory_bytes", 1400).is_empty());
+        assert_eq!(
+            vector_at("latency_seconds_bucket{le=~\"0.1|1\"}", 1060),
+            vec![
+                (
+                    String::from("{__name__=\"latency_seconds_bucket\", le=\"0.1\"}"),
+                    50.0
+                ),
+                (
+                    String::from("{__name__=\"latency_seconds_bucket\", le=\"1\"}"),
+                    90.0
+                ),
+            ]
+        );
+        assert_eq!(
+            vector_at("{__name__=~\"latency_seconds_(sum|count)\"}", 1060),
+            vec![
+                (String::from("{__name__=\"latency_seconds_count\"}"), 100.0),
+                (String::from("{__name__=\"latency_seconds_sum\"}"), 10.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eval_rate_and_aggregations() {
+        assert_eq!(
+            vector_at("sum by (code) (rate(http_requests_total[2m]))", 1060),
+            vec![
+                (String::from("{code=\"200\"}"), 1.25),
+                (String::from("{code=\"500\"}"), 0.5),
+            ]
+        );
+        assert_eq!(
+            vector_at("topk(1, increase(http_requests_total[2m]))", 1060),
+            vec![(String::from("{code=\"200\", job=\"api\"}"), 120.0)]
+        );
+        assert_eq!(
+            vector_at("avg(memory_bytes) without (job)", 1060),
+            vec![(String::from("{}"), 200.0)]
+        );
+        // 70 of 100 observations, half way into the bucket from 0.1 to 1
+        let p70 = vector_at(
+            "histogram_quantile(0.7, rate(latency_seconds_bucket[2m]))",
+            1060,
+        );
+        assert!((p70[0].1 - 0.55).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_eval_binary_operations() {
+        assert_eq!(
+            vector_at(
+                "increase(http_requests_total{code=\"500\"}[2m]) / ignoring(code) increase(http_requests_total{code=\"200\"}[2m])",
+                1060
+            ),
+            vec![(String::from("{job=\"api\"}"), 0.5)]
+        );
+        assert_eq!(
+            vector_at("memory_bytes > 200", 1060),
+            vec![(
+                String::from("{__name__=\"memory_bytes\", job=\"web\"}"),
+                300.0
+            )]
+        );
+        assert_eq!(
+            vector_at("memory_bytes > bool 200", 1060),
+            vec![
+                (String::from("{job=\"api\"}"), 0.0),
+                (String::from("{job=\"web\"}"), 1.0),
+            ]
+        );
+        assert_eq!(
+            vector_at(
+                "memory_bytes unless on(job) http_requests_total{job=\"api\"}",
+                1060
+            ),
+            vec![(
+                String::from("{__name__=\"memory_bytes\", job=\"web\"}"),
+                300.0
+            )]
+        );
+        assert!(matches!(eval_at("2 ^ 3 - 1", 1060), Value::Scalar(value) if value == 7.0));
+
+        let expr = parse("http_requests_total + on(job) memory_bytes").unwrap();
+        let history = history();
+        let error = Evaluator::new(&expr, &history, 0)
+            .eval(&expr, 1060)
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("multiple matches for labels {job=\"api\"}"));
+    }
+
+    #[test]
+    fn test_evaluate_range() {
+        let expr = parse("sum(memory_bytes)").unwrap();
+        let series = evaluate_range(&expr, &history(), 20).unwrap();
+        assert_eq!(
+            series,
+            vec![Series {
+                labels: Labels::new(),
+                points: vec![(1000, 400.0), (1020, 400.0), (1040, 400.0), (1060, 400.0)],
+            }]
+        );
+    }
+}
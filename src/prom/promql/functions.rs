@@ -0,0 +1,232 @@
+//! The math behind the functions and aggregations, following the implementation of Prometheus.
+
+/// The increase of the samples over the range `(range_start, range_end]`, extrapolated to the
+/// boundaries of the range, like `delta()`, `increase()` and `rate()` in Prometheus. For
+/// counters, resets are compensated and the extrapolation stops where the counter would be zero.
+pub fn extrapolated_rate(
+    points: &[(u64, f64)],
+    range_start: f64,
+    range_end: f64,
+    is_counter: bool,
+    is_rate: bool,
+) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let (first_time, first_value) = (points[0].0 as f64, points[0].1);
+    let (last_time, last_value) = (
+        points[points.len() - 1].0 as f64,
+        points[points.len() - 1].1,
+    );
+    let mut result = last_value - first_value;
+    if is_counter {
+        for pair in points.windows(2) {
+            if pair[1].1 < pair[0].1 {
+                result += pair[0].1;
+            }
+        }
+    }
+
+    let mut duration_to_start = first_time - range_start;
+    let duration_to_end = range_end - last_time;
+    let sampled_interval = last_time - first_time;
+    if sampled_interval <= 0.0 {
+        return None;
+    }
+    let average_duration_between_samples = sampled_interval / (points.len() - 1) as f64;
+
+    // a counter does not go below zero, do not extrapolate further than its start
+    if is_counter && result > 0.0 && first_value >= 0.0 {
+        let duration_to_zero = sampled_interval * (first_value / result);
+        if duration_to_zero < duration_to_start {
+            duration_to_start = duration_to_zero;
+        }
+    }
+
+    // extrapolate to the boundary of the range if the samples are close to it, otherwise only
+    // by half of the average interval between the samples
+    let extrapolation_threshold = average_duration_between_samples * 1.1;
+    let mut extrapolate_to_interval = sampled_interval;
+    extrapolate_to_interval += if duration_to_start < extrapolation_threshold {
+        duration_to_start
+    } else {
+        average_duration_between_samples / 2.0
+    };
+    extrapolate_to_interval += if duration_to_end < extrapolation_threshold {
+        duration_to_end
+    } else {
+        average_duration_between_samples / 2.0
+    };
+    result *= extrapolate_to_interval / sampled_interval;
+    if is_rate {
+        result /= range_end - range_start;
+    }
+    Some(result)
+}
+
+/// The per-second rate between the last two samples, like `irate()`.
+pub fn instant_rate(points: &[(u64, f64)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let (previous, last) = (points[points.len() - 2], points[points.len() - 1]);
+    let elapsed = last
+        .0
+        .checked_sub(previous.0)
+        .filter(|elapsed| *elapsed > 0)? as f64;
+    // after a counter reset, the last value is the increase since the reset
+    let increase = if last.1 < previous.1 {
+        last.1
+    } else {
+        last.1 - previous.1
+    };
+    Some(increase / elapsed)
+}
+
+/// Estimates the quantile from cumulative bucket counts given as `(upper bound, count)`, like
+/// `histogram_quantile()`. The observations are assumed to be spread linearly within a bucket.
+/// The buckets must end with the `+Inf` bucket, otherwise the result is NaN.
+pub fn bucket_quantile(quantile: f64, buckets: &[(f64, f64)]) -> f64 {
+    if quantile.is_nan() {
+        return f64::NAN;
+    }
+    if quantile < 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if quantile > 1.0 {
+        return f64::INFINITY;
+    }
+    let mut buckets = buckets.to_vec();
+    buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if buckets.len() < 2 || buckets[buckets.len() - 1].0 != f64::INFINITY {
+        return f64::NAN;
+    }
+    // the counts of the buckets are scraped at slightly different times and may not be
+    // monotonic, which would break the interpolation
+    for index in 1..buckets.len() {
+        if buckets[index].1 < buckets[index - 1].1 {
+            buckets[index].1 = buckets[index - 1].1;
+        }
+    }
+
+    let observations = buckets[buckets.len() - 1].1;
+    if observations == 0.0 {
+        return f64::NAN;
+    }
+    let mut rank = quantile * observations;
+    let bucket = buckets
+        .iter()
+        .position(|(_, count)| *count >= rank)
+        .unwrap_or(buckets.len() - 1);
+    if bucket == buckets.len() - 1 {
+        // the quantile is in the +Inf bucket, return the highest finite bound
+        return buckets[buckets.len() - 2].0;
+    }
+    if bucket == 0 && buckets[0].0 <= 0.0 {
+        return buckets[0].0;
+    }
+    let mut bucket_start = 0.0;
+    let bucket_end = buckets[bucket].0;
+    let mut count = buckets[bucket].1;
+    if bucket > 0 {
+        bucket_start = buckets[bucket - 1].0;
+        count -= buckets[bucket - 1].1;
+        rank -= buckets[bucket - 1].1;
+    }
+    bucket_start + (bucket_end - bucket_start) * (rank / count)
+}
+
+/// The quantile of the values, interpolated between the closest ranks like `quantile()`.
+pub fn quantile(quantile: f64, values: &[f64]) -> f64 {
+    if values.is_empty() || quantile.is_nan() {
+        return f64::NAN;
+    }
+    if quantile < 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if quantile > 1.0 {
+        return f64::INFINITY;
+    }
+    let mut values = values.to_vec();
+    values.sort_by(|a, b| a.total_cmp(b));
+    let rank = quantile * (values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = (lower + 1).min(values.len() - 1);
+    let weight = rank - rank.floor();
+    values[lower] * (1.0 - weight) + values[upper] * weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extrapolated_rate_of_counter() {
+        // scraped every 10s over a 60s range, increasing by 10 per scrape
+        let points: Vec<(u64, f64)> = (0..6)
+            .map(|i| (1005 + i * 10, 100.0 + i as f64 * 10.0))
+            .collect();
+        let rate = extrapolated_rate(&points, 1000.0, 1060.0, true, true).unwrap();
+        assert!((rate - 1.0).abs() < 1e-9, "rate is {}", rate);
+        let increase = extrapolated_rate(&points, 1000.0, 1060.0, true, false).unwrap();
+        assert!((increase - 60.0).abs() < 1e-9, "increase is {}", increase);
+    }
+
+    #[test]
+    fn test_extrapolated_rate_with_counter_reset() {
+        let points = vec![(1000, 10.0), (1010, 20.0), (1020, 5.0), (1030, 15.0)];
+        // 10 before the reset, 5 since the restart, 10 after
+        let increase = extrapolated_rate(&points, 1000.0, 1030.0, true, false).unwrap();
+        assert_eq!(increase, 25.0);
+        // a gauge going down is not a reset
+        let delta = extrapolated_rate(&points, 1000.0, 1030.0, false, false).unwrap();
+        assert_eq!(delta, 5.0);
+        assert_eq!(
+            extrapolated_rate(&points[..1], 1000.0, 1030.0, true, true),
+            None
+        );
+    }
+
+    #[test]
+    fn test_extrapolation_stops_at_zero() {
+        // the counter started shortly before the first sample
+        let points = vec![(1050, 2.0), (1060, 12.0)];
+        let increase = extrapolated_rate(&points, 1000.0, 1060.0, true, false).unwrap();
+        assert_eq!(increase, 12.0);
+    }
+
+    #[test]
+    fn test_instant_rate() {
+        assert_eq!(instant_rate(&[(1000, 5.0), (1010, 25.0)]), Some(2.0));
+        assert_eq!(instant_rate(&[(1000, 50.0), (1010, 20.0)]), Some(2.0));
+        assert_eq!(instant_rate(&[(1000, 5.0)]), None);
+    }
+
+    #[test]
+    fn test_bucket_quantile() {
+        let buckets = [
+            (0.1, 50.0),
+            (0.5, 90.0),
+            (1.0, 100.0),
+            (f64::INFINITY, 100.0),
+        ];
+        assert_eq!(bucket_quantile(0.5, &buckets), 0.1);
+        assert!((bucket_quantile(0.7, &buckets) - 0.3).abs() < 1e-9);
+        assert!((bucket_quantile(0.95, &buckets) - 0.75).abs() < 1e-9);
+        assert_eq!(bucket_quantile(1.5, &buckets), f64::INFINITY);
+        // in the +Inf bucket, the highest finite bound is returned
+        let buckets = [(0.1, 50.0), (f64::INFINITY, 100.0)];
+        assert_eq!(bucket_quantile(0.99, &buckets), 0.1);
+        // without +Inf bucket or observations, the quantile is unknown
+        assert!(bucket_quantile(0.5, &[(0.1, 50.0), (0.5, 90.0)]).is_nan());
+        assert!(bucket_quantile(0.5, &[(0.1, 0.0), (f64::INFINITY, 0.0)]).is_nan());
+    }
+
+    #[test]
+    fn test_quantile() {
+        assert_eq!(quantile(0.5, &[4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert_eq!(quantile(0.0, &[4.0, 1.0]), 1.0);
+        assert_eq!(quantile(1.0, &[4.0, 1.0]), 4.0);
+        assert!(quantile(0.5, &[]).is_nan());
+    }
+}
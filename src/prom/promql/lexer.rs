@@ -0,0 +1,343 @@
+use super::ParseError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    /// metric, label and function names, and the keywords like `by` or `and`
+    Identifier(String),
+    Number(f64),
+    /// in seconds
+    Duration(f64),
+    String(String),
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eql,
+    Neq,
+    Gtr,
+    Lss,
+    Gte,
+    Lte,
+    Assign,
+    RegexMatch,
+    RegexNoMatch,
+    Eof,
+}
+
+impl TokenKind {
+    /// How the token is shown in error messages.
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Identifier(name) => format!("'{}'", name),
+            TokenKind::Number(number) => format!("number {}", number),
+            TokenKind::Duration(_) => String::from("duration"),
+            TokenKind::String(value) => format!("string {:?}", value),
+            TokenKind::Eof => String::from("end of input"),
+            symbol => format!("'{}'", symbol.symbol()),
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            TokenKind::LeftParen => "(",
+            TokenKind::RightParen => ")",
+            TokenKind::LeftBrace => "{",
+            TokenKind::RightBrace => "}",
+            TokenKind::LeftBracket => "[",
+            TokenKind::RightBracket => "]",
+            TokenKind::Comma => ",",
+            TokenKind::Add => "+",
+            TokenKind::Sub => "-",
+            TokenKind::Mul => "*",
+            TokenKind::Div => "/",
+            TokenKind::Mod => "%",
+            TokenKind::Pow => "^",
+            TokenKind::Eql => "==",
+            TokenKind::Neq => "!=",
+            TokenKind::Gtr => ">",
+            TokenKind::Lss => "<",
+            TokenKind::Gte => ">=",
+            TokenKind::Lte => "<=",
+            TokenKind::Assign => "=",
+            TokenKind::RegexMatch => "=~",
+            TokenKind::RegexNoMatch => "!~",
+            _ => "",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// byte offset of the token in the expression
+    pub position: usize,
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut index = 0;
+    while index < chars.len() {
+        let (position, c) = chars[index];
+        let next = chars.get(index + 1).map(|(_, c)| *c);
+        let mut symbol = |kind, length| {
+            tokens.push(Token { kind, position });
+            index += length;
+        };
+        match (c, next) {
+            (c, _) if c.is_whitespace() => index += 1,
+            ('(', _) => symbol(TokenKind::LeftParen, 1),
+            (')', _) => symbol(TokenKind::RightParen, 1),
+            ('{', _) => symbol(TokenKind::LeftBrace, 1),
+            ('}', _) => symbol(TokenKind::RightBrace, 1),
+            ('[', _) => symbol(TokenKind::LeftBracket, 1),
+            (']', _) => symbol(TokenKind::RightBracket, 1),
+            (',', _) => symbol(TokenKind::Comma, 1),
+            ('+', _) => symbol(TokenKind::Add, 1),
+            ('-', _) => symbol(TokenKind::Sub, 1),
+            ('*', _) => symbol(TokenKind::Mul, 1),
+            ('/', _) => symbol(TokenKind::Div, 1),
+            ('%', _) => symbol(TokenKind::Mod, 1),
+            ('^', _) => symbol(TokenKind::Pow, 1),
+            ('=', Some('=')) => symbol(TokenKind::Eql, 2),
+            ('=', Some('~')) => symbol(TokenKind::RegexMatch, 2),
+            ('=', _) => symbol(TokenKind::Assign, 1),
+            ('!', Some('=')) => symbol(TokenKind::Neq, 2),
+            ('!', Some('~')) => symbol(TokenKind::RegexNoMatch, 2),
+            ('>', Some('=')) => symbol(TokenKind::Gte, 2),
+            ('>', _) => symbol(TokenKind::Gtr, 1),
+            ('<', Some('=')) => symbol(TokenKind::Lte, 2),
+            ('<', _) => symbol(TokenKind::Lss, 1),
+            ('"', _) | ('\'', _) | ('`', _) => {
+                let (value, end) = lex_string(&chars, index, input.len())?;
+                tokens.push(Token {
+                    kind: TokenKind::String(value),
+                    position,
+                });
+                index = end;
+            }
+            (c, _)
+                if c.is_ascii_digit()
+                    || (c == '.' && matches!(next, Some(n) if n.is_ascii_digit())) =>
+            {
+                let (kind, end) = lex_number_or_duration(input, &chars, index)?;
+                tokens.push(Token { kind, position });
+                index = end;
+            }
+            (c, _) if c.is_ascii_alphabetic() || c == '_' || c == ':' => {
+                let start = index;
+                while index < chars.len()
+                    && (chars[index].1.is_ascii_alphanumeric()
+                        || chars[index].1 == '_'
+                        || chars[index].1 == ':')
+                {
+                    index += 1;
+                }
+                let end = chars.get(index).map_or(input.len(), |(p, _)| *p);
+                tokens.push(Token {
+                    kind: TokenKind::Identifier(input[chars[start].0..end].to_string()),
+                    position,
+                });
+            }
+            (c, _) => {
+                return Err(ParseError::new(
+                    position,
+                    format!("unexpected character '{}'", c),
+                ))
+            }
+        }
+    }
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        position: input.len(),
+    });
+    Ok(tokens)
+}
+
+/// Returns the unescaped string and the index of the first character after the closing quote.
+fn lex_string(
+    chars: &[(usize, char)],
+    start: usize,
+    input_len: usize,
+) -> Result<(String, usize), ParseError> {
+    let quote = chars[start].1;
+    let mut value = String::new();
+    let mut index = start + 1;
+    while let Some((_, c)) = chars.get(index) {
+        index += 1;
+        match *c {
+            c if c == quote => return Ok((value, index)),
+            // raw strings in backticks have no escape sequences
+            '\\' if quote != '`' => {
+                let escaped = chars.get(index).map(|(_, c)| *c);
+                index += 1;
+                match escaped {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(escaped) => value.push(escaped),
+                    None => break,
+                }
+            }
+            c => value.push(c),
+        }
+    }
+    Err(ParseError::new(
+        chars.get(start).map_or(input_len, |(p, _)| *p),
+        String::from("unterminated string"),
+    ))
+}
+
+/// A number directly followed by a unit is a duration, e.g. `5m` or `1h30m`.
+fn lex_number_or_duration(
+    input: &str,
+    chars: &[(usize, char)],
+    start: usize,
+) -> Result<(TokenKind, usize), ParseError> {
+    let position_at = |index: usize| chars.get(index).map_or(input.len(), |(p, _)| *p);
+    let mut index = start;
+    while index < chars.len() && (chars[index].1.is_ascii_digit() || chars[index].1 == '.') {
+        index += 1;
+    }
+    // exponent, e.g. 1e3 or 2.5E-4
+    if matches!(chars.get(index), Some((_, 'e')) | Some((_, 'E')))
+        && is_char_at(chars, index + 1, |c| {
+            c.is_ascii_digit() || c == '-' || c == '+'
+        })
+    {
+        index += 2;
+        while index < chars.len() && chars[index].1.is_ascii_digit() {
+            index += 1;
+        }
+    }
+    let number_text = &input[position_at(start)..position_at(index)];
+    let number: f64 = number_text.parse().map_err(|_| {
+        ParseError::new(
+            position_at(start),
+            format!("invalid number '{}'", number_text),
+        )
+    })?;
+
+    if !is_char_at(chars, index, |c| c.is_ascii_alphabetic()) {
+        return Ok((TokenKind::Number(number), index));
+    }
+
+    // a duration is made of integers followed by their units
+    let mut seconds = 0.0;
+    let mut index = start;
+    while is_char_at(chars, index, |c| c.is_ascii_digit()) {
+        let digits_start = index;
+        while is_char_at(chars, index, |c| c.is_ascii_digit()) {
+            index += 1;
+        }
+        let amount: f64 = input[position_at(digits_start)..position_at(index)]
+            .parse()
+            .expect("digits to be a number");
+        let unit_start = index;
+        while is_char_at(chars, index, |c| c.is_ascii_alphabetic()) {
+            index += 1;
+        }
+        let unit = &input[position_at(unit_start)..position_at(index)];
+        let unit_seconds = match unit {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            "w" => 604800.0,
+            "y" => 31536000.0,
+            _ => {
+                return Err(ParseError::new(
+                    position_at(start),
+                    format!(
+                        "invalid duration '{}', the units are ms, s, m, h, d, w and y",
+                        &input[position_at(start)..position_at(index)]
+                    ),
+                ))
+            }
+        };
+        seconds += amount * unit_seconds;
+    }
+    if is_char_at(chars, index, |c| c.is_ascii_alphanumeric() || c == '.') {
+        return Err(ParseError::new(
+            position_at(start),
+            String::from("invalid duration"),
+        ));
+    }
+    Ok((TokenKind::Duration(seconds), index))
+}
+
+/// Whether the character at the index exists and matches the predicate.
+fn is_char_at(chars: &[(usize, char)], index: usize, predicate: impl Fn(char) -> bool) -> bool {
+    matches!(chars.get(index), Some((_, c)) if predicate(*c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_expression() {
+        assert_eq!(
+            kinds(r#"rate(http_requests_total{code=~"5.."}[1h30m]) >= 1.5e3"#),
+            vec![
+                TokenKind::Identifier(String::from("rate")),
+                TokenKind::LeftParen,
+                TokenKind::Identifier(String::from("http_requests_total")),
+                TokenKind::LeftBrace,
+                TokenKind::Identifier(String::from("code")),
+                TokenKind::RegexMatch,
+                TokenKind::String(String::from("5..")),
+                TokenKind::RightBrace,
+                TokenKind::LeftBracket,
+                TokenKind::Duration(5400.0),
+                TokenKind::RightBracket,
+                TokenKind::RightParen,
+                TokenKind::Gte,
+                TokenKind::Number(1500.0),
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(
+            kinds("a!=b/.5"),
+            vec![
+                TokenKind::Identifier(String::from("a")),
+                TokenKind::Neq,
+                TokenKind::Identifier(String::from("b")),
+                TokenKind::Div,
+                TokenKind::Number(0.5),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        let error = tokenize("up{job=\"api}").err().unwrap();
+        assert_eq!(error.position, 7);
+        assert_eq!(error.message, "unterminated string");
+
+        let error = tokenize("rate(up[5x])").err().unwrap();
+        assert_eq!(error.position, 8);
+        assert!(error.message.starts_with("invalid duration '5x'"));
+
+        let error = tokenize("up # comment").err().unwrap();
+        assert_eq!(error.message, "unexpected character '#'");
+    }
+}
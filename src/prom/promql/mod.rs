@@ -0,0 +1,226 @@
+//! A subset of PromQL, evaluated against the local history instead of a Prometheus server.
+//!
+//! Supported are vector and range selectors, number literals, the arithmetic, comparison and set
+//! operators with `bool`, `on` and `ignoring`, the aggregations `sum`, `avg`, `min`, `max`,
+//! `count`, `stddev`, `stdvar`, `topk`, `bottomk` and `quantile` with `by` and `without`, and the
+//! functions listed in `ast::Function`. Subqueries, `offset`, `@` and `group_left`/`group_right`
+//! are not.
+
+use std::fmt;
+use std::sync::Arc;
+
+use self::ast::Expr;
+use super::encoder::escape_label_value;
+use super::model::{
+    Metric, MetricDetails, MetricHistory, MetricType, Sample, SingleValueSample, TimeSeries,
+    NO_LABELS_KEY,
+};
+use super::storage::SampleStorage;
+
+mod ast;
+mod eval;
+pub(super) mod functions;
+mod lexer;
+mod parser;
+
+pub use self::eval::time_range;
+
+/// An error of the lexer or the parser, at a byte offset of the expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: String) -> Self {
+        Self { position, message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "parse error at position {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parsed expression, ready to be evaluated against the history.
+#[derive(Clone, Debug)]
+pub struct Query {
+    pub expression: String,
+    expr: Expr,
+}
+
+impl Query {
+    pub fn parse(expression: &str) -> Result<Self, ParseError> {
+        let expr = parser::parse(expression)?;
+        Ok(Self {
+            expression: expression.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// Evaluates the expression every `step` seconds over the time range of the history. The
+    /// result is a gauge named after the expression, with one time series per label set.
+    pub fn evaluate(&self, history: &MetricHistory, step: u64) -> anyhow::Result<Metric> {
+        let series = eval::evaluate_range(&self.expr, history, step)?;
+        Ok(into_metric(
+            MetricDetails {
+                name: self.expression.clone(),
+                docstring: String::from("Evaluated from the local history"),
+                metric_type: MetricType::Gauge,
+            },
+            series,
+        ))
+    }
+
+    /// Evaluates the expression every `step` seconds from `start` to `end`, into a gauge with
+    /// the given name. Like a recording rule of Prometheus, the metric name of the series is
+    /// dropped and the labels are added to them, replacing the ones of the result.
+    pub fn record(
+        &self,
+        history: &MetricHistory,
+        (start, end): (u64, u64),
+        step: u64,
+        name: &str,
+        labels: &[(String, String)],
+    ) -> anyhow::Result<Metric> {
+        let mut series = eval::evaluate_steps(&self.expr, history, start, end, step)?;
+        for series in &mut series {
+            series.labels.remove("__name__");
+            series.labels.extend(labels.iter().cloned());
+        }
+        Ok(into_metric(
+            MetricDetails {
+                name: name.to_string(),
+                docstring: format!("Recorded from {}", self.expression),
+                metric_type: MetricType::Gauge,
+            },
+            series,
+        ))
+    }
+
+    /// Evaluates the expression at the given time. The samples are returned with the key of their
+    /// labels, without the metric name.
+    pub fn evaluate_at(
+        &self,
+        history: &MetricHistory,
+        time: u64,
+    ) -> anyhow::Result<Vec<(String, f64)>> {
+        Ok(eval::evaluate_instant(&self.expr, history, time)?
+            .into_iter()
+            .map(|mut sample| {
+                sample.labels.remove("__name__");
+                (series_key(&sample.labels), sample.value)
+            })
+            .collect())
+    }
+}
+
+fn into_metric(details: MetricDetails, series: Vec<eval::Series>) -> Metric {
+    let mut metric = Metric::new(details);
+    for series in series {
+        let labels = Arc::new(
+            series
+                .labels
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        );
+        let samples: SampleStorage = series
+            .points
+            .into_iter()
+            .map(|(timestamp, value)| Sample::GaugeSample(SingleValueSample { timestamp, value }))
+            .collect();
+        metric.insert_time_series(series_key(&series.labels), TimeSeries { labels, samples });
+    }
+    metric
+}
+
+/// Parses a duration like `5m` or `1h30m` into seconds.
+pub fn parse_duration(input: &str) -> Result<u64, ParseError> {
+    let tokens = lexer::tokenize(input.trim())?;
+    match tokens.as_slice() {
+        [duration, end] => match (&duration.kind, &end.kind) {
+            (lexer::TokenKind::Duration(seconds), lexer::TokenKind::Eof) => {
+                Ok(seconds.round() as u64)
+            }
+            _ => Err(ParseError::new(
+                0,
+                format!("invalid duration '{}'", input.trim()),
+            )),
+        },
+        _ => Err(ParseError::new(
+            0,
+            format!("invalid duration '{}'", input.trim()),
+        )),
+    }
+}
+
+/// Like the keys of the scraped series, but with the metric name when the result keeps it, as
+/// the series of different metrics can be selected together.
+fn series_key(labels: &eval::Labels) -> String {
+    if labels.is_empty() {
+        return String::from(NO_LABELS_KEY);
+    }
+    labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::test_data::{history_of, jobs};
+
+    #[test]
+    fn test_evaluate_query_into_metric() {
+        let history = history_of(
+            [(1000, 1.0), (1010, 3.0), (1020, 8.0)]
+                .map(|(timestamp, value)| (timestamp, jobs(&[value, 1.0]))),
+        );
+
+        let metric = Query::parse(" jobs * 2 ")
+            .unwrap()
+            .evaluate(&history, 10)
+            .unwrap();
+        assert_eq!(metric.details.name, "jobs * 2");
+        let values: Vec<f64> = metric.time_series()["queue=\"a\""]
+            .samples
+            .iter()
+            .map(|sample| match sample {
+                Sample::GaugeSample(sample) => sample.value,
+                _ => panic!("expected a gauge sample"),
+            })
+            .collect();
+        assert_eq!(values, vec![2.0, 6.0, 16.0]);
+
+        // the series keep the metric name unless the expression drops it
+        let metric = Query::parse("jobs")
+            .unwrap()
+            .evaluate(&history, 10)
+            .unwrap();
+        assert!(metric
+            .time_series()
+            .contains_key("__name__=\"jobs\",queue=\"b\""));
+        let metric = Query::parse("sum(jobs)")
+            .unwrap()
+            .evaluate(&history, 10)
+            .unwrap();
+        assert_eq!(metric.time_series()[NO_LABELS_KEY].samples.len(), 3);
+
+        let error = Query::parse("sum(jobs").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "parse error at position 8: unexpected end of input, expected ',' or ')'"
+        );
+    }
+}
@@ -0,0 +1,584 @@
+use super::super::selector::{LabelMatcher, Selector};
+use super::ast::{AggregateOp, BinaryOp, Expr, Function, Grouping, ValueType, VectorMatching};
+use super::lexer::{tokenize, Token, TokenKind};
+use super::ParseError;
+
+/// Parses and type checks an expression.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        index: 0,
+    };
+    let expr = parser.parse_expr(0)?;
+    parser.expect(TokenKind::Eof, "the end of the expression")?;
+    if expr.value_type() == ValueType::Matrix {
+        return Err(ParseError::new(
+            0,
+            String::from(
+                "a range vector can only be used as function argument, \
+                 use e.g. rate() or avg_over_time() to graph it",
+            ),
+        ));
+    }
+    Ok(expr)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index]
+    }
+
+    fn peek_nth(&self, n: usize) -> &TokenKind {
+        let index = (self.index + n).min(self.tokens.len() - 1);
+        &self.tokens[index].kind
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.index].clone();
+        if self.index < self.tokens.len() - 1 {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        Err(ParseError::new(self.peek().position, message))
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ParseError> {
+        self.error(format!(
+            "unexpected {}, expected {}",
+            self.peek().kind.describe(),
+            expected
+        ))
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> Result<Token, ParseError> {
+        if self.peek().kind == kind {
+            Ok(self.next())
+        } else {
+            self.unexpected(expected)
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Identifier(name) if name == keyword)
+    }
+
+    fn parse_expr(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.binary_op() {
+                Some(op) if op.precedence() >= min_precedence => op,
+                _ => return Ok(lhs),
+            };
+            let position = self.next().position;
+            let return_bool = self.is_keyword("bool");
+            if return_bool {
+                if !op.is_comparison() {
+                    return self.error(String::from(
+                        "bool modifier can only be used on comparison operators",
+                    ));
+                }
+                self.next();
+            }
+            let matching = self.parse_vector_matching()?;
+            // all the operators are left associative, except the power
+            let next_precedence = if op == BinaryOp::Pow {
+                op.precedence()
+            } else {
+                op.precedence() + 1
+            };
+            let rhs = self.parse_expr(next_precedence)?;
+            lhs = check_binary(op, lhs, rhs, return_bool, matching, position)?;
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        Some(match &self.peek().kind {
+            TokenKind::Add => BinaryOp::Add,
+            TokenKind::Sub => BinaryOp::Sub,
+            TokenKind::Mul => BinaryOp::Mul,
+            TokenKind::Div => BinaryOp::Div,
+            TokenKind::Mod => BinaryOp::Mod,
+            TokenKind::Pow => BinaryOp::Pow,
+            TokenKind::Eql => BinaryOp::Eql,
+            TokenKind::Neq => BinaryOp::Neq,
+            TokenKind::Gtr => BinaryOp::Gtr,
+            TokenKind::Lss => BinaryOp::Lss,
+            TokenKind::Gte => BinaryOp::Gte,
+            TokenKind::Lte => BinaryOp::Lte,
+            TokenKind::Identifier(name) => match name.as_str() {
+                "and" => BinaryOp::And,
+                "or" => BinaryOp::Or,
+                "unless" => BinaryOp::Unless,
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    fn parse_vector_matching(&mut self) -> Result<Option<VectorMatching>, ParseError> {
+        let on = match &self.peek().kind {
+            TokenKind::Identifier(name) if name == "on" => true,
+            TokenKind::Identifier(name) if name == "ignoring" => false,
+            _ => return Ok(None),
+        };
+        self.next();
+        let labels = self.parse_label_list()?;
+        if self.is_keyword("group_left") || self.is_keyword("group_right") {
+            return self.error(String::from(
+                "group_left and group_right are not supported, only one-to-one matching is",
+            ));
+        }
+        Ok(Some(VectorMatching { on, labels }))
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek().kind {
+            TokenKind::Sub | TokenKind::Add => {
+                let negate = self.next().kind == TokenKind::Sub;
+                // the unary operators bind tighter than all binary operators except the power
+                let expr = self.parse_expr(BinaryOp::Pow.precedence())?;
+                if expr.value_type() == ValueType::Matrix {
+                    return self.error(String::from(
+                        "unary expression only allowed on scalars and instant vectors",
+                    ));
+                }
+                Ok(match (negate, expr) {
+                    (false, expr) => expr,
+                    (true, Expr::Number(number)) => Expr::Number(-number),
+                    (true, expr) => Expr::Negation(Box::new(expr)),
+                })
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Number(number) => {
+                self.next();
+                Ok(Expr::Number(number))
+            }
+            TokenKind::LeftParen => {
+                self.next();
+                let expr = self.parse_expr(0)?;
+                self.expect(TokenKind::RightParen, "')'")?;
+                if self.peek().kind == TokenKind::LeftBracket {
+                    return self.error(String::from("subqueries are not supported"));
+                }
+                Ok(expr)
+            }
+            TokenKind::LeftBrace => self.parse_selector(None, token.position),
+            TokenKind::Identifier(name) => {
+                if name.eq_ignore_ascii_case("inf") || name.eq_ignore_ascii_case("nan") {
+                    self.next();
+                    let number = if name.eq_ignore_ascii_case("inf") {
+                        f64::INFINITY
+                    } else {
+                        f64::NAN
+                    };
+                    return Ok(Expr::Number(number));
+                }
+                let starts_aggregation = matches!(self.peek_nth(1), TokenKind::LeftParen)
+                    || matches!(self.peek_nth(1), TokenKind::Identifier(keyword) if keyword == "by" || keyword == "without");
+                if let (Some(op), true) = (AggregateOp::from_name(&name), starts_aggregation) {
+                    self.next();
+                    return self.parse_aggregation(op, token.position);
+                }
+                if self.peek_nth(1) == &TokenKind::LeftParen {
+                    self.next();
+                    return match Function::from_name(&name) {
+                        Some(function) => self.parse_call(function, &name, token.position),
+                        None => Err(ParseError::new(
+                            token.position,
+                            format!("unknown function '{}'", name),
+                        )),
+                    };
+                }
+                self.next();
+                self.parse_selector(Some(name), token.position)
+            }
+            TokenKind::Duration(_) => self.error(String::from(
+                "unexpected duration, durations are only allowed in range selectors like [5m]",
+            )),
+            TokenKind::String(_) => self.error(String::from(
+                "unexpected string, strings are only allowed as label values",
+            )),
+            _ => self.unexpected("an expression"),
+        }
+    }
+
+    fn parse_selector(
+        &mut self,
+        metric: Option<String>,
+        position: usize,
+    ) -> Result<Expr, ParseError> {
+        let mut matchers = Vec::new();
+        if self.peek().kind == TokenKind::LeftBrace {
+            self.next();
+            while self.peek().kind != TokenKind::RightBrace {
+                matchers.push(self.parse_matcher()?);
+                if self.peek().kind != TokenKind::Comma {
+                    break;
+                }
+                self.next();
+            }
+            self.expect(TokenKind::RightBrace, "',' or '}'")?;
+        }
+        if metric.is_none() && matchers.iter().all(|matcher| matcher.matches("")) {
+            return Err(ParseError::new(
+                position,
+                String::from("vector selector must contain at least one non-empty matcher"),
+            ));
+        }
+        let selector = Selector { metric, matchers };
+        if self.is_keyword("offset") {
+            return self.error(String::from("offset modifiers are not supported"));
+        }
+        if self.peek().kind != TokenKind::LeftBracket {
+            return Ok(Expr::VectorSelector(selector));
+        }
+        self.next();
+        let range = match self.next().kind {
+            TokenKind::Duration(range) if range > 0.0 => range,
+            _ => {
+                self.index -= 1;
+                return self.unexpected("a duration like 5m in the range selector");
+            }
+        };
+        // the lexer reads the step of a subquery like [5m:1m] as an identifier
+        if matches!(&self.peek().kind, TokenKind::Identifier(name) if name.starts_with(':')) {
+            return self.error(String::from("subqueries are not supported"));
+        }
+        self.expect(TokenKind::RightBracket, "']'")?;
+        Ok(Expr::MatrixSelector(selector, range))
+    }
+
+    fn parse_matcher(&mut self) -> Result<LabelMatcher, ParseError> {
+        let token = self.next();
+        let name = match token.kind {
+            TokenKind::Identifier(name) => name,
+            _ => {
+                self.index -= 1;
+                return self.unexpected("a label name");
+            }
+        };
+        let op = match self.next().kind {
+            TokenKind::Assign => "=",
+            TokenKind::Neq => "!=",
+            TokenKind::RegexMatch => "=~",
+            TokenKind::RegexNoMatch => "!~",
+            _ => {
+                self.index -= 1;
+                return self.unexpected("one of '=', '!=', '=~', '!~'");
+            }
+        };
+        let value = match self.next().kind {
+            TokenKind::String(value) => value,
+            _ => {
+                self.index -= 1;
+                return self.unexpected("a quoted label value");
+            }
+        };
+        LabelMatcher::new(&name, op, &value).map_err(|err| {
+            ParseError::new(
+                token.position,
+                format!("invalid matcher for label '{}': {}", name, err),
+            )
+        })
+    }
+
+    fn parse_label_list(&mut self) -> Result<Vec<String>, ParseError> {
+        self.expect(TokenKind::LeftParen, "'(' starting the label list")?;
+        let mut labels = Vec::new();
+        while let TokenKind::Identifier(label) = &self.peek().kind {
+            labels.push(label.clone());
+            self.next();
+            if self.peek().kind != TokenKind::Comma {
+                break;
+            }
+            self.next();
+        }
+        self.expect(TokenKind::RightParen, "a label name or ')'")?;
+        Ok(labels)
+    }
+
+    fn parse_grouping(&mut self) -> Result<Option<Grouping>, ParseError> {
+        if self.is_keyword("by") {
+            self.next();
+            Ok(Some(Grouping::By(self.parse_label_list()?)))
+        } else if self.is_keyword("without") {
+            self.next();
+            Ok(Some(Grouping::Without(self.parse_label_list()?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `sum by (code) (expr)` or `sum(expr) by (code)`.
+    fn parse_aggregation(&mut self, op: AggregateOp, position: usize) -> Result<Expr, ParseError> {
+        let leading_grouping = self.parse_grouping()?;
+        let mut args = self.parse_args()?;
+        let grouping = match (leading_grouping, self.parse_grouping()?) {
+            (Some(_), Some(_)) => {
+                return self.error(String::from("the grouping can only be given once"))
+            }
+            (Some(grouping), None) | (None, Some(grouping)) => grouping,
+            (None, None) => Grouping::By(Vec::new()),
+        };
+        let expected_args = if op.has_param() { 2 } else { 1 };
+        if args.len() != expected_args {
+            return Err(ParseError::new(
+                position,
+                format!(
+                    "wrong number of arguments for aggregate expression, expected {}, got {}",
+                    expected_args,
+                    args.len()
+                ),
+            ));
+        }
+        let expr = args.pop().expect("the aggregated expression");
+        check_type(&expr, ValueType::Vector, "aggregation", position)?;
+        let param = args.pop();
+        if let Some(param) = &param {
+            check_type(param, ValueType::Scalar, "aggregation parameter", position)?;
+        }
+        Ok(Expr::Aggregate {
+            op,
+            grouping,
+            param: param.map(Box::new),
+            expr: Box::new(expr),
+        })
+    }
+
+    fn parse_call(
+        &mut self,
+        function: Function,
+        name: &str,
+        position: usize,
+    ) -> Result<Expr, ParseError> {
+        let args = self.parse_args()?;
+        let arg_types = function.arg_types();
+        if args.len() != arg_types.len() {
+            return Err(ParseError::new(
+                position,
+                format!(
+                    "expected {} argument(s) in call to '{}', got {}",
+                    arg_types.len(),
+                    name,
+                    args.len()
+                ),
+            ));
+        }
+        for (arg, expected) in args.iter().zip(arg_types) {
+            check_type(arg, *expected, &format!("call to '{}'", name), position)?;
+        }
+        Ok(Expr::Call { function, args })
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.expect(TokenKind::LeftParen, "'('")?;
+        let mut args = Vec::new();
+        if self.peek().kind != TokenKind::RightParen {
+            loop {
+                args.push(self.parse_expr(0)?);
+                if self.peek().kind != TokenKind::Comma {
+                    break;
+                }
+                self.next();
+            }
+        }
+        self.expect(TokenKind::RightParen, "',' or ')'")?;
+        Ok(args)
+    }
+}
+
+fn check_type(
+    expr: &Expr,
+    expected: ValueType,
+    context: &str,
+    position: usize,
+) -> Result<(), ParseError> {
+    let actual = expr.value_type();
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(ParseError::new(
+            position,
+            format!("expected type {} in {}, got {}", expected, context, actual),
+        ))
+    }
+}
+
+fn check_binary(
+    op: BinaryOp,
+    lhs: Expr,
+    rhs: Expr,
+    return_bool: bool,
+    matching: Option<VectorMatching>,
+    position: usize,
+) -> Result<Expr, ParseError> {
+    let (lhs_type, rhs_type) = (lhs.value_type(), rhs.value_type());
+    let error = |message: &str| Err(ParseError::new(position, String::from(message)));
+    if lhs_type == ValueType::Matrix || rhs_type == ValueType::Matrix {
+        return error("binary expression must contain only scalar and instant vector types");
+    }
+    let both_vectors = lhs_type == ValueType::Vector && rhs_type == ValueType::Vector;
+    if op.is_set_operator() && !both_vectors {
+        return error("set operator not allowed in binary scalar expression");
+    }
+    if matching.is_some() && !both_vectors {
+        return error("vector matching only allowed between instant vectors");
+    }
+    if op.is_comparison()
+        && !return_bool
+        && lhs_type == ValueType::Scalar
+        && rhs_type == ValueType::Scalar
+    {
+        return error("comparisons between scalars must use bool modifier");
+    }
+    Ok(Expr::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+        return_bool,
+        matching,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(input: &str) -> ParseError {
+        parse(input).expect_err("the expression to be invalid")
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        match parse("1 + 2 * 3 ^ 2 ^ 0.5").unwrap() {
+            Expr::Binary {
+                op: BinaryOp::Add,
+                rhs,
+                ..
+            } => match *rhs {
+                Expr::Binary {
+                    op: BinaryOp::Mul,
+                    rhs,
+                    ..
+                } => match *rhs {
+                    // the power is right associative
+                    Expr::Binary {
+                        op: BinaryOp::Pow,
+                        lhs,
+                        rhs,
+                        ..
+                    } => {
+                        assert!(matches!(*lhs, Expr::Number(n) if n == 3.0));
+                        assert!(matches!(
+                            *rhs,
+                            Expr::Binary {
+                                op: BinaryOp::Pow,
+                                ..
+                            }
+                        ));
+                    }
+                    other => panic!("unexpected {:?}", other),
+                },
+                other => panic!("unexpected {:?}", other),
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(parse("-2 ^ 2").unwrap(), Expr::Negation(_)));
+    }
+
+    #[test]
+    fn test_parse_aggregations_and_calls() {
+        for input in [
+            "sum by (code) (rate(http_requests_total[5m]))",
+            "sum(rate(http_requests_total[5m])) without (instance, job)",
+            "topk(5, avg_over_time(memory_bytes{pod=~\"api-.*\"}[1h]))",
+            "histogram_quantile(0.99, sum by (le) (rate(latency_bucket[5m])))",
+            "a / on(job) b > bool 0.5 or c unless ignoring(code) d",
+            "{__name__=~\"node_.*\", job!=\"\"}",
+        ] {
+            parse(input).unwrap_or_else(|err| panic!("failed to parse '{}': {}", input, err));
+        }
+        match parse("sum(x) by (a, b)").unwrap() {
+            Expr::Aggregate { grouping, .. } => assert_eq!(
+                grouping,
+                Grouping::By(vec![String::from("a"), String::from("b")])
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (input, position, message) in [
+            ("rate(x[5m)", 9, "unexpected ')', expected ']'"),
+            (
+                "rate(x)",
+                0,
+                "expected type range vector in call to 'rate', got instant vector",
+            ),
+            ("foo(x)", 0, "unknown function 'foo'"),
+            (
+                "sum(x[5m])",
+                0,
+                "expected type instant vector in aggregation, got range vector",
+            ),
+            (
+                "topk(x)",
+                0,
+                "wrong number of arguments for aggregate expression, expected 2, got 1",
+            ),
+            (
+                "1 > 2",
+                2,
+                "comparisons between scalars must use bool modifier",
+            ),
+            ("x + ", 4, "unexpected end of input, expected an expression"),
+            (
+                "x[5m]",
+                0,
+                "a range vector can only be used as function argument",
+            ),
+            (
+                "{job=\"\"}",
+                0,
+                "vector selector must contain at least one non-empty matcher",
+            ),
+            (
+                "x{job=\"a\" code=\"b\"}",
+                10,
+                "unexpected 'code', expected ',' or '}'",
+            ),
+            (
+                "x * on(a) group_left y",
+                10,
+                "group_left and group_right are not supported",
+            ),
+            ("x offset 5m", 2, "offset modifiers are not supported"),
+            ("(x)[5m:1m]", 3, "subqueries are not supported"),
+        ] {
+            let error = parse_error(input);
+            assert!(
+                error.message.starts_with(message) && error.position == position,
+                "'{}' should fail at {} with '{}', got {}",
+                input,
+                position,
+                message,
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use super::metric_scraper::{get_body_from_endpoint, get_timestamp_unix_epoch};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// First line of every recording. The archive stays readable text, so recordings can be
+/// inspected, edited and checked in as test fixtures.
+const RECORDING_HEADER: &str = "prom-tui recording v1";
+
+/// One entry of a recording: the raw exposition body of a scrape, or the error of a failed scrape.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordedScrape {
+    Body { timestamp: u64, body: String },
+    Error { timestamp: u64, message: String },
+}
+
+impl RecordedScrape {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            RecordedScrape::Body { timestamp, .. } | RecordedScrape::Error { timestamp, .. } => {
+                *timestamp
+            }
+        }
+    }
+}
+
+/// Appends scrapes to a recording. Every entry is written as a line `<kind> <timestamp> <length>`
+/// followed by `length` bytes of content and a newline.
+pub struct RecordingWriter {
+    writer: BufWriter<File>,
+}
+
+impl RecordingWriter {
+    /// Creates the recording, or appends to it if it already exists.
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let is_new = !path.as_ref().exists();
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = BufWriter::new(file);
+        if is_new {
+            writeln!(writer, "{}", RECORDING_HEADER)?;
+        }
+        Ok(Self { writer })
+    }
+
+    pub fn append(&mut self, scrape: &RecordedScrape) -> anyhow::Result<()> {
+        let (kind, timestamp, content) = match scrape {
+            RecordedScrape::Body { timestamp, body } => ("scrape", timestamp, body),
+            RecordedScrape::Error { timestamp, message } => ("error", timestamp, message),
+        };
+        writeln!(self.writer, "{} {} {}", kind, timestamp, content.len())?;
+        self.writer.write_all(content.as_bytes())?;
+        writeln!(self.writer)?;
+        // flush every scrape, so an interrupted recording is still readable
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub fn read_recording<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<RecordedScrape>> {
+    parse_recording(BufReader::new(File::open(path)?))
+}
+
+pub fn parse_recording<R: BufRead>(mut reader: R) -> anyhow::Result<Vec<RecordedScrape>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != RECORDING_HEADER {
+        return Err(anyhow::anyhow!("not a prom-tui recording"));
+    }
+
+    let mut scrapes = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (kind, timestamp, length) = match parts.as_slice() {
+            [kind, timestamp, length] => {
+                (*kind, timestamp.parse::<u64>()?, length.parse::<usize>()?)
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "invalid recording entry: '{}'",
+                    line.trim_end()
+                ))
+            }
+        };
+        // content plus the newline closing the entry
+        let mut content = vec![0; length + 1];
+        reader.read_exact(&mut content)?;
+        content.truncate(length);
+        let content = String::from_utf8(content)?;
+        scrapes.push(match kind {
+            "scrape" => RecordedScrape::Body {
+                timestamp,
+                body: content,
+            },
+            "error" => RecordedScrape::Error {
+                timestamp,
+                message: content,
+            },
+            _ => return Err(anyhow::anyhow!("unknown recording entry kind: '{}'", kind)),
+        });
+    }
+    Ok(scrapes)
+}
+
+/// Scrapes the endpoint every `scrape_interval` seconds and appends the raw bodies to the
+/// recording, until `max_scrapes` are recorded or forever if no limit is given.
+pub async fn record<P: AsRef<Path>>(
+    url: &str,
+    scrape_interval: u64,
+    path: P,
+    max_scrapes: Option<u64>,
+) -> anyhow::Result<()> {
+    let mut writer = RecordingWriter::open(path)?;
+    let tick_rate = Duration::from_millis(scrape_interval * 1000);
+    let mut recorded = 0;
+    while max_scrapes != Some(recorded) {
+        let last_tick = Instant::now();
+        let timestamp = get_timestamp_unix_epoch();
+        let scrape = match get_body_from_endpoint(url).await {
+            Ok(body) => RecordedScrape::Body { timestamp, body },
+            Err(err) => {
+                log::error!("Not able to scrape the metrics endpoint: {}", err);
+                RecordedScrape::Error {
+                    timestamp,
+                    message: err.to_string(),
+                }
+            }
+        };
+        writer.append(&scrape)?;
+        recorded += 1;
+        log::info!("Recorded scrape {} at {}", recorded, timestamp);
+        sleep(
+            tick_rate
+                .checked_sub(last_tick.elapsed())
+                .unwrap_or_default(),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read_recording() {
+        let path = std::env::temp_dir().join("prom-tui-test-write-and-read.recording");
+        let _ = std::fs::remove_file(&path);
+        let scrapes = vec![
+            RecordedScrape::Body {
+                timestamp: 1657000000,
+                body: "# HELP metric_1 Description\n# TYPE metric_1 gauge\nmetric_1 1\n"
+                    .to_string(),
+            },
+            RecordedScrape::Error {
+                timestamp: 1657000010,
+                message: "connection refused".to_string(),
+            },
+        ];
+        // appending to an existing recording keeps the previous scrapes
+        let mut writer = RecordingWriter::open(&path).unwrap();
+        writer.append(&scrapes[0]).unwrap();
+        drop(writer);
+        let mut writer = RecordingWriter::open(&path).unwrap();
+        writer.append(&scrapes[1]).unwrap();
+        drop(writer);
+
+        let read_scrapes = read_recording(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_scrapes, scrapes);
+    }
+
+    #[test]
+    fn test_read_truncated_recording() {
+        let path = std::env::temp_dir().join("prom-tui-test-truncated.recording");
+        std::fs::write(
+            &path,
+            "prom-tui recording v1\nscrape 1657000000 100\nmetric_1 1\n",
+        )
+        .unwrap();
+        let result = read_recording(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
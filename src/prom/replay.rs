@@ -0,0 +1,220 @@
+use super::{
+    metric_scraper::{split_body, update_error_status, update_history_with_new_scrape},
+    metric_source::{new_shared_history, publish, MetricSource, SharedHistory},
+    model::MetricHistory,
+    recording::RecordedScrape,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::{sync::Notify, task, time::sleep};
+
+/// How fast the recorded scrapes are replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// the time between two recorded scrapes is divided by the factor, 1.0 replays in real time
+    Factor(f64),
+    /// the next scrape is replayed on request only
+    Step,
+}
+
+/// Feeds recorded scrapes through the parser and into the history, as if they were scraped live
+/// at the time of the recording.
+pub struct ReplaySource {
+    metrics_history: SharedHistory,
+    error_msg: Arc<RwLock<Option<String>>>,
+    next_step: Arc<Notify>,
+    replayed: Arc<AtomicUsize>,
+    total: usize,
+    speed: ReplaySpeed,
+}
+
+impl ReplaySource {
+    pub fn new(scrapes: Vec<RecordedScrape>, speed: ReplaySpeed) -> Self {
+        let metrics_history = new_shared_history(MetricHistory::new());
+        let error_msg = Arc::new(RwLock::new(None));
+        let next_step = Arc::new(Notify::new());
+        let replayed = Arc::new(AtomicUsize::new(0));
+        let total = scrapes.len();
+
+        {
+            let shared_history = Arc::clone(&metrics_history);
+            let error_msg = Arc::clone(&error_msg);
+            let next_step = Arc::clone(&next_step);
+            let replayed = Arc::clone(&replayed);
+            task::spawn(async move {
+                let mut history = MetricHistory::new();
+                let mut previous_timestamp = None;
+                for scrape in scrapes {
+                    if let Some(previous_timestamp) = previous_timestamp {
+                        match speed {
+                            ReplaySpeed::Factor(factor) => {
+                                let elapsed = scrape.timestamp().saturating_sub(previous_timestamp);
+                                sleep(Duration::from_secs_f64(elapsed as f64 / factor)).await;
+                            }
+                            ReplaySpeed::Step => next_step.notified().await,
+                        }
+                    }
+                    previous_timestamp = Some(scrape.timestamp());
+                    replay_scrape(&mut history, &error_msg, scrape);
+                    publish(&shared_history, &history);
+                    replayed.fetch_add(1, Ordering::Relaxed);
+                }
+                log::info!("Replay finished");
+            });
+        }
+        Self {
+            metrics_history,
+            error_msg,
+            next_step,
+            replayed,
+            total,
+            speed,
+        }
+    }
+}
+
+impl MetricSource for ReplaySource {
+    fn history_snapshot(&self) -> Arc<MetricHistory> {
+        self.metrics_history.load_full()
+    }
+
+    fn get_error_msg_read_guard(&self) -> anyhow::Result<RwLockReadGuard<'_, Option<String>>> {
+        self.error_msg
+            .read()
+            .map_err(|err| anyhow::anyhow!("failed to aquire lock: {}", err))
+    }
+
+    fn step(&self) {
+        self.next_step.notify_one();
+    }
+
+    fn status(&self) -> Option<String> {
+        let speed = match self.speed {
+            ReplaySpeed::Factor(factor) => format!("{}x", factor),
+            ReplaySpeed::Step => String::from("step by step, press 'n' for the next scrape"),
+        };
+        Some(format!(
+            "Replaying scrape {} of {} ({})",
+            self.replayed.load(Ordering::Relaxed),
+            self.total,
+            speed
+        ))
+    }
+}
+
+/// Replays all the scrapes at once and returns the resulting history.
+pub fn replay_into_history(scrapes: Vec<RecordedScrape>) -> MetricHistory {
+    let mut history = MetricHistory::new();
+    let error_msg = Arc::new(RwLock::new(None));
+    for scrape in scrapes {
+        replay_scrape(&mut history, &error_msg, scrape);
+    }
+    history
+}
+
+/// Updates the history with a recorded scrape, using the timestamp of the recording.
+pub fn replay_scrape(
+    history: &mut MetricHistory,
+    error_msg: &Arc<RwLock<Option<String>>>,
+    scrape: RecordedScrape,
+) {
+    match scrape {
+        RecordedScrape::Body { timestamp, body } => {
+            update_history_with_new_scrape(history, split_body(&body), timestamp);
+            update_error_status(error_msg, None);
+        }
+        RecordedScrape::Error { timestamp, message } => {
+            history.add_scrape_error(timestamp, message.clone());
+            update_error_status(error_msg, Some(message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::{model::Sample, recording::parse_recording};
+
+    const RESTART_RECORDING: &str = include_str!("fixtures/restart.recording");
+
+    #[test]
+    fn test_replay_recording_fixture() {
+        let scrapes = parse_recording(RESTART_RECORDING.as_bytes()).unwrap();
+        assert_eq!(scrapes.len(), 4);
+
+        let mut history = MetricHistory::new();
+        let error_msg = Arc::new(RwLock::new(None));
+        let mut scrapes = scrapes.into_iter();
+        for scrape in scrapes.by_ref().take(3) {
+            replay_scrape(&mut history, &error_msg, scrape);
+        }
+        assert_eq!(
+            *error_msg.read().unwrap(),
+            Some(String::from(
+                "error sending request for url (http://localhost:8080/metrics): connection refused"
+            ))
+        );
+        replay_scrape(&mut history, &error_msg, scrapes.next().unwrap());
+        assert_eq!(*error_msg.read().unwrap(), None);
+
+        assert_eq!(history.metrics.len(), 3);
+        assert_eq!(history.scrape_errors.len(), 1);
+        assert_eq!(history.scrape_errors[0].timestamp, 1657000020);
+
+        let samples = &history
+            .get_metric("http_requests_total")
+            .unwrap()
+            .time_series()["code=\"200\""]
+            .samples;
+        let values: Vec<(u64, f64)> = samples
+            .iter()
+            .map(|sample| match sample {
+                Sample::CounterSample(sample) => (sample.timestamp, sample.value),
+                _ => panic!("expected a counter sample"),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![(1657000000, 100.0), (1657000010, 130.0), (1657000030, 5.0)]
+        );
+
+        let samples = &history
+            .get_metric("request_duration_seconds")
+            .unwrap()
+            .time_series()["single-value-with-no-labels"]
+            .samples;
+        assert_eq!(samples.len(), 3);
+        match &samples.get(1).unwrap() {
+            Sample::HistogramSample(histogram) => {
+                assert_eq!(histogram.count, 45);
+                assert_eq!(histogram.sum, 9.0);
+                assert_eq!(histogram.bucket_values[1].value, 40);
+            }
+            _ => panic!("expected a histogram sample"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_step_by_step() {
+        let scrapes = parse_recording(RESTART_RECORDING.as_bytes()).unwrap();
+        let source = ReplaySource::new(scrapes, ReplaySpeed::Step);
+        let replayed = |source: &ReplaySource| source.replayed.load(Ordering::Relaxed);
+
+        for expected in 1..=4 {
+            // give the replay task the time to process the step
+            for _ in 0..100 {
+                if replayed(&source) == expected {
+                    break;
+                }
+                sleep(Duration::from_millis(5)).await;
+            }
+            assert_eq!(replayed(&source), expected);
+            source.step();
+        }
+        assert_eq!(
+            source.status().unwrap(),
+            "Replaying scrape 4 of 4 (step by step, press 'n' for the next scrape)"
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::Arc;
+
+use super::alert::AlertRule;
+use super::model::{Metric, MetricHistory};
+use super::promql::{parse_duration, time_range, Query};
+
+/// A rule file of Prometheus, e.g.
+///
+/// ```yaml
+/// groups:
+///   - name: http
+///     rules:
+///       - record: job:http_requests:rate5m
+///         expr: sum by (job) (rate(http_requests_total[5m]))
+///       - alert: HighErrorRate
+///         expr: job:http_requests:rate5m > 100
+///         for: 10m
+///         labels:
+///           severity: page
+/// ```
+///
+/// The interval of the groups and the annotations of the rules are ignored.
+#[derive(Deserialize)]
+struct RuleFile {
+    groups: Vec<RuleGroup>,
+}
+
+#[derive(Deserialize)]
+struct RuleGroup {
+    name: String,
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Deserialize)]
+struct RuleConfig {
+    record: Option<String>,
+    alert: Option<String>,
+    expr: String,
+    #[serde(default, rename = "for")]
+    for_duration: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+/// Records the result of the expression as a metric of its own.
+#[derive(Clone, Debug)]
+pub struct RecordingRule {
+    pub name: String,
+    pub query: Query,
+    /// added to the series of the result
+    pub labels: Vec<(String, String)>,
+}
+
+/// The rules of Prometheus rule files. The rules with an expression outside of the supported
+/// PromQL are skipped, with the parse error by rule name.
+#[derive(Default)]
+pub struct RuleFiles {
+    pub recording_rules: Vec<RecordingRule>,
+    pub alert_rules: Vec<AlertRule>,
+    pub skipped_recording_rules: HashMap<String, String>,
+    pub skipped_alert_rules: HashMap<String, String>,
+}
+
+impl RuleFiles {
+    pub fn load(paths: &[&str]) -> anyhow::Result<Self> {
+        let mut rule_files = Self::default();
+        for path in paths {
+            let content = fs::read_to_string(path)
+                .map_err(|err| anyhow::anyhow!("failed to read the rule file {}: {}", path, err))?;
+            let file = Self::parse(&content).map_err(|err| err.context(path.to_string()))?;
+            rule_files.recording_rules.extend(file.recording_rules);
+            rule_files.alert_rules.extend(file.alert_rules);
+            rule_files
+                .skipped_recording_rules
+                .extend(file.skipped_recording_rules);
+            rule_files
+                .skipped_alert_rules
+                .extend(file.skipped_alert_rules);
+        }
+        Ok(rule_files)
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let file: RuleFile = serde_yaml::from_str(content)
+            .map_err(|err| anyhow::anyhow!("invalid rule file: {}", err))?;
+        let mut rule_files = Self::default();
+        for group in file.groups {
+            for rule in group.rules {
+                rule_files.add_rule(&group.name, rule)?;
+            }
+        }
+        Ok(rule_files)
+    }
+
+    fn add_rule(&mut self, group: &str, rule: RuleConfig) -> anyhow::Result<()> {
+        match (rule.record, rule.alert) {
+            (Some(name), None) => match Query::parse(&rule.expr) {
+                Ok(query) => self.recording_rules.push(RecordingRule {
+                    name,
+                    query,
+                    labels: rule.labels.into_iter().collect(),
+                }),
+                Err(err) => {
+                    self.skipped_recording_rules.insert(name, err.to_string());
+                }
+            },
+            (None, Some(name)) => {
+                let for_duration = match &rule.for_duration {
+                    Some(duration) => parse_duration(duration).map_err(|err| {
+                        anyhow::anyhow!("invalid for of rule '{}': {}", name, err)
+                    })?,
+                    None => 0,
+                };
+                match Query::parse(&rule.expr) {
+                    Ok(query) => self.alert_rules.push(AlertRule {
+                        severity: rule
+                            .labels
+                            .get("severity")
+                            .cloned()
+                            .unwrap_or_else(|| String::from("warning")),
+                        name,
+                        query,
+                        threshold: None,
+                        for_duration,
+                    }),
+                    Err(err) => {
+                        self.skipped_alert_rules.insert(name, err.to_string());
+                    }
+                }
+            }
+            _ => anyhow::bail!(
+                "a rule of group '{}' must have either 'record' or 'alert'",
+                group
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates the recording rules for every new generation of the history. The recorded series
+/// are only extended by the evaluations after the previous generation.
+pub struct Recorder {
+    pub rules: Vec<RecordingRule>,
+    /// the error of a rule, by recorded name, including the skipped rules
+    pub errors: HashMap<String, String>,
+    skipped: HashMap<String, String>,
+    /// a generation of the history and the same history with the recorded metrics
+    recorded: Option<(Arc<MetricHistory>, Arc<MetricHistory>)>,
+    /// the recorded metrics, by name
+    metrics: HashMap<String, Arc<Metric>>,
+    /// the times of the first and the last evaluation
+    evaluated: Option<(u64, u64)>,
+}
+
+impl Recorder {
+    pub fn new(rules: Vec<RecordingRule>, skipped: HashMap<String, String>) -> Self {
+        Self {
+            rules,
+            errors: skipped.clone(),
+            skipped,
+            recorded: None,
+            metrics: HashMap::new(),
+            evaluated: None,
+        }
+    }
+
+    /// The history with a metric per recorded name, evaluated every `step` seconds. The rules
+    /// are evaluated in order, so a rule can use the metrics recorded by the rules before it.
+    pub fn record(&mut self, history: &Arc<MetricHistory>, step: u64) -> Arc<MetricHistory> {
+        if let Some((source, recorded)) = &self.recorded {
+            if Arc::ptr_eq(source, history) {
+                return Arc::clone(recorded);
+            }
+        }
+        let step = step.max(1);
+        // the metrics are shared with the history, only the recorded ones are added
+        let mut recorded = MetricHistory::clone(history);
+        if let Some((start, end)) = time_range(history) {
+            let first = match self.evaluated {
+                Some((first, last)) if first <= start => last + step,
+                // older samples were loaded, e.g. by a backfill, everything is recorded again
+                _ => {
+                    self.metrics.clear();
+                    self.evaluated = Some((start, start));
+                    start
+                }
+            };
+            for (name, metric) in &self.metrics {
+                add_recorded(&mut recorded, history, name, metric);
+            }
+            if first <= end {
+                self.evaluate(&mut recorded, history, (first, end), step);
+                let last = end - (end - first) % step;
+                self.evaluated = self.evaluated.map(|(first, _)| (first, last));
+            }
+        }
+        let recorded = Arc::new(recorded);
+        self.recorded = Some((Arc::clone(history), Arc::clone(&recorded)));
+        recorded
+    }
+
+    /// Appends the results of the rules between the times to the recorded metrics.
+    fn evaluate(
+        &mut self,
+        recorded: &mut MetricHistory,
+        history: &MetricHistory,
+        range: (u64, u64),
+        step: u64,
+    ) {
+        self.errors = self.skipped.clone();
+        for rule in &self.rules {
+            match rule
+                .query
+                .record(recorded, range, step, &rule.name, &rule.labels)
+            {
+                // like Prometheus, nothing is recorded without result
+                Ok(metric) if metric.time_series().is_empty() => {}
+                Ok(metric) => {
+                    let existing = self
+                        .metrics
+                        .entry(rule.name.clone())
+                        .or_insert_with(|| Arc::new(Metric::new(metric.details.clone())));
+                    let existing = Arc::make_mut(existing);
+                    for (key, time_series) in metric.time_series() {
+                        match existing.time_series_mut(key) {
+                            Some(existing_series) => {
+                                for sample in time_series.samples.iter() {
+                                    existing_series.samples.push(sample);
+                                }
+                            }
+                            None => existing.insert_time_series(key.clone(), time_series.clone()),
+                        }
+                    }
+                    add_recorded(recorded, history, &rule.name, &self.metrics[&rule.name]);
+                }
+                Err(err) => {
+                    self.errors.insert(rule.name.clone(), err.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Adds a recorded metric to the history with the recorded metrics. Its series are added to
+/// the scraped metric of the same name, if any.
+fn add_recorded(
+    recorded: &mut MetricHistory,
+    history: &MetricHistory,
+    name: &str,
+    metric: &Arc<Metric>,
+) {
+    let metric = match history.metrics.get(name) {
+        Some(scraped) => {
+            let mut merged = Metric::clone(scraped);
+            for (key, time_series) in metric.time_series() {
+                merged.insert_time_series(key.clone(), time_series.clone());
+            }
+            Arc::new(merged)
+        }
+        None => Arc::clone(metric),
+    };
+    recorded.metrics.insert(name.to_string(), metric);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::alert::Alerting;
+    use crate::prom::model::{Sample, NO_LABELS_KEY};
+    use crate::prom::test_data::{jobs, scrape};
+    use crate::prom::AlertState;
+
+    const RULES: &str = r#"
+groups:
+  - name: jobs
+    interval: 30s
+    rules:
+      - record: queue:jobs:sum
+        expr: sum(jobs)
+        labels:
+          team: backend
+      - record: queue:jobs:double
+        expr: queue:jobs:sum * 2
+      - record: queue:jobs:old
+        expr: jobs offset 5m
+      - alert: TooManyJobs
+        expr: queue:jobs:sum > 25
+        for: 10s
+        labels:
+          severity: page
+        annotations:
+          summary: Too many jobs
+      - alert: NoJobs
+        expr: absent(jobs)
+"#;
+
+    fn values(history: &MetricHistory, metric: &str, key: &str) -> Vec<f64> {
+        history.metrics[metric].time_series()[key]
+            .samples
+            .iter()
+            .map(|sample| match sample {
+                Sample::GaugeSample(sample) => sample.value,
+                _ => panic!("expected a gauge sample"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_rule_files() {
+        let rule_files = RuleFiles::parse(RULES).unwrap();
+        let names: Vec<&str> = rule_files
+            .recording_rules
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["queue:jobs:sum", "queue:jobs:double"]);
+        assert!(rule_files
+            .skipped_recording_rules
+            .contains_key("queue:jobs:old"));
+        assert!(rule_files.skipped_alert_rules.contains_key("NoJobs"));
+        let alert = &rule_files.alert_rules[0];
+        assert_eq!(
+            (
+                alert.name.as_str(),
+                alert.severity.as_str(),
+                alert.for_duration
+            ),
+            ("TooManyJobs", "page", 10)
+        );
+        assert!(alert.threshold.is_none());
+
+        let both = RULES.replace("      - alert: NoJobs", "        alert: NoJobs");
+        assert!(RuleFiles::parse(&both).is_err());
+        let invalid_for = RULES.replace("for: 10s", "for: soon");
+        assert!(RuleFiles::parse(&invalid_for).is_err());
+    }
+
+    #[test]
+    fn test_record_and_alert() {
+        let rule_files = RuleFiles::parse(RULES).unwrap();
+        let mut recorder = Recorder::new(
+            rule_files.recording_rules,
+            rule_files.skipped_recording_rules,
+        );
+        let mut alerting = Alerting::new(rule_files.alert_rules);
+        let mut history = MetricHistory::new();
+        for (timestamp, a, b) in [(1000, 10.0, 5.0), (1010, 20.0, 10.0), (1020, 30.0, 5.0)] {
+            scrape(&mut history, timestamp, &jobs(&[a, b]));
+            let snapshot = Arc::new(history.clone());
+            let recorded = recorder.record(&snapshot, 10);
+            assert!(Arc::ptr_eq(&recorded, &recorder.record(&snapshot, 10)));
+            alerting.evaluate(&recorded);
+        }
+
+        let recorded = recorder.record(&Arc::new(history), 10);
+        let key = "team=\"backend\"";
+        assert_eq!(
+            values(&recorded, "queue:jobs:sum", key),
+            vec![15.0, 30.0, 35.0]
+        );
+        assert_eq!(
+            values(&recorded, "queue:jobs:double", key),
+            vec![30.0, 60.0, 70.0]
+        );
+        assert_eq!(recorder.errors.len(), 1);
+
+        // active since the second scrape, for at least ten seconds
+        let alerts = alerting.alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].state, AlertState::Firing);
+        assert_eq!(alerts[0].labels, key);
+        assert_eq!(alerts[0].value, 35.0);
+    }
+
+    #[test]
+    fn test_record_incrementally() {
+        let rules = RULES.replace("queue:jobs:sum * 2", "sum(max_over_time(jobs[30s]))");
+        let rule_files = RuleFiles::parse(&rules).unwrap();
+        let mut recorder = Recorder::new(rule_files.recording_rules.clone(), HashMap::new());
+        let mut history = MetricHistory::new();
+        let mut generations = Vec::new();
+        for (index, timestamp) in (1000..1200).step_by(10).enumerate() {
+            scrape(&mut history, timestamp, &jobs(&[index as f64 % 7.0, 1.0]));
+            generations.push(recorder.record(&Arc::new(history.clone()), 10));
+        }
+        // the published generations are not extended, the scraped metrics are shared
+        assert_eq!(
+            values(&generations[0], "queue:jobs:sum", "team=\"backend\"").len(),
+            1
+        );
+        let latest = generations.last().unwrap();
+        assert!(Arc::ptr_eq(
+            &latest.metrics["jobs"],
+            &history.metrics["jobs"]
+        ));
+
+        // the same as recording the whole history at once
+        let history = Arc::new(history);
+        let mut at_once = Recorder::new(rule_files.recording_rules.clone(), HashMap::new());
+        let recorded = at_once.record(&history, 10);
+        for (name, key) in [
+            ("queue:jobs:sum", "team=\"backend\""),
+            ("queue:jobs:double", NO_LABELS_KEY),
+        ] {
+            assert_eq!(values(latest, name, key), values(&recorded, name, key));
+        }
+        assert_eq!(values(latest, "queue:jobs:double", NO_LABELS_KEY).len(), 20);
+
+        // older samples are recorded again from the start
+        let mut backfilled = MetricHistory::new();
+        scrape(&mut backfilled, 990, &jobs(&[1.0, 1.0]));
+        scrape(&mut backfilled, 1000, &jobs(&[1.0, 1.0]));
+        let recorded = recorder.record(&Arc::new(backfilled), 10);
+        assert_eq!(
+            values(&recorded, "queue:jobs:sum", "team=\"backend\""),
+            vec![2.0, 2.0]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use regex::Regex;
+use std::fmt;
+
+/// How a label matcher compares the label value.
+#[derive(Clone, Debug)]
+pub enum MatchOp {
+    Equal,
+    NotEqual,
+    /// the regex is anchored at both ends, like in Prometheus
+    RegexMatch(Regex),
+    RegexNoMatch(Regex),
+}
+
+/// A single matcher of a selector, e.g. `status=~"5.."`.
+#[derive(Clone, Debug)]
+pub struct LabelMatcher {
+    pub name: String,
+    pub op: MatchOp,
+    pub value: String,
+}
+
+impl LabelMatcher {
+    pub fn new(name: &str, op: &str, value: &str) -> anyhow::Result<Self> {
+        let anchored = || Regex::new(&format!("^(?:{})$", value));
+        let op = match op {
+            "=" => MatchOp::Equal,
+            "!=" => MatchOp::NotEqual,
+            "=~" => MatchOp::RegexMatch(anchored()?),
+            "!~" => MatchOp::RegexNoMatch(anchored()?),
+            _ => return Err(anyhow::anyhow!("unknown matching operator '{}'", op)),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            op,
+            value: value.to_string(),
+        })
+    }
+
+    /// Whether the label value matches. A missing label has the empty string as value.
+    pub fn matches(&self, value: &str) -> bool {
+        match &self.op {
+            MatchOp::Equal => value == self.value,
+            MatchOp::NotEqual => value != self.value,
+            MatchOp::RegexMatch(regex) => regex.is_match(value),
+            MatchOp::RegexNoMatch(regex) => !regex.is_match(value),
+        }
+    }
+
+    fn op_str(&self) -> &'static str {
+        match self.op {
+            MatchOp::Equal => "=",
+            MatchOp::NotEqual => "!=",
+            MatchOp::RegexMatch(_) => "=~",
+            MatchOp::RegexNoMatch(_) => "!~",
+        }
+    }
+}
+
+impl fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{:?}", self.name, self.op_str(), self.value)
+    }
+}
+
+/// A Prometheus series selector: `metric{a="b", c!="d", e=~"re", f!~"re"}`. The metric name and
+/// the matchers are both optional.
+#[derive(Clone, Debug)]
+pub struct Selector {
+    pub metric: Option<String>,
+    pub matchers: Vec<LabelMatcher>,
+}
+
+impl Selector {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut parser = SelectorParser { input, position: 0 };
+        let selector = parser.parse_selector()?;
+        parser.skip_whitespace();
+        if parser.position < input.len() {
+            return Err(parser.error("unexpected characters after the selector"));
+        }
+        Ok(selector)
+    }
+
+    /// Whether the metric is selected, by the metric name or by `__name__` matchers.
+    pub fn matches_metric_name(&self, name: &str) -> bool {
+        self.metric.iter().all(|metric| metric == name)
+            && self
+                .matchers
+                .iter()
+                .filter(|matcher| matcher.name == "__name__")
+                .all(|matcher| matcher.matches(name))
+    }
+
+    /// The matchers applying to the labels of the time series.
+    pub fn label_matchers(&self) -> Vec<&LabelMatcher> {
+        self.matchers
+            .iter()
+            .filter(|matcher| matcher.name != "__name__")
+            .collect()
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(metric) = &self.metric {
+            write!(f, "{}", metric)?;
+        }
+        if !self.matchers.is_empty() || self.metric.is_none() {
+            let matchers: Vec<String> = self.matchers.iter().map(|m| m.to_string()).collect();
+            write!(f, "{{{}}}", matchers.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+struct SelectorParser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> SelectorParser<'a> {
+    fn parse_selector(&mut self) -> anyhow::Result<Selector> {
+        self.skip_whitespace();
+        let start = self.position;
+        let metric = match self.peek() {
+            Some(c) if is_name_start(c) || c == ':' => Some(self.parse_name(true)?),
+            _ => None,
+        };
+        let mut matchers = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('{') {
+            self.position += 1;
+            loop {
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.position += 1;
+                    break;
+                }
+                matchers.push(self.parse_matcher()?);
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(',') => self.position += 1,
+                    Some('}') => {
+                        self.position += 1;
+                        break;
+                    }
+                    _ => return Err(self.error("expected ',' or '}'")),
+                }
+            }
+        }
+        if metric.is_none() && matchers.is_empty() && self.position == start {
+            return Err(self.error("expected a metric name or '{'"));
+        }
+        Ok(Selector { metric, matchers })
+    }
+
+    fn parse_matcher(&mut self) -> anyhow::Result<LabelMatcher> {
+        let name = self.parse_name(false)?;
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        let op = ["=~", "!~", "!=", "="]
+            .into_iter()
+            .find(|op| rest.starts_with(op))
+            .ok_or_else(|| self.error("expected one of '=', '!=', '=~', '!~'"))?;
+        self.position += op.len();
+        self.skip_whitespace();
+        let value = self.parse_string()?;
+        LabelMatcher::new(&name, op, &value)
+            .map_err(|err| self.error(&format!("invalid matcher for '{}': {}", name, err)))
+    }
+
+    /// Metric names may contain colons, label names may not.
+    fn parse_name(&mut self, is_metric: bool) -> anyhow::Result<String> {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            let valid = if self.position == start {
+                is_name_start(c) || (is_metric && c == ':')
+            } else {
+                is_name_start(c) || c.is_ascii_digit() || (is_metric && c == ':')
+            };
+            if !valid {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+        if start == self.position {
+            return Err(self.error("expected a label name"));
+        }
+        Ok(self.input[start..self.position].to_string())
+    }
+
+    fn parse_string(&mut self) -> anyhow::Result<String> {
+        let quote = match self.peek() {
+            Some(quote) if quote == '"' || quote == '\'' || quote == '`' => quote,
+            _ => return Err(self.error("expected a quoted string")),
+        };
+        self.position += 1;
+        let mut value = String::new();
+        let mut chars = self.input[self.position..].chars();
+        while let Some(c) = chars.next() {
+            self.position += c.len_utf8();
+            match c {
+                c if c == quote => return Ok(value),
+                // raw strings in backticks have no escape sequences
+                '\\' if quote != '`' => {
+                    let escaped = chars
+                        .next()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.position += escaped.len_utf8();
+                    value.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        other => other,
+                    });
+                }
+                c => value.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "invalid selector '{}' at position {}: {}",
+            self.input,
+            self.position,
+            message
+        )
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selector() {
+        let selector =
+            Selector::parse(r#"http_requests_total{code=~"5..", method!="GET",path!~'/api/.*',}"#)
+                .unwrap();
+        assert_eq!(selector.metric.as_deref(), Some("http_requests_total"));
+        assert_eq!(selector.matchers.len(), 3);
+        assert!(selector.matchers[0].matches("503"));
+        assert!(!selector.matchers[0].matches("5030"));
+        assert!(selector.matchers[1].matches("POST"));
+        assert!(selector.matchers[2].matches("/metrics"));
+        assert!(!selector.matchers[2].matches("/api/users"));
+        assert_eq!(
+            selector.to_string(),
+            r#"http_requests_total{code=~"5..", method!="GET", path!~"/api/.*"}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_selector_without_metric_name() {
+        let selector = Selector::parse(r#" { __name__=~"node_.*" , msg="say \"hi\"" } "#).unwrap();
+        assert_eq!(selector.metric, None);
+        assert!(selector.matches_metric_name("node_load1"));
+        assert!(!selector.matches_metric_name("up"));
+        assert_eq!(selector.label_matchers().len(), 1);
+        assert_eq!(selector.label_matchers()[0].value, "say \"hi\"");
+
+        let selector = Selector::parse("node:load1").unwrap();
+        assert!(selector.matches_metric_name("node:load1"));
+        assert!(selector.matchers.is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid_selectors() {
+        for (input, error) in [
+            ("", "expected a metric name or '{'"),
+            ("   ", "expected a metric name or '{'"),
+            ("up{code}", "expected one of '=', '!=', '=~', '!~'"),
+            ("up{code=200}", "expected a quoted string"),
+            ("up{code=\"200\"", "expected ',' or '}'"),
+            ("up{code=\"200}", "unterminated string"),
+            ("up{code=~\"(\"}", "invalid matcher for 'code'"),
+            ("up{} down", "unexpected characters after the selector"),
+        ] {
+            let message = Selector::parse(input).err().unwrap().to_string();
+            assert!(
+                message.contains(error),
+                "'{}' should fail with '{}' but failed with '{}'",
+                input,
+                error,
+                message
+            );
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use super::{
+    metric_source::{new_shared_history, MetricSource, SharedHistory},
+    model::MetricHistory,
+};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// Identifies session files, followed by the version of the format.
+const SESSION_MAGIC: &[u8; 8] = b"PROMTUIS";
+const SESSION_VERSION: u32 = 2;
+
+/// The full model collected while watching a source, as stored on disk.
+#[derive(Deserialize)]
+pub struct Session {
+    pub endpoint: String,
+    pub scrape_interval: u64,
+    pub history: MetricHistory,
+}
+
+/// Borrowing twin of `Session`, so saving does not need to clone the history.
+#[derive(Serialize)]
+struct SessionRef<'a> {
+    endpoint: &'a str,
+    scrape_interval: u64,
+    history: &'a MetricHistory,
+}
+
+/// Variable length integers keep the many timestamps and bucket counts compact.
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new().with_varint_encoding()
+}
+
+pub fn save_session<P: AsRef<Path>>(
+    path: P,
+    endpoint: &str,
+    scrape_interval: u64,
+    history: &MetricHistory,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(SESSION_MAGIC)?;
+    writer.write_all(&SESSION_VERSION.to_le_bytes())?;
+    bincode_options().serialize_into(
+        &mut writer,
+        &SessionRef {
+            endpoint,
+            scrape_interval,
+            history,
+        },
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_session<P: AsRef<Path>>(path: P) -> anyhow::Result<Session> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != SESSION_MAGIC {
+        return Err(anyhow::anyhow!("not a prom-tui session file"));
+    }
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != SESSION_VERSION {
+        return Err(anyhow::anyhow!(
+            "unsupported session file version {}, expected {}",
+            version,
+            SESSION_VERSION
+        ));
+    }
+    Ok(bincode_options().deserialize_from(reader)?)
+}
+
+/// Serves the history of a saved session. Nothing is scraped, the history is read-only.
+pub struct SessionSource {
+    metrics_history: SharedHistory,
+    error_msg: Arc<RwLock<Option<String>>>,
+}
+
+impl SessionSource {
+    pub fn new(history: MetricHistory) -> Self {
+        Self {
+            metrics_history: new_shared_history(history),
+            error_msg: Arc::new(RwLock::new(None)),
+        }
+    }
+}
+
+impl MetricSource for SessionSource {
+    fn history_snapshot(&self) -> Arc<MetricHistory> {
+        self.metrics_history.load_full()
+    }
+
+    fn get_error_msg_read_guard(&self) -> anyhow::Result<RwLockReadGuard<'_, Option<String>>> {
+        self.error_msg
+            .read()
+            .map_err(|err| anyhow::anyhow!("failed to aquire lock: {}", err))
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::{
+        model::Sample,
+        test_data::{generate_metric_lines, history_of},
+    };
+
+    fn history_with_two_scrapes() -> MetricHistory {
+        let body = generate_metric_lines().join("\n");
+        let mut history = history_of([(1657000000, &body), (1657000010, &body)]);
+        history.add_scrape_error(1657000020, "connection refused".to_string());
+        history
+    }
+
+    #[test]
+    fn test_save_and_load_session() {
+        let path = std::env::temp_dir().join("prom-tui-test-save-and-load.session");
+        let history = history_with_two_scrapes();
+        save_session(&path, "http://localhost:8080/metrics", 10, &history).unwrap();
+        let session = load_session(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(session.endpoint, "http://localhost:8080/metrics");
+        assert_eq!(session.scrape_interval, 10);
+        assert_eq!(session.history.scrape_errors, history.scrape_errors);
+        assert_eq!(session.history.metrics.len(), history.metrics.len());
+        let metric = session.history.get_metric("response_time").unwrap();
+        assert_eq!(metric.details.docstring, "Response Times");
+        let time_series = &metric.time_series()["env=\"testing\""];
+        assert_eq!(time_series.labels.get("env").unwrap(), "testing");
+        assert_eq!(time_series.samples.len(), 2);
+        match &time_series.samples.get(1).unwrap() {
+            Sample::HistogramSample(histogram) => {
+                assert_eq!(histogram.timestamp, 1657000010);
+                assert_eq!(histogram.count, 6451);
+                assert_eq!(histogram.bucket_values.len(), 8);
+            }
+            _ => panic!("expected a histogram sample"),
+        }
+    }
+
+    #[test]
+    fn test_load_session_rejects_other_files() {
+        let path = std::env::temp_dir().join("prom-tui-test-not-a-session");
+        std::fs::write(&path, "# HELP metric_1 Description of the metric").unwrap();
+        let result = load_session(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "not a prom-tui session file"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use std::cmp::Ordering;
+
+use super::counter::{counter_points, sample_points, CounterValue, CounterView};
+use super::histogram::{bucket_counts, histogram_increase};
+use super::model::{HistogramValueSample, MetricHistory, MetricType, Sample};
+use super::promql::functions::bucket_quantile;
+use super::stats::average_rate;
+use super::storage::SampleStorage;
+
+/// The quantiles a histogram shift is described by.
+pub const SHIFT_QUANTILES: [f64; 2] = [0.5, 0.99];
+
+/// How a time series changed between two marks, compared to what came before the first mark.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// the values at the marks
+    Gauge { before: f64, after: f64 },
+    /// the average rate per second before the first mark and between the marks, and the
+    /// increase between the marks
+    Counter {
+        before: Option<f64>,
+        after: f64,
+        increase: f64,
+    },
+    /// the `SHIFT_QUANTILES` of the observations up to the first mark and of the ones between
+    /// the marks, and the number of observations between the marks
+    Histogram {
+        before: Vec<f64>,
+        after: Vec<f64>,
+        observations: u64,
+    },
+}
+
+impl Change {
+    /// The relative change in percent, the largest one of the quantiles of a histogram. Infinite
+    /// when there was nothing to compare with, e.g. a gauge going up from zero.
+    pub fn percent(&self) -> f64 {
+        match self {
+            Change::Gauge { before, after } => percent(*before, *after),
+            Change::Counter { before, after, .. } => percent(before.unwrap_or(0.0), *after),
+            Change::Histogram { before, after, .. } => before
+                .iter()
+                .zip(after)
+                .map(|(before, after)| percent(*before, *after))
+                .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+                .unwrap_or(0.0),
+        }
+    }
+}
+
+fn percent(before: f64, after: f64) -> f64 {
+    if before == after || (before.is_nan() && after.is_nan()) {
+        0.0
+    } else if before == 0.0 || !before.is_finite() {
+        f64::INFINITY
+    } else {
+        (after - before) / before.abs() * 100.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeriesChange {
+    pub metric: String,
+    /// key of the labels of the series
+    pub labels: String,
+    pub change: Change,
+}
+
+/// The time series which changed between the marks, the largest relative change first. Gauges
+/// are compared by their values at the marks, counters by their rate before the first mark and
+/// between the marks, and histograms by the quantiles of their observations up to the first mark
+/// and between the marks. A series needs a sample at or before each mark.
+pub fn snapshot_changes(history: &MetricHistory, from: u64, to: u64) -> Vec<SeriesChange> {
+    let mut changes: Vec<SeriesChange> = history
+        .metrics
+        .values()
+        .flat_map(|metric| {
+            metric
+                .time_series()
+                .iter()
+                .filter_map(move |(labels, time_series)| {
+                    let change =
+                        series_change(&time_series.samples, &metric.details.metric_type, from, to)?;
+                    Some(SeriesChange {
+                        metric: metric.details.name.clone(),
+                        labels: labels.clone(),
+                        change,
+                    })
+                })
+        })
+        .filter(|change| change.change.percent() != 0.0)
+        .collect();
+    changes.sort_by(|a, b| {
+        b.change
+            .percent()
+            .abs()
+            .partial_cmp(&a.change.percent().abs())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| (&a.metric, &a.labels).cmp(&(&b.metric, &b.labels)))
+    });
+    changes
+}
+
+fn series_change(
+    samples: &SampleStorage,
+    metric_type: &MetricType,
+    from: u64,
+    to: u64,
+) -> Option<Change> {
+    match metric_type {
+        MetricType::Gauge => {
+            let points = sample_points(samples, CounterValue::Value);
+            let (before, after) = mark_indexes(&points, from, to)?;
+            Some(Change::Gauge {
+                before: points[before].1,
+                after: points[after].1,
+            })
+        }
+        MetricType::Counter => {
+            let points = sample_points(samples, CounterValue::Value);
+            let (before, after) = mark_indexes(&points, from, to)?;
+            let between = &points[before..=after];
+            Some(Change::Counter {
+                before: average_rate(&points[..=before]),
+                after: average_rate(between)?,
+                increase: counter_points(between, CounterView::Delta, 0)
+                    .iter()
+                    .map(|(_, delta)| delta)
+                    .sum(),
+            })
+        }
+        MetricType::Histogram => {
+            let histograms: Vec<HistogramValueSample> = samples
+                .iter()
+                .filter_map(|sample| match sample {
+                    Sample::HistogramSample(histogram) => Some(histogram),
+                    _ => None,
+                })
+                .collect();
+            let timestamps: Vec<(u64, f64)> = histograms
+                .iter()
+                .map(|histogram| (histogram.timestamp, 0.0))
+                .collect();
+            let (before, after) = mark_indexes(&timestamps, from, to)?;
+            let between = histogram_increase(&histograms[before..=after])?;
+            let quantiles = |histogram: &HistogramValueSample| {
+                let buckets = bucket_counts(histogram);
+                SHIFT_QUANTILES
+                    .iter()
+                    .map(|quantile| bucket_quantile(*quantile, &buckets))
+                    .collect()
+            };
+            Some(Change::Histogram {
+                before: quantiles(&histograms[before]),
+                after: quantiles(&between),
+                observations: between.count,
+            })
+        }
+    }
+}
+
+/// The indexes of the last points at or before the marks, if they differ.
+fn mark_indexes(points: &[(u64, f64)], from: u64, to: u64) -> Option<(usize, usize)> {
+    let last_at = |mark: u64| points.iter().rposition(|(timestamp, _)| *timestamp <= mark);
+    match (last_at(from)?, last_at(to)?) {
+        (before, after) if before < after => Some((before, after)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prom::test_data::{exposition, history_of, jobs};
+
+    fn body(queue: f64, total: f64, slow: u64) -> String {
+        format!(
+            "{}{}# HELP latency Latency.\n# TYPE latency histogram\n\
+             latency_bucket{{le=\"1\"}} 100\nlatency_bucket{{le=\"10\"}} {}\nlatency_bucket{{le=\"+Inf\"}} {}\n\
+             latency_sum 0\nlatency_count {}\n",
+            jobs(&[queue, 1.0]),
+            exposition("requests_total", "counter", &[("", total)]),
+            100 + slow,
+            100 + slow,
+            100 + slow
+        )
+    }
+
+    #[test]
+    fn test_snapshot_changes() {
+        let history = history_of([
+            (1000, body(10.0, 0.0, 0)),
+            (1010, body(10.0, 10.0, 0)),
+            // the load step between the marks at 1010 and 1030
+            (1020, body(15.0, 40.0, 50)),
+            (1030, body(12.0, 70.0, 100)),
+            (1040, body(50.0, 80.0, 100)),
+        ]);
+
+        let changes = snapshot_changes(&history, 1010, 1035);
+        let ranked: Vec<&str> = changes
+            .iter()
+            .map(|change| change.metric.as_str())
+            .collect();
+        // the unchanged gauge is left out
+        assert_eq!(ranked, vec!["latency", "requests_total", "jobs"]);
+
+        assert_eq!(
+            changes[1].change,
+            Change::Counter {
+                before: Some(1.0),
+                after: 3.0,
+                increase: 60.0
+            }
+        );
+        assert_eq!(changes[1].change.percent(), 200.0);
+        assert_eq!(
+            changes[2].change,
+            Change::Gauge {
+                before: 10.0,
+                after: 12.0
+            }
+        );
+        match &changes[0].change {
+            Change::Histogram {
+                before,
+                after,
+                observations,
+            } => {
+                assert_eq!(*observations, 100);
+                assert_eq!(before[0], 0.5);
+                // all the observations between the marks were slow
+                assert_eq!(after[0], 5.5);
+            }
+            change => panic!("expected a histogram change, got {:?}", change),
+        }
+
+        // without a sample before the first mark, nothing is compared
+        assert!(snapshot_changes(&history, 900, 1040).is_empty());
+    }
+
+    #[test]
+    fn test_histogram_restarted_between_marks() {
+        let history = history_of([(1000, 100, 100), (1010, 10, 20), (1020, 40, 150)].map(
+            |(timestamp, fast, total)| {
+                let body = format!(
+                    "# HELP latency Latency.\n# TYPE latency histogram\n\
+                     latency_bucket{{le=\"1\"}} {}\nlatency_bucket{{le=\"10\"}} {}\n\
+                     latency_bucket{{le=\"+Inf\"}} {}\nlatency_sum 0\nlatency_count {}\n",
+                    fast, total, total, total
+                );
+                (timestamp, body)
+            },
+        ));
+        let changes = snapshot_changes(&history, 1000, 1020);
+        match &changes[0].change {
+            // 20 observations until the scrape after the restart, 130 after it
+            Change::Histogram { observations, .. } => assert_eq!(*observations, 150),
+            change => panic!("expected a histogram change, got {:?}", change),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use super::counter::{counter_points, CounterView};
+use super::promql::functions::quantile;
+
+/// Summary of the points of a time series. Points without a number as value are left out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// population standard deviation, like `stddev_over_time()`
+    pub stddev: f64,
+    pub p95: f64,
+    pub first: f64,
+    pub last: f64,
+    /// the last value minus the first one
+    pub change: f64,
+    /// timestamp of the first point with the maximum value
+    pub time_of_max: u64,
+}
+
+impl Stats {
+    pub fn from_points(points: &[(u64, f64)]) -> Option<Self> {
+        let points: Vec<(u64, f64)> = points
+            .iter()
+            .filter(|(_, value)| !value.is_nan())
+            .cloned()
+            .collect();
+        let (_, first) = *points.first()?;
+        let (_, last) = *points.last()?;
+        let values: Vec<f64> = points.iter().map(|(_, value)| *value).collect();
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / count as f64;
+        let (time_of_max, max) =
+            points.iter().cloned().fold(
+                points[0],
+                |max, point| if point.1 > max.1 { point } else { max },
+            );
+        Some(Self {
+            count,
+            min: values.iter().cloned().fold(f64::INFINITY, f64::min),
+            max,
+            mean,
+            median: quantile(0.5, &values),
+            stddev: variance.sqrt(),
+            p95: quantile(0.95, &values),
+            first,
+            last,
+            change: last - first,
+            time_of_max,
+        })
+    }
+}
+
+/// The average increase per second of a counter between its first and its last sample. The
+/// value after a reset counts as increase, like in `rate()`.
+pub fn average_rate(points: &[(u64, f64)]) -> Option<f64> {
+    let (first, _) = points.first()?;
+    let (last, _) = points.last()?;
+    if last <= first {
+        return None;
+    }
+    let increase: f64 = counter_points(points, CounterView::Delta, 0)
+        .iter()
+        .map(|(_, delta)| delta)
+        .sum();
+    Some(increase / (last - first) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_of_points() {
+        let points = vec![(10, 4.0), (20, 2.0), (30, 9.0), (40, 9.0), (50, 1.0)];
+        let stats = Stats::from_points(&points).unwrap();
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 9.0);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.median, 4.0);
+        assert!((stats.stddev - 11.6_f64.sqrt()).abs() < 1e-9);
+        assert!((stats.p95 - 9.0).abs() < 1e-9);
+        assert_eq!((stats.first, stats.last, stats.change), (4.0, 1.0, -3.0));
+        assert_eq!(stats.time_of_max, 30);
+    }
+
+    #[test]
+    fn test_stats_skip_missing_values() {
+        assert_eq!(Stats::from_points(&[]), None);
+        assert_eq!(Stats::from_points(&[(10, f64::NAN)]), None);
+        let stats = Stats::from_points(&[(10, f64::NAN), (20, 3.0)]).unwrap();
+        assert_eq!((stats.count, stats.first, stats.stddev), (1, 3.0, 0.0));
+    }
+
+    #[test]
+    fn test_average_rate_of_counter() {
+        assert_eq!(average_rate(&[(10, 5.0)]), None);
+        assert_eq!(
+            average_rate(&[(10, 5.0), (20, 25.0), (30, 45.0)]),
+            Some(2.0)
+        );
+        // restarted after 20s, the 10 counted since the restart are an increase as well
+        assert_eq!(
+            average_rate(&[(10, 5.0), (20, 25.0), (30, 10.0)]),
+            Some(1.5)
+        );
+    }
+}
@@ -0,0 +1,660 @@
+use super::model::{Bucket, HistogramValueSample, Sample, SingleValueSample};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Number of samples per chunk. Only the last chunk of a series is appended to.
+const CHUNK_SIZE: usize = 120;
+
+/// The samples of a time series, compressed in chunks: timestamps as delta of deltas, values and
+/// histogram sums XORed with the previous value, histogram counts as deltas to the previous
+/// sample. The bucket boundaries of histograms are stored once per series.
+///
+/// Samples are decoded on the fly while iterating, the latest sample is kept decoded. Chunks are
+/// shared between clones, a clone only copies the last chunk when a sample is appended to it.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SampleStorage {
+    chunks: Vec<Arc<Chunk>>,
+    /// bucket boundaries of the histogram samples, referenced by the chunks
+    bucket_layouts: Vec<Arc<[String]>>,
+    len: usize,
+    last: Option<Sample>,
+}
+
+impl SampleStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        let (kind, layout) = self.kind_and_layout(&sample);
+        let needs_new_chunk = match self.chunks.last() {
+            Some(chunk) => chunk.len >= CHUNK_SIZE || chunk.kind != kind || chunk.layout != layout,
+            None => true,
+        };
+        if needs_new_chunk {
+            let bucket_count = match kind {
+                SampleKind::Histogram => self.bucket_layouts[layout].len(),
+                _ => 0,
+            };
+            self.chunks
+                .push(Arc::new(Chunk::new(kind, layout, bucket_count)));
+        }
+        Arc::make_mut(self.chunks.last_mut().expect("a chunk to append to")).append(&sample);
+        self.len += 1;
+        self.last = Some(sample);
+    }
+
+    /// Inserts samples before the stored ones. The samples are expected to be ordered by time
+    /// and older than the stored ones.
+    pub fn prepend(&mut self, samples: Vec<Sample>) {
+        if samples.is_empty() {
+            return;
+        }
+        let mut storage = SampleStorage {
+            bucket_layouts: std::mem::take(&mut self.bucket_layouts),
+            ..SampleStorage::default()
+        };
+        for sample in samples {
+            storage.push(sample);
+        }
+        storage.chunks.append(&mut self.chunks);
+        storage.len += self.len;
+        if self.last.is_some() {
+            storage.last = self.last.take();
+        }
+        *self = storage;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn last(&self) -> Option<&Sample> {
+        self.last.as_ref()
+    }
+
+    pub fn first_timestamp(&self) -> Option<u64> {
+        self.chunks.first().map(|chunk| chunk.first_timestamp)
+    }
+
+    /// Decodes the sample at the index, skipping the chunks before it.
+    pub fn get(&self, index: usize) -> Option<Sample> {
+        let mut skipped = 0;
+        for (chunk_index, chunk) in self.chunks.iter().enumerate() {
+            if index < skipped + chunk.len {
+                return SampleIter {
+                    chunks: self.chunks[chunk_index..].iter(),
+                    bucket_layouts: &self.bucket_layouts,
+                    current: None,
+                }
+                .nth(index - skipped);
+            }
+            skipped += chunk.len;
+        }
+        None
+    }
+
+    pub fn iter(&self) -> SampleIter<'_> {
+        SampleIter {
+            chunks: self.chunks.iter(),
+            bucket_layouts: &self.bucket_layouts,
+            current: None,
+        }
+    }
+
+    /// Iterates over the samples not older than the timestamp. The chunks ending before the
+    /// timestamp are not decoded.
+    pub fn iter_since(&self, timestamp: u64) -> impl Iterator<Item = Sample> + '_ {
+        SampleIter {
+            chunks: self.chunks[self.first_chunk_since(timestamp)..].iter(),
+            bucket_layouts: &self.bucket_layouts,
+            current: None,
+        }
+        .filter(move |sample| sample.timestamp() >= timestamp)
+    }
+
+    /// Iterates over the samples not older than the timestamp, preceded by the latest older
+    /// sample, e.g. for the first delta of a counter. Only the chunk before the ones of
+    /// `iter_since` is decoded in addition.
+    pub fn iter_since_previous(&self, timestamp: u64) -> impl Iterator<Item = Sample> + '_ {
+        let mut samples = SampleIter {
+            chunks: self.chunks[self.first_chunk_since(timestamp).saturating_sub(1)..].iter(),
+            bucket_layouts: &self.bucket_layouts,
+            current: None,
+        }
+        .peekable();
+        let mut previous = None;
+        while let Some(sample) = samples.next_if(|sample| sample.timestamp() < timestamp) {
+            previous = Some(sample);
+        }
+        previous.into_iter().chain(samples)
+    }
+
+    /// The index of the first chunk ending at or after the timestamp.
+    fn first_chunk_since(&self, timestamp: u64) -> usize {
+        self.chunks
+            .iter()
+            .position(|chunk| chunk.max_timestamp >= timestamp)
+            .unwrap_or(self.chunks.len())
+    }
+
+    /// Size of the compressed samples in bytes.
+    pub fn compressed_size(&self) -> usize {
+        self.chunks
+            .iter()
+            .map(|chunk| chunk.stream.bytes.len())
+            .sum()
+    }
+
+    fn kind_and_layout(&mut self, sample: &Sample) -> (SampleKind, usize) {
+        match sample {
+            Sample::GaugeSample(_) => (SampleKind::Gauge, 0),
+            Sample::CounterSample(_) => (SampleKind::Counter, 0),
+            Sample::HistogramSample(histogram) => {
+                let names = histogram.bucket_values.iter().map(|bucket| &bucket.name);
+                let layout = self
+                    .bucket_layouts
+                    .iter()
+                    .position(|layout| layout.iter().eq(names.clone()));
+                let layout = match layout {
+                    Some(layout) => layout,
+                    None => {
+                        self.bucket_layouts
+                            .push(names.cloned().collect::<Vec<String>>().into());
+                        self.bucket_layouts.len() - 1
+                    }
+                };
+                (SampleKind::Histogram, layout)
+            }
+        }
+    }
+}
+
+impl FromIterator<Sample> for SampleStorage {
+    fn from_iter<I: IntoIterator<Item = Sample>>(samples: I) -> Self {
+        let mut storage = SampleStorage::new();
+        for sample in samples {
+            storage.push(sample);
+        }
+        storage
+    }
+}
+
+impl<'a> IntoIterator for &'a SampleStorage {
+    type Item = Sample;
+    type IntoIter = SampleIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Debug for SampleStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct SampleIter<'a> {
+    chunks: std::slice::Iter<'a, Arc<Chunk>>,
+    bucket_layouts: &'a [Arc<[String]>],
+    current: Option<ChunkReader<'a>>,
+}
+
+impl<'a> Iterator for SampleIter<'a> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        loop {
+            if let Some(sample) = self.current.as_mut().and_then(ChunkReader::next) {
+                return Some(sample);
+            }
+            let chunk = self.chunks.next()?;
+            self.current = Some(ChunkReader::new(chunk, self.bucket_layouts));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum SampleKind {
+    Gauge,
+    Counter,
+    Histogram,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Chunk {
+    kind: SampleKind,
+    /// index of the bucket boundaries, for histogram chunks
+    layout: usize,
+    len: usize,
+    first_timestamp: u64,
+    max_timestamp: u64,
+    stream: BitStream,
+    /// the previous sample, needed to append the next one
+    state: CodecState,
+}
+
+/// What the encoding of a sample depends on. The reader keeps the same state while decoding.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct CodecState {
+    timestamp: u64,
+    delta: i64,
+    value: XorState,
+    count: u64,
+    buckets: Vec<u64>,
+}
+
+impl Chunk {
+    fn new(kind: SampleKind, layout: usize, bucket_count: usize) -> Self {
+        Self {
+            kind,
+            layout,
+            len: 0,
+            first_timestamp: 0,
+            max_timestamp: 0,
+            stream: BitStream::default(),
+            state: CodecState {
+                buckets: vec![0; bucket_count],
+                ..CodecState::default()
+            },
+        }
+    }
+
+    fn append(&mut self, sample: &Sample) {
+        let timestamp = sample.timestamp();
+        if self.len == 0 {
+            self.stream.write_bits(timestamp, 64);
+            self.first_timestamp = timestamp;
+        } else {
+            let delta = timestamp.wrapping_sub(self.state.timestamp) as i64;
+            write_signed(&mut self.stream, delta.wrapping_sub(self.state.delta));
+            self.state.delta = delta;
+        }
+        self.state.timestamp = timestamp;
+        self.max_timestamp = self.max_timestamp.max(timestamp);
+
+        match sample {
+            Sample::GaugeSample(sample) | Sample::CounterSample(sample) => {
+                write_value(&mut self.stream, &mut self.state.value, sample.value);
+            }
+            Sample::HistogramSample(histogram) => {
+                write_value(&mut self.stream, &mut self.state.value, histogram.sum);
+                write_signed(
+                    &mut self.stream,
+                    histogram.count.wrapping_sub(self.state.count) as i64,
+                );
+                self.state.count = histogram.count;
+                for (bucket, previous) in histogram
+                    .bucket_values
+                    .iter()
+                    .zip(self.state.buckets.iter_mut())
+                {
+                    write_signed(
+                        &mut self.stream,
+                        bucket.value.wrapping_sub(*previous) as i64,
+                    );
+                    *previous = bucket.value;
+                }
+            }
+        }
+        self.len += 1;
+    }
+}
+
+struct ChunkReader<'a> {
+    chunk: &'a Chunk,
+    bucket_names: &'a [String],
+    reader: BitReader<'a>,
+    read: usize,
+    state: CodecState,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(chunk: &'a Chunk, bucket_layouts: &'a [Arc<[String]>]) -> Self {
+        let bucket_names: &[String] = match chunk.kind {
+            SampleKind::Histogram => &bucket_layouts[chunk.layout],
+            _ => &[],
+        };
+        Self {
+            chunk,
+            bucket_names,
+            reader: BitReader {
+                bytes: &chunk.stream.bytes,
+                position: 0,
+            },
+            read: 0,
+            state: CodecState {
+                buckets: vec![0; bucket_names.len()],
+                ..CodecState::default()
+            },
+        }
+    }
+
+    fn next(&mut self) -> Option<Sample> {
+        if self.read == self.chunk.len {
+            return None;
+        }
+        if self.read == 0 {
+            self.state.timestamp = self.reader.read_bits(64);
+        } else {
+            self.state.delta = self.state.delta.wrapping_add(read_signed(&mut self.reader));
+            self.state.timestamp = self.state.timestamp.wrapping_add(self.state.delta as u64);
+        }
+        self.read += 1;
+
+        let timestamp = self.state.timestamp;
+        let sample = match self.chunk.kind {
+            SampleKind::Gauge => Sample::GaugeSample(SingleValueSample {
+                timestamp,
+                value: read_value(&mut self.reader, &mut self.state.value),
+            }),
+            SampleKind::Counter => Sample::CounterSample(SingleValueSample {
+                timestamp,
+                value: read_value(&mut self.reader, &mut self.state.value),
+            }),
+            SampleKind::Histogram => {
+                let sum = read_value(&mut self.reader, &mut self.state.value);
+                self.state.count = self
+                    .state
+                    .count
+                    .wrapping_add(read_signed(&mut self.reader) as u64);
+                for previous in self.state.buckets.iter_mut() {
+                    *previous = previous.wrapping_add(read_signed(&mut self.reader) as u64);
+                }
+                Sample::HistogramSample(HistogramValueSample {
+                    timestamp,
+                    bucket_values: self
+                        .bucket_names
+                        .iter()
+                        .zip(self.state.buckets.iter())
+                        .map(|(name, value)| Bucket::new(name.clone(), *value))
+                        .collect(),
+                    sum,
+                    count: self.state.count,
+                })
+            }
+        };
+        Some(sample)
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct BitStream {
+    bytes: Vec<u8>,
+    /// number of written bits
+    len: usize,
+}
+
+impl BitStream {
+    fn write_bit(&mut self, bit: bool) {
+        if self.len == self.bytes.len() * 8 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().expect("a byte to write to") |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// Writes the `count` lowest bits of the value, the most significant first.
+    fn write_bits(&mut self, value: u64, count: u32) {
+        for shift in (0..count).rev() {
+            self.write_bit((value >> shift) & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bit(&mut self) -> bool {
+        let bit = self.bytes[self.position / 8] & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        bit
+    }
+
+    fn read_bits(&mut self, count: u32) -> u64 {
+        (0..count).fold(0, |value, _| (value << 1) | self.read_bit() as u64)
+    }
+}
+
+/// Writes a signed integer after a prefix giving its width, so the common small deltas (and the
+/// zero delta of deltas of regular scrapes) take a few bits only.
+fn write_signed(stream: &mut BitStream, value: i64) {
+    let zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    match zigzag {
+        0 => stream.write_bit(false),
+        1..=0x7f => {
+            stream.write_bits(0b10, 2);
+            stream.write_bits(zigzag, 7);
+        }
+        0x80..=0xfff => {
+            stream.write_bits(0b110, 3);
+            stream.write_bits(zigzag, 12);
+        }
+        0x1000..=0xf_ffff => {
+            stream.write_bits(0b1110, 4);
+            stream.write_bits(zigzag, 20);
+        }
+        _ => {
+            stream.write_bits(0b1111, 4);
+            stream.write_bits(zigzag, 64);
+        }
+    }
+}
+
+fn read_signed(reader: &mut BitReader) -> i64 {
+    let width = if !reader.read_bit() {
+        return 0;
+    } else if !reader.read_bit() {
+        7
+    } else if !reader.read_bit() {
+        12
+    } else if !reader.read_bit() {
+        20
+    } else {
+        64
+    };
+    let zigzag = reader.read_bits(width);
+    ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64)
+}
+
+/// The previous value and the window of its meaningful bits, as in the Gorilla paper.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct XorState {
+    previous: u64,
+    /// leading and trailing zeros of the last written XOR
+    window: Option<(u32, u32)>,
+}
+
+fn write_value(stream: &mut BitStream, state: &mut XorState, value: f64) {
+    let bits = value.to_bits();
+    let xor = bits ^ state.previous;
+    state.previous = bits;
+    if xor == 0 {
+        stream.write_bit(false);
+        return;
+    }
+    stream.write_bit(true);
+    let leading = xor.leading_zeros().min(31);
+    let trailing = xor.trailing_zeros();
+    match state.window {
+        Some((window_leading, window_trailing))
+            if leading >= window_leading && trailing >= window_trailing =>
+        {
+            stream.write_bit(false);
+            stream.write_bits(
+                xor >> window_trailing,
+                64 - window_leading - window_trailing,
+            );
+        }
+        _ => {
+            let meaningful = 64 - leading - trailing;
+            stream.write_bit(true);
+            stream.write_bits(leading as u64, 5);
+            stream.write_bits((meaningful - 1) as u64, 6);
+            stream.write_bits(xor >> trailing, meaningful);
+            state.window = Some((leading, trailing));
+        }
+    }
+}
+
+fn read_value(reader: &mut BitReader, state: &mut XorState) -> f64 {
+    if reader.read_bit() {
+        let (leading, trailing) = match (reader.read_bit(), state.window) {
+            (false, Some(window)) => window,
+            _ => {
+                let leading = reader.read_bits(5) as u32;
+                let meaningful = reader.read_bits(6) as u32 + 1;
+                (leading, 64 - leading - meaningful)
+            }
+        };
+        let xor = reader.read_bits(64 - leading - trailing) << trailing;
+        state.previous ^= xor;
+        state.window = Some((leading, trailing));
+    }
+    f64::from_bits(state.previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn gauge(timestamp: u64, value: f64) -> Sample {
+        Sample::GaugeSample(SingleValueSample { timestamp, value })
+    }
+
+    fn histogram(timestamp: u64, buckets: &[(&str, u64)], sum: f64) -> Sample {
+        Sample::HistogramSample(HistogramValueSample {
+            timestamp,
+            bucket_values: buckets
+                .iter()
+                .map(|(name, value)| Bucket::new(name.to_string(), *value))
+                .collect(),
+            sum,
+            count: buckets.last().map(|(_, value)| *value).unwrap_or_default(),
+        })
+    }
+
+    #[test]
+    fn test_regular_scrapes_compress_well() {
+        let storage: SampleStorage = (0..1000)
+            .map(|index| gauge(1657000000 + index * 10, 21.5))
+            .collect();
+        assert_eq!(storage.len(), 1000);
+        // 1 bit for the timestamp and 1 bit for the value of most samples
+        assert!(storage.compressed_size() < 1000 / 2);
+        assert_eq!(storage.iter().count(), 1000);
+        assert_eq!(
+            format!("{:?}", storage.get(999)),
+            format!("{:?}", Some(gauge(1657009990, 21.5)))
+        );
+        assert_eq!(storage.first_timestamp(), Some(1657000000));
+    }
+
+    #[test]
+    fn test_histograms_share_bucket_boundaries() {
+        let mut storage = SampleStorage::new();
+        storage.push(histogram(10, &[("0.1", 1), ("+Inf", 2)], 0.4));
+        storage.push(histogram(20, &[("0.1", 3), ("+Inf", 5)], 1.2));
+        // the exporter changed its buckets
+        storage.push(histogram(30, &[("0.5", 6), ("+Inf", 7)], 2.0));
+        storage.push(histogram(40, &[("0.5", 8), ("+Inf", 9)], 2.5));
+
+        assert_eq!(storage.bucket_layouts.len(), 2);
+        assert_eq!(storage.chunks.len(), 2);
+        let samples: Vec<Sample> = storage.iter().collect();
+        assert_eq!(
+            format!("{:?}", samples),
+            format!(
+                "{:?}",
+                vec![
+                    histogram(10, &[("0.1", 1), ("+Inf", 2)], 0.4),
+                    histogram(20, &[("0.1", 3), ("+Inf", 5)], 1.2),
+                    histogram(30, &[("0.5", 6), ("+Inf", 7)], 2.0),
+                    histogram(40, &[("0.5", 8), ("+Inf", 9)], 2.5),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_prepend_and_iter_since() {
+        let mut storage: SampleStorage = (300..500).map(|index| gauge(index, 1.0)).collect();
+        storage.prepend((0..300).map(|index| gauge(index, 0.5)).collect());
+        assert_eq!(storage.len(), 500);
+        assert_eq!(storage.first_timestamp(), Some(0));
+        assert_eq!(storage.last().unwrap().timestamp(), 499);
+
+        let timestamps: Vec<u64> = storage.iter().map(|sample| sample.timestamp()).collect();
+        assert_eq!(timestamps, (0..500).collect::<Vec<u64>>());
+        let since: Vec<u64> = storage
+            .iter_since(450)
+            .map(|sample| sample.timestamp())
+            .collect();
+        assert_eq!(since, (450..500).collect::<Vec<u64>>());
+        let since_previous = |timestamp| -> Vec<u64> {
+            storage
+                .iter_since_previous(timestamp)
+                .map(|sample| sample.timestamp())
+                .collect()
+        };
+        assert_eq!(since_previous(450), (449..500).collect::<Vec<u64>>());
+        // the previous sample is the last one of the prepended chunk
+        assert_eq!(since_previous(300), (299..500).collect::<Vec<u64>>());
+        assert_eq!(since_previous(0), (0..500).collect::<Vec<u64>>());
+        assert_eq!(since_previous(600), vec![499]);
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(
+            samples in prop::collection::vec(
+                (0u64..u64::MAX, prop::num::f64::ANY),
+                0..400,
+            ),
+        ) {
+            let samples: Vec<Sample> = samples
+                .into_iter()
+                .map(|(timestamp, value)| gauge(timestamp, value))
+                .collect();
+            let storage: SampleStorage = samples.iter().cloned().collect();
+            prop_assert_eq!(storage.len(), samples.len());
+            let decoded: Vec<Sample> = storage.iter().collect();
+            // the debug output compares NaN and negative zeros by bits
+            prop_assert_eq!(format!("{:?}", decoded), format!("{:?}", samples));
+        }
+
+        #[test]
+        fn test_round_trip_histograms(
+            samples in prop::collection::vec(
+                (0u64..2_000_000_000, prop::collection::vec(any::<u64>(), 3), -1e12f64..1e12),
+                0..300,
+            ),
+        ) {
+            let samples: Vec<Sample> = samples
+                .into_iter()
+                .map(|(timestamp, values, sum)| {
+                    histogram(
+                        timestamp,
+                        &[("0.1", values[0]), ("1", values[1]), ("+Inf", values[2])],
+                        sum,
+                    )
+                })
+                .collect();
+            let storage: SampleStorage = samples.iter().cloned().collect();
+            let decoded: Vec<Sample> = storage.iter().collect();
+            prop_assert_eq!(format!("{:?}", decoded), format!("{:?}", samples));
+        }
+    }
+}
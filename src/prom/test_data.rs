@@ -1,3 +1,6 @@
+use super::metric_scraper::{split_body, update_history_with_new_scrape};
+use super::model::MetricHistory;
+
 pub fn generate_metric_lines() -> Vec<String> {
     let mut lines = Vec::new();
     lines.push(String::from("# HELP metric_1 Description of the metric"));
@@ -104,3 +107,52 @@ pub fn generate_metric_lines() -> Vec<String> {
     lines.push(String::from("response_time_count 6451"));
     return lines;
 }
+
+/// Decodes the exposition body into the history, as scraped at the timestamp.
+pub fn scrape(history: &mut MetricHistory, timestamp: u64, body: &str) {
+    update_history_with_new_scrape(history, split_body(body), timestamp);
+}
+
+/// The history of the exposition bodies, scraped at their timestamps.
+pub fn history_of<I, B>(scrapes: I) -> MetricHistory
+where
+    I: IntoIterator<Item = (u64, B)>,
+    B: AsRef<str>,
+{
+    let mut history = MetricHistory::new();
+    for (timestamp, body) in scrapes {
+        scrape(&mut history, timestamp, body.as_ref());
+    }
+    history
+}
+
+/// The exposition of a metric with a sample per series, e.g. `("queue=\"a\"", 1.0)`, or
+/// `("", 1.0)` for the series without labels.
+pub fn exposition(name: &str, metric_type: &str, series: &[(&str, f64)]) -> String {
+    let mut body = format!(
+        "# HELP {} {}.\n# TYPE {} {}\n",
+        name, name, name, metric_type
+    );
+    for (labels, value) in series {
+        if labels.is_empty() {
+            body.push_str(&format!("{} {}\n", name, value));
+        } else {
+            body.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+        }
+    }
+    body
+}
+
+/// The gauge `jobs` with a series per queue, named `a`, `b`, `c` and so on.
+pub fn jobs(queues: &[f64]) -> String {
+    let series: Vec<(String, f64)> = queues
+        .iter()
+        .zip('a'..)
+        .map(|(value, queue)| (format!("queue=\"{}\"", queue), *value))
+        .collect();
+    let series: Vec<(&str, f64)> = series
+        .iter()
+        .map(|(labels, value)| (labels.as_str(), *value))
+        .collect();
+    exposition("jobs", "gauge", &series)
+}